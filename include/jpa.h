@@ -0,0 +1,23 @@
+/* C interface of the jupiter_perpetuals_analytics cdylib. */
+#ifndef JPA_H
+#define JPA_H
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+/* Fetches a snapshot from rpc_url as a JSON object with "snapshot", "custodies" and
+ * "positions". Free the result with jpa_free_string. Returns NULL on failure. */
+char *jpa_fetch_snapshot_json(const char *rpc_url);
+
+/* Message of the last failure on the calling thread, or NULL. Do not free. */
+const char *jpa_last_error(void);
+
+/* Releases a string returned by jpa_fetch_snapshot_json. NULL is ignored. */
+void jpa_free_string(char *json);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* JPA_H */
@@ -0,0 +1,237 @@
+//! Alert delivery. Alerts are posted as JSON to a webhook, with the message under both
+//! `content` and `text` so Discord and Slack incoming webhooks accept it as is.
+//!
+//! [`AlertTracker`] sits in front of delivery so a condition that holds across snapshots
+//! alerts once rather than every snapshot, and announces when it clears.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::PathBuf;
+
+use serde::{Deserialize, Serialize};
+use serde_json::json;
+
+use crate::transport::blocking_client;
+
+#[derive(Clone, Debug)]
+pub struct Alert {
+    /// Stable identifier of what is alerting, e.g. `anomaly:SOL borrow rate`
+    pub key: String,
+    pub message: String,
+}
+
+/// Posts each alert to `webhook_url`
+pub fn send(
+    webhook_url: &str,
+    proxy_url: Option<&str>,
+    alerts: &[Alert],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if alerts.is_empty() {
+        return Ok(());
+    }
+    let client = blocking_client(proxy_url)?;
+    for alert in alerts {
+        let response = client
+            .post(webhook_url)
+            .json(&json!({
+                "key": alert.key,
+                "content": alert.message,
+                "text": alert.message,
+            }))
+            .send()?;
+        if !response.status().is_success() {
+            return Err(format!("alert webhook responded {}", response.status()).into());
+        }
+    }
+    Ok(())
+}
+
+/// What the tracker remembers about one alert key
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+struct KeyState {
+    /// Consecutive snapshots the condition has held for, 0 once it cleared
+    breaches: u32,
+    /// When the alert was last sent
+    last_sent: Option<u64>,
+    /// Whether the alert was sent since the condition started holding, so its
+    /// resolution is worth announcing
+    announced: bool,
+    /// Latest message, repeated in the resolution notice
+    message: String,
+}
+
+/// Decides which alerts are sent: an alert goes out once its condition has held for
+/// `consecutive` snapshots, then not again for `cooldown_secs`, and a resolution notice
+/// follows when an announced condition clears. With a state file, this carries across runs.
+pub struct AlertTracker {
+    cooldown_secs: u64,
+    consecutive: u32,
+    path: Option<PathBuf>,
+    keys: BTreeMap<String, KeyState>,
+}
+
+impl AlertTracker {
+    /// Starts from the state at `path` if it exists
+    pub fn new(
+        cooldown_secs: u64,
+        consecutive: u32,
+        path: Option<PathBuf>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let keys = match &path {
+            Some(path) if path.exists() => serde_json::from_slice(&fs::read(path)?)
+                .map_err(|err| format!("invalid alert state {}: {err}", path.display()))?,
+            _ => BTreeMap::new(),
+        };
+        Ok(AlertTracker {
+            cooldown_secs,
+            consecutive: consecutive.max(1),
+            path,
+            keys,
+        })
+    }
+
+    /// Takes the alerts raised by the snapshot at `unix_time` and returns those to send,
+    /// including resolution notices for conditions that cleared. Nothing counts as sent
+    /// until [`AlertTracker::delivered`], so alerts that fail to go out are retried with
+    /// the next snapshot.
+    pub fn update(&mut self, unix_time: u64, alerts: Vec<Alert>) -> Vec<Alert> {
+        let mut send = vec![];
+        let raised: BTreeMap<String, String> = alerts
+            .into_iter()
+            .map(|alert| (alert.key, alert.message))
+            .collect();
+        for (key, state) in &mut self.keys {
+            if raised.contains_key(key) {
+                continue;
+            }
+            state.breaches = 0;
+            if state.announced {
+                send.push(Alert {
+                    key: key.clone(),
+                    message: format!("Resolved: {}", state.message),
+                });
+            }
+        }
+        for (key, message) in raised {
+            let state = self.keys.entry(key.clone()).or_default();
+            state.breaches = state.breaches.saturating_add(1);
+            state.message = message.clone();
+            let cooled_down = state.last_sent.map_or(true, |last_sent| {
+                unix_time.saturating_sub(last_sent) >= self.cooldown_secs
+            });
+            if state.breaches >= self.consecutive && cooled_down {
+                send.push(Alert { key, message });
+            }
+        }
+        // cleared keys are only kept while their resolution is pending or their cooldown
+        // still applies
+        let cooldown_secs = self.cooldown_secs;
+        self.keys.retain(|_, state| {
+            state.breaches > 0
+                || state.announced
+                || state.last_sent.map_or(false, |last_sent| {
+                    unix_time.saturating_sub(last_sent) < cooldown_secs
+                })
+        });
+        send
+    }
+
+    /// Records that `alerts`, as returned by [`AlertTracker::update`] for the snapshot at
+    /// `unix_time`, were delivered
+    pub fn delivered(&mut self, unix_time: u64, alerts: &[Alert]) {
+        for alert in alerts {
+            let Some(state) = self.keys.get_mut(&alert.key) else {
+                continue;
+            };
+            // a cleared condition's alert is its resolution notice
+            if state.breaches == 0 {
+                state.announced = false;
+            } else {
+                state.last_sent = Some(unix_time);
+                state.announced = true;
+            }
+        }
+    }
+
+    /// Writes the state file, if any
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(path) = &self.path else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec(&self.keys)?)?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn alert(key: &str) -> Alert {
+        Alert {
+            key: key.to_string(),
+            message: format!("{key} is off"),
+        }
+    }
+
+    fn keys(alerts: &[Alert]) -> Vec<&str> {
+        alerts.iter().map(|alert| alert.key.as_str()).collect()
+    }
+
+    #[test]
+    fn waits_for_consecutive_breaches() {
+        let mut tracker = AlertTracker::new(0, 2, None).unwrap();
+        assert!(tracker.update(0, vec![alert("a")]).is_empty());
+        assert_eq!(keys(&tracker.update(1, vec![alert("a")])), ["a"]);
+        // a clear resets the count
+        assert!(tracker.update(2, vec![]).is_empty());
+        assert!(tracker.update(3, vec![alert("a")]).is_empty());
+    }
+
+    #[test]
+    fn holds_back_repeats_during_the_cooldown() {
+        let mut tracker = AlertTracker::new(60, 1, None).unwrap();
+        let sent = tracker.update(0, vec![alert("a")]);
+        tracker.delivered(0, &sent);
+        assert!(tracker.update(30, vec![alert("a")]).is_empty());
+        assert_eq!(keys(&tracker.update(60, vec![alert("a")])), ["a"]);
+    }
+
+    #[test]
+    fn announces_resolution_once() {
+        let mut tracker = AlertTracker::new(60, 1, None).unwrap();
+        let sent = tracker.update(0, vec![alert("a")]);
+        tracker.delivered(0, &sent);
+        let resolved = tracker.update(10, vec![]);
+        assert_eq!(resolved.len(), 1);
+        assert_eq!(resolved[0].message, "Resolved: a is off");
+        tracker.delivered(10, &resolved);
+        assert!(tracker.update(20, vec![]).is_empty());
+    }
+
+    #[test]
+    fn retries_undelivered_alerts() {
+        let mut tracker = AlertTracker::new(60, 1, None).unwrap();
+        assert_eq!(keys(&tracker.update(0, vec![alert("a")])), ["a"]);
+        assert_eq!(keys(&tracker.update(10, vec![alert("a")])), ["a"]);
+        // never delivered, so there's nothing to resolve
+        assert!(tracker.update(20, vec![]).is_empty());
+    }
+
+    #[test]
+    fn carries_state_across_runs() {
+        let path = std::env::temp_dir().join(format!("alert-state-{}.json", std::process::id()));
+        let mut tracker = AlertTracker::new(60, 1, Some(path.clone())).unwrap();
+        let sent = tracker.update(0, vec![alert("a")]);
+        tracker.delivered(0, &sent);
+        tracker.save().unwrap();
+
+        let mut tracker = AlertTracker::new(60, 1, Some(path.clone())).unwrap();
+        assert!(tracker.update(30, vec![alert("a")]).is_empty());
+        assert_eq!(keys(&tracker.update(40, vec![])), ["a"]);
+        fs::remove_file(path).unwrap();
+    }
+}
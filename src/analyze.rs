@@ -0,0 +1,393 @@
+//! Replays stored snapshots and recomputes analytics from the per-position rows recorded
+//! with them, so metrics added later apply to the whole history.
+
+use std::collections::BTreeMap;
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use chrono::{TimeZone as _, Utc};
+use thousands::Separable;
+
+use crate::store::{
+    child_path, read_records, CustodyRecord, MarketRecord, PositionRecord, Record as _,
+};
+
+impl PositionRecord {
+    fn is_long(&self) -> bool {
+        self.side == "Long"
+    }
+
+    fn current_value(&self) -> f64 {
+        self.size_usd.div(self.entry_price).mul(self.price)
+    }
+
+    /// Paper P&L, as in the snapshot
+    pub(crate) fn unrealized_pnl(&self) -> f64 {
+        let pnl = self.current_value().sub(self.size_usd);
+        if self.is_long() {
+            pnl
+        } else {
+            -pnl
+        }
+    }
+
+    /// Collateral left after P&L and accrued borrow fees, as in the snapshot but with
+    /// stablecoin collateral taken at par
+    pub(crate) fn remaining_collateral(&self) -> f64 {
+        self.collateral_usd
+            .add(self.unrealized_pnl())
+            .sub(self.borrow_fees_usd)
+    }
+}
+
+/// Width of the long share bars in the skew view
+const SKEW_BAR_WIDTH: usize = 20;
+
+/// A metric derived from all positions of one snapshot. New metrics added here are
+/// computed for every stored snapshot.
+struct Metric {
+    name: &'static str,
+    compute: fn(&[&PositionRecord]) -> f64,
+    format: fn(f64) -> String,
+}
+
+fn sum(positions: &[&PositionRecord], value: impl Fn(&PositionRecord) -> f64) -> f64 {
+    positions.iter().map(|position| value(position)).sum()
+}
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+fn ratio(value: f64) -> String {
+    format!("{value:.2}")
+}
+
+fn percent(value: f64) -> String {
+    format!("{:.1}%", value.mul(100.0))
+}
+
+const METRICS: &[Metric] = &[
+    Metric {
+        name: "Positions",
+        compute: |positions| positions.len() as f64,
+        format: |value| format!("{value}"),
+    },
+    Metric {
+        name: "Open interest",
+        compute: |positions| sum(positions, PositionRecord::current_value),
+        format: usd,
+    },
+    Metric {
+        name: "Leverage at entry",
+        compute: |positions| {
+            sum(positions, |position| position.size_usd)
+                .div(sum(positions, |position| position.collateral_usd))
+        },
+        format: ratio,
+    },
+    Metric {
+        name: "Effective leverage",
+        compute: |positions| {
+            sum(positions, PositionRecord::current_value)
+                .div(sum(positions, PositionRecord::remaining_collateral))
+        },
+        format: ratio,
+    },
+    Metric {
+        name: "Long share of OI",
+        compute: |positions| {
+            sum(positions, |position| {
+                if position.is_long() {
+                    position.current_value()
+                } else {
+                    0.0
+                }
+            })
+            .div(sum(positions, PositionRecord::current_value))
+        },
+        format: percent,
+    },
+    Metric {
+        name: "Unrealized P&L",
+        compute: |positions| sum(positions, PositionRecord::unrealized_pnl),
+        format: usd,
+    },
+    Metric {
+        name: "Winning share",
+        compute: |positions| {
+            (positions
+                .iter()
+                .filter(|position| position.unrealized_pnl() > 0.0)
+                .count() as f64)
+                .div(positions.len() as f64)
+        },
+        format: percent,
+    },
+];
+
+/// Loads the position rows of every snapshot in `history` taken within `from..=to`, keyed
+/// by snapshot time, optionally only those in `market`
+fn load_snapshots(
+    history: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+    market: Option<&str>,
+) -> Result<BTreeMap<u64, Vec<PositionRecord>>, Box<dyn std::error::Error>> {
+    let path = child_path(history, PositionRecord::TABLE);
+    let mut snapshots: BTreeMap<u64, Vec<PositionRecord>> = BTreeMap::new();
+    for record in read_records::<PositionRecord>(&path)? {
+        if from.map_or(false, |from| record.unix_time() < from)
+            || to.map_or(false, |to| record.unix_time() > to)
+            || market.map_or(false, |market| !record.market.eq_ignore_ascii_case(market))
+        {
+            continue;
+        }
+        snapshots.entry(record.unix_time).or_default().push(record);
+    }
+    if snapshots.is_empty() {
+        return Err(format!(
+            "no position rows in {path} for this range, record them with -c {history} --record-positions"
+        )
+        .into());
+    }
+    Ok(snapshots)
+}
+
+pub(crate) fn format_time(unix_time: u64) -> String {
+    Utc.timestamp_opt(unix_time as i64, 0).single().map_or_else(
+        || unix_time.to_string(),
+        |time| time.format("%Y-%m-%d %H:%M").to_string(),
+    )
+}
+
+/// Prints the metrics of every snapshot in `history` taken within `from..=to`, optionally
+/// only over the positions of `market`
+pub fn run(
+    history: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+    market: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let snapshots = load_snapshots(history, from, to, market)?;
+
+    let header: Vec<_> = METRICS.iter().map(|metric| metric.name).collect();
+    println!(
+        "{:<20} {}",
+        "Time (UTC)",
+        header
+            .iter()
+            .map(|name| format!("{name:>18}"))
+            .collect::<String>()
+    );
+    for (unix_time, positions) in &snapshots {
+        let positions: Vec<_> = positions.iter().collect();
+        let time = format_time(*unix_time);
+        let values: String = METRICS
+            .iter()
+            .map(|metric| format!("{:>18}", (metric.format)((metric.compute)(&positions))))
+            .collect();
+        println!("{time:<20} {values}");
+    }
+    Ok(())
+}
+
+/// Relative change of `series` between consecutive `times`, where both values are known
+fn changes(series: &BTreeMap<u64, f64>, times: &[u64]) -> Vec<Option<f64>> {
+    times
+        .windows(2)
+        .map(|pair| {
+            let previous = series.get(&pair[0])?;
+            let current = series.get(&pair[1])?;
+            (*previous != 0.0).then(|| current.div(previous).sub(1.0))
+        })
+        .collect()
+}
+
+/// Pearson correlation over the points where both changes are known
+fn correlation(x: &[Option<f64>], y: &[Option<f64>]) -> Option<f64> {
+    let points: Vec<(f64, f64)> = x
+        .iter()
+        .zip(y)
+        .filter_map(|(x, y)| Some((*x?, *y?)))
+        .collect();
+    if points.len() < 3 {
+        return None;
+    }
+    let count = points.len() as f64;
+    let mean_x = points.iter().map(|(x, _)| x).sum::<f64>().div(count);
+    let mean_y = points.iter().map(|(_, y)| y).sum::<f64>().div(count);
+    let covariance: f64 = points
+        .iter()
+        .map(|(x, y)| x.sub(mean_x).mul(y.sub(mean_y)))
+        .sum();
+    let variance_x: f64 = points.iter().map(|(x, _)| x.sub(mean_x).powi(2)).sum();
+    let variance_y: f64 = points.iter().map(|(_, y)| y.sub(mean_y).powi(2)).sum();
+    (variance_x > 0.0 && variance_y > 0.0)
+        .then(|| covariance.div(variance_x.mul(variance_y).sqrt()))
+}
+
+/// Prints the correlation matrix of snapshot-to-snapshot changes in each market's open
+/// interest and price over the snapshots in `history` taken within `from..=to`, and how
+/// each market's open interest follows its previous price move
+pub fn correlations(
+    history: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let snapshots = load_snapshots(history, from, to, None)?;
+    let times: Vec<u64> = snapshots.keys().copied().collect();
+
+    let mut open_interest: BTreeMap<String, BTreeMap<u64, f64>> = BTreeMap::new();
+    for (unix_time, positions) in &snapshots {
+        for position in positions {
+            *open_interest
+                .entry(position.market.clone())
+                .or_default()
+                .entry(*unix_time)
+                .or_default() += position.current_value();
+        }
+    }
+    let mut prices: BTreeMap<String, BTreeMap<u64, f64>> = BTreeMap::new();
+    for record in read_records::<CustodyRecord>(&child_path(history, CustodyRecord::TABLE))? {
+        if snapshots.contains_key(&record.unix_time) {
+            prices
+                .entry(record.market)
+                .or_default()
+                .insert(record.unix_time, record.price);
+        }
+    }
+
+    let mut series: Vec<(String, Vec<Option<f64>>)> = vec![];
+    for (market, values) in &open_interest {
+        series.push((format!("{market} OI"), changes(values, &times)));
+        if let Some(values) = prices.get(market) {
+            series.push((format!("{market} price"), changes(values, &times)));
+        }
+    }
+
+    println!(
+        "Correlation of changes over {} snapshots, {} to {}:",
+        times.len(),
+        format_time(times[0]),
+        format_time(times[times.len() - 1]),
+    );
+    let format = |value: Option<f64>| value.map_or("-".to_string(), |value| format!("{value:+.2}"));
+    println!(
+        "{:<12}{}",
+        "",
+        series
+            .iter()
+            .map(|(name, _)| format!("{name:>12}"))
+            .collect::<String>()
+    );
+    for (name, x) in &series {
+        let row: String = series
+            .iter()
+            .map(|(_, y)| format!("{:>12}", format(correlation(x, y))))
+            .collect();
+        println!("{name:<12}{row}");
+    }
+
+    // positive when open interest grows after the price rises, i.e. traders chase momentum
+    println!();
+    println!("Correlation of OI changes with the previous price change:");
+    for (market, values) in &open_interest {
+        let Some(market_prices) = prices.get(market) else {
+            continue;
+        };
+        let open_interest_changes = changes(values, &times);
+        let price_changes = changes(market_prices, &times);
+        let lagged = match price_changes.len() {
+            0 => None,
+            len => correlation(&open_interest_changes[1..], &price_changes[..len - 1]),
+        };
+        println!("  {market:<5} {}", format(lagged));
+    }
+    Ok(())
+}
+
+/// Prints how each market's long/short ratios evolved around the snapshots in `history`
+/// taken within `from..=to` where its price moved by `threshold` percent or more since the
+/// previous one, with `context` snapshots either side
+pub fn skew(
+    history: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+    market: Option<&str>,
+    threshold: f64,
+    context: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = child_path(history, MarketRecord::TABLE);
+    let mut markets: BTreeMap<String, Vec<MarketRecord>> = BTreeMap::new();
+    for record in read_records::<MarketRecord>(&path)? {
+        if from.map_or(false, |from| record.unix_time() < from)
+            || to.map_or(false, |to| record.unix_time() > to)
+            || market.map_or(false, |market| !record.market.eq_ignore_ascii_case(market))
+        {
+            continue;
+        }
+        markets
+            .entry(record.market.clone())
+            .or_default()
+            .push(record);
+    }
+    if markets.is_empty() {
+        return Err(format!(
+            "no market rows in {path} for this range, they're recorded with snapshots taken with -c {history}"
+        )
+        .into());
+    }
+
+    let ratio = |ratio: Option<f64>| ratio.map_or("-".to_string(), |ratio| format!("{ratio:.2}"));
+    let mut moves = 0;
+    for (market, mut records) in markets {
+        records.sort_by_key(|record| record.unix_time);
+        for i in 1..records.len() {
+            let change = records[i].price.div(records[i - 1].price).sub(1.0);
+            if change.is_nan() || change.abs().mul(100.0) < threshold {
+                continue;
+            }
+            moves += 1;
+            println!(
+                "{market} {:+.2}% at {}",
+                change.mul(100.0),
+                format_time(records[i].unix_time)
+            );
+            println!(
+                "  {:<18} {:>12} {:>10} {:>10}  Long share of OI",
+                "Time (UTC)", "Price", "L/S count", "L/S value"
+            );
+            for (j, record) in records
+                .iter()
+                .enumerate()
+                .take(i + context + 1)
+                .skip(i.saturating_sub(context))
+            {
+                let long_share = record
+                    .long_value
+                    .div(record.long_value.add(record.short_value));
+                let filled = if long_share.is_finite() {
+                    (long_share.mul(SKEW_BAR_WIDTH as f64).round() as usize).min(SKEW_BAR_WIDTH)
+                } else {
+                    0
+                };
+                let price = format!("${:.4}", record.price);
+                println!(
+                    "{} {:<18} {price:>12} {:>10} {:>10}  {}{} {:.0}%",
+                    if j == i { ">" } else { " " },
+                    format_time(record.unix_time),
+                    ratio(record.long_short_ratio),
+                    ratio(record.long_short_value_ratio),
+                    "#".repeat(filled),
+                    ".".repeat(SKEW_BAR_WIDTH - filled),
+                    long_share.mul(100.0),
+                );
+            }
+            println!();
+        }
+    }
+    if moves == 0 {
+        println!("No price moves of {threshold}% or more between snapshots");
+    }
+    Ok(())
+}
@@ -0,0 +1,150 @@
+//! Flags unusual jumps in stored metrics: each snapshot-to-snapshot change is compared to
+//! an exponentially weighted mean and variance of the previous changes.
+
+use std::collections::BTreeMap;
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use crate::alerts::Alert;
+use crate::snapshot::{custody_records, Snapshot};
+use crate::state::PerpState;
+use crate::store::{child_path, read_records, CustodyRecord, Record as _, SnapshotRecord};
+
+/// Changes needed before the bands are trusted
+const MIN_SAMPLES: usize = 20;
+
+pub struct Anomaly {
+    pub series: String,
+    pub previous: f64,
+    pub current: f64,
+    /// Standard deviations the change is away from the recent mean change
+    pub z_score: f64,
+}
+
+impl Anomaly {
+    pub fn to_alert(&self) -> Alert {
+        Alert {
+            key: format!("anomaly:{}", self.series),
+            message: format!("Unusual move in {}: {self}", self.series),
+        }
+    }
+}
+
+impl std::fmt::Display for Anomaly {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{} moved from {:.4} to {:.4} ({:+.1} sigma)",
+            self.series, self.previous, self.current, self.z_score
+        )
+    }
+}
+
+/// Z-score of the change from the last value in `history` to `current`, against the EWMA
+/// bands of the changes within `history`
+fn change_z_score(history: &[f64], current: f64, span: f64) -> Option<f64> {
+    let changes: Vec<f64> = history
+        .windows(2)
+        .map(|pair| pair[1].sub(pair[0]))
+        .filter(|change| change.is_finite())
+        .collect();
+    if changes.len() < MIN_SAMPLES {
+        return None;
+    }
+    let alpha = 2.0_f64.div(span.add(1.0));
+    let mut mean = changes[0];
+    let mut variance = 0.0;
+    for change in &changes[1..] {
+        let deviation = change.sub(mean);
+        mean += alpha.mul(deviation);
+        variance = 1.0_f64
+            .sub(alpha)
+            .mul(variance.add(alpha.mul(deviation).mul(deviation)));
+    }
+    let change = current.sub(*history.last()?);
+    (variance > 0.0).then(|| change.sub(mean).div(variance.sqrt()))
+}
+
+/// Share of open interest that is long
+fn long_share(long_value: f64, short_value: f64) -> f64 {
+    long_value.div(long_value.add(short_value))
+}
+
+/// Compares `snapshot` with the history at `history_path`, before it's appended there, and
+/// returns the open interest, skew, borrow rate and plugin metric moves beyond `threshold`
+/// sigma
+pub fn detect(
+    history_path: &str,
+    state: &PerpState,
+    snapshot: &Snapshot,
+    span: f64,
+    threshold: f64,
+) -> Result<Vec<Anomaly>, Box<dyn std::error::Error>> {
+    let mut series: BTreeMap<String, (Vec<f64>, f64)> = BTreeMap::new();
+
+    // nothing is flagged until the history, which may not exist yet, is long enough
+    let mut records = read_records::<SnapshotRecord>(history_path).unwrap_or_default();
+    records.sort_by_key(|record| record.unix_time());
+    let current = snapshot.to_record();
+    series.insert(
+        "open interest".to_string(),
+        (
+            records
+                .iter()
+                .map(|record| record.total_position_value)
+                .collect(),
+            current.total_position_value,
+        ),
+    );
+    series.insert(
+        "long share of open interest".to_string(),
+        (
+            records
+                .iter()
+                .map(|record| long_share(record.long_value, record.short_value))
+                .collect(),
+            long_share(current.long_value, current.short_value),
+        ),
+    );
+
+    for (name, value) in &snapshot.custom_metrics {
+        series.insert(
+            name.clone(),
+            (
+                records
+                    .iter()
+                    .filter_map(|record| record.custom_metrics().get(name).copied())
+                    .collect(),
+                *value,
+            ),
+        );
+    }
+
+    let custodies_path = child_path(history_path, CustodyRecord::TABLE);
+    let mut custody_rows = read_records::<CustodyRecord>(&custodies_path).unwrap_or_default();
+    custody_rows.sort_by_key(|record| record.unix_time());
+    for custody in custody_records(state) {
+        let history = custody_rows
+            .iter()
+            .filter(|record| record.market == custody.market)
+            .map(|record| record.borrow_rate)
+            .collect();
+        series.insert(
+            format!("{} borrow rate", custody.market),
+            (history, custody.borrow_rate),
+        );
+    }
+
+    Ok(series
+        .into_iter()
+        .filter_map(|(name, (history, current))| {
+            let z_score = change_z_score(&history, current, span)?;
+            let previous = *history.last()?;
+            (z_score.abs() > threshold).then_some(Anomaly {
+                series: name,
+                previous,
+                current,
+                z_score,
+            })
+        })
+        .collect())
+}
@@ -0,0 +1,167 @@
+//! Times each stage of taking a snapshot over several runs, to compare RPC providers and to
+//! catch slowdowns in the tool itself.
+
+use std::collections::HashMap;
+use std::ops::Div as _;
+use std::time::{Duration, Instant, SystemTime};
+
+use anchor_lang::Discriminator as _;
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::idl;
+use crate::rpc::{
+    get_accounts_data, get_program_accounts_filtered, get_program_accounts_with_discrim,
+    program_id, redacted_url,
+};
+use crate::snapshot::Snapshot;
+use crate::state::{decode_open_positions, position_account_size, PerpState};
+
+/// Stages timed, in pipeline order
+const STAGES: [&str; 6] = [
+    "Pool/custody gPA",
+    "Oracle accounts",
+    "Position gPA",
+    "Decoding",
+    "Aggregation",
+    "Total",
+];
+
+fn millis(duration: Duration) -> String {
+    format!("{}ms", duration.as_millis().separate_with_commas())
+}
+
+/// Prints the time spent in each stage of `runs` snapshots, then the median, fastest and
+/// slowest run of each
+pub fn run(connection: &RpcClient, runs: usize) -> Result<(), Box<dyn std::error::Error>> {
+    let mut timings: Vec<[Duration; 6]> = vec![];
+    let mut counts = (0, 0, 0, 0);
+    for run in 0..runs {
+        let total = Instant::now();
+
+        let started = Instant::now();
+        let pool_accounts = get_program_accounts_with_discrim(
+            connection,
+            &program_id().to_string(),
+            &perp_abi::state::Pool::DISCRIMINATOR,
+        )?;
+        let custody_accounts: Vec<_> = get_program_accounts_with_discrim(
+            connection,
+            &program_id().to_string(),
+            &perp_abi::state::Custody::DISCRIMINATOR,
+        )?
+        .into_iter()
+        .map(|(pubkey, account)| (pubkey, account.data))
+        .collect();
+        let program_accounts_time = started.elapsed();
+
+        // the oracle keys come from the custodies, so their decoding counts towards decoding
+        let started = Instant::now();
+        let mut oracle_pubkeys = custody_accounts
+            .iter()
+            .map(|(_, data)| Ok(idl::decode_custody(data)?.oracle.oracle_account))
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        oracle_pubkeys.sort();
+        oracle_pubkeys.dedup();
+        let mut decoding_time = started.elapsed();
+
+        let started = Instant::now();
+        let oracle_accounts: HashMap<_, _> = get_accounts_data(connection, &oracle_pubkeys)?
+            .into_iter()
+            .collect();
+        let oracle_time = started.elapsed();
+
+        let started = Instant::now();
+        let position_accounts: Vec<_> = get_program_accounts_filtered(
+            connection,
+            &program_id().to_string(),
+            &perp_abi::state::Position::DISCRIMINATOR,
+            position_account_size(),
+            None,
+        )?
+        .into_iter()
+        .map(|(pubkey, account)| (pubkey, account.data))
+        .collect();
+        let positions_time = started.elapsed();
+
+        let started = Instant::now();
+        let (pool_pubkey, pool_account) = pool_accounts
+            .first()
+            .ok_or("no pool account found for the program")?;
+        let unix_time = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs();
+        let state = PerpState::from_accounts(
+            (*pool_pubkey, &pool_account.data[..]),
+            &custody_accounts,
+            &oracle_accounts,
+            unix_time,
+            0,
+        )?;
+        let positions = decode_open_positions(&position_accounts)?;
+        decoding_time += started.elapsed();
+
+        let started = Instant::now();
+        Snapshot::compute(&state, &positions);
+        let aggregation_time = started.elapsed();
+
+        let run_timings = [
+            program_accounts_time,
+            oracle_time,
+            positions_time,
+            decoding_time,
+            aggregation_time,
+            total.elapsed(),
+        ];
+        println!(
+            "Run {}/{runs}: {}",
+            run + 1,
+            STAGES
+                .iter()
+                .zip(run_timings)
+                .map(|(stage, time)| format!("{stage} {}", millis(time)))
+                .collect::<Vec<_>>()
+                .join(", ")
+        );
+        timings.push(run_timings);
+        counts = (
+            custody_accounts.len(),
+            oracle_accounts.len(),
+            position_accounts.len(),
+            position_accounts
+                .iter()
+                .map(|(_, data)| data.len())
+                .sum::<usize>(),
+        );
+    }
+    if timings.is_empty() {
+        return Ok(());
+    }
+
+    println!();
+    println!(
+        "Over {runs} runs against {}:",
+        redacted_url(&connection.url())
+    );
+    println!(
+        "  {:<20} {:>10} {:>10} {:>10}",
+        "", "Median", "Fastest", "Slowest"
+    );
+    for (i, stage) in STAGES.iter().enumerate() {
+        let mut times: Vec<Duration> = timings.iter().map(|run| run[i]).collect();
+        times.sort();
+        println!(
+            "  {stage:<20} {:>10} {:>10} {:>10}",
+            millis(times[times.len() / 2]),
+            millis(times[0]),
+            millis(times[times.len() - 1]),
+        );
+    }
+    let (custodies, oracles, positions, position_bytes) = counts;
+    println!(
+        "Accounts per run: {custodies} custodies, {oracles} oracles, {} positions ({:.1} MB)",
+        positions.separate_with_commas(),
+        (position_bytes as f64).div(1_000_000.0),
+    );
+    Ok(())
+}
@@ -0,0 +1,267 @@
+//! On-disk cache of the pool and custody accounts, and a short-lived in-memory cache of
+//! oracle accounts for quick successive cycles.
+//!
+//! The pool and custodies hold AUM, token balances and fee reserves that change with every
+//! trade, so by default their data is refetched by pubkey each snapshot. What the cache
+//! saves is the program scans finding them, which only rerun when the pool lists a
+//! custody the cache doesn't know. Serving their data from the cache is opt-in, with a TTL.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::time::{Duration, Instant, SystemTime};
+
+use anchor_lang::{AccountDeserialize as _, Discriminator as _};
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::fetcher::AccountFetcher;
+use crate::rpc::{get_accounts_data, get_program_accounts_with_discrim, program_id};
+
+#[derive(Serialize, Deserialize)]
+struct CachedAccount {
+    /// Slot the account was fetched at
+    slot: u64,
+    fetched_at: u64,
+    /// SHA-256 of the account data
+    hash: String,
+    /// Base64 account data
+    data: String,
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct CacheFile {
+    pool: Option<String>,
+    custodies: Vec<String>,
+    accounts: HashMap<String, CachedAccount>,
+}
+
+pub struct AccountCache {
+    path: PathBuf,
+    /// Seconds cached accounts are served without refetching, zero to always refetch
+    ttl: u64,
+    file: CacheFile,
+    /// How long fetched oracle accounts are reused, zero to always refetch
+    oracle_ttl: Duration,
+    oracle_accounts: HashMap<Pubkey, (Instant, Vec<u8>)>,
+    oracle_hits: u64,
+    oracle_misses: u64,
+}
+
+impl AccountCache {
+    /// `$XDG_CACHE_HOME/jupiter_perpetuals_analytics`, falling back to `~/.cache`
+    pub fn default_dir() -> Option<PathBuf> {
+        std::env::var_os("XDG_CACHE_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".cache")))
+            .map(|dir| dir.join("jupiter_perpetuals_analytics"))
+    }
+
+    /// Opens the cache in `dir` of the program deployed on the cluster with `genesis_hash`,
+    /// starting empty if it doesn't exist or can't be read. Each program and cluster gets a
+    /// file of its own, so a devnet run never reads mainnet's accounts.
+    pub fn open(dir: &Path, genesis_hash: &str, ttl: u64) -> Self {
+        let path = dir.join(format!("accounts-{}-{genesis_hash}.json", program_id()));
+        let file = fs::read(&path)
+            .ok()
+            .and_then(|contents| serde_json::from_slice(&contents).ok())
+            .unwrap_or_default();
+        AccountCache {
+            path,
+            ttl,
+            file,
+            oracle_ttl: Duration::ZERO,
+            oracle_accounts: HashMap::new(),
+            oracle_hits: 0,
+            oracle_misses: 0,
+        }
+    }
+
+    /// Reuses fetched oracle accounts for `ttl` instead of refetching them
+    pub fn with_oracle_ttl(mut self, ttl: Duration) -> Self {
+        self.oracle_ttl = ttl;
+        self
+    }
+
+    /// Oracle account lookups served from memory and fetched, since the cache was opened
+    pub fn oracle_stats(&self) -> (u64, u64) {
+        (self.oracle_hits, self.oracle_misses)
+    }
+
+    /// Data of the `pubkeys` oracle accounts, fetching in one request those not fetched
+    /// within the oracle TTL
+    pub fn oracle_accounts(
+        &mut self,
+        connection: &dyn AccountFetcher,
+        pubkeys: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, Vec<u8>>, Box<dyn std::error::Error>> {
+        let now = Instant::now();
+        let stale: Vec<Pubkey> = pubkeys
+            .iter()
+            .filter(|pubkey| {
+                self.oracle_accounts
+                    .get(pubkey)
+                    .map_or(true, |(fetched_at, _)| {
+                        now.duration_since(*fetched_at) >= self.oracle_ttl
+                    })
+            })
+            .copied()
+            .collect();
+        self.oracle_hits += (pubkeys.len() - stale.len()) as u64;
+        self.oracle_misses += stale.len() as u64;
+        for (pubkey, data) in get_accounts_data(connection, &stale)? {
+            self.oracle_accounts.insert(pubkey, (now, data));
+        }
+        Ok(pubkeys
+            .iter()
+            .filter_map(|pubkey| {
+                let (_, data) = self.oracle_accounts.get(pubkey)?;
+                Some((*pubkey, data.clone()))
+            })
+            .collect())
+    }
+
+    fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        if let Some(dir) = self.path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(&self.path, serde_json::to_vec(&self.file)?)?;
+        Ok(())
+    }
+
+    fn insert(&mut self, pubkey: Pubkey, data: &[u8], slot: u64, now: u64) {
+        let hash = solana_sdk::hash::hash(data).to_string();
+        self.file.accounts.insert(
+            pubkey.to_string(),
+            CachedAccount {
+                slot,
+                fetched_at: now,
+                hash,
+                data: base64::prelude::BASE64_STANDARD.encode(data),
+            },
+        );
+    }
+
+    fn cached(&self, key: &str, now: u64) -> Option<(Pubkey, Vec<u8>)> {
+        let cached = self.file.accounts.get(key)?;
+        if now.saturating_sub(cached.fetched_at) > self.ttl {
+            return None;
+        }
+        let data = base64::prelude::BASE64_STANDARD.decode(&cached.data).ok()?;
+        // guard against a corrupted cache file
+        if solana_sdk::hash::hash(&data).to_string() != cached.hash {
+            return None;
+        }
+        Some((Pubkey::from_str(key).ok()?, data))
+    }
+
+    fn fresh_accounts(&self, now: u64) -> Option<((Pubkey, Vec<u8>), Vec<(Pubkey, Vec<u8>)>)> {
+        if self.ttl == 0 {
+            return None;
+        }
+        let pool_account = self.cached(self.file.pool.as_ref()?, now)?;
+        let custody_accounts = self
+            .file
+            .custodies
+            .iter()
+            .map(|key| self.cached(key, now))
+            .collect::<Option<Vec<_>>>()?;
+        Some((pool_account, custody_accounts))
+    }
+
+    /// Pool and custody accounts, refetched by pubkey in one request, or from the cache
+    /// while fresher than a nonzero TTL. The program is only scanned when the cache is empty
+    /// or the pool lists a new custody.
+    pub fn pool_and_custody_accounts(
+        &mut self,
+        connection: &dyn AccountFetcher,
+    ) -> Result<((Pubkey, Vec<u8>), Vec<(Pubkey, Vec<u8>)>), Box<dyn std::error::Error>> {
+        let now = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        if let Some(accounts) = self.fresh_accounts(now) {
+            return Ok(accounts);
+        }
+
+        let slot = connection.slot()?;
+        let known_pubkeys = self
+            .file
+            .pool
+            .iter()
+            .chain(&self.file.custodies)
+            .map(|key| Pubkey::from_str(key))
+            .collect::<Result<Vec<_>, _>>()?;
+        let refetched = if known_pubkeys.is_empty() {
+            None
+        } else {
+            let accounts = connection.multiple_accounts(&known_pubkeys)?;
+            known_pubkeys
+                .into_iter()
+                .zip(accounts)
+                .map(|(pubkey, account)| account.map(|account| (pubkey, account.data)))
+                .collect::<Option<Vec<_>>>()
+        };
+
+        // a custody added to the pool since the cache was written needs a full scan
+        let refetched = refetched.filter(|accounts| {
+            let Some((_, pool_data)) = accounts.first() else {
+                return false;
+            };
+            perp_abi::state::Pool::try_deserialize(&mut &**pool_data).map_or(false, |pool| {
+                pool.custodies.len() == accounts.len() - 1
+                    && accounts[1..]
+                        .iter()
+                        .all(|(custody_pubkey, _)| pool.custodies.contains(custody_pubkey))
+            })
+        });
+
+        let (pool_account, custody_accounts) = match refetched {
+            Some(mut accounts) => {
+                let custody_accounts = accounts.split_off(1);
+                (accounts.remove(0), custody_accounts)
+            }
+            _ => {
+                let pool_accounts = get_program_accounts_with_discrim(
+                    connection,
+                    &program_id().to_string(),
+                    &perp_abi::state::Pool::DISCRIMINATOR,
+                )?;
+                let (pool_pubkey, pool) = pool_accounts
+                    .first()
+                    .ok_or_else(|| format!("no Pool account owned by {}", program_id()))?;
+                let custody_accounts = get_program_accounts_with_discrim(
+                    connection,
+                    &program_id().to_string(),
+                    &perp_abi::state::Custody::DISCRIMINATOR,
+                )?;
+                (
+                    (*pool_pubkey, pool.data.clone()),
+                    custody_accounts
+                        .into_iter()
+                        .map(|(custody_pubkey, custody)| (custody_pubkey, custody.data))
+                        .collect(),
+                )
+            }
+        };
+
+        self.insert(pool_account.0, &pool_account.1, slot, now);
+        for (custody_pubkey, custody_data) in &custody_accounts {
+            self.insert(*custody_pubkey, custody_data, slot, now);
+        }
+        self.file.pool = Some(pool_account.0.to_string());
+        self.file.custodies = custody_accounts
+            .iter()
+            .map(|(custody_pubkey, _)| custody_pubkey.to_string())
+            .collect();
+        if let Err(err) = self.save() {
+            eprintln!(
+                "Could not write account cache {}: {err}",
+                self.path.display()
+            );
+        }
+
+        Ok((pool_account, custody_accounts))
+    }
+}
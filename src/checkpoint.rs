@@ -0,0 +1,190 @@
+//! Progress checkpoints for paged position scans, so a snapshot of many positions that's
+//! interrupted resumes from the pages already fetched instead of fetching, and counting,
+//! them again.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::io::Write as _;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use base64::Engine as _;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+/// Age past which a checkpoint is started over rather than resumed, as the positions it
+/// holds would be too stale to join a new snapshot
+const MAX_AGE_SECS: u64 = 900;
+
+/// First line of a checkpoint: the position keys being paged through
+#[derive(Serialize, Deserialize)]
+struct Header {
+    unix_time: u64,
+    keys: Vec<String>,
+}
+
+/// Each following line: a page fetched, with the data of its accounts base64 encoded and
+/// `None` for those closed since the keys were listed
+#[derive(Serialize, Deserialize)]
+struct Page {
+    page: usize,
+    accounts: Vec<Option<String>>,
+}
+
+/// A checkpoint file, holding the keys of a paged position scan and every page fetched
+/// so far
+pub struct PageCheckpoint {
+    path: PathBuf,
+    file: fs::File,
+    pub keys: Vec<Pubkey>,
+    /// Account data of the pages fetched before the scan was interrupted, by page index
+    pub fetched: BTreeMap<usize, Vec<Option<Vec<u8>>>>,
+}
+
+fn now() -> Result<u64, Box<dyn std::error::Error>> {
+    Ok(SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs())
+}
+
+fn line(value: &impl Serialize) -> Result<Vec<u8>, Box<dyn std::error::Error>> {
+    let mut line = serde_json::to_vec(value)?;
+    line.push(b'\n');
+    Ok(line)
+}
+
+/// The keys and pages of the checkpoint at `path`, if it's recent enough to resume. A
+/// line cut short by the interruption ends it.
+fn read(path: &Path) -> Option<(Header, BTreeMap<usize, Vec<Option<Vec<u8>>>>)> {
+    let contents = fs::read_to_string(path).ok()?;
+    let mut lines = contents.lines();
+    let header: Header = serde_json::from_str(lines.next()?).ok()?;
+    if now().ok()?.saturating_sub(header.unix_time) > MAX_AGE_SECS {
+        return None;
+    }
+    let mut fetched = BTreeMap::new();
+    for line in lines {
+        let Ok(page) = serde_json::from_str::<Page>(line) else {
+            break;
+        };
+        let accounts = page
+            .accounts
+            .into_iter()
+            .map(|data| {
+                data.map(|data| base64::prelude::BASE64_STANDARD.decode(data))
+                    .transpose()
+            })
+            .collect::<Result<_, _>>()
+            .ok()?;
+        fetched.insert(page.page, accounts);
+    }
+    Some((header, fetched))
+}
+
+impl PageCheckpoint {
+    /// Resumes the checkpoint at `path` if a recent one is there, or else starts one over
+    /// the keys `list_keys` returns
+    pub fn open(
+        path: &Path,
+        list_keys: impl FnOnce() -> Result<Vec<Pubkey>, Box<dyn std::error::Error>>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (header, fetched) = match read(path) {
+            Some(resumed) => resumed,
+            None => {
+                let keys = list_keys()?;
+                let header = Header {
+                    unix_time: now()?,
+                    keys: keys.iter().map(Pubkey::to_string).collect(),
+                };
+                (header, BTreeMap::new())
+            }
+        };
+        let keys = header
+            .keys
+            .iter()
+            .map(|key| key.parse())
+            .collect::<Result<_, _>>()?;
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        // rewritten without any line cut short, so pages are appended after whole lines
+        let mut contents = line(&header)?;
+        for (page, accounts) in &fetched {
+            contents.extend(line(&Page {
+                page: *page,
+                accounts: encode(accounts),
+            })?);
+        }
+        fs::write(path, contents)?;
+        let file = fs::OpenOptions::new().append(true).open(path)?;
+        Ok(PageCheckpoint {
+            path: path.to_path_buf(),
+            file,
+            keys,
+            fetched,
+        })
+    }
+
+    /// Records that `page` was fetched, with the data of its accounts
+    pub fn record(
+        &mut self,
+        page: usize,
+        accounts: &[Option<Vec<u8>>],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.file.write_all(&line(&Page {
+            page,
+            accounts: encode(accounts),
+        })?)?;
+        Ok(())
+    }
+
+    /// Removes the checkpoint once the scan completed
+    pub fn finish(self) -> Result<(), Box<dyn std::error::Error>> {
+        drop(self.file);
+        fs::remove_file(&self.path)?;
+        Ok(())
+    }
+}
+
+fn encode(accounts: &[Option<Vec<u8>>]) -> Vec<Option<String>> {
+    accounts
+        .iter()
+        .map(|data| {
+            data.as_ref()
+                .map(|data| base64::prelude::BASE64_STANDARD.encode(data))
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn resumes_fetched_pages() {
+        let path = std::env::temp_dir().join(format!("checkpoint-{}.jsonl", std::process::id()));
+        let _ = fs::remove_file(&path);
+        let keys = vec![Pubkey::new_unique(), Pubkey::new_unique()];
+
+        let mut checkpoint = PageCheckpoint::open(&path, || Ok(keys.clone())).unwrap();
+        checkpoint.record(0, &[Some(vec![1, 2]), None]).unwrap();
+        drop(checkpoint);
+        // an interrupted write
+        fs::OpenOptions::new()
+            .append(true)
+            .open(&path)
+            .unwrap()
+            .write_all(b"{\"page\":1,\"acc")
+            .unwrap();
+
+        let checkpoint =
+            PageCheckpoint::open(&path, || Err("keys are listed again".into())).unwrap();
+        assert_eq!(checkpoint.keys, keys);
+        assert_eq!(
+            checkpoint.fetched,
+            BTreeMap::from([(0, vec![Some(vec![1, 2]), None])])
+        );
+        checkpoint.finish().unwrap();
+        assert!(!path.exists());
+    }
+}
@@ -0,0 +1,327 @@
+//! Builder for collecting snapshots from library code with the options the CLI exposes,
+//! without going through clap.
+//!
+//! ```no_run
+//! use jupiter_perpetuals_analytics::client::SnapshotClient;
+//! use jupiter_perpetuals_analytics::prices::{Hermes, PythPull};
+//! use solana_sdk::commitment_config::CommitmentConfig;
+//!
+//! let client = SnapshotClient::builder()
+//!     .rpc("https://api.mainnet-beta.solana.com")
+//!     .commitment(CommitmentConfig::confirmed())
+//!     .filters(r#"market == "SOL" && side == "long""#.parse()?)
+//!     .price_provider(Hermes { client: reqwest::blocking::Client::new() })
+//!     .market_price_provider("JUP", PythPull)
+//!     .build()?;
+//! let snapshot = client.snapshot()?;
+//! # Ok::<(), Box<dyn std::error::Error>>(())
+//! ```
+
+use std::cell::OnceCell;
+use std::collections::BTreeMap;
+use std::time::Instant;
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::columns::Columns;
+use crate::config::Config;
+use crate::consensus::{self, PriceConsensus, PriceSource};
+use crate::fallback::{self, FallbackSource};
+use crate::filter::Filter;
+use crate::prices::{PriceProvider, PriceProviders, PythLegacy};
+use crate::rpc::{self, Cluster};
+use crate::snapshot::{Snapshot, SnapshotAccumulator};
+use crate::state::{
+    for_each_open_position, load_open_positions, load_open_positions_paged, PerpState, PriceKind,
+};
+use crate::transport;
+
+pub struct SnapshotClientBuilder {
+    rpc_url: Option<String>,
+    commitment: CommitmentConfig,
+    proxy_url: Option<String>,
+    headers: BTreeMap<String, String>,
+    config: Config,
+    filter: Option<Filter>,
+    price_provider: Box<dyn PriceProvider>,
+    market_price_providers: Vec<(String, Box<dyn PriceProvider>)>,
+    price_kind: PriceKind,
+    fallback_prices: Option<FallbackSource>,
+    consensus: Vec<PriceSource>,
+    consensus_tolerance: f64,
+    paged_positions: bool,
+}
+
+impl Default for SnapshotClientBuilder {
+    fn default() -> Self {
+        SnapshotClientBuilder {
+            rpc_url: None,
+            commitment: CommitmentConfig::default(),
+            proxy_url: None,
+            headers: BTreeMap::new(),
+            config: Config::default(),
+            filter: None,
+            price_provider: Box::new(PythLegacy),
+            market_price_providers: vec![],
+            price_kind: PriceKind::default(),
+            fallback_prices: None,
+            consensus: vec![],
+            // same default as --consensus-tolerance
+            consensus_tolerance: 1.0,
+            paged_positions: false,
+        }
+    }
+}
+
+impl SnapshotClientBuilder {
+    /// RPC endpoint to read the program from, required unless `cluster` is set
+    pub fn rpc(mut self, rpc_url: impl Into<String>) -> Self {
+        self.rpc_url = Some(rpc_url.into());
+        self
+    }
+
+    /// The cluster's public RPC endpoint, as with `--cluster`
+    pub fn cluster(self, cluster: Cluster) -> Self {
+        self.rpc(cluster.rpc_url())
+    }
+
+    /// Commitment accounts are read at, `finalized` by default
+    pub fn commitment(mut self, commitment: CommitmentConfig) -> Self {
+        self.commitment = commitment;
+        self
+    }
+
+    /// Routes RPC and HTTP price requests through an egress proxy, as with `--proxy`
+    pub fn proxy(mut self, proxy_url: impl Into<String>) -> Self {
+        self.proxy_url = Some(proxy_url.into());
+        self
+    }
+
+    /// Sends `name: value` with every RPC request, on top of headers from the config
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.headers.insert(name.into(), value.into());
+        self
+    }
+
+    /// Per-endpoint headers and per-market price sources, as from the config file
+    pub fn config(mut self, config: Config) -> Self {
+        self.config = config;
+        self
+    }
+
+    /// Only aggregates positions matching `filter`, as with `--filter`
+    pub fn filters(mut self, filter: Filter) -> Self {
+        self.filter = Some(filter);
+        self
+    }
+
+    /// Prices markets without a provider of their own, legacy Pyth oracle accounts by
+    /// default
+    pub fn price_provider(mut self, provider: impl PriceProvider + 'static) -> Self {
+        self.price_provider = Box::new(provider);
+        self
+    }
+
+    /// Prices the market `symbol` with `provider`, taking precedence over the config file
+    pub fn market_price_provider(
+        mut self,
+        symbol: impl Into<String>,
+        provider: impl PriceProvider + 'static,
+    ) -> Self {
+        self.market_price_providers
+            .push((symbol.into(), Box::new(provider)));
+        self
+    }
+
+    /// Spot or EMA oracle prices, as with `--price-kind`
+    pub fn price_kind(mut self, price_kind: PriceKind) -> Self {
+        self.price_kind = price_kind;
+        self
+    }
+
+    /// Prices markets with stale oracles from `source`, as with `--fallback-prices`
+    pub fn fallback_prices(mut self, source: FallbackSource) -> Self {
+        self.fallback_prices = Some(source);
+        self
+    }
+
+    /// Prices every market with the consensus of `sources`, rejecting those further than
+    /// `tolerance` percent from the median, as with `--consensus`
+    pub fn consensus(mut self, sources: Vec<PriceSource>, tolerance: f64) -> Self {
+        self.consensus = sources;
+        self.consensus_tolerance = tolerance;
+        self
+    }
+
+    /// Scans positions page by page, as with `--paged-positions`
+    pub fn paged_positions(mut self, paged_positions: bool) -> Self {
+        self.paged_positions = paged_positions;
+        self
+    }
+
+    pub fn build(self) -> Result<SnapshotClient, Box<dyn std::error::Error>> {
+        let rpc_url = self
+            .rpc_url
+            .ok_or("an RPC endpoint or cluster is required")?;
+        let mut headers = self.config.headers_for(&rpc_url);
+        headers.extend(self.headers);
+        let rpc_endpoint = rpc::redacted_url(&rpc_url);
+        let connection = transport::rpc_client_with_commitment(
+            rpc_url,
+            self.proxy_url.as_deref(),
+            &headers,
+            self.commitment,
+        )?;
+        let mut price_providers = PriceProviders::from_config(
+            self.price_provider,
+            &self.config.oracles,
+            self.proxy_url.as_deref(),
+        )?;
+        for (symbol, provider) in self.market_price_providers {
+            price_providers = price_providers.with_market(&symbol, provider);
+        }
+        let columns = Columns::from_config(&self.config.columns)?;
+        Ok(SnapshotClient {
+            connection,
+            rpc_endpoint,
+            cluster: OnceCell::new(),
+            proxy_url: self.proxy_url,
+            config: self.config,
+            filter: self.filter,
+            price_providers,
+            price_kind: self.price_kind,
+            fallback_prices: self.fallback_prices,
+            consensus: self.consensus,
+            consensus_tolerance: self.consensus_tolerance,
+            paged_positions: self.paged_positions,
+            columns,
+        })
+    }
+}
+
+/// Collects snapshots with the options it was built with
+pub struct SnapshotClient {
+    connection: RpcClient,
+    rpc_endpoint: String,
+    /// Looked up on the first snapshot
+    cluster: OnceCell<Option<Cluster>>,
+    proxy_url: Option<String>,
+    config: Config,
+    filter: Option<Filter>,
+    price_providers: PriceProviders,
+    price_kind: PriceKind,
+    fallback_prices: Option<FallbackSource>,
+    consensus: Vec<PriceSource>,
+    consensus_tolerance: f64,
+    paged_positions: bool,
+    /// Computed columns from the config
+    columns: Columns,
+}
+
+impl SnapshotClient {
+    pub fn builder() -> SnapshotClientBuilder {
+        SnapshotClientBuilder::default()
+    }
+
+    /// The RPC client, for loaders the builder doesn't cover
+    pub fn connection(&self) -> &RpcClient {
+        &self.connection
+    }
+
+    /// Pool and custody state, priced as configured, with the consensus of each market if
+    /// consensus pricing is on
+    pub fn state(
+        &self,
+    ) -> Result<(PerpState, BTreeMap<String, PriceConsensus>), Box<dyn std::error::Error>> {
+        let state = PerpState::load_with_prices(&self.connection, None, &self.price_providers)?;
+        // fallback prices fill in for bad oracles before the consensus weighs the oracle in
+        let state = state.with_price_kind(self.price_kind);
+        let state = match self.fallback_prices {
+            Some(source) => fallback::apply(
+                state,
+                source,
+                self.proxy_url.as_deref(),
+                &self.config.headers_for(source.url()),
+            )?,
+            None => state,
+        };
+        if self.consensus.is_empty() && self.config.price_sources.is_empty() {
+            return Ok((state, BTreeMap::new()));
+        }
+        consensus::apply(
+            state,
+            &self.consensus,
+            self.consensus_tolerance / 100.0,
+            self.proxy_url.as_deref(),
+            &self.config,
+        )
+    }
+
+    /// Every open position, before the filter
+    pub fn open_positions(
+        &self,
+    ) -> Result<Vec<(Pubkey, perp_abi::state::Position)>, Box<dyn std::error::Error>> {
+        if self.paged_positions {
+            load_open_positions_paged(&self.connection)
+        } else {
+            load_open_positions(&self.connection)
+        }
+    }
+
+    /// Whether the position passes the filter, if any
+    pub fn matches(
+        &self,
+        state: &PerpState,
+        pubkey: &Pubkey,
+        position: &perp_abi::state::Position,
+    ) -> bool {
+        self.filter
+            .as_ref()
+            .map_or(true, |filter| filter.matches(state, pubkey, position))
+    }
+
+    /// Loads the state and positions and aggregates them, with provenance and the config's
+    /// computed columns filled in
+    pub fn snapshot(&self) -> Result<Snapshot, Box<dyn std::error::Error>> {
+        let started = Instant::now();
+        let (state, price_consensus) = self.state()?;
+        // paged positions are folded into the snapshot as they arrive rather than collected
+        let mut accumulator = SnapshotAccumulator::new(&state);
+        let mut position_accounts = 0;
+        let mut add = |pubkey: Pubkey, position: perp_abi::state::Position| {
+            position_accounts += 1;
+            if self.matches(&state, &pubkey, &position) {
+                accumulator.add(&pubkey, &position);
+            }
+        };
+        if self.paged_positions {
+            for_each_open_position(&self.connection, add)?;
+        } else {
+            for (pubkey, position) in load_open_positions(&self.connection)? {
+                add(pubkey, position);
+            }
+        }
+        let collection = started.elapsed();
+
+        let mut snapshot = accumulator.finish();
+        snapshot.position_accounts = position_accounts;
+        snapshot.price_consensus = price_consensus;
+        let cluster = match self.cluster.get() {
+            Some(cluster) => *cluster,
+            None => {
+                let genesis_hash = self.connection.get_genesis_hash()?.to_string();
+                *self
+                    .cluster
+                    .get_or_init(|| Cluster::from_genesis_hash(&genesis_hash))
+            }
+        };
+        snapshot.provenance.cluster =
+            cluster.map_or(String::new(), |cluster| cluster.name().to_string());
+        snapshot.provenance.rpc_endpoint = self.rpc_endpoint.clone();
+        snapshot.provenance.collection_secs = collection.as_secs_f64();
+        self.columns.apply(&mut snapshot);
+        Ok(snapshot)
+    }
+}
@@ -0,0 +1,254 @@
+//! Computed columns defined in the config as arithmetic over snapshot fields, such as
+//! `net_pnl = "cumulative_pnl - cumulative_fees"`, evaluated on each snapshot and stored
+//! with its custom metrics.
+//!
+//! Fields and numbers combine with `+`, `-`, `*`, `/`, unary minus and parentheses.
+//! Division by zero gives infinity or NaN rather than an error, as in the built-in ratios.
+
+use std::collections::BTreeMap;
+use std::ops::{Add as _, Div as _, Mul as _, Neg as _, Sub as _};
+use std::str::FromStr;
+
+use crate::snapshot::Snapshot;
+
+/// Fields expressions can refer to; the stored column names are accepted next to the
+/// snapshot's own names
+const FIELDS: [&str; 23] = [
+    "unix_time",
+    "slot",
+    "total_pool_value",
+    "num_positions",
+    "num_longs",
+    "num_shorts",
+    "num_winning",
+    "cumulative_positions",
+    "total_position_value",
+    "cumulative_long",
+    "long_value",
+    "cumulative_short",
+    "short_value",
+    "cumulative_collateral",
+    "total_collateral_value",
+    "cumulative_positions_at_entry",
+    "cumulative_collateral_at_entry",
+    "cumulative_fees",
+    "total_fees",
+    "cumulative_pnl",
+    "unrealized_pnl",
+    "average_leverage_at_entry",
+    "average_effective_leverage",
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Op(char),
+    LeftParen,
+    RightParen,
+}
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if "+-*/".contains(c) {
+            tokens.push(Token::Op(c));
+            rest = &rest[1..];
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' {
+                Token::LeftParen
+            } else {
+                Token::RightParen
+            });
+            rest = &rest[1..];
+        } else if c.is_ascii_digit() || c == '.' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                .unwrap_or(rest.len());
+            let number = rest[..end].replace('_', "");
+            tokens.push(Token::Number(
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid number {}", &rest[..end]))?,
+            ));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("unexpected {c:?} in {rest}"));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Number(f64),
+    Field(&'static str),
+    Neg(Box<Expr>),
+    Binary(char, Box<Expr>, Box<Expr>),
+}
+
+/// Recursive descent over the token list, lowest precedence first
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn sum(&mut self) -> Result<Expr, String> {
+        let mut expr = self.product()?;
+        while let Some(&Token::Op(op @ ('+' | '-'))) = self.peek() {
+            self.advance();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.product()?));
+        }
+        Ok(expr)
+    }
+
+    fn product(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while let Some(&Token::Op(op @ ('*' | '/'))) = self.peek() {
+            self.advance();
+            expr = Expr::Binary(op, Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.advance() {
+            Some(Token::Op('-')) => Ok(Expr::Neg(Box::new(self.unary()?))),
+            Some(Token::LeftParen) => {
+                let expr = self.sum()?;
+                match self.advance() {
+                    Some(Token::RightParen) => Ok(expr),
+                    _ => Err("missing closing parenthesis".to_string()),
+                }
+            }
+            Some(Token::Number(number)) => Ok(Expr::Number(number)),
+            Some(Token::Ident(name)) => {
+                let field = FIELDS.iter().find(|field| **field == name).ok_or_else(|| {
+                    format!(
+                        "unknown field {name}, expected one of {}",
+                        FIELDS.join(", ")
+                    )
+                })?;
+                Ok(Expr::Field(field))
+            }
+            Some(token) => Err(format!("expected a field or number, got {token:?}")),
+            None => Err("unexpected end of expression".to_string()),
+        }
+    }
+}
+
+/// A parsed column expression
+#[derive(Clone, Debug)]
+pub struct Column {
+    expr: Expr,
+}
+
+impl FromStr for Column {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        let expr = parser.sum()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {token:?} after end of expression"));
+        }
+        Ok(Column { expr })
+    }
+}
+
+impl Column {
+    pub fn evaluate(&self, snapshot: &Snapshot) -> f64 {
+        evaluate(&self.expr, snapshot)
+    }
+}
+
+fn evaluate(expr: &Expr, snapshot: &Snapshot) -> f64 {
+    match expr {
+        Expr::Number(number) => *number,
+        Expr::Field(field) => field_value(field, snapshot),
+        Expr::Neg(expr) => evaluate(expr, snapshot).neg(),
+        Expr::Binary(op, left, right) => {
+            let (left, right) = (evaluate(left, snapshot), evaluate(right, snapshot));
+            match op {
+                '+' => left.add(right),
+                '-' => left.sub(right),
+                '*' => left.mul(right),
+                _ => left.div(right),
+            }
+        }
+    }
+}
+
+fn field_value(field: &str, snapshot: &Snapshot) -> f64 {
+    match field {
+        "unix_time" => snapshot.unix_time as f64,
+        "slot" => snapshot.slot as f64,
+        "total_pool_value" => snapshot.total_pool_value,
+        "num_positions" => snapshot.num_positions as f64,
+        "num_longs" => snapshot.num_longs as f64,
+        "num_shorts" => snapshot.num_shorts() as f64,
+        "num_winning" => snapshot.num_winning as f64,
+        "cumulative_positions" | "total_position_value" => snapshot.cumulative_positions,
+        "cumulative_long" | "long_value" => snapshot.cumulative_long,
+        "cumulative_short" | "short_value" => snapshot.cumulative_short(),
+        "cumulative_collateral" | "total_collateral_value" => snapshot.cumulative_collateral,
+        "cumulative_positions_at_entry" => snapshot.cumulative_positions_at_entry,
+        "cumulative_collateral_at_entry" => snapshot.cumulative_collateral_at_entry,
+        "cumulative_fees" | "total_fees" => snapshot.cumulative_fees,
+        "cumulative_pnl" | "unrealized_pnl" => snapshot.cumulative_pnl,
+        "average_leverage_at_entry" => snapshot.average_leverage_at_entry(),
+        _ => snapshot.average_effective_leverage(),
+    }
+}
+
+/// The computed columns of the config, by name
+#[derive(Clone, Debug, Default)]
+pub struct Columns {
+    columns: BTreeMap<String, Column>,
+}
+
+impl Columns {
+    /// Parses the `columns` table of the config
+    pub fn from_config(columns: &BTreeMap<String, String>) -> Result<Self, String> {
+        let columns = columns
+            .iter()
+            .map(|(name, expression)| {
+                let column = expression
+                    .parse()
+                    .map_err(|err| format!("invalid column {name} = {expression:?}: {err}"))?;
+                Ok((name.clone(), column))
+            })
+            .collect::<Result<_, String>>()?;
+        Ok(Columns { columns })
+    }
+
+    /// Adds each column's value to the snapshot's custom metrics
+    pub fn apply(&self, snapshot: &mut Snapshot) {
+        for (name, column) in &self.columns {
+            let value = column.evaluate(snapshot);
+            snapshot.custom_metrics.insert(name.clone(), value);
+        }
+    }
+}
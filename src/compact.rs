@@ -0,0 +1,95 @@
+use std::collections::HashSet;
+use std::time::SystemTime;
+
+use crate::store::{
+    child_path, read_existing_records, read_records, replace_records, CustodyRecord, MarketRecord,
+    PositionRecord, Record, SnapshotRecord, WalletRecord,
+};
+
+const SECONDS_PER_HOUR: u64 = 3600;
+const SECONDS_PER_DAY: u64 = 24 * SECONDS_PER_HOUR;
+
+/// Time bucket a snapshot falls in under the retention policy, `None` keeps it as is
+fn bucket(unix_time: u64, now: u64, raw_days: u64, hourly_days: u64) -> Option<u64> {
+    let age = now.saturating_sub(unix_time);
+    if age < raw_days * SECONDS_PER_DAY {
+        None
+    } else if age < hourly_days * SECONDS_PER_DAY {
+        Some(unix_time / SECONDS_PER_HOUR * SECONDS_PER_HOUR)
+    } else {
+        Some(unix_time / SECONDS_PER_DAY * SECONDS_PER_DAY)
+    }
+}
+
+/// Drops the rows of child table `R` stored with `path` that belong to none of the
+/// `snapshots` kept, returning how many
+fn prune_children<R: Record>(
+    path: &str,
+    snapshots: &HashSet<(u64, u64)>,
+) -> Result<usize, Box<dyn std::error::Error>> {
+    let path = child_path(path, R::TABLE);
+    let mut records = read_existing_records::<R>(&path)?;
+    let num_before = records.len();
+    records.retain(|record| snapshots.contains(&(record.unix_time(), record.slot())));
+    let num_pruned = num_before - records.len();
+    if num_pruned > 0 {
+        replace_records(&path, &records)?;
+    }
+    Ok(num_pruned)
+}
+
+/// Drops snapshots of slots already stored and keeps the last snapshot of every hour/day
+/// bucket for data older than `raw_days`, along with the child rows of the snapshots dropped
+pub fn run(path: &str, raw_days: u64, hourly_days: u64) -> Result<(), Box<dyn std::error::Error>> {
+    let now = SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)?
+        .as_secs();
+    let mut records = read_records::<SnapshotRecord>(path)?;
+    let num_before = records.len();
+    records.sort_by_key(|record| record.unix_time);
+
+    let mut seen_slots = HashSet::new();
+    let mut seen_times = HashSet::new();
+    records.retain(|record| {
+        // rows written before slots were recorded have slot 0
+        let new_slot = record.slot == 0 || seen_slots.insert(record.slot);
+        new_slot && seen_times.insert(record.unix_time)
+    });
+    let num_deduplicated = num_before - records.len();
+
+    let mut compacted: Vec<SnapshotRecord> = vec![];
+    for record in records {
+        let record_bucket = bucket(record.unix_time, now, raw_days, hourly_days);
+        if let Some(last) = compacted.last_mut() {
+            if record_bucket.is_some()
+                && record_bucket == bucket(last.unix_time, now, raw_days, hourly_days)
+            {
+                *last = record;
+                continue;
+            }
+        }
+        compacted.push(record);
+    }
+    let num_downsampled = num_before - num_deduplicated - compacted.len();
+
+    replace_records(path, &compacted)?;
+    println!(
+        "Compacted {path}: {num_before} -> {} snapshots ({num_deduplicated} duplicates, {num_downsampled} downsampled)",
+        compacted.len()
+    );
+
+    let kept: HashSet<(u64, u64)> = compacted
+        .iter()
+        .map(|record| (record.unix_time, record.slot))
+        .collect();
+    let num_pruned = prune_children::<CustodyRecord>(path, &kept)?
+        + prune_children::<MarketRecord>(path, &kept)?
+        + prune_children::<PositionRecord>(path, &kept)?
+        + prune_children::<WalletRecord>(path, &kept)?;
+    if num_pruned > 0 {
+        println!(
+            "Removed {num_pruned} custody, market, position and wallet rows of dropped snapshots"
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,178 @@
+//! Optional TOML config file for settings that don't fit on the command line, such as
+//! per-endpoint HTTP headers.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+use serde::Deserialize;
+
+const CONFIG_FILE: &str = "config.toml";
+
+#[derive(Default, Deserialize)]
+pub struct Config {
+    #[serde(default, rename = "endpoint")]
+    pub endpoints: Vec<EndpointConfig>,
+    /// Price sources to take the consensus of, by market symbol, overriding `--consensus`
+    #[serde(default)]
+    pub price_sources: BTreeMap<String, Vec<String>>,
+    /// Price provider by market symbol, e.g. `pyth-pull` for a market migrated off the
+    /// legacy oracle
+    #[serde(default)]
+    pub oracles: BTreeMap<String, String>,
+    /// Computed columns, expressions over snapshot fields by column name
+    #[serde(default)]
+    pub columns: BTreeMap<String, String>,
+    /// Rhai script run on each snapshot for derived metrics and alerts
+    #[serde(default)]
+    pub script: Option<PathBuf>,
+    /// Wallets whose positions are stored with each snapshot, addresses by label
+    #[serde(default)]
+    pub wallets: BTreeMap<String, String>,
+    /// SMTP server emails are sent through
+    #[serde(default)]
+    pub smtp: Option<SmtpConfig>,
+    /// Who is emailed which alerts and reports
+    #[serde(default, rename = "email")]
+    pub emails: Vec<EmailRoute>,
+    /// Keys of the X account the `tweet` subcommand posts as
+    #[serde(default)]
+    pub x: Option<XConfig>,
+    /// Bot answering slash commands with `--discord-bot`
+    #[serde(default)]
+    pub discord: Option<DiscordConfig>,
+    /// Bot answering commands with `--telegram-bot`
+    #[serde(default)]
+    pub telegram: Option<TelegramConfig>,
+    /// Secret `top-positions --anonymize` salts owner hashes with, unless the
+    /// `PERPS_ANONYMIZE_SALT` environment variable is set
+    #[serde(default)]
+    pub anonymize_salt: Option<String>,
+}
+
+/// Settings for RPC endpoints whose URL starts with `url`
+#[derive(Deserialize)]
+pub struct EndpointConfig {
+    pub url: String,
+    /// Sent with every HTTP request and the websocket handshake, e.g. `Authorization`
+    #[serde(default)]
+    pub headers: BTreeMap<String, String>,
+}
+
+#[derive(Deserialize)]
+pub struct SmtpConfig {
+    pub host: String,
+    /// 587 with STARTTLS or 465 with implicit TLS by default
+    #[serde(default)]
+    pub port: Option<u16>,
+    #[serde(default)]
+    pub username: Option<String>,
+    #[serde(default)]
+    pub password: Option<String>,
+    /// Sender, e.g. `Perps <perps@example.com>`
+    pub from: String,
+    /// Connect over TLS from the start instead of upgrading with STARTTLS
+    #[serde(default)]
+    pub implicit_tls: bool,
+}
+
+/// Recipients of the alerts and reports listed
+#[derive(Clone, Deserialize)]
+pub struct EmailRoute {
+    pub to: Vec<String>,
+    /// Keys of the alerts to email, `*` for all; a trailing `*` matches any key starting
+    /// with the rest, e.g. `anomaly:*`
+    #[serde(default)]
+    pub alerts: Vec<String>,
+    /// Periods of the `report` summaries to email, e.g. `week`
+    #[serde(default)]
+    pub reports: Vec<String>,
+}
+
+impl EmailRoute {
+    pub fn wants_alert(&self, key: &str) -> bool {
+        self.alerts
+            .iter()
+            .any(|pattern| match pattern.strip_suffix('*') {
+                Some(prefix) => key.starts_with(prefix),
+                None => key == pattern,
+            })
+    }
+
+    pub fn wants_report(&self, period: &str) -> bool {
+        self.reports.iter().any(|report| report == period)
+    }
+}
+
+/// OAuth 1.0a keys from the X developer portal: the app's API key and secret, and an
+/// access token and secret of the posting account with write permission
+#[derive(Deserialize)]
+pub struct XConfig {
+    pub api_key: String,
+    pub api_secret: String,
+    pub access_token: String,
+    pub access_token_secret: String,
+}
+
+/// A bot from the Discord developer portal, invited to the server with the
+/// `applications.commands` scope
+#[derive(Clone, Deserialize)]
+pub struct DiscordConfig {
+    pub token: String,
+    pub application_id: String,
+    /// Server to register the commands in, where they show up right away rather than
+    /// within the hour global commands take
+    #[serde(default)]
+    pub guild_id: Option<String>,
+}
+
+/// A bot from @BotFather
+#[derive(Clone, Deserialize)]
+pub struct TelegramConfig {
+    pub token: String,
+    /// Ids of the chats the bot answers in; it ignores everyone else
+    pub chats: Vec<i64>,
+    /// Seconds a chat waits between answers, commands sent sooner are ignored
+    #[serde(default = "default_min_interval_secs")]
+    pub min_interval_secs: u64,
+}
+
+fn default_min_interval_secs() -> u64 {
+    10
+}
+
+impl Config {
+    /// `$XDG_CONFIG_HOME/jupiter_perpetuals_analytics/config.toml`, falling back to
+    /// `~/.config`
+    pub fn default_path() -> Option<PathBuf> {
+        std::env::var_os("XDG_CONFIG_HOME")
+            .map(PathBuf::from)
+            .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+            .map(|dir| dir.join("jupiter_perpetuals_analytics").join(CONFIG_FILE))
+    }
+
+    /// Loads `path`, or the default config file if there is one
+    pub fn load(path: Option<&str>) -> Result<Self, Box<dyn std::error::Error>> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => match Config::default_path() {
+                Some(path) if path.exists() => path,
+                _ => return Ok(Config::default()),
+            },
+        };
+        let contents = fs::read_to_string(&path)
+            .map_err(|err| format!("can't read config {}: {err}", path.display()))?;
+        toml::from_str(&contents)
+            .map_err(|err| format!("invalid config {}: {err}", path.display()).into())
+    }
+
+    /// Headers configured for `url`, from the entry with the longest matching prefix
+    pub fn headers_for(&self, url: &str) -> BTreeMap<String, String> {
+        self.endpoints
+            .iter()
+            .filter(|endpoint| url.starts_with(&endpoint.url))
+            .max_by_key(|endpoint| endpoint.url.len())
+            .map(|endpoint| endpoint.headers.clone())
+            .unwrap_or_default()
+    }
+}
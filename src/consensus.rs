@@ -0,0 +1,240 @@
+//! Consensus pricing across several price sources per market: the median of the sources
+//! that agree with it, so one misbehaving oracle or API doesn't skew the analytics.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Add as _, Div as _, Sub as _};
+
+use clap::ValueEnum;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::config::Config;
+use crate::fallback::FallbackSource;
+use crate::jupiter;
+use crate::rpc::OraclePrice;
+use crate::snapshot::PriceConsensus;
+use crate::state::PerpState;
+use crate::transport::{blocking_client, blocking_client_with_headers};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, ValueEnum)]
+pub enum PriceSource {
+    /// The custody's on-chain oracle
+    Oracle,
+    /// Jupiter price API, derived from DEX liquidity
+    Jupiter,
+    Coingecko,
+    Birdeye,
+}
+
+impl PriceSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            PriceSource::Oracle => "oracle",
+            PriceSource::Jupiter => "jupiter",
+            PriceSource::Coingecko => "coingecko",
+            PriceSource::Birdeye => "birdeye",
+        }
+    }
+}
+
+/// Median of `prices`, which must not be empty
+fn median(prices: &mut [f64]) -> f64 {
+    prices.sort_by(f64::total_cmp);
+    let middle = prices.len() / 2;
+    if prices.len() % 2 == 0 {
+        prices[middle - 1].add(prices[middle]).div(2.0)
+    } else {
+        prices[middle]
+    }
+}
+
+/// Consensus of the prices quoted by each source: sources more than `tolerance` (a
+/// fraction) off the median are rejected and the median of the rest is taken
+pub fn consensus(sources: BTreeMap<String, f64>, tolerance: f64) -> Option<PriceConsensus> {
+    let mut all: Vec<f64> = sources.values().copied().collect();
+    if all.is_empty() {
+        return None;
+    }
+    let median_price = median(&mut all);
+    let (mut agreeing, mut outliers) = (vec![], vec![]);
+    for (source, price) in &sources {
+        if price.div(median_price).sub(1.0).abs() > tolerance {
+            outliers.push(source.clone());
+        } else {
+            agreeing.push(*price);
+        }
+    }
+    // with no majority left, e.g. two sources that disagree, fall back to the plain median
+    let price = if agreeing.is_empty() {
+        median_price
+    } else {
+        median(&mut agreeing)
+    };
+    Some(PriceConsensus {
+        price,
+        sources,
+        outliers,
+    })
+}
+
+/// Prices every market from its configured sources, `sources` unless the config file lists
+/// others for it, and reprices the state with the consensus of each
+pub fn apply(
+    state: PerpState,
+    sources: &[PriceSource],
+    tolerance: f64,
+    proxy_url: Option<&str>,
+    config: &Config,
+) -> Result<(PerpState, BTreeMap<String, PriceConsensus>), Box<dyn std::error::Error>> {
+    let mut market_sources = HashMap::new();
+    for custody_state in state.custodies.values() {
+        let symbol = custody_state.symbol();
+        let configured = match config.price_sources.get(&symbol) {
+            Some(names) => names
+                .iter()
+                .map(|name| PriceSource::from_str(name, true))
+                .collect::<Result<Vec<_>, _>>()
+                .map_err(|err| format!("invalid price source for {symbol}: {err}"))?,
+            None => sources.to_vec(),
+        };
+        market_sources.insert(custody_state.pubkey, configured);
+    }
+
+    // one request per HTTP source for all the mints it prices
+    let mut quotes: HashMap<PriceSource, HashMap<Pubkey, f64>> = HashMap::new();
+    for (source, fallback_source) in [
+        (PriceSource::Jupiter, None),
+        (PriceSource::Coingecko, Some(FallbackSource::Coingecko)),
+        (PriceSource::Birdeye, Some(FallbackSource::Birdeye)),
+    ] {
+        let mints: Vec<Pubkey> = state
+            .custodies
+            .values()
+            .filter(|custody_state| market_sources[&custody_state.pubkey].contains(&source))
+            .map(|custody_state| custody_state.custody.mint)
+            .collect();
+        if mints.is_empty() {
+            continue;
+        }
+        let prices = match fallback_source {
+            Some(fallback_source) => {
+                let headers = config.headers_for(fallback_source.url());
+                fallback_source.prices(&blocking_client_with_headers(proxy_url, &headers)?, &mints)
+            }
+            None => jupiter::prices(&blocking_client(proxy_url)?, &mints),
+        };
+        // a source that fails counts as quoting none of its markets
+        match prices {
+            Ok(prices) => {
+                quotes.insert(source, prices);
+            }
+            Err(err) => eprintln!(
+                "Price source {} failed, counting its quotes as missing: {err}",
+                source.name()
+            ),
+        }
+    }
+
+    let mut oracle_prices = HashMap::new();
+    let mut price_consensus = BTreeMap::new();
+    for custody_state in state.custodies.values() {
+        let mut prices = BTreeMap::new();
+        for source in &market_sources[&custody_state.pubkey] {
+            let price = match source {
+                PriceSource::Oracle => Some(custody_state.price()),
+                _ => quotes
+                    .get(source)
+                    .and_then(|prices| prices.get(&custody_state.custody.mint))
+                    .copied(),
+            };
+            if let Some(price) = price.filter(|price| price.is_finite()) {
+                prices.insert(source.name().to_string(), price);
+            }
+        }
+        let Some(consensus) = consensus(prices, tolerance) else {
+            continue;
+        };
+        oracle_prices.insert(
+            custody_state.pubkey,
+            OraclePrice {
+                price: consensus.price,
+                ..custody_state.oracle
+            },
+        );
+        price_consensus.insert(custody_state.symbol(), consensus);
+    }
+    Ok((state.with_oracle_prices(&oracle_prices), price_consensus))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn quotes(prices: &[(&str, f64)]) -> BTreeMap<String, f64> {
+        prices
+            .iter()
+            .map(|(source, price)| (source.to_string(), *price))
+            .collect()
+    }
+
+    #[test]
+    fn rejects_sources_off_the_median() {
+        let consensus = consensus(
+            quotes(&[("oracle", 100.0), ("jupiter", 100.5), ("coingecko", 120.0)]),
+            0.01,
+        )
+        .unwrap();
+        assert_eq!(consensus.price, 100.25);
+        assert_eq!(consensus.outliers, vec!["coingecko".to_string()]);
+        assert_eq!(consensus.sources.len(), 3);
+    }
+
+    #[test]
+    fn falls_back_to_the_median_without_agreement() {
+        let consensus = consensus(quotes(&[("oracle", 100.0), ("jupiter", 110.0)]), 0.01).unwrap();
+        assert_eq!(consensus.price, 105.0);
+        assert_eq!(consensus.outliers.len(), 2);
+    }
+
+    #[test]
+    fn no_quotes_no_consensus() {
+        assert!(consensus(BTreeMap::new(), 0.01).is_none());
+    }
+
+    #[test]
+    fn failing_source_counts_as_missing() {
+        let custody_pubkey = Pubkey::new_unique();
+        let custody = perp_abi::state::Custody {
+            mint: "So11111111111111111111111111111111111111112"
+                .parse()
+                .unwrap(),
+            ..Default::default()
+        };
+        let oracle = OraclePrice {
+            price: 150.0,
+            ema_price: 150.0,
+            publish_time: 0,
+            fallback: None,
+        };
+        let state = PerpState::from_parts(
+            Pubkey::new_unique(),
+            perp_abi::state::Pool::default(),
+            vec![(custody_pubkey, custody, oracle)],
+            0,
+            0,
+        );
+        // nothing listens on the proxy, so the Jupiter request fails
+        let (state, consensus) = apply(
+            state,
+            &[PriceSource::Oracle, PriceSource::Jupiter],
+            0.01,
+            Some("http://127.0.0.1:1"),
+            &Config::default(),
+        )
+        .unwrap();
+        let sol = &consensus["SOL"];
+        assert_eq!(sol.price, 150.0);
+        assert_eq!(sol.sources, quotes(&[("oracle", 150.0)]));
+        assert!(sol.outliers.is_empty());
+        assert_eq!(state.custodies[&custody_pubkey].price(), 150.0);
+    }
+}
@@ -0,0 +1,124 @@
+use std::ops::{Div as _, Mul as _};
+
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::state::{self, load_open_position_summaries, PerpState};
+
+/// Prints everything known about a single custody
+pub fn run(connection: &RpcClient, market: &str) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let custody_state = state
+        .find_custody(market)
+        .ok_or_else(|| format!("No custody found for {market}"))?;
+    let custody = &custody_state.custody;
+    let decimals = custody.decimals;
+
+    let mut num_longs: u64 = 0;
+    let mut num_shorts: u64 = 0;
+    let mut long_size_usd: u64 = 0;
+    let mut short_size_usd: u64 = 0;
+    let mut num_collateralized: u64 = 0;
+    for position in load_open_position_summaries(connection)? {
+        if position.collateral_custody == custody_state.pubkey {
+            num_collateralized += 1;
+        }
+        if position.custody != custody_state.pubkey {
+            continue;
+        }
+        if let perp_abi::Side::Long = position.side {
+            num_longs += 1;
+            long_size_usd += position.size_usd;
+        } else {
+            num_shorts += 1;
+            short_size_usd += position.size_usd;
+        }
+    }
+
+    let owned = spl_token::amount_to_ui_amount(custody.assets.owned, decimals);
+    let locked = spl_token::amount_to_ui_amount(custody.assets.locked, decimals);
+    let fees_reserves = spl_token::amount_to_ui_amount(custody.assets.fees_reserves, decimals);
+    let price = custody_state.price();
+    let price_age = (state.unix_time as i64) - custody_state.oracle.publish_time;
+
+    println!(
+        "Custody: {} ({})
+Mint: {}
+Token account: {}
+Stablecoin: {}
+Target weight: {:.2}%
+
+Assets owned: {} (${})
+Assets locked: {} (${})
+Fee reserves: {} (${})
+Guaranteed USD: ${}
+Utilization: {:.2}%
+
+Hourly funding BPS: {}
+Hourly borrow rate: {:.4} BPS
+Borrow APR: {:.2}%
+Cumulative interest rate: {}
+Funding last updated: {}
+
+Oracle account: {}
+Oracle price: ${:.4}
+Oracle price age: {}s (max {}s)
+
+Max leverage: {:.2}x
+Trade spread long/short: {} / {} BPS
+Swap spread: {} BPS
+
+Long OI: ${} (cap ${})
+Short OI: ${} (cap ${})
+Open long positions: {num_longs}
+Open short positions: {num_shorts}
+Positions using as collateral: {num_collateralized}",
+        custody_state.symbol(),
+        custody_state.pubkey,
+        match state::token_name(&custody.mint) {
+            Some(name) => format!("{} ({name})", custody.mint),
+            None => custody.mint.to_string(),
+        },
+        custody.token_account,
+        custody_state.is_stable(),
+        (custody.target_ratio_bps as f64).div(100.0),
+        owned.separate_with_commas(),
+        owned.mul(price).round().separate_with_commas(),
+        locked.separate_with_commas(),
+        locked.mul(price).round().separate_with_commas(),
+        fees_reserves.separate_with_commas(),
+        fees_reserves.mul(price).round().separate_with_commas(),
+        spl_token::amount_to_ui_amount(custody.assets.guaranteed_usd, 6)
+            .round()
+            .separate_with_commas(),
+        custody_state.utilization.mul(100.0),
+        custody.funding_rate_state.hourly_funding_bps,
+        custody_state.borrow_rate,
+        custody_state.borrow_apr(),
+        custody.funding_rate_state.cumulative_interest_rate,
+        custody.funding_rate_state.last_update,
+        custody.oracle.oracle_account,
+        price,
+        price_age,
+        custody.oracle.max_price_age_sec,
+        // max leverage is stored in BPS
+        (custody.pricing.max_leverage as f64).div(10_000.0),
+        custody.pricing.trade_spread_long,
+        custody.pricing.trade_spread_short,
+        custody.pricing.swap_spread,
+        spl_token::amount_to_ui_amount(long_size_usd, 6)
+            .round()
+            .separate_with_commas(),
+        spl_token::amount_to_ui_amount(custody.pricing.max_global_long_sizes, 6)
+            .round()
+            .separate_with_commas(),
+        spl_token::amount_to_ui_amount(short_size_usd, 6)
+            .round()
+            .separate_with_commas(),
+        spl_token::amount_to_ui_amount(custody.pricing.max_global_short_sizes, 6)
+            .round()
+            .separate_with_commas(),
+    );
+
+    Ok(())
+}
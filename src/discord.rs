@@ -0,0 +1,233 @@
+//! Discord bot answering `/oi`, `/funding` and `/position` slash commands from the
+//! [`LiveView`] of the latest snapshot. The commands are registered when the bot starts,
+//! and interactions arrive over the Discord gateway, so no public endpoint is needed.
+
+use std::net::TcpStream;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+use tungstenite::stream::MaybeTlsStream;
+use tungstenite::{Message, WebSocket};
+
+use crate::config::DiscordConfig;
+use crate::live::LiveView;
+use crate::transport::blocking_client;
+
+const API_URL: &str = "https://discord.com/api/v10";
+const GATEWAY_URL: &str = "wss://gateway.discord.gg/?v=10&encoding=json";
+/// How long a read waits before checking whether a heartbeat is due
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const RECONNECT_DELAY: Duration = Duration::from_secs(10);
+
+/// Gateway opcodes
+const DISPATCH: u64 = 0;
+const HEARTBEAT: u64 = 1;
+const IDENTIFY: u64 = 2;
+const RECONNECT: u64 = 7;
+const INVALID_SESSION: u64 = 9;
+const HELLO: u64 = 10;
+
+/// Interaction and option types
+const APPLICATION_COMMAND: u64 = 2;
+const STRING_OPTION: u64 = 3;
+const CHANNEL_MESSAGE: u64 = 4;
+
+type Socket = WebSocket<MaybeTlsStream<TcpStream>>;
+
+fn commands() -> Value {
+    json!([
+        {
+            "name": "oi",
+            "description": "Open interest of every market, or of one",
+            "options": [{
+                "type": STRING_OPTION,
+                "name": "market",
+                "description": "Market symbol, e.g. SOL",
+                "required": false,
+            }],
+        },
+        {
+            "name": "funding",
+            "description": "Borrow APR and utilization of every market",
+        },
+        {
+            "name": "position",
+            "description": "Size, P&L and leverage of an open position",
+            "options": [{
+                "type": STRING_OPTION,
+                "name": "pubkey",
+                "description": "Position account",
+                "required": true,
+            }],
+        },
+    ])
+}
+
+/// The reply to an `APPLICATION_COMMAND` interaction's `data`
+fn answer(view: &LiveView, data: &Value) -> String {
+    let option = |name: &str| {
+        data["options"]
+            .as_array()
+            .into_iter()
+            .flatten()
+            .find(|option| option["name"] == name)
+            .and_then(|option| option["value"].as_str())
+    };
+    match data["name"].as_str() {
+        Some("oi") => view.open_interest(option("market")),
+        Some("funding") => view.funding(),
+        Some("position") => view.position(option("pubkey").unwrap_or_default()),
+        _ => "Unknown command".to_string(),
+    }
+}
+
+fn set_read_timeout(socket: &Socket, timeout: Duration) -> std::io::Result<()> {
+    match socket.get_ref() {
+        MaybeTlsStream::Plain(stream) => stream.set_read_timeout(Some(timeout)),
+        MaybeTlsStream::Rustls(stream) => stream.get_ref().set_read_timeout(Some(timeout)),
+        _ => Ok(()),
+    }
+}
+
+struct Bot {
+    config: DiscordConfig,
+    client: reqwest::blocking::Client,
+    view: LiveView,
+}
+
+impl Bot {
+    /// Overwrites the application's commands with ours, in the configured guild if any
+    fn register_commands(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let url = match &self.config.guild_id {
+            Some(guild_id) => format!(
+                "{API_URL}/applications/{}/guilds/{guild_id}/commands",
+                self.config.application_id
+            ),
+            None => format!(
+                "{API_URL}/applications/{}/commands",
+                self.config.application_id
+            ),
+        };
+        self.client
+            .put(url)
+            .header("Authorization", format!("Bot {}", self.config.token))
+            .json(&commands())
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    fn reply(&self, interaction: &Value) -> Result<(), Box<dyn std::error::Error>> {
+        let (Some(id), Some(token)) = (interaction["id"].as_str(), interaction["token"].as_str())
+        else {
+            return Ok(());
+        };
+        let content = answer(&self.view, &interaction["data"]);
+        self.client
+            .post(format!("{API_URL}/interactions/{id}/{token}/callback"))
+            .json(&json!({ "type": CHANNEL_MESSAGE, "data": { "content": content } }))
+            .send()?
+            .error_for_status()?;
+        Ok(())
+    }
+
+    /// Identifies with the gateway and answers interactions until the connection drops or
+    /// Discord asks for a reconnect
+    fn run_session(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let (mut socket, _) = tungstenite::connect(GATEWAY_URL)?;
+        set_read_timeout(&socket, READ_TIMEOUT)?;
+        let mut heartbeat_interval = None;
+        let mut last_heartbeat = Instant::now();
+        let mut sequence = Value::Null;
+        loop {
+            if let Some(interval) = heartbeat_interval {
+                if last_heartbeat.elapsed() >= interval {
+                    let heartbeat = json!({ "op": HEARTBEAT, "d": sequence });
+                    socket.write_message(Message::Text(heartbeat.to_string()))?;
+                    last_heartbeat = Instant::now();
+                }
+            }
+            let text = match socket.read_message() {
+                Ok(Message::Text(text)) => text,
+                Ok(Message::Close(frame)) => {
+                    return Err(format!("gateway closed the connection: {frame:?}").into())
+                }
+                Ok(_) => continue,
+                Err(tungstenite::Error::Io(err))
+                    if matches!(
+                        err.kind(),
+                        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+                    ) =>
+                {
+                    continue
+                }
+                Err(err) => return Err(err.into()),
+            };
+            let payload: Value = serde_json::from_str(&text)?;
+            if !payload["s"].is_null() {
+                sequence = payload["s"].clone();
+            }
+            match payload["op"].as_u64() {
+                Some(HELLO) => {
+                    let interval = payload["d"]["heartbeat_interval"]
+                        .as_u64()
+                        .ok_or("gateway hello without a heartbeat interval")?;
+                    heartbeat_interval = Some(Duration::from_millis(interval));
+                    // commands only, so no gateway intents are needed
+                    let identify = json!({
+                        "op": IDENTIFY,
+                        "d": {
+                            "token": self.config.token,
+                            "intents": 0,
+                            "properties": {
+                                "os": std::env::consts::OS,
+                                "browser": env!("CARGO_PKG_NAME"),
+                                "device": env!("CARGO_PKG_NAME"),
+                            },
+                        },
+                    });
+                    socket.write_message(Message::Text(identify.to_string()))?;
+                }
+                // the gateway asking for a heartbeat right away
+                Some(HEARTBEAT) => {
+                    let heartbeat = json!({ "op": HEARTBEAT, "d": sequence });
+                    socket.write_message(Message::Text(heartbeat.to_string()))?;
+                    last_heartbeat = Instant::now();
+                }
+                Some(RECONNECT | INVALID_SESSION) => return Ok(()),
+                Some(DISPATCH)
+                    if payload["t"] == "INTERACTION_CREATE"
+                        && payload["d"]["type"].as_u64() == Some(APPLICATION_COMMAND) =>
+                {
+                    // a failed reply only loses that answer
+                    if let Err(err) = self.reply(&payload["d"]) {
+                        eprintln!("Discord reply failed: {err}");
+                    }
+                }
+                _ => {}
+            }
+        }
+    }
+}
+
+/// Registers the slash commands and answers them from `view` on a background thread,
+/// reconnecting to the gateway whenever the connection drops
+pub fn spawn(
+    config: &DiscordConfig,
+    proxy_url: Option<&str>,
+    view: LiveView,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let bot = Bot {
+        config: config.clone(),
+        client: blocking_client(proxy_url)?,
+        view,
+    };
+    bot.register_commands()?;
+    std::thread::spawn(move || loop {
+        if let Err(err) = bot.run_session() {
+            eprintln!("Discord gateway session ended: {err}");
+        }
+        std::thread::sleep(RECONNECT_DELAY);
+    });
+    Ok(())
+}
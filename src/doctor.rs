@@ -0,0 +1,182 @@
+use std::time::{Duration, Instant};
+
+use anchor_lang::{AccountDeserialize as _, Discriminator as _};
+use solana_client::pubsub_client::PubsubClient;
+use solana_client::rpc_client::RpcClient;
+
+use crate::rpc::{get_oracle_price, get_program_accounts_with_discrim, program_id, websocket_url};
+
+/// Slots per epoch on mainnet, used to judge how far back the endpoint keeps blocks
+const SLOTS_PER_EPOCH: u64 = 432_000;
+
+fn report(ok: bool, check: &str, detail: &str) {
+    println!("[{}] {check}: {detail}", if ok { " OK " } else { "FAIL" });
+}
+
+fn warn(check: &str, detail: &str) {
+    println!("[WARN] {check}: {detail}");
+}
+
+/// Checks the RPC endpoint supports everything the tool relies on
+pub fn run(connection: &RpcClient) -> Result<(), Box<dyn std::error::Error>> {
+    let start = Instant::now();
+    let version = match connection.get_version() {
+        Ok(version) => version,
+        Err(err) => {
+            report(
+                false,
+                "RPC reachable",
+                &format!("{err} (check the URL, API key and network access)"),
+            );
+            return Ok(());
+        }
+    };
+    report(
+        true,
+        "RPC reachable",
+        &format!("solana-core {}", version.solana_core),
+    );
+
+    let mut latencies = vec![];
+    for _ in 0..5 {
+        let start = Instant::now();
+        connection.get_slot()?;
+        latencies.push(start.elapsed());
+    }
+    latencies.sort();
+    let median = latencies[latencies.len() / 2];
+    report(
+        true,
+        "Latency",
+        &format!(
+            "median {}ms, max {}ms over {} getSlot calls",
+            median.as_millis(),
+            latencies[latencies.len() - 1].as_millis(),
+            latencies.len()
+        ),
+    );
+    if median > Duration::from_millis(500) {
+        warn(
+            "Latency",
+            "slow endpoint, snapshots will take a while; consider a provider closer to you",
+        );
+    }
+
+    let start_gpa = Instant::now();
+    match get_program_accounts_with_discrim(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Custody::DISCRIMINATOR,
+    ) {
+        Ok(custody_accounts) => {
+            report(
+                true,
+                "getProgramAccounts",
+                &format!(
+                    "{} custody accounts in {}ms",
+                    custody_accounts.len(),
+                    start_gpa.elapsed().as_millis()
+                ),
+            );
+            let mut decoded = 0;
+            for (custody_pubkey, account) in &custody_accounts {
+                match perp_abi::state::Custody::try_deserialize(&mut &*account.data) {
+                    Ok(custody) => {
+                        decoded += 1;
+                        if let Err(err) = get_oracle_price(connection, &custody.oracle.oracle_account)
+                        {
+                            report(
+                                false,
+                                "Oracle",
+                                &format!(
+                                    "{} for custody {custody_pubkey} could not be read: {err}",
+                                    custody.oracle.oracle_account
+                                ),
+                            );
+                        }
+                    }
+                    Err(err) => report(
+                        false,
+                        "Decode custody",
+                        &format!("{custody_pubkey}: {err} (the program layout may have changed, update perp-abi)"),
+                    ),
+                }
+            }
+            report(
+                decoded == custody_accounts.len(),
+                "Decode custody",
+                &format!("{decoded}/{} accounts decoded", custody_accounts.len()),
+            );
+        }
+        Err(err) => report(
+            false,
+            "getProgramAccounts",
+            &format!("{err} (many free endpoints disable it, use a provider that allows it for the perpetuals program)"),
+        ),
+    }
+
+    match get_program_accounts_with_discrim(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Pool::DISCRIMINATOR,
+    ) {
+        Ok(pool_accounts) => match pool_accounts
+            .first()
+            .map(|(_, account)| perp_abi::state::Pool::try_deserialize(&mut &*account.data))
+        {
+            Some(Ok(pool)) => report(true, "Decode pool", &pool.name),
+            Some(Err(err)) => report(
+                false,
+                "Decode pool",
+                &format!("{err} (the program layout may have changed, update perp-abi)"),
+            ),
+            None => report(false, "Decode pool", "no pool account returned"),
+        },
+        Err(err) => report(false, "Decode pool", &err.to_string()),
+    }
+
+    let ws_url = websocket_url(&connection.url());
+    match PubsubClient::slot_subscribe(&ws_url) {
+        Ok((mut subscription, receiver)) => {
+            match receiver.recv_timeout(Duration::from_secs(5)) {
+                Ok(_) => report(true, "Websocket", &ws_url),
+                Err(_) => warn(
+                    "Websocket",
+                    &format!("connected to {ws_url} but no slot notification within 5s"),
+                ),
+            }
+            let _ = subscription.shutdown();
+        }
+        Err(err) => warn(
+            "Websocket",
+            &format!(
+                "{ws_url}: {err} (watch features relying on subscriptions will be unavailable)"
+            ),
+        ),
+    }
+
+    match (
+        connection.get_first_available_block(),
+        connection.get_slot(),
+    ) {
+        (Ok(first_block), Ok(slot)) => {
+            let epochs = slot.saturating_sub(first_block) / SLOTS_PER_EPOCH;
+            if epochs > 10 {
+                report(
+                    true,
+                    "Archival",
+                    &format!("blocks available from slot {first_block}"),
+                );
+            } else {
+                warn(
+                    "Archival",
+                    &format!("blocks only available from slot {first_block} (~{epochs} epochs), historical backfills need an archival endpoint"),
+                );
+            }
+        }
+        (Err(err), _) | (_, Err(err)) => warn("Archival", &err.to_string()),
+    }
+
+    println!("Completed in {}ms", start.elapsed().as_millis());
+    Ok(())
+}
@@ -0,0 +1,211 @@
+//! Elasticsearch/OpenSearch sink: indexes a summary document per snapshot and a document
+//! per open position, for Kibana or OpenSearch Dashboards.
+
+use std::collections::BTreeMap;
+use std::ops::Div as _;
+
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::liquidations;
+use crate::sink::OutputSink;
+use crate::snapshot::Snapshot;
+use crate::state::PerpState;
+use crate::transport::blocking_client_with_headers;
+
+/// Indexes snapshots into `<prefix>-snapshots` and positions into `<prefix>-positions`
+pub struct ElasticsearchSink {
+    client: reqwest::blocking::Client,
+    url: String,
+    prefix: String,
+    /// Bulk actions written since the last flush
+    pending: String,
+}
+
+/// Index mappings, keeping pubkeys and market names exact-match keywords and storing
+/// amounts as doubles rather than the dynamically guessed floats
+fn mappings(index: &str) -> serde_json::Value {
+    let properties = match index {
+        "positions" => json!({
+            "@timestamp": { "type": "date", "format": "epoch_second" },
+            "slot": { "type": "long" },
+            "pubkey": { "type": "keyword" },
+            "owner": { "type": "keyword" },
+            "market": { "type": "keyword" },
+            "side": { "type": "keyword" },
+            "size_usd": { "type": "double" },
+            "collateral_usd": { "type": "double" },
+            "leverage": { "type": "double" },
+            "entry_price": { "type": "double" },
+            "price": { "type": "double" },
+            "liquidation_price": { "type": "double" },
+            "liquidation_distance": { "type": "double" },
+            "open_time": { "type": "date", "format": "epoch_second" },
+        }),
+        _ => json!({
+            "@timestamp": { "type": "date", "format": "epoch_second" },
+            "slot": { "type": "long" },
+        }),
+    };
+    json!({
+        "mappings": {
+            "dynamic_templates": [
+                { "doubles": { "match_mapping_type": "double", "mapping": { "type": "double" } } },
+            ],
+            "properties": properties,
+        }
+    })
+}
+
+impl ElasticsearchSink {
+    /// Creates the indices at `url` with their mappings if they don't exist yet
+    pub fn connect(
+        url: &str,
+        prefix: &str,
+        proxy_url: Option<&str>,
+        headers: &BTreeMap<String, String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let sink = ElasticsearchSink {
+            client: blocking_client_with_headers(proxy_url, headers)?,
+            url: url.trim_end_matches('/').to_string(),
+            prefix: prefix.to_string(),
+            pending: String::new(),
+        };
+        for index in ["snapshots", "positions"] {
+            let index_url = format!("{}/{}-{index}", sink.url, sink.prefix);
+            if sink.client.head(&index_url).send()?.status().is_success() {
+                continue;
+            }
+            let response = sink.client.put(&index_url).json(&mappings(index)).send()?;
+            if !response.status().is_success() {
+                return Err(format!(
+                    "creating {index_url} failed with {}: {}",
+                    response.status(),
+                    response.text().unwrap_or_default()
+                )
+                .into());
+            }
+        }
+        Ok(sink)
+    }
+
+    /// Bulk indexes the snapshot summary and every position. Documents are keyed by time,
+    /// so reindexing a snapshot overwrites rather than duplicates them.
+    pub fn index(
+        &self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut body = self.snapshot_actions(snapshot)?;
+        body += &self.position_actions(state, positions, snapshot);
+        self.bulk(body)
+    }
+
+    fn action(&self, index: &str, id: String, document: serde_json::Value) -> String {
+        let action =
+            json!({ "index": { "_index": format!("{}-{index}", self.prefix), "_id": id } });
+        format!("{action}\n{document}\n")
+    }
+
+    fn snapshot_actions(&self, snapshot: &Snapshot) -> Result<String, Box<dyn std::error::Error>> {
+        let mut summary = serde_json::to_value(snapshot.to_record())?;
+        summary["@timestamp"] = json!(snapshot.unix_time);
+        summary["markets"] = serde_json::to_value(&snapshot.markets)?;
+        Ok(self.action("snapshots", snapshot.unix_time.to_string(), summary))
+    }
+
+    fn position_actions(
+        &self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> String {
+        let mut body = String::new();
+        for (pubkey, position) in positions {
+            let Some(custody_state) = state.custodies.get(&position.custody) else {
+                continue;
+            };
+            let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+            let collateral_usd = spl_token::amount_to_ui_amount(position.collateral_usd, 6);
+            let estimate = liquidations::estimate(state, *pubkey, position);
+            body += &self.action(
+                "positions",
+                format!("{pubkey}-{}", snapshot.unix_time),
+                json!({
+                    "@timestamp": snapshot.unix_time,
+                    "slot": snapshot.slot,
+                    "pubkey": pubkey.to_string(),
+                    "owner": position.owner.to_string(),
+                    "market": custody_state.symbol(),
+                    "side": format!("{:?}", position.side),
+                    "size_usd": size_usd,
+                    "collateral_usd": collateral_usd,
+                    "leverage": size_usd.div(collateral_usd),
+                    "entry_price": spl_token::amount_to_ui_amount(position.price, 6),
+                    "price": custody_state.price(),
+                    "liquidation_price": estimate.as_ref().map(|estimate| estimate.liquidation_price),
+                    "liquidation_distance": estimate.as_ref().map(|estimate| estimate.distance),
+                    "open_time": position.open_time,
+                }),
+            );
+        }
+        body
+    }
+
+    fn bulk(&self, body: String) -> Result<(), Box<dyn std::error::Error>> {
+        let response = self
+            .client
+            .post(format!("{}/_bulk", self.url))
+            .header("Content-Type", "application/x-ndjson")
+            .body(body)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(format!("bulk indexing responded {}", response.status()).into());
+        }
+        let result: serde_json::Value = response.json()?;
+        if result["errors"].as_bool().unwrap_or_default() {
+            let first_error = result["items"]
+                .as_array()
+                .into_iter()
+                .flatten()
+                .find_map(|item| item["index"].get("error"))
+                .cloned()
+                .unwrap_or_default();
+            return Err(format!("bulk indexing failed for some documents: {first_error}").into());
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for ElasticsearchSink {
+    fn write_snapshot(
+        &mut self,
+        _state: &PerpState,
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let actions = self.snapshot_actions(snapshot)?;
+        self.pending += &actions;
+        Ok(())
+    }
+
+    fn write_positions(
+        &mut self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let actions = self.position_actions(state, positions, snapshot);
+        self.pending += &actions;
+        Ok(())
+    }
+
+    /// Sends the snapshot and its positions in one bulk request
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        if self.pending.is_empty() {
+            return Ok(());
+        }
+        let body = std::mem::take(&mut self.pending);
+        self.bulk(body)
+    }
+}
@@ -0,0 +1,124 @@
+//! Email delivery of alerts and `report` summaries over SMTP with TLS, to the recipients
+//! of each `[[email]]` route in the config whose alert keys or report periods match.
+
+use lettre::message::header::ContentType;
+use lettre::message::Mailbox;
+use lettre::transport::smtp::authentication::Credentials;
+use lettre::{Message, SmtpTransport, Transport as _};
+
+use crate::alerts::Alert;
+use crate::config::{Config, EmailRoute};
+
+pub struct Mailer {
+    transport: SmtpTransport,
+    from: Mailbox,
+    routes: Vec<EmailRoute>,
+}
+
+impl Mailer {
+    /// Connects to the config's SMTP server, `None` if no email routes are configured
+    pub fn from_config(config: &Config) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        if config.emails.is_empty() {
+            return Ok(None);
+        }
+        let smtp = config
+            .smtp
+            .as_ref()
+            .ok_or("email routes are configured but [smtp] is missing from the config")?;
+        let mut builder = if smtp.implicit_tls {
+            SmtpTransport::relay(&smtp.host)?
+        } else {
+            SmtpTransport::starttls_relay(&smtp.host)?
+        };
+        if let Some(port) = smtp.port {
+            builder = builder.port(port);
+        }
+        if let (Some(username), Some(password)) = (&smtp.username, &smtp.password) {
+            builder = builder.credentials(Credentials::new(username.clone(), password.clone()));
+        }
+        Ok(Some(Mailer {
+            transport: builder.build(),
+            from: smtp
+                .from
+                .parse()
+                .map_err(|err| format!("invalid sender {}: {err}", smtp.from))?,
+            routes: config.emails.clone(),
+        }))
+    }
+
+    /// Emails each route the alerts it wants, one email per route
+    pub fn send_alerts(&self, alerts: &[Alert]) -> Result<(), Box<dyn std::error::Error>> {
+        for route in &self.routes {
+            let alerts: Vec<&Alert> = alerts
+                .iter()
+                .filter(|alert| route.wants_alert(&alert.key))
+                .collect();
+            let subject = match alerts.as_slice() {
+                [] => continue,
+                [alert] => alert.message.clone(),
+                alerts => format!("{} Jupiter perpetuals alerts", alerts.len()),
+            };
+            let body: Vec<String> = alerts
+                .iter()
+                .map(|alert| format!("[{}] {}", alert.key, alert.message))
+                .collect();
+            self.send(
+                &route.to,
+                &subject,
+                body.join("\n"),
+                ContentType::TEXT_PLAIN,
+            )?;
+        }
+        Ok(())
+    }
+
+    /// Emails a rendered `report` of `period` to the routes that want it, as HTML if `html`
+    pub fn send_report(
+        &self,
+        period: &str,
+        report: &str,
+        html: bool,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let routes: Vec<&EmailRoute> = self
+            .routes
+            .iter()
+            .filter(|route| route.wants_report(period))
+            .collect();
+        if routes.is_empty() {
+            return Err(format!("no email route in the config lists the {period} report").into());
+        }
+        let content_type = if html {
+            ContentType::TEXT_HTML
+        } else {
+            ContentType::TEXT_PLAIN
+        };
+        let subject = format!("Jupiter perpetuals {period} summary");
+        for route in routes {
+            self.send(
+                &route.to,
+                &subject,
+                report.to_string(),
+                content_type.clone(),
+            )?;
+        }
+        Ok(())
+    }
+
+    fn send(
+        &self,
+        to: &[String],
+        subject: &str,
+        body: String,
+        content_type: ContentType,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut message = Message::builder().from(self.from.clone()).subject(subject);
+        for recipient in to {
+            message = message.to(recipient
+                .parse()
+                .map_err(|err| format!("invalid recipient {recipient}: {err}"))?);
+        }
+        self.transport
+            .send(&message.header(content_type).body(body)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,135 @@
+//! Position changes as a continuous JSONL stream, one event per line as each snapshot is
+//! taken, written to stdout or to every process connected to a Unix socket, so consumers
+//! can react to changes without polling storage. The changes are the ones the snapshot's
+//! [position index](crate::lifecycle::PositionIndex) found.
+
+use std::io::Write as _;
+#[cfg(unix)]
+use std::sync::{Arc, Mutex};
+#[cfg(unix)]
+use std::time::Duration;
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::events::{position_events, PositionEvent};
+use crate::sink::OutputSink;
+use crate::snapshot::Snapshot;
+use crate::state::PerpState;
+
+/// How long a write to a socket client may block before it's dropped
+#[cfg(unix)]
+const WRITE_TIMEOUT: Duration = Duration::from_secs(5);
+
+#[cfg(unix)]
+type Clients = Arc<Mutex<Vec<std::os::unix::net::UnixStream>>>;
+
+enum Destination {
+    Stdout,
+    /// Streams accepted on the socket, which the events are written to
+    #[cfg(unix)]
+    Socket(Clients),
+}
+
+/// Streams the position changes each snapshot found
+pub struct EventStream {
+    destination: Destination,
+}
+
+/// Listens on the Unix socket at `path`, accepting clients in the background. A socket
+/// left behind by a previous run is replaced.
+#[cfg(unix)]
+fn listen(path: &str) -> Result<Clients, Box<dyn std::error::Error>> {
+    use std::os::unix::fs::FileTypeExt as _;
+    use std::os::unix::net::UnixListener;
+
+    if let Ok(metadata) = std::fs::symlink_metadata(path) {
+        if !metadata.file_type().is_socket() {
+            return Err(format!("{path} exists and isn't a socket").into());
+        }
+        std::fs::remove_file(path)?;
+    }
+    let listener =
+        UnixListener::bind(path).map_err(|err| format!("can't listen on socket {path}: {err}"))?;
+    let clients = Clients::default();
+    let accepted = clients.clone();
+    std::thread::spawn(move || {
+        for stream in listener.incoming().flatten() {
+            // a client that stops reading is dropped rather than stalling the snapshots
+            if stream.set_write_timeout(Some(WRITE_TIMEOUT)).is_err() {
+                continue;
+            }
+            accepted
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .push(stream);
+        }
+    });
+    Ok(clients)
+}
+
+impl EventStream {
+    /// Streams to stdout for `-`, or to the clients of the Unix socket at `unix:<PATH>`
+    pub fn open(destination: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let destination = match destination {
+            "-" => Destination::Stdout,
+            #[cfg(unix)]
+            _ if destination.starts_with("unix:") => {
+                Destination::Socket(listen(&destination["unix:".len()..])?)
+            }
+            #[cfg(not(unix))]
+            _ if destination.starts_with("unix:") => {
+                return Err("event streams over Unix sockets need a Unix platform".into())
+            }
+            _ => {
+                return Err(
+                    format!("event stream {destination} is neither `-` nor unix:<PATH>").into(),
+                )
+            }
+        };
+        Ok(EventStream { destination })
+    }
+
+    fn write(&self, events: &[PositionEvent]) -> Result<(), Box<dyn std::error::Error>> {
+        if events.is_empty() {
+            return Ok(());
+        }
+        let mut lines = vec![];
+        for event in events {
+            serde_json::to_writer(&mut lines, event)?;
+            lines.push(b'\n');
+        }
+        match &self.destination {
+            Destination::Stdout => {
+                let mut stdout = std::io::stdout().lock();
+                stdout.write_all(&lines)?;
+                stdout.flush()?;
+            }
+            // clients that hung up are dropped, the rest keep streaming
+            #[cfg(unix)]
+            Destination::Socket(clients) => clients
+                .lock()
+                .unwrap_or_else(|err| err.into_inner())
+                .retain_mut(|client| client.write_all(&lines).is_ok()),
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for EventStream {
+    fn write_snapshot(
+        &mut self,
+        _state: &PerpState,
+        _snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn write_positions(
+        &mut self,
+        _state: &PerpState,
+        _positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write(&position_events(snapshot))
+    }
+}
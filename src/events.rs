@@ -0,0 +1,80 @@
+//! Position changes between consecutive snapshots, for the streaming sinks. They're the
+//! changes the [position index](crate::lifecycle::PositionIndex) found for the snapshot.
+
+use schemars::JsonSchema;
+use serde::Serialize;
+
+use crate::lifecycle::Transition;
+use crate::snapshot::Snapshot;
+use crate::store::PositionRecord;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum ChangeKind {
+    Opened,
+    Closed,
+    Resized,
+    CollateralChanged,
+}
+
+impl ChangeKind {
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            ChangeKind::Opened => "opened",
+            ChangeKind::Closed => "closed",
+            ChangeKind::Resized => "resized",
+            ChangeKind::CollateralChanged => "collateral_changed",
+        }
+    }
+}
+
+#[derive(Clone, Debug, Serialize, JsonSchema)]
+pub struct PositionEvent {
+    /// Time and slot of the snapshot the change was seen in
+    pub unix_time: u64,
+    pub slot: u64,
+    pub kind: ChangeKind,
+    pub pubkey: String,
+    pub owner: String,
+    pub market: String,
+    pub side: String,
+    /// Size and collateral after the change, or before closing
+    pub size_usd: f64,
+    pub collateral_usd: f64,
+}
+
+impl PositionEvent {
+    fn new(kind: ChangeKind, position: &PositionRecord) -> Self {
+        PositionEvent {
+            unix_time: position.unix_time,
+            slot: position.slot,
+            kind,
+            pubkey: position.position.clone(),
+            owner: position.owner.clone(),
+            market: position.market.clone(),
+            side: position.side.clone(),
+            size_usd: position.size_usd,
+            collateral_usd: position.collateral_usd,
+        }
+    }
+}
+
+/// The position changes `snapshot` found against the previous one, none for the first
+pub fn position_events(snapshot: &Snapshot) -> Vec<PositionEvent> {
+    let Some(lifecycle) = &snapshot.lifecycle else {
+        return vec![];
+    };
+    lifecycle
+        .positions
+        .iter()
+        .map(|change| {
+            let kind = match change.transition {
+                Transition::Opened => ChangeKind::Opened,
+                Transition::Increased | Transition::Decreased => ChangeKind::Resized,
+                Transition::CollateralChanged => ChangeKind::CollateralChanged,
+                Transition::Closed | Transition::Liquidated => ChangeKind::Closed,
+            };
+            PositionEvent::new(kind, &change.position)
+        })
+        .collect()
+}
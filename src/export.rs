@@ -0,0 +1,116 @@
+use std::collections::{HashMap, HashSet};
+
+use crate::store::{
+    append_records, child_path, read_existing_records, read_records, CustodyRecord, MarketRecord,
+    PositionRecord, Record, SnapshotRecord, WalletRecord,
+};
+
+/// Snapshots appended per write, and so at most redone after an interruption
+const BATCH_SIZE: usize = 10_000;
+
+/// Identifies a snapshot and the child rows taken with it
+type SnapshotKey = (u64, u64);
+
+fn key<R: Record>(record: &R) -> SnapshotKey {
+    (record.unix_time(), record.slot())
+}
+
+/// The rows of one child table to export, by the snapshot they belong to
+struct ChildTable<R: Record> {
+    output: String,
+    rows: HashMap<SnapshotKey, Vec<R>>,
+    /// Snapshots whose rows the output already holds
+    exported: HashSet<SnapshotKey>,
+    appended: usize,
+}
+
+impl<R: Record> ChildTable<R> {
+    fn open(input: &str, output: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = child_path(output, R::TABLE);
+        let mut rows: HashMap<SnapshotKey, Vec<R>> = HashMap::new();
+        for record in read_existing_records::<R>(&child_path(input, R::TABLE))? {
+            rows.entry(key(&record)).or_default().push(record);
+        }
+        let exported = read_existing_records::<R>(&output)?
+            .iter()
+            .map(key)
+            .collect();
+        Ok(ChildTable {
+            output,
+            rows,
+            exported,
+            appended: 0,
+        })
+    }
+
+    /// Appends the rows of `snapshots` the output doesn't hold yet
+    fn append(&mut self, snapshots: &[SnapshotRecord]) -> Result<(), Box<dyn std::error::Error>> {
+        let mut batch = vec![];
+        for snapshot in snapshots {
+            let snapshot_key = key(snapshot);
+            if self.exported.contains(&snapshot_key) {
+                continue;
+            }
+            if let Some(rows) = self.rows.remove(&snapshot_key) {
+                batch.extend(rows);
+            }
+        }
+        if !batch.is_empty() {
+            append_records(&self.output, &batch)?;
+            self.appended += batch.len();
+        }
+        Ok(())
+    }
+}
+
+/// Copies the snapshots taken within `from..=to` from one history file to another, with
+/// their custody, market, position and wallet rows. Snapshots the output already holds, by
+/// unix time and slot, are skipped, so rerunning an interrupted export resumes it instead
+/// of duplicating what was appended.
+pub fn run(
+    input: &str,
+    output: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let exported: HashSet<SnapshotKey> = read_existing_records::<SnapshotRecord>(output)?
+        .iter()
+        .map(key)
+        .collect();
+    let mut records: Vec<SnapshotRecord> = read_records::<SnapshotRecord>(input)?
+        .into_iter()
+        .filter(|record| from.map_or(true, |from| record.unix_time() >= from))
+        .filter(|record| to.map_or(true, |to| record.unix_time() <= to))
+        .collect();
+    let num_selected = records.len();
+    records.retain(|record| !exported.contains(&key(record)));
+    records.sort_by_key(|record| record.unix_time());
+    if records.len() < num_selected {
+        println!(
+            "Skipping {} snapshots already in {output}",
+            num_selected - records.len()
+        );
+    }
+
+    let mut custodies = ChildTable::<CustodyRecord>::open(input, output)?;
+    let mut markets = ChildTable::<MarketRecord>::open(input, output)?;
+    let mut positions = ChildTable::<PositionRecord>::open(input, output)?;
+    let mut wallets = ChildTable::<WalletRecord>::open(input, output)?;
+    // child rows go first, like a collection run writes them
+    for batch in records.chunks(BATCH_SIZE) {
+        custodies.append(batch)?;
+        markets.append(batch)?;
+        positions.append(batch)?;
+        wallets.append(batch)?;
+        append_records(output, batch)?;
+    }
+    println!(
+        "Exported {} snapshots from {input} to {output}, with {} custody, {} market, {} position and {} wallet rows",
+        records.len(),
+        custodies.appended,
+        markets.appended,
+        positions.appended,
+        wallets.appended,
+    );
+    Ok(())
+}
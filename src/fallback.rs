@@ -0,0 +1,153 @@
+//! HTTP price sources standing in for oracles that are stale or undecodable, so a single
+//! bad feed doesn't degrade the whole snapshot.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Sub as _;
+
+use clap::ValueEnum;
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::rpc::OraclePrice;
+use crate::state::PerpState;
+use crate::transport::blocking_client_with_headers;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum FallbackSource {
+    Coingecko,
+    /// Needs an API key, sent as the `X-API-KEY` header configured for its URL
+    Birdeye,
+}
+
+#[derive(Deserialize)]
+struct CoingeckoPrice {
+    usd: f64,
+}
+
+#[derive(Deserialize)]
+struct BirdeyeResponse {
+    data: HashMap<String, Option<BirdeyePrice>>,
+}
+
+#[derive(Deserialize)]
+struct BirdeyePrice {
+    value: f64,
+}
+
+impl FallbackSource {
+    pub fn name(self) -> &'static str {
+        match self {
+            FallbackSource::Coingecko => "coingecko",
+            FallbackSource::Birdeye => "birdeye",
+        }
+    }
+
+    /// API base URL, which configured headers are matched against
+    pub fn url(self) -> &'static str {
+        match self {
+            FallbackSource::Coingecko => "https://api.coingecko.com/api/v3",
+            FallbackSource::Birdeye => "https://public-api.birdeye.so",
+        }
+    }
+
+    /// USD prices of `mints`, leaving out those the source doesn't know
+    pub(crate) fn prices(
+        self,
+        client: &reqwest::blocking::Client,
+        mints: &[Pubkey],
+    ) -> Result<HashMap<Pubkey, f64>, Box<dyn std::error::Error>> {
+        let addresses: Vec<String> = mints.iter().map(Pubkey::to_string).collect();
+        let request = match self {
+            FallbackSource::Coingecko => client
+                .get(format!("{}/simple/token_price/solana", self.url()))
+                .query(&[
+                    ("contract_addresses", addresses.join(",")),
+                    ("vs_currencies", "usd".to_string()),
+                ]),
+            FallbackSource::Birdeye => client
+                .get(format!("{}/defi/multi_price", self.url()))
+                .header("x-chain", "solana")
+                .query(&[("list_address", addresses.join(","))]),
+        };
+        let response = request.send()?;
+        if !response.status().is_success() {
+            return Err(format!("{} responded {}", self.name(), response.status()).into());
+        }
+        let prices: Vec<(String, f64)> = match self {
+            FallbackSource::Coingecko => response
+                .json::<HashMap<String, CoingeckoPrice>>()?
+                .into_iter()
+                .map(|(address, price)| (address, price.usd))
+                .collect(),
+            FallbackSource::Birdeye => response
+                .json::<BirdeyeResponse>()?
+                .data
+                .into_iter()
+                .filter_map(|(address, price)| Some((address, price?.value)))
+                .collect(),
+        };
+        // CoinGecko may lowercase the addresses it echoes back
+        Ok(mints
+            .iter()
+            .filter_map(|mint| {
+                let address = mint.to_string();
+                prices
+                    .iter()
+                    .find(|(priced, _)| priced.eq_ignore_ascii_case(&address))
+                    .map(|(_, price)| (*mint, *price))
+            })
+            .collect())
+    }
+}
+
+/// Prices custodies whose oracle is stale or undecodable from `source` instead, keeping the
+/// oracle's publish time so the staleness is still reported
+pub fn apply(
+    state: PerpState,
+    source: FallbackSource,
+    proxy_url: Option<&str>,
+    headers: &BTreeMap<String, String>,
+) -> Result<PerpState, Box<dyn std::error::Error>> {
+    let unpriced: Vec<_> = state
+        .custodies
+        .values()
+        .filter(|custody_state| {
+            let price_age = (state.unix_time as i64).sub(custody_state.oracle.publish_time);
+            !custody_state.oracle.price.is_finite()
+                || price_age > custody_state.custody.oracle.max_price_age_sec as i64
+        })
+        .map(|custody_state| (custody_state.pubkey, custody_state.custody.mint))
+        .collect();
+    if unpriced.is_empty() {
+        return Ok(state);
+    }
+    let mints: Vec<Pubkey> = unpriced.iter().map(|(_, mint)| *mint).collect();
+    // an unreachable fallback leaves those custodies unpriced rather than failing the snapshot
+    let prices = match source.prices(&blocking_client_with_headers(proxy_url, headers)?, &mints) {
+        Ok(prices) => prices,
+        Err(err) => {
+            eprintln!(
+                "Fallback prices from {} failed, leaving {} custodies unpriced: {err}",
+                source.name(),
+                unpriced.len()
+            );
+            return Ok(state);
+        }
+    };
+    let oracle_prices: HashMap<Pubkey, OraclePrice> = unpriced
+        .iter()
+        .filter_map(|(custody, mint)| {
+            let price = *prices.get(mint)?;
+            Some((
+                *custody,
+                OraclePrice {
+                    price,
+                    ema_price: price,
+                    publish_time: state.custodies[custody].oracle.publish_time,
+                    fallback: Some(source.name()),
+                },
+            ))
+        })
+        .collect();
+    Ok(state.with_oracle_prices(&oracle_prices))
+}
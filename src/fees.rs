@@ -0,0 +1,200 @@
+//! Attributes the fees the protocol collected over trailing windows to position fees,
+//! borrow fees and the rest, mostly swaps, from what stored snapshots recorded.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use thousands::Separable;
+
+use crate::funding::{fees_by_window, MAX_SAMPLE_GAP_SECS};
+use crate::store::{
+    child_path, read_existing_records, read_records, CustodyRecord, FundingRecord, PositionRecord,
+    Record as _,
+};
+
+/// Sums `fee` over the consecutive sample pairs ending within each trailing window,
+/// skipping collector downtime
+fn sum_by_window<T>(
+    samples: &[(u64, T)],
+    now: u64,
+    windows: &[u64],
+    fee: impl Fn(&T, &T) -> f64,
+) -> Vec<f64> {
+    let mut fees = vec![0.0; windows.len()];
+    for pair in samples.windows(2) {
+        let ((start_time, start), (end_time, end)) = (&pair[0], &pair[1]);
+        if end_time - start_time > MAX_SAMPLE_GAP_SECS {
+            continue;
+        }
+        let pair_fee = fee(start, end);
+        for (total, window_hours) in fees.iter_mut().zip(windows) {
+            if *end_time > now.saturating_sub(window_hours * 3600) {
+                *total += pair_fee;
+            }
+        }
+    }
+    fees
+}
+
+/// Fees a custody collected between two samples of its fee reserves, in its token. A drop
+/// means the reserves were distributed in between, and only what has accrued since, the
+/// later sample, is known to have been collected.
+fn collected_between(start: f64, end: f64) -> f64 {
+    if end < start {
+        end
+    } else {
+        end.sub(start)
+    }
+}
+
+/// Fees collected per window in USD: growth of the custodies' fee reserves in their tokens,
+/// each interval's growth valued at the average price over the interval
+pub(crate) fn collected_by_window(rows: Vec<CustodyRecord>, now: u64, windows: &[u64]) -> Vec<f64> {
+    // (reserves in the custody's token, price)
+    let mut custodies: BTreeMap<String, Vec<(u64, (f64, f64))>> = BTreeMap::new();
+    for row in rows {
+        // rows from before the token amount was recorded valued it at their own price
+        let reserves = row
+            .fees_reserves
+            .unwrap_or_else(|| row.fees_reserves_usd.div(row.price));
+        if !reserves.is_finite() || !row.price.is_finite() {
+            continue;
+        }
+        custodies
+            .entry(row.custody)
+            .or_default()
+            .push((row.unix_time, (reserves, row.price)));
+    }
+    let mut fees = vec![0.0; windows.len()];
+    for mut samples in custodies.into_values() {
+        samples.sort_by_key(|(unix_time, _)| *unix_time);
+        let custody_fees = sum_by_window(
+            &samples,
+            now,
+            windows,
+            |(start_reserves, start_price), (end_reserves, end_price)| {
+                collected_between(*start_reserves, *end_reserves)
+                    .mul(start_price.add(*end_price).div(2.0))
+            },
+        );
+        for (total, fee) in fees.iter_mut().zip(custody_fees) {
+            *total += fee;
+        }
+    }
+    fees
+}
+
+/// Position size traded per window: every change in size between snapshots, from opens,
+/// closes and resizes
+pub(crate) fn volume_by_window(rows: Vec<PositionRecord>, now: u64, windows: &[u64]) -> Vec<f64> {
+    let mut snapshots: BTreeMap<u64, HashMap<String, f64>> = BTreeMap::new();
+    for row in rows {
+        snapshots
+            .entry(row.unix_time)
+            .or_default()
+            .insert(row.position, row.size_usd);
+    }
+    let samples: Vec<_> = snapshots.into_iter().collect();
+    sum_by_window(&samples, now, windows, |start, end| {
+        start
+            .keys()
+            .chain(end.keys().filter(|position| !start.contains_key(*position)))
+            .map(|position| {
+                let before = start.get(position).copied().unwrap_or_default();
+                let after = end.get(position).copied().unwrap_or_default();
+                after.sub(before).abs()
+            })
+            .sum()
+    })
+}
+
+/// Open and close fees per window, charged at `fee_bps` on every change in position size
+/// between snapshots
+fn position_fees_by_window(
+    rows: Vec<PositionRecord>,
+    now: u64,
+    windows: &[u64],
+    fee_bps: f64,
+) -> Vec<f64> {
+    volume_by_window(rows, now, windows)
+        .into_iter()
+        .map(|volume| volume.mul(fee_bps).div(10_000.0))
+        .collect()
+}
+
+/// Prints the fees collected over trailing windows of `windows` hours, ending at the latest
+/// snapshot in `history`, split into open/close fees from the recorded positions, borrow
+/// fees from `funding_history` and the remainder, and between JLP holders and the protocol
+/// if its share in BPS is known
+pub fn run(
+    history: &str,
+    funding_history: Option<&str>,
+    windows: &[u64],
+    position_fee_bps: f64,
+    protocol_share_bps: Option<f64>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let custody_rows = read_records::<CustodyRecord>(&child_path(history, CustodyRecord::TABLE))?;
+    let Some(now) = custody_rows.iter().map(|row| row.unix_time).max() else {
+        return Err(
+            format!("no custody rows for {history}, take snapshots with -c {history}").into(),
+        );
+    };
+    let collected = collected_by_window(custody_rows, now, windows);
+
+    let position_rows =
+        read_existing_records::<PositionRecord>(&child_path(history, PositionRecord::TABLE))?;
+    let position_fees = (!position_rows.is_empty())
+        .then(|| position_fees_by_window(position_rows, now, windows, position_fee_bps));
+
+    let borrow_fees = match funding_history {
+        Some(funding_history) => {
+            let mut series: BTreeMap<(String, String), Vec<FundingRecord>> = BTreeMap::new();
+            for record in read_records::<FundingRecord>(funding_history)? {
+                series
+                    .entry((record.market.clone(), record.side.clone()))
+                    .or_default()
+                    .push(record);
+            }
+            let mut fees = vec![0.0; windows.len()];
+            for mut samples in series.into_values() {
+                samples.sort_by_key(|record| record.unix_time());
+                for (total, fee) in fees.iter_mut().zip(fees_by_window(&samples, now, windows)) {
+                    *total += fee;
+                }
+            }
+            Some(fees)
+        }
+        None => None,
+    };
+
+    let usd = |value: f64| format!("${}", value.round().separate_with_commas());
+    let component = |fees: &Option<Vec<f64>>, i: usize| {
+        fees.as_ref().map_or("-".to_string(), |fees| usd(fees[i]))
+    };
+    println!("Unix time: {now}");
+    println!("Fees collected:");
+    for (i, window_hours) in windows.iter().enumerate() {
+        // swaps and anything else the estimates don't cover
+        let remainder = match (&position_fees, &borrow_fees) {
+            (Some(position_fees), Some(borrow_fees)) => {
+                usd(collected[i].sub(position_fees[i].add(borrow_fees[i])))
+            }
+            _ => "-".to_string(),
+        };
+        println!(
+            "  {window_hours:>4}h Total: {} Open/close: {} Borrow: {} Swaps and other: {remainder}",
+            usd(collected[i]),
+            component(&position_fees, i),
+            component(&borrow_fees, i),
+        );
+        if let Some(protocol_share_bps) = protocol_share_bps {
+            let protocol = collected[i].mul(protocol_share_bps).div(10_000.0);
+            println!(
+                "        To JLP holders: {} To the protocol: {}",
+                usd(collected[i].sub(protocol)),
+                usd(protocol),
+            );
+        }
+    }
+    Ok(())
+}
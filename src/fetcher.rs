@@ -0,0 +1,523 @@
+//! The RPC calls the loaders make, behind [`AccountFetcher`], so the pipeline can run
+//! against canned accounts in tests or through an in-memory cache as well as over RPC.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::Mutex;
+use std::time::{Duration, Instant};
+
+use base64::Engine;
+use solana_account_decoder::{UiAccountEncoding, UiDataSliceConfig};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_sdk::account::Account;
+use solana_sdk::instruction::Instruction;
+use solana_sdk::message::Message;
+use solana_sdk::pubkey::Pubkey;
+use solana_sdk::transaction::Transaction;
+
+/// Reads accounts and simulates instructions. Shared across the threads that page through
+/// positions, hence `Sync`.
+pub trait AccountFetcher: Sync {
+    /// Accounts owned by `program_id` whose data starts with `discriminator`, only those of
+    /// `data_size` bytes if given, with only `data_slice` of their data if given
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        discriminator: &[u8],
+        data_size: Option<u64>,
+        data_slice: Option<UiDataSliceConfig>,
+    ) -> Result<Vec<(Pubkey, Account)>, Box<dyn std::error::Error>>;
+
+    /// Each of `pubkeys`, or `None` where it doesn't exist
+    fn multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, Box<dyn std::error::Error>>;
+
+    fn slot(&self) -> Result<u64, Box<dyn std::error::Error>>;
+
+    /// Return data of simulating `instructions` paid for by `payer`
+    fn simulate(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>>;
+}
+
+impl AccountFetcher for RpcClient {
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        discriminator: &[u8],
+        data_size: Option<u64>,
+        data_slice: Option<UiDataSliceConfig>,
+    ) -> Result<Vec<(Pubkey, Account)>, Box<dyn std::error::Error>> {
+        let memcmp = RpcFilterType::Memcmp(Memcmp::new(
+            0,
+            MemcmpEncodedBytes::Bytes(discriminator.into()),
+        ));
+        let mut filters = vec![memcmp];
+        if let Some(data_size) = data_size {
+            filters.push(RpcFilterType::DataSize(data_size));
+        }
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                data_slice,
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        Ok(self.get_program_accounts_with_config(program_id, config)?)
+    }
+
+    fn multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, Box<dyn std::error::Error>> {
+        Ok(self.get_multiple_accounts(pubkeys)?)
+    }
+
+    fn slot(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(self.get_slot()?)
+    }
+
+    fn simulate(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        let transaction = Transaction::new_unsigned(Message::new_with_blockhash(
+            instructions,
+            Some(payer),
+            &self.get_latest_blockhash()?,
+        ));
+        let Some(return_data) = self.simulate_transaction(&transaction)?.value.return_data else {
+            return Ok(None);
+        };
+        Ok(Some(
+            base64::prelude::BASE64_STANDARD.decode(return_data.data.0)?,
+        ))
+    }
+}
+
+/// `data` cut to `data_slice`, as the RPC slices it
+fn slice(data: &[u8], data_slice: Option<UiDataSliceConfig>) -> Vec<u8> {
+    match data_slice {
+        Some(UiDataSliceConfig { offset, length }) => {
+            data.iter().skip(offset).take(length).copied().collect()
+        }
+        None => data.to_vec(),
+    }
+}
+
+/// Canned accounts and simulation results, for deterministic runs of the pipeline without
+/// a network
+#[derive(Default)]
+pub struct MockFetcher {
+    pub accounts: BTreeMap<Pubkey, Account>,
+    pub slot: u64,
+    /// Return data by the data of the first simulated instruction, e.g. its discriminator
+    pub return_data: HashMap<Vec<u8>, Vec<u8>>,
+}
+
+impl MockFetcher {
+    /// Adds an account owned by `owner` holding `data`
+    pub fn with_account(mut self, pubkey: Pubkey, owner: Pubkey, data: Vec<u8>) -> Self {
+        self.accounts.insert(
+            pubkey,
+            Account {
+                lamports: 1,
+                data,
+                owner,
+                executable: false,
+                rent_epoch: 0,
+            },
+        );
+        self
+    }
+}
+
+impl AccountFetcher for MockFetcher {
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        discriminator: &[u8],
+        data_size: Option<u64>,
+        data_slice: Option<UiDataSliceConfig>,
+    ) -> Result<Vec<(Pubkey, Account)>, Box<dyn std::error::Error>> {
+        Ok(self
+            .accounts
+            .iter()
+            .filter(|(_, account)| {
+                account.owner == *program_id
+                    && account.data.starts_with(discriminator)
+                    && data_size.map_or(true, |size| account.data.len() as u64 == size)
+            })
+            .map(|(pubkey, account)| {
+                let account = Account {
+                    data: slice(&account.data, data_slice),
+                    ..account.clone()
+                };
+                (*pubkey, account)
+            })
+            .collect())
+    }
+
+    fn multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, Box<dyn std::error::Error>> {
+        Ok(pubkeys
+            .iter()
+            .map(|pubkey| self.accounts.get(pubkey).cloned())
+            .collect())
+    }
+
+    fn slot(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        Ok(self.slot)
+    }
+
+    fn simulate(
+        &self,
+        instructions: &[Instruction],
+        _payer: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        Ok(instructions
+            .first()
+            .and_then(|instruction| self.return_data.get(&instruction.data))
+            .cloned())
+    }
+}
+
+type ProgramAccountsKey = (Pubkey, Vec<u8>, Option<u64>, Option<(usize, usize)>);
+
+/// Serves repeated reads from memory for `ttl`, e.g. across quick successive cycles.
+/// Simulations and the slot always go to the wrapped fetcher.
+pub struct CachingFetcher<F> {
+    inner: F,
+    ttl: Duration,
+    accounts: Mutex<HashMap<Pubkey, (Instant, Option<Account>)>>,
+    program_accounts: Mutex<HashMap<ProgramAccountsKey, (Instant, Vec<(Pubkey, Account)>)>>,
+}
+
+impl<F: AccountFetcher> CachingFetcher<F> {
+    pub fn new(inner: F, ttl: Duration) -> Self {
+        CachingFetcher {
+            inner,
+            ttl,
+            accounts: Mutex::default(),
+            program_accounts: Mutex::default(),
+        }
+    }
+
+    pub fn inner(&self) -> &F {
+        &self.inner
+    }
+}
+
+impl<F: AccountFetcher> AccountFetcher for CachingFetcher<F> {
+    fn program_accounts(
+        &self,
+        program_id: &Pubkey,
+        discriminator: &[u8],
+        data_size: Option<u64>,
+        data_slice: Option<UiDataSliceConfig>,
+    ) -> Result<Vec<(Pubkey, Account)>, Box<dyn std::error::Error>> {
+        let key = (
+            *program_id,
+            discriminator.to_vec(),
+            data_size,
+            data_slice.map(|data_slice| (data_slice.offset, data_slice.length)),
+        );
+        let now = Instant::now();
+        if let Some((fetched_at, accounts)) = self
+            .program_accounts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .get(&key)
+        {
+            if now.duration_since(*fetched_at) < self.ttl {
+                return Ok(accounts.clone());
+            }
+        }
+        let accounts =
+            self.inner
+                .program_accounts(program_id, discriminator, data_size, data_slice)?;
+        self.program_accounts
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .insert(key, (now, accounts.clone()));
+        Ok(accounts)
+    }
+
+    fn multiple_accounts(
+        &self,
+        pubkeys: &[Pubkey],
+    ) -> Result<Vec<Option<Account>>, Box<dyn std::error::Error>> {
+        let now = Instant::now();
+        let stale: Vec<Pubkey> = {
+            let cached = self.accounts.lock().unwrap_or_else(|err| err.into_inner());
+            pubkeys
+                .iter()
+                .filter(|pubkey| {
+                    cached.get(pubkey).map_or(true, |(fetched_at, _)| {
+                        now.duration_since(*fetched_at) >= self.ttl
+                    })
+                })
+                .copied()
+                .collect()
+        };
+        let fetched = if stale.is_empty() {
+            vec![]
+        } else {
+            self.inner.multiple_accounts(&stale)?
+        };
+        let mut cached = self.accounts.lock().unwrap_or_else(|err| err.into_inner());
+        for (pubkey, account) in stale.into_iter().zip(fetched) {
+            cached.insert(pubkey, (now, account));
+        }
+        Ok(pubkeys
+            .iter()
+            .map(|pubkey| cached.get(pubkey).and_then(|(_, account)| account.clone()))
+            .collect())
+    }
+
+    fn slot(&self) -> Result<u64, Box<dyn std::error::Error>> {
+        self.inner.slot()
+    }
+
+    fn simulate(
+        &self,
+        instructions: &[Instruction],
+        payer: &Pubkey,
+    ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+        self.inner.simulate(instructions, payer)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicUsize, Ordering};
+    use std::time::SystemTime;
+
+    use anchor_lang::AccountSerialize;
+
+    use super::*;
+    use crate::prices::{PriceProviders, PythPull};
+    use crate::rpc::program_id;
+    use crate::snapshot::Snapshot;
+    use crate::state::{load_open_positions, PerpState};
+
+    const SOL_MINT: &str = "So11111111111111111111111111111111111111112";
+    const USDC_MINT: &str = "EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v";
+
+    fn serialize(account: &impl AccountSerialize) -> Vec<u8> {
+        let mut data = vec![];
+        account.try_serialize(&mut data).unwrap();
+        data
+    }
+
+    /// A Pyth pull oracle `PriceUpdateV2` account quoting `price` now
+    fn price_update(price: f64) -> Vec<u8> {
+        let publish_time = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap()
+            .as_secs() as i64;
+        let price = (price * 1e8) as i64;
+        // discriminator, write authority and full verification level
+        let mut data = vec![0; 8 + 32 + 1];
+        data[8 + 32] = 1;
+        data.extend([0; 32]); // feed id
+        data.extend(price.to_le_bytes());
+        data.extend(0u64.to_le_bytes()); // confidence
+        data.extend((-8i32).to_le_bytes());
+        data.extend(publish_time.to_le_bytes());
+        data.extend(publish_time.to_le_bytes()); // previous publish time
+        data.extend(price.to_le_bytes()); // EMA price
+        data.extend(0u64.to_le_bytes()); // EMA confidence
+        data.extend(0u64.to_le_bytes()); // posted slot
+        data
+    }
+
+    /// A pool with SOL at $110 and USDC at $1, and a $1,000 SOL long opened at $100
+    fn fixture() -> MockFetcher {
+        let (pool_pubkey, sol, usdc) = (
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+            Pubkey::new_unique(),
+        );
+        let (sol_oracle, usdc_oracle) = (Pubkey::new_unique(), Pubkey::new_unique());
+        let oracle_owner = Pubkey::new_unique();
+
+        let pool = perp_abi::state::Pool {
+            name: "Pool".to_string(),
+            custodies: vec![sol, usdc],
+            ..Default::default()
+        };
+        let custody = |mint: &str, oracle_account: Pubkey, decimals: u8| {
+            let mut custody = perp_abi::state::Custody {
+                pool: pool_pubkey,
+                mint: mint.parse().unwrap(),
+                decimals,
+                ..Default::default()
+            };
+            custody.oracle.oracle_account = oracle_account;
+            custody.pricing.max_leverage = 1_000_000;
+            custody.assets.owned = 1_000_000_000_000;
+            custody
+        };
+        let position = perp_abi::state::Position {
+            owner: Pubkey::new_unique(),
+            pool: pool_pubkey,
+            custody: sol,
+            collateral_custody: sol,
+            open_time: 1_700_000_000,
+            update_time: 1_700_000_000,
+            side: perp_abi::Side::Long,
+            price: 100_000_000,
+            size_usd: 1_000_000_000,
+            collateral_usd: 100_000_000,
+            ..Default::default()
+        };
+
+        MockFetcher {
+            slot: 42,
+            ..Default::default()
+        }
+        .with_account(pool_pubkey, program_id(), serialize(&pool))
+        .with_account(
+            sol,
+            program_id(),
+            serialize(&custody(SOL_MINT, sol_oracle, 9)),
+        )
+        .with_account(
+            usdc,
+            program_id(),
+            serialize(&custody(USDC_MINT, usdc_oracle, 6)),
+        )
+        .with_account(Pubkey::new_unique(), program_id(), serialize(&position))
+        .with_account(sol_oracle, oracle_owner, price_update(110.0))
+        .with_account(usdc_oracle, oracle_owner, price_update(1.0))
+    }
+
+    #[test]
+    fn loads_state_from_mock_accounts() {
+        let fetcher = fixture();
+        // the oracles aren't legacy Pyth accounts, so the default provider can't price them
+        let state = PerpState::load(&fetcher).unwrap();
+        assert_eq!(state.slot, 42);
+        assert_eq!(state.custodies.len(), 2);
+        assert!(state
+            .custodies
+            .values()
+            .all(|custody| custody.price().is_nan()));
+
+        let providers = PriceProviders::new(Box::new(PythPull));
+        let state = PerpState::load_with_prices(&fetcher, None, &providers).unwrap();
+        let price = |symbol: &str| {
+            state
+                .custodies
+                .values()
+                .find(|custody| custody.symbol() == symbol)
+                .map(|custody| custody.price())
+        };
+        assert!((price("SOL").unwrap() - 110.0).abs() < 1e-9);
+        assert!((price("USDC").unwrap() - 1.0).abs() < 1e-9);
+    }
+
+    #[test]
+    fn missing_pool_is_an_error() {
+        let err = PerpState::load(&MockFetcher::default()).err().unwrap();
+        assert!(err.to_string().starts_with("no Pool account owned by"));
+    }
+
+    #[test]
+    fn computes_snapshot_from_mock_accounts() {
+        let fetcher = fixture();
+        let providers = PriceProviders::new(Box::new(PythPull));
+        let state = PerpState::load_with_prices(&fetcher, None, &providers).unwrap();
+        let positions = load_open_positions(&fetcher).unwrap();
+        assert_eq!(positions.len(), 1);
+
+        let snapshot = Snapshot::compute(&state, &positions);
+        assert_eq!(snapshot.slot, 42);
+        assert_eq!(snapshot.num_positions, 1);
+        assert_eq!(snapshot.num_longs, 1);
+        assert!((snapshot.cumulative_pnl - 100.0).abs() < 1e-6);
+        let sol = &snapshot.markets["SOL"];
+        assert_eq!((sol.num_longs, sol.num_shorts), (1, 0));
+        assert!((sol.long_oi - 1_100.0).abs() < 1e-6);
+    }
+
+    /// Counts the account reads reaching the wrapped fetcher
+    struct CountingFetcher {
+        inner: MockFetcher,
+        reads: AtomicUsize,
+    }
+
+    impl AccountFetcher for CountingFetcher {
+        fn program_accounts(
+            &self,
+            program_id: &Pubkey,
+            discriminator: &[u8],
+            data_size: Option<u64>,
+            data_slice: Option<UiDataSliceConfig>,
+        ) -> Result<Vec<(Pubkey, Account)>, Box<dyn std::error::Error>> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner
+                .program_accounts(program_id, discriminator, data_size, data_slice)
+        }
+
+        fn multiple_accounts(
+            &self,
+            pubkeys: &[Pubkey],
+        ) -> Result<Vec<Option<Account>>, Box<dyn std::error::Error>> {
+            self.reads.fetch_add(1, Ordering::Relaxed);
+            self.inner.multiple_accounts(pubkeys)
+        }
+
+        fn slot(&self) -> Result<u64, Box<dyn std::error::Error>> {
+            self.inner.slot()
+        }
+
+        fn simulate(
+            &self,
+            instructions: &[Instruction],
+            payer: &Pubkey,
+        ) -> Result<Option<Vec<u8>>, Box<dyn std::error::Error>> {
+            self.inner.simulate(instructions, payer)
+        }
+    }
+
+    #[test]
+    fn caching_fetcher_serves_repeated_reads_from_memory() {
+        let fetcher = CachingFetcher::new(
+            CountingFetcher {
+                inner: fixture(),
+                reads: AtomicUsize::new(0),
+            },
+            Duration::from_secs(60),
+        );
+        let providers = PriceProviders::new(Box::new(PythPull));
+        let first = PerpState::load_with_prices(&fetcher, None, &providers).unwrap();
+        let reads = fetcher.inner().reads.load(Ordering::Relaxed);
+        let second = PerpState::load_with_prices(&fetcher, None, &providers).unwrap();
+        assert_eq!(fetcher.inner().reads.load(Ordering::Relaxed), reads);
+        assert_eq!(first.custodies.len(), second.custodies.len());
+
+        let expired = CachingFetcher::new(
+            CountingFetcher {
+                inner: fixture(),
+                reads: AtomicUsize::new(0),
+            },
+            Duration::ZERO,
+        );
+        PerpState::load_with_prices(&expired, None, &providers).unwrap();
+        let reads = expired.inner().reads.load(Ordering::Relaxed);
+        PerpState::load_with_prices(&expired, None, &providers).unwrap();
+        assert_eq!(expired.inner().reads.load(Ordering::Relaxed), reads * 2);
+    }
+}
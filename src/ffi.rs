@@ -0,0 +1,77 @@
+//! C ABI for embedding the analytics in non-Rust services, built into the `cdylib`.
+//! The declarations are in `include/jpa.h`.
+
+use std::cell::RefCell;
+use std::ffi::{c_char, CStr, CString};
+
+use solana_client::rpc_client::RpcClient;
+
+use crate::snapshot;
+use crate::state::{load_open_positions, PerpState};
+
+thread_local! {
+    static LAST_ERROR: RefCell<Option<CString>> = RefCell::new(None);
+}
+
+fn set_last_error(message: String) {
+    let message = CString::new(message.replace('\0', "")).unwrap_or_default();
+    LAST_ERROR.with(|last_error| *last_error.borrow_mut() = Some(message));
+}
+
+fn snapshot_json(rpc_url: *const c_char) -> Result<String, Box<dyn std::error::Error>> {
+    if rpc_url.is_null() {
+        return Err("rpc_url is null".into());
+    }
+    // SAFETY: the caller passes a NUL-terminated string that outlives the call
+    let rpc_url = unsafe { CStr::from_ptr(rpc_url) }.to_str()?;
+    let connection = RpcClient::new(rpc_url.to_string());
+    let state = PerpState::load(&connection)?;
+    let positions = load_open_positions(&connection)?;
+    Ok(snapshot::to_json(&state, &positions)?.to_string())
+}
+
+/// Fetches a snapshot from `rpc_url` and returns it as a JSON string with `snapshot`,
+/// `custodies` and `positions`, to be released with `jpa_free_string`. Returns null on
+/// failure, see `jpa_last_error`.
+///
+/// # Safety
+///
+/// `rpc_url` must be null or a valid NUL-terminated string.
+#[no_mangle]
+pub unsafe extern "C" fn jpa_fetch_snapshot_json(rpc_url: *const c_char) -> *mut c_char {
+    let result = std::panic::catch_unwind(|| snapshot_json(rpc_url))
+        .unwrap_or_else(|_| Err("panicked while fetching the snapshot".into()))
+        .and_then(|json| Ok(CString::new(json)?));
+    match result {
+        Ok(json) => json.into_raw(),
+        Err(err) => {
+            set_last_error(err.to_string());
+            std::ptr::null_mut()
+        }
+    }
+}
+
+/// Message of the last failure on this thread, or null. Owned by the library and valid
+/// until the next call on the same thread.
+#[no_mangle]
+pub extern "C" fn jpa_last_error() -> *const c_char {
+    LAST_ERROR.with(|last_error| {
+        last_error
+            .borrow()
+            .as_ref()
+            .map_or(std::ptr::null(), |message| message.as_ptr())
+    })
+}
+
+/// Releases a string returned by `jpa_fetch_snapshot_json`.
+///
+/// # Safety
+///
+/// `json` must be null or a pointer returned by `jpa_fetch_snapshot_json` that hasn't been
+/// released yet.
+#[no_mangle]
+pub unsafe extern "C" fn jpa_free_string(json: *mut c_char) {
+    if !json.is_null() {
+        drop(CString::from_raw(json));
+    }
+}
@@ -0,0 +1,290 @@
+//! Position filter expressions such as `size_usd > 100000 && side == "long"`, evaluated
+//! against each open position before aggregation.
+//!
+//! Comparisons (`==`, `!=`, `>`, `>=`, `<`, `<=`) of a field with a literal combine with
+//! `&&`, `||`, `!` and parentheses. String comparisons ignore case.
+
+use std::ops::Div as _;
+use std::str::FromStr;
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::snapshot::PositionValuation;
+use crate::state::PerpState;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum FieldKind {
+    Number,
+    Text,
+}
+
+/// Fields positions can be filtered on
+const FIELDS: [(&str, FieldKind); 11] = [
+    ("size_usd", FieldKind::Number),
+    ("collateral_usd", FieldKind::Number),
+    ("entry_price", FieldKind::Number),
+    ("leverage", FieldKind::Number),
+    ("effective_leverage", FieldKind::Number),
+    ("open_time", FieldKind::Number),
+    ("update_time", FieldKind::Number),
+    ("side", FieldKind::Text),
+    ("market", FieldKind::Text),
+    ("owner", FieldKind::Text),
+    ("pubkey", FieldKind::Text),
+];
+
+#[derive(Clone, Debug, PartialEq)]
+enum Token {
+    Ident(String),
+    Number(f64),
+    Text(String),
+    Op(&'static str),
+    LeftParen,
+    RightParen,
+}
+
+const OPERATORS: [&str; 9] = ["&&", "||", "==", "!=", ">=", "<=", ">", "<", "!"];
+
+fn tokenize(expression: &str) -> Result<Vec<Token>, String> {
+    let mut tokens = vec![];
+    let mut rest = expression.trim_start();
+    while let Some(c) = rest.chars().next() {
+        if let Some(op) = OPERATORS.iter().find(|op| rest.starts_with(**op)) {
+            tokens.push(Token::Op(*op));
+            rest = &rest[op.len()..];
+        } else if c == '(' || c == ')' {
+            tokens.push(if c == '(' {
+                Token::LeftParen
+            } else {
+                Token::RightParen
+            });
+            rest = &rest[1..];
+        } else if c == '"' || c == '\'' {
+            let end = rest[1..]
+                .find(c)
+                .ok_or_else(|| format!("unterminated string in {rest}"))?;
+            tokens.push(Token::Text(rest[1..=end].to_string()));
+            rest = &rest[end + 2..];
+        } else if c.is_ascii_digit() || c == '-' || c == '.' {
+            let end = rest[1..]
+                .find(|c: char| !(c.is_ascii_digit() || c == '.' || c == '_'))
+                .map_or(rest.len(), |end| end + 1);
+            let number = rest[..end].replace('_', "");
+            tokens.push(Token::Number(
+                number
+                    .parse()
+                    .map_err(|_| format!("invalid number {}", &rest[..end]))?,
+            ));
+            rest = &rest[end..];
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            let end = rest
+                .find(|c: char| !(c.is_ascii_alphanumeric() || c == '_'))
+                .unwrap_or(rest.len());
+            tokens.push(Token::Ident(rest[..end].to_string()));
+            rest = &rest[end..];
+        } else {
+            return Err(format!("unexpected {c:?} in {rest}"));
+        }
+        rest = rest.trim_start();
+    }
+    Ok(tokens)
+}
+
+#[derive(Clone, Debug)]
+enum Literal {
+    Number(f64),
+    Text(String),
+}
+
+#[derive(Clone, Debug)]
+enum Expr {
+    Compare {
+        field: &'static str,
+        op: &'static str,
+        literal: Literal,
+    },
+    Not(Box<Expr>),
+    And(Box<Expr>, Box<Expr>),
+    Or(Box<Expr>, Box<Expr>),
+}
+
+/// Recursive descent over the token list, lowest precedence first
+struct Parser {
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser {
+    fn peek(&self) -> Option<&Token> {
+        self.tokens.get(self.position)
+    }
+
+    fn advance(&mut self) -> Option<Token> {
+        let token = self.tokens.get(self.position).cloned();
+        self.position += 1;
+        token
+    }
+
+    fn or(&mut self) -> Result<Expr, String> {
+        let mut expr = self.and()?;
+        while self.peek() == Some(&Token::Op("||")) {
+            self.advance();
+            expr = Expr::Or(Box::new(expr), Box::new(self.and()?));
+        }
+        Ok(expr)
+    }
+
+    fn and(&mut self) -> Result<Expr, String> {
+        let mut expr = self.unary()?;
+        while self.peek() == Some(&Token::Op("&&")) {
+            self.advance();
+            expr = Expr::And(Box::new(expr), Box::new(self.unary()?));
+        }
+        Ok(expr)
+    }
+
+    fn unary(&mut self) -> Result<Expr, String> {
+        match self.advance() {
+            Some(Token::Op("!")) => Ok(Expr::Not(Box::new(self.unary()?))),
+            Some(Token::LeftParen) => {
+                let expr = self.or()?;
+                match self.advance() {
+                    Some(Token::RightParen) => Ok(expr),
+                    _ => Err("missing closing parenthesis".to_string()),
+                }
+            }
+            Some(Token::Ident(name)) => self.comparison(&name),
+            Some(token) => Err(format!("expected a field, got {token:?}")),
+            None => Err("unexpected end of filter".to_string()),
+        }
+    }
+
+    fn comparison(&mut self, name: &str) -> Result<Expr, String> {
+        let &(field, kind) = FIELDS
+            .iter()
+            .find(|(field, _)| *field == name)
+            .ok_or_else(|| {
+                let fields: Vec<_> = FIELDS.iter().map(|(field, _)| *field).collect();
+                format!(
+                    "unknown field {name}, expected one of {}",
+                    fields.join(", ")
+                )
+            })?;
+        let op = match self.advance() {
+            Some(Token::Op(op)) if !["&&", "||", "!"].contains(&op) => op,
+            _ => return Err(format!("expected a comparison after {name}")),
+        };
+        let literal = match (self.advance(), kind) {
+            (Some(Token::Number(number)), FieldKind::Number) => Literal::Number(number),
+            (Some(Token::Text(text)), FieldKind::Text) => Literal::Text(text),
+            (Some(Token::Ident(text)), FieldKind::Text) => Literal::Text(text),
+            _ => return Err(format!("{name} must be compared with a {kind:?} value")),
+        };
+        if kind == FieldKind::Text && !["==", "!="].contains(&op) {
+            return Err(format!("{name} can only be compared with == or !="));
+        }
+        Ok(Expr::Compare { field, op, literal })
+    }
+}
+
+/// A parsed filter expression
+#[derive(Clone, Debug)]
+pub struct Filter {
+    expr: Expr,
+}
+
+impl FromStr for Filter {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            tokens: tokenize(expression)?,
+            position: 0,
+        };
+        let expr = parser.or()?;
+        if let Some(token) = parser.peek() {
+            return Err(format!("unexpected {token:?} after end of filter"));
+        }
+        Ok(Filter { expr })
+    }
+}
+
+impl Filter {
+    /// Whether the position passes the filter
+    pub fn matches(
+        &self,
+        state: &PerpState,
+        pubkey: &Pubkey,
+        position: &perp_abi::state::Position,
+    ) -> bool {
+        evaluate(&self.expr, state, pubkey, position)
+    }
+}
+
+fn evaluate(
+    expr: &Expr,
+    state: &PerpState,
+    pubkey: &Pubkey,
+    position: &perp_abi::state::Position,
+) -> bool {
+    match expr {
+        Expr::Not(expr) => !evaluate(expr, state, pubkey, position),
+        Expr::And(left, right) => {
+            evaluate(left, state, pubkey, position) && evaluate(right, state, pubkey, position)
+        }
+        Expr::Or(left, right) => {
+            evaluate(left, state, pubkey, position) || evaluate(right, state, pubkey, position)
+        }
+        Expr::Compare { field, op, literal } => match literal {
+            Literal::Number(expected) => {
+                let value = number_field(field, state, position);
+                match *op {
+                    "==" => value == *expected,
+                    "!=" => value != *expected,
+                    ">" => value > *expected,
+                    ">=" => value >= *expected,
+                    "<" => value < *expected,
+                    _ => value <= *expected,
+                }
+            }
+            Literal::Text(expected) => {
+                let equal =
+                    text_field(field, state, pubkey, position).eq_ignore_ascii_case(expected);
+                (*op == "==") == equal
+            }
+        },
+    }
+}
+
+fn number_field(field: &str, state: &PerpState, position: &perp_abi::state::Position) -> f64 {
+    let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+    let collateral_usd = spl_token::amount_to_ui_amount(position.collateral_usd, 6);
+    match field {
+        "size_usd" => size_usd,
+        "collateral_usd" => collateral_usd,
+        "entry_price" => spl_token::amount_to_ui_amount(position.price, 6),
+        "leverage" => size_usd.div(collateral_usd),
+        "effective_leverage" => PositionValuation::compute(state, position)
+            .map_or(f64::NAN, |valuation| valuation.effective_leverage()),
+        "open_time" => position.open_time as f64,
+        _ => position.update_time as f64,
+    }
+}
+
+fn text_field(
+    field: &str,
+    state: &PerpState,
+    pubkey: &Pubkey,
+    position: &perp_abi::state::Position,
+) -> String {
+    match field {
+        "side" => format!("{:?}", position.side),
+        "market" => state
+            .custodies
+            .get(&position.custody)
+            .map(|custody_state| custody_state.symbol())
+            .unwrap_or_default(),
+        "owner" => position.owner.to_string(),
+        _ => pubkey.to_string(),
+    }
+}
@@ -0,0 +1,210 @@
+//! Projects borrow rates over the next hours under a few utilization scenarios, to judge the
+//! cost of holding a leveraged position for a while.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::state::{
+    load_open_position_summaries, load_pending_position_requests, CustodyState, PerpState,
+};
+use crate::store::{child_path, read_records, CustodyRecord, Record as _};
+
+/// Hours of recorded utilization the trend is fitted over
+const TREND_WINDOW_HOURS: f64 = 24.0;
+/// Position size the projected fees are quoted for
+const QUOTE_SIZE_USD: f64 = 10_000.0;
+
+/// Utilization of every custody if `locked_delta` tokens (in native units) were locked on
+/// top of what already is, pooled across stablecoins like the on-chain rate
+fn utilization_after(
+    state: &PerpState,
+    locked_delta: &HashMap<Pubkey, f64>,
+) -> HashMap<Pubkey, f64> {
+    let locked = |pubkey: &Pubkey, locked: u64| {
+        (locked as f64)
+            .add(locked_delta.get(pubkey).copied().unwrap_or_default())
+            .max(0.0)
+    };
+    let (mut stable_locked, mut stable_owned) = (0.0, 0.0);
+    for custody_state in state.custodies.values() {
+        if custody_state.is_stable() {
+            stable_locked += locked(&custody_state.pubkey, custody_state.custody.assets.locked);
+            stable_owned += custody_state.custody.assets.owned as f64;
+        }
+    }
+    state
+        .custodies
+        .values()
+        .map(|custody_state| {
+            let (locked, owned) = if custody_state.is_stable() {
+                (stable_locked, stable_owned)
+            } else {
+                (
+                    locked(&custody_state.pubkey, custody_state.custody.assets.locked),
+                    custody_state.custody.assets.owned as f64,
+                )
+            };
+            let utilization = if owned > 0.0 {
+                locked.div(owned).min(1.0)
+            } else {
+                0.0
+            };
+            (custody_state.pubkey, utilization)
+        })
+        .collect()
+}
+
+/// Tokens pending requests would lock in each custody once executed, negative for decreases
+fn pending_locked_delta(
+    connection: &RpcClient,
+    state: &PerpState,
+) -> Result<HashMap<Pubkey, f64>, Box<dyn std::error::Error>> {
+    let position_sizes: HashMap<_, _> = load_open_position_summaries(connection)?
+        .into_iter()
+        .map(|position| (position.pubkey, position.size_usd))
+        .collect();
+    let mut delta: HashMap<Pubkey, f64> = HashMap::new();
+    for (_, request) in load_pending_position_requests(connection)? {
+        let Some(custody_state) = state.custodies.get(&request.collateral_custody) else {
+            continue;
+        };
+        let size_usd = if request.entire_position.unwrap_or_default() {
+            position_sizes
+                .get(&request.position)
+                .copied()
+                .unwrap_or_default()
+        } else {
+            request.size_usd_delta
+        };
+        // positions borrow their size from the collateral custody
+        let tokens = spl_token::amount_to_ui_amount(size_usd, 6)
+            .div(custody_state.price())
+            .mul(10_f64.powi(custody_state.custody.decimals as i32));
+        let sign = if let perp_abi::RequestChange::Increase = request.request_change {
+            1.0
+        } else {
+            -1.0
+        };
+        *delta.entry(custody_state.pubkey).or_default() += tokens.mul(sign);
+    }
+    Ok(delta)
+}
+
+/// Utilization change per hour fitted by least squares over the recent recorded rows of
+/// `market`
+fn utilization_trend(rows: &[CustodyRecord], market: &str, now: u64) -> Option<f64> {
+    let points: Vec<(f64, f64)> = rows
+        .iter()
+        .filter(|row| row.market == market)
+        .map(|row| {
+            (
+                (row.unix_time as f64).sub(now as f64).div(3600.0),
+                row.utilization,
+            )
+        })
+        .filter(|(hours, _)| *hours >= -TREND_WINDOW_HOURS)
+        .collect();
+    if points.len() < 3 {
+        return None;
+    }
+    let count = points.len() as f64;
+    let mean_hours = points
+        .iter()
+        .map(|(hours, _)| hours)
+        .sum::<f64>()
+        .div(count);
+    let mean_utilization = points
+        .iter()
+        .map(|(_, utilization)| utilization)
+        .sum::<f64>()
+        .div(count);
+    let covariance: f64 = points
+        .iter()
+        .map(|(hours, utilization)| hours.sub(mean_hours).mul(utilization.sub(mean_utilization)))
+        .sum();
+    let variance: f64 = points
+        .iter()
+        .map(|(hours, _)| hours.sub(mean_hours).powi(2))
+        .sum();
+    (variance > 0.0).then(|| covariance.div(variance))
+}
+
+/// Prints each market's borrow APR now and averaged over the next `hours` if nothing
+/// changes, if pending requests execute, and if the recorded utilization trend continues
+pub fn run(
+    connection: &RpcClient,
+    hours: f64,
+    history: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let after_requests = utilization_after(&state, &pending_locked_delta(connection, &state)?);
+    let history_rows = match history {
+        Some(history) => read_records::<CustodyRecord>(&child_path(history, CustodyRecord::TABLE))?,
+        None => vec![],
+    };
+
+    let apr = |custody_state: &CustodyState, utilization: f64| {
+        utilization
+            .clamp(0.0, 1.0)
+            .mul(custody_state.custody.funding_rate_state.hourly_funding_bps as f64)
+            .mul(24.0 * 365.0)
+            .div(100.0)
+    };
+    // fees on the quote size over the horizon at an annual rate in percent
+    let fees = |apr: f64| {
+        QUOTE_SIZE_USD
+            .mul(apr)
+            .div(100.0)
+            .mul(hours)
+            .div(24.0 * 365.0)
+    };
+
+    let mut markets = BTreeMap::new();
+    for custody_state in state.custodies.values() {
+        markets.insert(custody_state.symbol(), custody_state);
+    }
+
+    println!("Unix time: {}", state.unix_time);
+    println!("Borrow APR over the next {hours}h, and fees on a ${QUOTE_SIZE_USD} position:");
+    for (symbol, custody_state) in markets {
+        let now = apr(custody_state, custody_state.utilization);
+        let requests = apr(
+            custody_state,
+            after_requests
+                .get(&custody_state.pubkey)
+                .copied()
+                .unwrap_or(custody_state.utilization),
+        );
+        println!(
+            "  {symbol:<5} Utilization: {:.2}% Now: {now:.2}% (${:.2})",
+            custody_state.utilization.mul(100.0),
+            fees(now),
+        );
+        println!(
+            "        Requests execute: {requests:.2}% (${:.2})",
+            fees(requests)
+        );
+        match utilization_trend(&history_rows, &symbol, state.unix_time) {
+            Some(slope) => {
+                // the average over a linear path is its value halfway through
+                let trend = apr(
+                    custody_state,
+                    custody_state.utilization.add(slope.mul(hours).div(2.0)),
+                );
+                println!(
+                    "        Trend continues: {trend:.2}% (${:.2}), utilization {:+.2} pts/h",
+                    fees(trend),
+                    slope.mul(100.0),
+                );
+            }
+            None if history.is_some() => {
+                println!("        Trend continues: not enough recorded utilization")
+            }
+            None => {}
+        }
+    }
+    Ok(())
+}
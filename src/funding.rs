@@ -0,0 +1,114 @@
+//! Attributes borrow fees to longs and shorts per market by integrating the accrual rate
+//! recorded with each snapshot, to show which side is paying the pool.
+
+use std::collections::BTreeMap;
+use std::ops::{Div as _, Mul as _};
+
+use solana_sdk::pubkey::Pubkey;
+use thousands::Separable;
+
+use crate::state::PerpState;
+use crate::store::{read_records, FundingRecord};
+
+/// Intervals between samples longer than this are treated as collector downtime and not
+/// interpolated over
+pub(crate) const MAX_SAMPLE_GAP_SECS: u64 = 2 * 3600;
+
+/// Size and borrow accrual rate of each market side, for appending to the funding history
+pub fn funding_records(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+) -> Vec<FundingRecord> {
+    let mut sides: BTreeMap<(String, String), FundingRecord> = BTreeMap::new();
+    for (_, position) in positions {
+        let (Some(custody_state), Some(collateral_custody_state)) = (
+            state.custodies.get(&position.custody),
+            state.custodies.get(&position.collateral_custody),
+        ) else {
+            continue;
+        };
+        let side = format!("{:?}", position.side);
+        let record = sides
+            .entry((custody_state.symbol(), side.clone()))
+            .or_insert_with(|| FundingRecord {
+                unix_time: state.unix_time,
+                market: custody_state.symbol(),
+                side,
+                ..Default::default()
+            });
+        let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+        record.size_usd += size_usd;
+        // positions borrow from their collateral custody, as in the snapshot's fee estimate
+        record.borrow_usd_per_hour += collateral_custody_state
+            .borrow_rate
+            .mul(size_usd)
+            .div(10_000.0);
+    }
+    sides.into_values().collect()
+}
+
+/// Borrow fees paid by a market side within each trailing window
+pub(crate) fn fees_by_window(samples: &[FundingRecord], now: u64, windows: &[u64]) -> Vec<f64> {
+    let mut fees = vec![0.0; windows.len()];
+    for pair in samples.windows(2) {
+        let (start, end) = (&pair[0], &pair[1]);
+        if end.unix_time - start.unix_time > MAX_SAMPLE_GAP_SECS {
+            continue;
+        }
+        for (fee, window_hours) in fees.iter_mut().zip(windows) {
+            let window_start = now.saturating_sub(window_hours * 3600);
+            let covered = end
+                .unix_time
+                .saturating_sub(start.unix_time.max(window_start));
+            *fee += start.borrow_usd_per_hour.mul(covered as f64).div(3600.0);
+        }
+    }
+    fees
+}
+
+/// Prints estimated borrow fees paid by longs and shorts per market over trailing windows
+/// of `windows` hours, ending at the latest record in `funding_history`
+pub fn run(funding_history: &str, windows: &[u64]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut series: BTreeMap<(String, String), Vec<FundingRecord>> = BTreeMap::new();
+    for record in read_records::<FundingRecord>(funding_history)? {
+        series
+            .entry((record.market.clone(), record.side.clone()))
+            .or_default()
+            .push(record);
+    }
+    let Some(now) = series
+        .values()
+        .flatten()
+        .map(|record| record.unix_time)
+        .max()
+    else {
+        return Err(format!("no funding records in {funding_history}").into());
+    };
+
+    let mut markets: BTreeMap<String, BTreeMap<String, Vec<f64>>> = BTreeMap::new();
+    for ((market, side), mut samples) in series {
+        samples.sort_by_key(|record| record.unix_time);
+        markets
+            .entry(market)
+            .or_default()
+            .insert(side, fees_by_window(&samples, now, windows));
+    }
+
+    println!("Unix time: {now}");
+    println!("Estimated borrow fees paid by side:");
+    for (market, sides) in &markets {
+        println!("  {market}");
+        for (i, window_hours) in windows.iter().enumerate() {
+            let paid_by = |side: &str| sides.get(side).map_or(0.0, |fees| fees[i]);
+            let long = paid_by("Long");
+            let short = paid_by("Short");
+            println!(
+                "    {window_hours:>4}h Longs: ${} Shorts: ${} Long share: {:.1}%",
+                long.round().separate_with_commas(),
+                short.round().separate_with_commas(),
+                long.div(long + short).mul(100.0),
+            );
+        }
+    }
+    Ok(())
+}
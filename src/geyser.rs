@@ -0,0 +1,165 @@
+//! Yellowstone Geyser gRPC data source. The account view is seeded over RPC once, then
+//! perpetuals program and price account updates are streamed into it in the background,
+//! while snapshots are taken from it by the usual report loop.
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use futures::{SinkExt as _, StreamExt as _};
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use tokio::sync::watch;
+use yellowstone_grpc_client::GeyserGrpcClient;
+use yellowstone_grpc_proto::prelude::{
+    subscribe_update::UpdateOneof, CommitmentLevel, SubscribeRequest,
+    SubscribeRequestFilterAccounts,
+};
+
+use crate::prices::PriceProviders;
+use crate::rpc::program_id;
+use crate::state::PerpState;
+use crate::view::AccountView;
+
+/// Subscription to the perpetuals program's accounts and to `price_accounts`
+fn subscribe_request(price_accounts: &[Pubkey]) -> SubscribeRequest {
+    let mut accounts = HashMap::new();
+    accounts.insert(
+        "perpetuals".to_string(),
+        SubscribeRequestFilterAccounts {
+            account: vec![],
+            owner: vec![program_id().to_string()],
+            filters: vec![],
+        },
+    );
+    accounts.insert(
+        "prices".to_string(),
+        SubscribeRequestFilterAccounts {
+            account: price_accounts.iter().map(Pubkey::to_string).collect(),
+            owner: vec![],
+            filters: vec![],
+        },
+    );
+    SubscribeRequest {
+        accounts,
+        commitment: Some(CommitmentLevel::Confirmed as i32),
+        ..Default::default()
+    }
+}
+
+/// An account view kept current from a Geyser stream
+pub struct GeyserSource {
+    view: Arc<Mutex<AccountView>>,
+    /// Why the stream stopped, once it has
+    ended: Arc<Mutex<Option<String>>>,
+    /// Price accounts to stream, resubscribed to whenever they change
+    price_accounts: watch::Sender<Vec<Pubkey>>,
+}
+
+impl GeyserSource {
+    /// Seeds the view over RPC, with the accounts `providers` price from, and starts
+    /// streaming updates into it from `endpoint`
+    pub fn start(
+        connection: &RpcClient,
+        endpoint: &str,
+        x_token: Option<String>,
+        providers: &PriceProviders,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let view = AccountView::load(connection, providers)?;
+        let (price_accounts, watched) = watch::channel(providers.accounts(&view.custody_list()));
+        let view = Arc::new(Mutex::new(view));
+        let ended = Arc::new(Mutex::new(None));
+
+        let runtime = tokio::runtime::Runtime::new()?;
+        let (streamed, stream_ended) = (view.clone(), ended.clone());
+        let endpoint = endpoint.to_string();
+        std::thread::spawn(move || {
+            let reason = match runtime.block_on(stream(&endpoint, x_token, &streamed, watched)) {
+                Ok(()) => "geyser stream ended".to_string(),
+                Err(err) => format!("geyser stream failed: {err}"),
+            };
+            *stream_ended.lock().unwrap_or_else(|err| err.into_inner()) = Some(reason);
+        });
+        Ok(GeyserSource {
+            view,
+            ended,
+            price_accounts,
+        })
+    }
+
+    /// The state priced by `providers` and the open positions, as streamed so far. Price
+    /// accounts of custodies added or repointed since the last call are streamed from then
+    /// on.
+    pub fn load(
+        &self,
+        providers: &PriceProviders,
+    ) -> Result<(PerpState, Vec<(Pubkey, perp_abi::state::Position)>), Box<dyn std::error::Error>>
+    {
+        if let Some(reason) = self
+            .ended
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone()
+        {
+            return Err(reason.into());
+        }
+        let view = self
+            .view
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .clone();
+        let price_accounts = providers.accounts(&view.custody_list());
+        self.price_accounts.send_if_modified(|watched| {
+            let changed = *watched != price_accounts;
+            if changed {
+                *watched = price_accounts;
+            }
+            changed
+        });
+        Ok((view.state(providers)?, view.open_positions()))
+    }
+}
+
+/// Streams account updates into `view` until the stream ends or the source is dropped
+async fn stream(
+    endpoint: &str,
+    x_token: Option<String>,
+    view: &Mutex<AccountView>,
+    mut price_accounts: watch::Receiver<Vec<Pubkey>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = GeyserGrpcClient::connect(endpoint.to_string(), x_token, None)?;
+    let request = subscribe_request(&price_accounts.borrow_and_update());
+    let (mut subscribe_tx, mut stream) = client.subscribe_with_request(Some(request)).await?;
+
+    loop {
+        tokio::select! {
+            message = stream.next() => {
+                let Some(message) = message else {
+                    return Ok(());
+                };
+                let Some(UpdateOneof::Account(update)) = message?.update_oneof else {
+                    continue;
+                };
+                let Some(account) = update.account else {
+                    continue;
+                };
+                let pubkey = Pubkey::try_from(account.pubkey.as_slice())
+                    .map_err(|_| "invalid pubkey in account update")?;
+                let mut view = view.lock().unwrap_or_else(|err| err.into_inner());
+                view.slot = view.slot.max(update.slot);
+                if update.filters.iter().any(|filter| filter == "prices") {
+                    view.apply_price_account(pubkey, &account.data);
+                } else {
+                    view.apply_program_account(pubkey, &account.data)?;
+                }
+            }
+            changed = price_accounts.changed() => {
+                // the source was dropped
+                if changed.is_err() {
+                    return Ok(());
+                }
+                let request = subscribe_request(&price_accounts.borrow_and_update());
+                subscribe_tx.send(request).await?;
+            }
+        }
+    }
+}
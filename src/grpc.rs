@@ -0,0 +1,146 @@
+//! gRPC server streaming each new snapshot, and the position changes since the previous
+//! one, to subscribers. Snapshots are taken by the regular watch loop and fanned out over a
+//! broadcast channel; the server runs on its own thread.
+
+use std::net::SocketAddr;
+use std::pin::Pin;
+
+use futures::{Stream, StreamExt as _};
+use tokio::sync::broadcast;
+use tokio_stream::wrappers::BroadcastStream;
+use tonic::{Request, Response, Status};
+
+use crate::events::{position_events, ChangeKind, PositionEvent};
+use crate::snapshot::Snapshot;
+
+pub mod proto {
+    tonic::include_proto!("jupiter_perpetuals_analytics.v1");
+}
+
+use proto::position_event::Kind;
+use proto::snapshot_update::Update;
+use proto::snapshots_server::{Snapshots, SnapshotsServer};
+use proto::{SnapshotUpdate, SubscribeRequest};
+
+/// Updates a subscriber can fall behind by before missing some
+const CHANNEL_CAPACITY: usize = 256;
+
+/// Publishes snapshots to the subscribers of a running server
+pub struct Publisher {
+    sender: broadcast::Sender<SnapshotUpdate>,
+}
+
+fn to_proto(snapshot: &Snapshot) -> proto::Snapshot {
+    let record = snapshot.to_record();
+    proto::Snapshot {
+        total_pool_value: record.total_pool_value,
+        unrealized_pnl: record.unrealized_pnl,
+        total_fees: record.total_fees,
+        total_position_value: record.total_position_value,
+        total_collateral_value: record.total_collateral_value,
+        average_leverage_at_entry: record.average_leverage_at_entry,
+        average_effective_leverage: record.average_effective_leverage,
+        long_trades: record.long_trades,
+        long_value: record.long_value,
+        short_trades: record.short_trades,
+        short_value: record.short_value,
+        markets: snapshot
+            .markets
+            .iter()
+            .map(|(symbol, market)| {
+                (
+                    symbol.clone(),
+                    proto::Market {
+                        price: market.price,
+                        num_longs: market.num_longs,
+                        num_shorts: market.num_shorts,
+                        long_oi: market.long_oi,
+                        short_oi: market.short_oi,
+                        unrealized_pnl: market.unrealized_pnl,
+                    },
+                )
+            })
+            .collect(),
+    }
+}
+
+fn to_proto_event(event: PositionEvent) -> proto::PositionEvent {
+    let kind = match event.kind {
+        ChangeKind::Opened => Kind::Opened,
+        ChangeKind::Closed => Kind::Closed,
+        ChangeKind::Resized => Kind::Resized,
+        ChangeKind::CollateralChanged => Kind::CollateralChanged,
+    };
+    proto::PositionEvent {
+        kind: kind as i32,
+        pubkey: event.pubkey,
+        owner: event.owner,
+        market: event.market,
+        side: event.side,
+        size_usd: event.size_usd,
+        collateral_usd: event.collateral_usd,
+    }
+}
+
+impl Publisher {
+    /// Sends `snapshot` to every subscriber, preceded by the position changes it found
+    pub fn publish(&self, snapshot: &Snapshot) {
+        let updates = position_events(snapshot)
+            .into_iter()
+            .map(|event| Update::PositionEvent(to_proto_event(event)))
+            .chain([Update::Snapshot(to_proto(snapshot))]);
+        for update in updates {
+            // no subscribers isn't an error
+            let _ = self.sender.send(SnapshotUpdate {
+                unix_time: snapshot.unix_time,
+                slot: snapshot.slot,
+                update: Some(update),
+            });
+        }
+    }
+}
+
+struct SnapshotService {
+    sender: broadcast::Sender<SnapshotUpdate>,
+}
+
+#[tonic::async_trait]
+impl Snapshots for SnapshotService {
+    type SubscribeSnapshotsStream =
+        Pin<Box<dyn Stream<Item = Result<SnapshotUpdate, Status>> + Send + 'static>>;
+
+    async fn subscribe_snapshots(
+        &self,
+        request: Request<SubscribeRequest>,
+    ) -> Result<Response<Self::SubscribeSnapshotsStream>, Status> {
+        let include_position_events = request.into_inner().include_position_events;
+        // subscribers that fall behind skip the updates they missed
+        let stream = BroadcastStream::new(self.sender.subscribe()).filter_map(move |update| {
+            let update = update.ok().filter(|update| {
+                include_position_events || !matches!(update.update, Some(Update::PositionEvent(_)))
+            });
+            async move { update.map(Ok) }
+        });
+        Ok(Response::new(Box::pin(stream)))
+    }
+}
+
+/// Starts serving `SubscribeSnapshots` on `listen` in the background
+pub fn spawn(listen: SocketAddr) -> Result<Publisher, Box<dyn std::error::Error>> {
+    let (sender, _) = broadcast::channel(CHANNEL_CAPACITY);
+    let service = SnapshotService {
+        sender: sender.clone(),
+    };
+    let runtime = tokio::runtime::Runtime::new()?;
+    std::thread::spawn(move || {
+        let served = runtime.block_on(
+            tonic::transport::Server::builder()
+                .add_service(SnapshotsServer::new(service))
+                .serve(listen),
+        );
+        if let Err(err) = served {
+            eprintln!("gRPC server on {listen} stopped: {err}");
+        }
+    });
+    Ok(Publisher { sender })
+}
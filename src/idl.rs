@@ -0,0 +1,409 @@
+//! Decoding the perpetuals program's accounts with its Anchor IDL, fetched from chain at
+//! runtime, so fields that moved in a program upgrade still decode without a rebuild. Once
+//! an IDL is installed, the fields the tool reads are taken from the IDL decoding and
+//! anything it can't decode falls back to the compiled `perp_abi` layout.
+
+use std::collections::HashMap;
+use std::sync::OnceLock;
+
+use anchor_lang::AccountDeserialize;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+use solana_sdk::pubkey::Pubkey;
+
+/// IDL the account decoders consult, set once at startup
+static INSTALLED: OnceLock<Idl> = OnceLock::new();
+
+#[derive(Deserialize)]
+struct TypeDef {
+    name: String,
+    #[serde(rename = "type")]
+    ty: TypeDefKind,
+}
+
+#[derive(Deserialize)]
+#[serde(tag = "kind", rename_all = "lowercase")]
+enum TypeDefKind {
+    Struct {
+        #[serde(default)]
+        fields: Vec<Field>,
+    },
+    Enum {
+        variants: Vec<Variant>,
+    },
+}
+
+#[derive(Deserialize)]
+struct Field {
+    name: String,
+    /// Kept as JSON, the IDL's type grammar is decoded on the fly
+    #[serde(rename = "type")]
+    ty: Value,
+}
+
+#[derive(Deserialize)]
+struct Variant {
+    name: String,
+    /// Named fields as objects or tuple fields as bare types
+    #[serde(default)]
+    fields: Vec<Value>,
+}
+
+#[derive(Deserialize)]
+struct IdlJson {
+    #[serde(default)]
+    accounts: Vec<TypeDef>,
+    #[serde(default)]
+    types: Vec<TypeDef>,
+}
+
+/// Account and type definitions of an Anchor IDL
+pub struct Idl {
+    definitions: HashMap<String, TypeDefKind>,
+}
+
+impl Idl {
+    pub fn parse(json: &[u8]) -> Result<Self, Box<dyn std::error::Error>> {
+        let idl: IdlJson = serde_json::from_slice(json)?;
+        Ok(Idl {
+            definitions: idl
+                .types
+                .into_iter()
+                .chain(idl.accounts)
+                .map(|definition| (definition.name, definition.ty))
+                .collect(),
+        })
+    }
+
+    /// Fetches the IDL the program published at its canonical Anchor IDL account
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn fetch(
+        connection: &solana_client::rpc_client::RpcClient,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        use std::io::Read as _;
+
+        use crate::rpc::program_id;
+
+        let base = Pubkey::find_program_address(&[], &program_id()).0;
+        let address = Pubkey::create_with_seed(&base, "anchor:idl", &program_id())?;
+        let data = connection.get_account_data(&address)?;
+        // discriminator and authority, then the length of the zlib-compressed JSON
+        let len = data
+            .get(40..44)
+            .ok_or("perpetuals IDL account is truncated")?;
+        let len = u32::from_le_bytes(len.try_into()?) as usize;
+        let compressed = data
+            .get(44..44 + len)
+            .ok_or("perpetuals IDL account is truncated")?;
+        let mut json = vec![];
+        flate2::read::ZlibDecoder::new(compressed).read_to_end(&mut json)?;
+        Self::parse(&json)
+    }
+
+    /// Makes the account decoders below use this IDL for the rest of the process
+    pub fn install(self) -> Result<(), Box<dyn std::error::Error>> {
+        INSTALLED
+            .set(self)
+            .map_err(|_| "an IDL is already installed".into())
+    }
+
+    /// Decodes the account `name` from its data, discriminator included, into JSON with
+    /// 128-bit integers as strings
+    pub fn decode_account(
+        &self,
+        name: &str,
+        data: &[u8],
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut data = data
+            .get(8..)
+            .ok_or("account is shorter than its discriminator")?;
+        self.decode_defined(name, &mut data)
+    }
+
+    fn decode_defined(
+        &self,
+        name: &str,
+        data: &mut &[u8],
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        match self.definitions.get(name) {
+            Some(TypeDefKind::Struct { fields }) => self.decode_fields(fields, data),
+            Some(TypeDefKind::Enum { variants }) => {
+                let index = take(data, 1)?[0] as usize;
+                let variant = variants
+                    .get(index)
+                    .ok_or_else(|| format!("{name} has no variant {index}"))?;
+                let mut value = Map::new();
+                let fields = if variant.fields.iter().all(Value::is_object) {
+                    let fields = serde_json::from_value(Value::Array(variant.fields.clone()))?;
+                    self.decode_fields(&fields, data)?
+                } else {
+                    Value::Array(
+                        variant
+                            .fields
+                            .iter()
+                            .map(|ty| self.decode(ty, data))
+                            .collect::<Result<_, _>>()?,
+                    )
+                };
+                value.insert(variant.name.clone(), fields);
+                Ok(Value::Object(value))
+            }
+            None => Err(format!("IDL doesn't define {name}").into()),
+        }
+    }
+
+    fn decode_fields(
+        &self,
+        fields: &[Field],
+        data: &mut &[u8],
+    ) -> Result<Value, Box<dyn std::error::Error>> {
+        let mut value = Map::new();
+        for field in fields {
+            value.insert(field.name.clone(), self.decode(&field.ty, data)?);
+        }
+        Ok(Value::Object(value))
+    }
+
+    /// Decodes one Borsh value of IDL type `ty`
+    fn decode(&self, ty: &Value, data: &mut &[u8]) -> Result<Value, Box<dyn std::error::Error>> {
+        macro_rules! int {
+            ($int:ty) => {
+                <$int>::from_le_bytes(take(data, std::mem::size_of::<$int>())?.try_into()?)
+            };
+        }
+        let value = match ty {
+            Value::String(primitive) => match primitive.as_str() {
+                "bool" => Value::from(take(data, 1)?[0] != 0),
+                "u8" => Value::from(int!(u8)),
+                "i8" => Value::from(int!(i8)),
+                "u16" => Value::from(int!(u16)),
+                "i16" => Value::from(int!(i16)),
+                "u32" => Value::from(int!(u32)),
+                "i32" => Value::from(int!(i32)),
+                "u64" => Value::from(int!(u64)),
+                "i64" => Value::from(int!(i64)),
+                "f32" => Value::from(f32::from_bits(int!(u32)) as f64),
+                "f64" => Value::from(f64::from_bits(int!(u64))),
+                "u128" => Value::from(int!(u128).to_string()),
+                "i128" => Value::from(int!(i128).to_string()),
+                "publicKey" | "pubkey" => {
+                    Value::from(Pubkey::try_from(take(data, 32)?)?.to_string())
+                }
+                "string" => {
+                    let len = int!(u32) as usize;
+                    Value::from(String::from_utf8(take(data, len)?.to_vec())?)
+                }
+                "bytes" => {
+                    let len = int!(u32) as usize;
+                    Value::from(take(data, len)?.to_vec())
+                }
+                other => return Err(format!("unsupported IDL type {other}").into()),
+            },
+            Value::Object(compound) => {
+                if let Some(inner) = compound.get("vec") {
+                    let len = int!(u32) as usize;
+                    Value::Array(
+                        (0..len)
+                            .map(|_| self.decode(inner, data))
+                            .collect::<Result<_, _>>()?,
+                    )
+                } else if let Some(inner) = compound.get("option") {
+                    match take(data, 1)?[0] {
+                        0 => Value::Null,
+                        _ => self.decode(inner, data)?,
+                    }
+                } else if let Some(Value::Array(array)) = compound.get("array") {
+                    let (Some(inner), Some(len)) =
+                        (array.first(), array.get(1).and_then(Value::as_u64))
+                    else {
+                        return Err(format!("unsupported IDL array {ty}").into());
+                    };
+                    Value::Array(
+                        (0..len)
+                            .map(|_| self.decode(inner, data))
+                            .collect::<Result<_, _>>()?,
+                    )
+                } else if let Some(defined) = compound.get("defined") {
+                    // older IDLs name the type directly, newer ones nest it under "name"
+                    let name = defined
+                        .as_str()
+                        .or_else(|| defined.get("name").and_then(Value::as_str))
+                        .ok_or_else(|| format!("unsupported IDL type {ty}"))?;
+                    self.decode_defined(name, data)?
+                } else {
+                    return Err(format!("unsupported IDL type {ty}").into());
+                }
+            }
+            _ => return Err(format!("unsupported IDL type {ty}").into()),
+        };
+        Ok(value)
+    }
+}
+
+/// Whether an IDL was installed, so account layouts may differ from the compiled ones
+pub fn is_installed() -> bool {
+    INSTALLED.get().is_some()
+}
+
+fn take<'a>(data: &mut &'a [u8], len: usize) -> Result<&'a [u8], Box<dyn std::error::Error>> {
+    if data.len() < len {
+        return Err("account data ends early".into());
+    }
+    let (taken, rest) = data.split_at(len);
+    *data = rest;
+    Ok(taken)
+}
+
+/// The value at a dotted `path` of snake_case names, matching the camelCase of older IDLs too
+fn field<'a>(value: &'a Value, path: &str) -> Result<&'a Value, Box<dyn std::error::Error>> {
+    let normalize = |name: &str| name.replace('_', "").to_lowercase();
+    path.split('.').try_fold(value, |value, name| {
+        value
+            .as_object()
+            .and_then(|fields| {
+                fields
+                    .iter()
+                    .find(|(key, _)| normalize(key) == normalize(name))
+            })
+            .map(|(_, value)| value)
+            .ok_or_else(|| format!("IDL decoding has no field {path}").into())
+    })
+}
+
+fn uint(value: &Value, path: &str) -> Result<u128, Box<dyn std::error::Error>> {
+    match field(value, path)? {
+        Value::Number(number) => Ok(number.as_u64().ok_or("not an unsigned integer")?.into()),
+        Value::String(number) => Ok(number.parse()?),
+        other => Err(format!("{path} is {other}, not an integer").into()),
+    }
+}
+
+fn int(value: &Value, path: &str) -> Result<i128, Box<dyn std::error::Error>> {
+    match field(value, path)? {
+        Value::Number(number) => Ok(number.as_i64().ok_or("not an integer")?.into()),
+        Value::String(number) => Ok(number.parse()?),
+        other => Err(format!("{path} is {other}, not an integer").into()),
+    }
+}
+
+fn pubkey(value: &Value, path: &str) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    Ok(field(value, path)?
+        .as_str()
+        .ok_or_else(|| format!("{path} is not a pubkey"))?
+        .parse()?)
+}
+
+/// Decodes with the installed IDL, patching what it decodes over the compiled decoding, or
+/// with the compiled layout alone if no IDL is installed or it can't decode the account
+fn decode<T: AccountDeserialize + Clone + Default>(
+    name: &str,
+    data: &[u8],
+    patch: impl Fn(&Value, &mut T) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<T, Box<dyn std::error::Error>> {
+    let compiled = T::try_deserialize(&mut &*data);
+    let Some(idl) = INSTALLED.get() else {
+        return Ok(compiled?);
+    };
+    let patched = idl.decode_account(name, data).and_then(|value| {
+        let mut account = compiled.as_ref().ok().cloned().unwrap_or_default();
+        patch(&value, &mut account)?;
+        Ok(account)
+    });
+    match patched {
+        Ok(account) => Ok(account),
+        Err(_) => Ok(compiled?),
+    }
+}
+
+pub fn decode_pool(data: &[u8]) -> Result<perp_abi::state::Pool, Box<dyn std::error::Error>> {
+    decode("Pool", data, |value, pool: &mut perp_abi::state::Pool| {
+        pool.name = field(value, "name")?
+            .as_str()
+            .ok_or("name is not a string")?
+            .to_string();
+        pool.custodies = field(value, "custodies")?
+            .as_array()
+            .ok_or("custodies is not a list")?
+            .iter()
+            .map(|custody| Ok(custody.as_str().ok_or("not a pubkey")?.parse()?))
+            .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+        pool.aum_usd = uint(value, "aum_usd")? as _;
+        pool.limit.max_aum_usd = uint(value, "limit.max_aum_usd")? as _;
+        pool.limit.max_individual_lp_token = uint(value, "limit.max_individual_lp_token")? as _;
+        pool.limit.max_position_usd = uint(value, "limit.max_position_usd")? as _;
+        pool.fees.increase_position_bps = uint(value, "fees.increase_position_bps")? as _;
+        pool.fees.decrease_position_bps = uint(value, "fees.decrease_position_bps")? as _;
+        pool.fees.add_remove_liquidity_bps = uint(value, "fees.add_remove_liquidity_bps")? as _;
+        pool.fees.tax_bps = uint(value, "fees.tax_bps")? as _;
+        pool.fees.protocol_share_bps = uint(value, "fees.protocol_share_bps")? as _;
+        pool.pool_apr.fee_apr_bps = uint(value, "pool_apr.fee_apr_bps")? as _;
+        pool.pool_apr.realized_fee_usd = uint(value, "pool_apr.realized_fee_usd")? as _;
+        pool.max_request_execution_sec = int(value, "max_request_execution_sec")? as _;
+        Ok(())
+    })
+}
+
+pub fn decode_custody(data: &[u8]) -> Result<perp_abi::state::Custody, Box<dyn std::error::Error>> {
+    decode(
+        "Custody",
+        data,
+        |value, custody: &mut perp_abi::state::Custody| {
+            custody.pool = pubkey(value, "pool")?;
+            custody.mint = pubkey(value, "mint")?;
+            custody.token_account = pubkey(value, "token_account")?;
+            custody.decimals = uint(value, "decimals")? as _;
+            custody.target_ratio_bps = uint(value, "target_ratio_bps")? as _;
+            custody.oracle.oracle_account = pubkey(value, "oracle.oracle_account")?;
+            custody.oracle.max_price_age_sec = uint(value, "oracle.max_price_age_sec")? as _;
+            custody.pricing.trade_spread_long = uint(value, "pricing.trade_spread_long")? as _;
+            custody.pricing.trade_spread_short = uint(value, "pricing.trade_spread_short")? as _;
+            custody.pricing.swap_spread = uint(value, "pricing.swap_spread")? as _;
+            custody.pricing.max_leverage = uint(value, "pricing.max_leverage")? as _;
+            custody.pricing.max_global_long_sizes =
+                uint(value, "pricing.max_global_long_sizes")? as _;
+            custody.pricing.max_global_short_sizes =
+                uint(value, "pricing.max_global_short_sizes")? as _;
+            custody.assets.fees_reserves = uint(value, "assets.fees_reserves")? as _;
+            custody.assets.owned = uint(value, "assets.owned")? as _;
+            custody.assets.locked = uint(value, "assets.locked")? as _;
+            custody.funding_rate_state.cumulative_interest_rate =
+                uint(value, "funding_rate_state.cumulative_interest_rate")? as _;
+            custody.funding_rate_state.last_update =
+                int(value, "funding_rate_state.last_update")? as _;
+            custody.funding_rate_state.hourly_funding_bps =
+                uint(value, "funding_rate_state.hourly_funding_bps")? as _;
+            Ok(())
+        },
+    )
+}
+
+pub fn decode_position(
+    data: &[u8],
+) -> Result<perp_abi::state::Position, Box<dyn std::error::Error>> {
+    decode(
+        "Position",
+        data,
+        |value, position: &mut perp_abi::state::Position| {
+            position.owner = pubkey(value, "owner")?;
+            position.pool = pubkey(value, "pool")?;
+            position.custody = pubkey(value, "custody")?;
+            position.collateral_custody = pubkey(value, "collateral_custody")?;
+            position.open_time = int(value, "open_time")? as _;
+            position.update_time = int(value, "update_time")? as _;
+            position.side = match field(value, "side")? {
+                Value::Object(variant) if variant.contains_key("Long") => perp_abi::Side::Long,
+                Value::Object(variant) if variant.contains_key("Short") => perp_abi::Side::Short,
+                Value::Object(variant) if variant.contains_key("None") => perp_abi::Side::None,
+                other => return Err(format!("unknown side {other}").into()),
+            };
+            position.price = uint(value, "price")? as _;
+            position.size_usd = uint(value, "size_usd")? as _;
+            position.collateral_usd = uint(value, "collateral_usd")? as _;
+            position.realised_pnl_usd = int(value, "realised_pnl_usd")? as _;
+            position.cumulative_interest_snapshot =
+                uint(value, "cumulative_interest_snapshot")? as _;
+            position.locked_amount = uint(value, "locked_amount")? as _;
+            Ok(())
+        },
+    )
+}
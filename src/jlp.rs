@@ -0,0 +1,154 @@
+//! JLP, the pool's liquidity token: the fee for minting or redeeming it with a given token,
+//! which grows as the deposit or withdrawal pushes the custody further from its target
+//! weight, and its market price against NAV.
+
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use thousands::Separable;
+
+use crate::jupiter;
+use crate::rpc::program_id;
+use crate::snapshot::JlpPrice;
+use crate::state::PerpState;
+use crate::transport::blocking_client;
+
+/// JLP the market price is quoted for, enough that rounding to native units doesn't matter
+const PRICE_QUOTE_JLP: f64 = 100.0;
+/// JLP mint decimals
+const JLP_DECIMALS: u8 = 6;
+
+/// JLP mint of the pool at `pool_pubkey`
+pub fn lp_token_mint(pool_pubkey: &Pubkey) -> Pubkey {
+    Pubkey::find_program_address(&[b"lp_token_mint", pool_pubkey.as_ref()], &program_id()).0
+}
+
+/// JLP's NAV per token from the pool value and supply, and the USDC a sale of
+/// `PRICE_QUOTE_JLP` currently fetches through Jupiter per token
+pub fn price(
+    connection: &RpcClient,
+    state: &PerpState,
+    proxy_url: Option<&str>,
+) -> Result<JlpPrice, Box<dyn std::error::Error>> {
+    let mint = lp_token_mint(&state.pool_pubkey);
+    let supply = connection.get_token_supply(&mint)?;
+    let supply = spl_token::amount_to_ui_amount(supply.amount.parse()?, supply.decimals);
+    let usdc = state
+        .find_custody("USDC")
+        .ok_or("No USDC custody to quote JLP against")?;
+    let quote_amount = spl_token::ui_amount_to_amount(PRICE_QUOTE_JLP, JLP_DECIMALS);
+    let out_amount = jupiter::quote(
+        &blocking_client(proxy_url)?,
+        &mint,
+        &usdc.custody.mint,
+        quote_amount,
+    )?;
+    Ok(JlpPrice {
+        nav: state.total_pool_value().div(supply),
+        market_price: spl_token::amount_to_ui_amount(out_amount, usdc.custody.decimals)
+            .div(PRICE_QUOTE_JLP),
+    })
+}
+
+/// Fee in BPS for moving a custody from `current_weight` to `next_weight`, given its
+/// `target_weight`: discounted by the tax while it moves closer to the target, taxed on
+/// the average deviation otherwise
+fn fee_bps(
+    base_bps: f64,
+    tax_bps: f64,
+    current_weight: f64,
+    next_weight: f64,
+    target_weight: f64,
+) -> f64 {
+    if target_weight <= 0.0 {
+        return base_bps.add(tax_bps);
+    }
+    let initial_diff = current_weight.sub(target_weight).abs();
+    let next_diff = next_weight.sub(target_weight).abs();
+    if next_diff < initial_diff {
+        let rebate = tax_bps.mul(initial_diff).div(target_weight);
+        return base_bps.sub(rebate).max(0.0);
+    }
+    let average_diff = initial_diff.add(next_diff).div(2.0);
+    base_bps.add(tax_bps.mul(average_diff.div(target_weight).min(1.0)))
+}
+
+/// Prints the expected fee for minting JLP with `amount` of `token`, or for redeeming JLP
+/// into that amount if `redeem` is set, at the pool's current allocations
+pub fn fee(
+    connection: &RpcClient,
+    amount: f64,
+    token: &str,
+    redeem: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let custody_state = state
+        .find_custody(token)
+        .ok_or_else(|| format!("No custody found for {token}"))?;
+    let custody = &custody_state.custody;
+    let fees = &state.pool.fees;
+
+    let price = custody_state.price();
+    let amount_usd = amount.mul(price);
+    let owned_value =
+        spl_token::amount_to_ui_amount(custody.assets.owned, custody.decimals).mul(price);
+    let total_pool_value = state.total_pool_value();
+    let (next_owned_value, next_pool_value) = if redeem {
+        let available = spl_token::amount_to_ui_amount(
+            custody.assets.owned.saturating_sub(custody.assets.locked),
+            custody.decimals,
+        );
+        if amount > available {
+            return Err(format!(
+                "Only {available} {} is available to redeem",
+                custody_state.symbol()
+            )
+            .into());
+        }
+        (
+            owned_value.sub(amount_usd),
+            total_pool_value.sub(amount_usd),
+        )
+    } else {
+        (
+            owned_value.add(amount_usd),
+            total_pool_value.add(amount_usd),
+        )
+    };
+
+    let current_weight = owned_value.div(total_pool_value);
+    let next_weight = if next_pool_value > 0.0 {
+        next_owned_value.div(next_pool_value)
+    } else {
+        0.0
+    };
+    let target_weight = (custody.target_ratio_bps as f64).div(10_000.0);
+    let bps = fee_bps(
+        fees.add_remove_liquidity_bps as f64,
+        fees.tax_bps as f64,
+        current_weight,
+        next_weight,
+        target_weight,
+    );
+    let fee_usd = amount_usd.mul(bps).div(10_000.0);
+
+    println!(
+        "{} {amount} {} (${})",
+        if redeem { "Redeem" } else { "Mint" },
+        custody_state.symbol(),
+        amount_usd.round().separate_with_commas(),
+    );
+    println!(
+        "Weight: {:.2}% -> {:.2}% (target {:.2}%)",
+        current_weight.mul(100.0),
+        next_weight.mul(100.0),
+        target_weight.mul(100.0),
+    );
+    println!(
+        "Fee: {bps:.1} bps, ${fee_usd:.2} ({:.6} {})",
+        fee_usd.div(price),
+        custody_state.symbol(),
+    );
+    Ok(())
+}
@@ -0,0 +1,97 @@
+//! Jupiter swap aggregator API, for DEX prices to set against the pool's own.
+
+use std::collections::HashMap;
+
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::state::PerpState;
+use crate::transport::blocking_client;
+
+const QUOTE_API_URL: &str = "https://quote-api.jup.ag/v6/quote";
+const PRICE_API_URL: &str = "https://api.jup.ag/price/v2";
+
+#[derive(Deserialize)]
+struct PriceResponse {
+    /// Keyed by mint, null for mints without a price
+    data: HashMap<String, Option<MintPrice>>,
+}
+
+#[derive(Deserialize)]
+struct MintPrice {
+    /// USD, as a string
+    price: String,
+}
+
+#[derive(Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct QuoteResponse {
+    /// Native units, as a string
+    out_amount: String,
+}
+
+/// Native units of `output_mint` the best route currently gives for `amount` native units of
+/// `input_mint`
+pub fn quote(
+    client: &reqwest::blocking::Client,
+    input_mint: &Pubkey,
+    output_mint: &Pubkey,
+    amount: u64,
+) -> Result<u64, Box<dyn std::error::Error>> {
+    let response = client
+        .get(QUOTE_API_URL)
+        .query(&[
+            ("inputMint", input_mint.to_string()),
+            ("outputMint", output_mint.to_string()),
+            ("amount", amount.to_string()),
+        ])
+        .send()?;
+    if !response.status().is_success() {
+        return Err(format!("Jupiter quote API responded {}", response.status()).into());
+    }
+    Ok(response.json::<QuoteResponse>()?.out_amount.parse()?)
+}
+
+/// USD spot prices of `mints` derived from DEX liquidity, leaving out mints Jupiter has no
+/// price for
+pub fn prices(
+    client: &reqwest::blocking::Client,
+    mints: &[Pubkey],
+) -> Result<HashMap<Pubkey, f64>, Box<dyn std::error::Error>> {
+    let ids: Vec<String> = mints.iter().map(Pubkey::to_string).collect();
+    let response = client
+        .get(PRICE_API_URL)
+        .query(&[("ids", ids.join(","))])
+        .send()?;
+    if !response.status().is_success() {
+        return Err(format!("Jupiter price API responded {}", response.status()).into());
+    }
+    let mut prices = HashMap::new();
+    for (mint, price) in response.json::<PriceResponse>()?.data {
+        if let Some(price) = price {
+            prices.insert(mint.parse()?, price.price.parse()?);
+        }
+    }
+    Ok(prices)
+}
+
+/// DEX spot price of every custody's token, keyed by market symbol
+pub fn market_prices(
+    state: &PerpState,
+    proxy_url: Option<&str>,
+) -> Result<HashMap<String, f64>, Box<dyn std::error::Error>> {
+    let mints: Vec<Pubkey> = state
+        .custodies
+        .values()
+        .map(|custody_state| custody_state.custody.mint)
+        .collect();
+    let prices = prices(&blocking_client(proxy_url)?, &mints)?;
+    Ok(state
+        .custodies
+        .values()
+        .filter_map(|custody_state| {
+            let price = prices.get(&custody_state.custody.mint)?;
+            Some((custody_state.symbol(), *price))
+        })
+        .collect())
+}
@@ -0,0 +1,163 @@
+//! Owners ranked by the unrealized P&L of all their open positions, and by that P&L as a
+//! return on the collateral they put up, leaving out dust accounts.
+
+use std::collections::HashMap;
+use std::ops::{Div as _, Mul as _};
+
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+use thousands::Separable;
+
+use crate::snapshot::PositionValuation;
+use crate::state::{load_open_positions, PerpState};
+use crate::table;
+
+#[derive(Default)]
+struct Owner {
+    positions: u64,
+    /// Size at entry
+    size_usd: f64,
+    /// Collateral at entry
+    collateral_usd: f64,
+    unrealized_pnl: f64,
+}
+
+impl Owner {
+    /// Return on equity: paper P&L over collateral put up
+    fn roe(&self) -> f64 {
+        self.unrealized_pnl.div(self.collateral_usd)
+    }
+}
+
+/// Sums each owner's open positions, keeping owners with at least `min_size` of size and
+/// `min_collateral` of collateral
+fn owners(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+    min_size: f64,
+    min_collateral: f64,
+) -> Vec<(Pubkey, Owner)> {
+    let mut owners: HashMap<Pubkey, Owner> = HashMap::new();
+    for (_, position) in positions {
+        let Some(valuation) = PositionValuation::compute(state, position) else {
+            continue;
+        };
+        let owner = owners.entry(position.owner).or_default();
+        owner.positions += 1;
+        owner.size_usd += valuation.value_at_entry;
+        owner.collateral_usd += spl_token::amount_to_ui_amount(position.collateral_usd, 6);
+        owner.unrealized_pnl += valuation.unrealized_pnl;
+    }
+    owners
+        .into_iter()
+        .filter(|(_, owner)| owner.size_usd >= min_size && owner.collateral_usd >= min_collateral)
+        .collect()
+}
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+/// Writes every owner with both ranks as CSV, by P&L
+fn write_csv(owners: &[(Pubkey, Owner)]) -> Result<(), Box<dyn std::error::Error>> {
+    let mut by_roe: Vec<usize> = (0..owners.len()).collect();
+    by_roe.sort_by(|a, b| owners[*b].1.roe().total_cmp(&owners[*a].1.roe()));
+    let mut roe_ranks = vec![0; owners.len()];
+    for (rank, i) in by_roe.into_iter().enumerate() {
+        roe_ranks[i] = rank + 1;
+    }
+
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    writer.write_record([
+        "owner",
+        "positions",
+        "size_usd",
+        "collateral_usd",
+        "unrealized_pnl",
+        "roe",
+        "pnl_rank",
+        "roe_rank",
+    ])?;
+    for (i, (pubkey, owner)) in owners.iter().enumerate() {
+        writer.write_record([
+            pubkey.to_string(),
+            owner.positions.to_string(),
+            owner.size_usd.to_string(),
+            owner.collateral_usd.to_string(),
+            owner.unrealized_pnl.to_string(),
+            owner.roe().to_string(),
+            (i + 1).to_string(),
+            roe_ranks[i].to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+fn print_table(title: &str, owners: &[&(Pubkey, Owner)]) {
+    println!("{title}:");
+    let rows = owners
+        .iter()
+        .enumerate()
+        .map(|(i, (pubkey, owner))| {
+            vec![
+                (i + 1).to_string(),
+                pubkey.to_string(),
+                owner.positions.to_string(),
+                usd(owner.size_usd),
+                usd(owner.collateral_usd),
+                usd(owner.unrealized_pnl),
+                format!("{:+.2}%", owner.roe().mul(100.0)),
+            ]
+        })
+        .collect();
+    println!(
+        "{}",
+        table::render(
+            &[
+                "#",
+                "Owner",
+                "Positions",
+                "Size",
+                "Collateral",
+                "P&L",
+                "ROE"
+            ],
+            rows
+        )
+    );
+}
+
+/// Prints the `limit` owners with the highest unrealized P&L and ROE, ignoring those with
+/// less than `min_size` of positions or `min_collateral` of collateral. With `csv`, every
+/// remaining owner is written, as CSV.
+pub fn run(
+    connection: &RpcClient,
+    limit: usize,
+    min_size: f64,
+    min_collateral: f64,
+    csv: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let positions = load_open_positions(connection)?;
+    let mut owners = owners(&state, &positions, min_size, min_collateral);
+    owners.sort_by(|(_, a), (_, b)| b.unrealized_pnl.total_cmp(&a.unrealized_pnl));
+    if csv {
+        return write_csv(&owners);
+    }
+
+    println!("Unix time: {}", state.unix_time);
+    println!(
+        "{} owners with at least {} of positions and {} of collateral",
+        owners.len(),
+        usd(min_size),
+        usd(min_collateral)
+    );
+    let by_pnl: Vec<_> = owners.iter().take(limit).collect();
+    print_table("By unrealized P&L", &by_pnl);
+    let mut by_roe: Vec<_> = owners.iter().collect();
+    by_roe.sort_by(|(_, a), (_, b)| b.roe().total_cmp(&a.roe()));
+    by_roe.truncate(limit);
+    print_table("By ROE", &by_roe);
+    Ok(())
+}
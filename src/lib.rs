@@ -0,0 +1,134 @@
+//! Collects analytics about Jupiter perpetuals usage: decodes the pool, custody and
+//! position accounts, aggregates them into snapshots and ships those to storage and
+//! monitoring backends. The `jupiter_perpetuals_analytics` binary is a CLI over this library.
+//!
+//! On wasm32 only the decoding and aggregation core is built, see [`wasm`]; modules that
+//! talk to an RPC node or the OS are left out.
+
+#[cfg(not(target_arch = "wasm32"))]
+pub mod alerts;
+pub mod analyze;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod anomaly;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod bench;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod cache;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod checkpoint;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod client;
+pub mod columns;
+pub mod compact;
+pub mod config;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod consensus;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod custody;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod discord;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod doctor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod elasticsearch;
+#[cfg(feature = "email")]
+pub mod email;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod event_stream;
+pub mod events;
+pub mod export;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fallback;
+pub mod fees;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod fetcher;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod ffi;
+pub mod filter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod forecast;
+pub mod funding;
+#[cfg(feature = "geyser")]
+pub mod geyser;
+#[cfg(feature = "grpc")]
+pub mod grpc;
+pub mod idl;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jlp;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod jupiter;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod leaderboard;
+pub mod lifecycle;
+pub mod liquidations;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod live;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod lock;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod metadata;
+pub mod metrics;
+#[cfg(feature = "mqtt")]
+pub mod mqtt;
+#[cfg(feature = "nats")]
+pub mod nats;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod orders;
+#[cfg(feature = "plugins")]
+pub mod plugin;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pool;
+pub mod porcelain;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod prices;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pushgateway;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod pyth;
+#[cfg(feature = "python")]
+mod python;
+#[cfg(feature = "duckdb")]
+pub mod query;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod risk;
+pub mod rpc;
+pub mod sanity;
+pub mod schedule;
+#[cfg(feature = "scripting")]
+pub mod script;
+#[cfg(feature = "graphql")]
+pub mod serve;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod signals;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod sink;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod slippage;
+pub mod snapshot;
+pub mod state;
+pub mod statsd;
+pub mod store;
+pub mod style;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod summary;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod supervisor;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod table;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod telegram;
+pub mod template;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod top_positions;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod transport;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod twitter;
+#[cfg(feature = "geyser")]
+pub mod view;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod wallets;
+#[cfg(target_arch = "wasm32")]
+pub mod wasm;
+#[cfg(not(target_arch = "wasm32"))]
+pub mod watch;
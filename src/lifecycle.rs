@@ -0,0 +1,228 @@
+//! Position lifecycle between snapshots: an index of the open positions keyed by account,
+//! which each new snapshot's positions are classified against as opened, increased,
+//! decreased, closed or suspected liquidated. With an index file this carries across runs,
+//! so snapshots taken by separate invocations are compared too. The changes found are also
+//! what the signal feed and the event sinks publish.
+
+use std::collections::BTreeMap;
+use std::fs;
+use std::ops::{Mul as _, Sub as _};
+use std::path::PathBuf;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::snapshot::position_records;
+use crate::state::PerpState;
+use crate::store::PositionRecord;
+
+/// Share of its collateral a closed position must have been losing, at the price of the
+/// snapshot it disappeared in, to be suspected liquidated
+pub const LIQUIDATION_LOSS_SHARE: f64 = 0.9;
+
+/// Whether `position` had lost most of its collateral at `price`, so its closing was
+/// likely a liquidation
+pub fn likely_liquidated(position: &PositionRecord, price: f64) -> bool {
+    let at_close = PositionRecord {
+        price,
+        ..position.clone()
+    };
+    position.collateral_usd.sub(at_close.remaining_collateral())
+        >= position.collateral_usd.mul(LIQUIDATION_LOSS_SHARE)
+}
+
+#[derive(Clone, Copy, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct ChangeTotals {
+    pub count: u64,
+    /// Size added or removed, in USD at entry prices
+    pub notional_usd: f64,
+}
+
+impl ChangeTotals {
+    fn add(&mut self, notional_usd: f64) {
+        self.count += 1;
+        self.notional_usd += notional_usd;
+    }
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Transition {
+    Opened,
+    Increased,
+    Decreased,
+    /// Same size with collateral added or removed
+    CollateralChanged,
+    Closed,
+    /// Closed having lost most of its collateral at the snapshot's price
+    Liquidated,
+}
+
+/// A position that changed since the previous snapshot
+#[derive(Clone, Debug)]
+pub struct PositionChange {
+    pub transition: Transition,
+    /// The position after the change, or before closing valued at the snapshot's price
+    pub position: PositionRecord,
+    /// Size before the change, zero when opened
+    pub previous_size_usd: f64,
+}
+
+/// Position changes since the previous snapshot the index saw
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct LifecycleChanges {
+    /// When the snapshot compared against was taken
+    pub since: u64,
+    pub opened: ChangeTotals,
+    pub increased: ChangeTotals,
+    pub decreased: ChangeTotals,
+    /// Closed by the trader, or liquidated without losing enough to tell
+    pub closed: ChangeTotals,
+    /// Closed having lost most of their collateral at the snapshot's price
+    pub liquidated: ChangeTotals,
+    /// Each position that changed
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub positions: Vec<PositionChange>,
+}
+
+impl LifecycleChanges {
+    pub fn categories(&self) -> [(&'static str, ChangeTotals); 5] {
+        [
+            ("Opened", self.opened),
+            ("Increased", self.increased),
+            ("Decreased", self.decreased),
+            ("Closed", self.closed),
+            ("Liquidated (suspected)", self.liquidated),
+        ]
+    }
+}
+
+#[derive(Default, Serialize, Deserialize)]
+struct Index {
+    unix_time: u64,
+    positions: BTreeMap<String, PositionRecord>,
+}
+
+/// The open positions as of the latest snapshot, kept in the file at `path` if given
+pub struct PositionIndex {
+    path: Option<PathBuf>,
+    index: Option<Index>,
+}
+
+impl PositionIndex {
+    /// Starts from the index at `path` if it exists
+    pub fn load(path: Option<PathBuf>) -> Result<Self, Box<dyn std::error::Error>> {
+        let index = match &path {
+            Some(path) if path.exists() => Some(
+                serde_json::from_slice(&fs::read(path)?)
+                    .map_err(|err| format!("invalid position index {}: {err}", path.display()))?,
+            ),
+            _ => None,
+        };
+        Ok(PositionIndex { path, index })
+    }
+
+    /// Classifies `positions`, all the open ones, against the index and replaces it with
+    /// them; `None` for the first snapshot, which there's nothing to compare to. A position
+    /// account reused for a new position, with a different open time, counts as the old
+    /// position closed and the new one opened.
+    pub fn update(
+        &mut self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+    ) -> Option<LifecycleChanges> {
+        let current: BTreeMap<String, PositionRecord> = position_records(state, positions)
+            .into_iter()
+            .map(|record| (record.position.clone(), record))
+            .collect();
+        let previous = self.index.replace(Index {
+            unix_time: state.unix_time,
+            positions: current,
+        })?;
+        let current = &self.index.as_ref()?.positions;
+
+        let mut changes = LifecycleChanges {
+            since: previous.unix_time,
+            ..Default::default()
+        };
+        let prices: BTreeMap<String, f64> = state
+            .custodies
+            .values()
+            .map(|custody| (custody.symbol(), custody.price()))
+            .collect();
+        for (pubkey, before) in &previous.positions {
+            if current
+                .get(pubkey)
+                .map_or(false, |position| position.open_time == before.open_time)
+            {
+                continue;
+            }
+            let price = prices.get(&before.market).copied();
+            let at_close = PositionRecord {
+                unix_time: state.unix_time,
+                slot: state.slot,
+                price: price.unwrap_or(before.price),
+                ..before.clone()
+            };
+            let transition = if price.map_or(false, |price| likely_liquidated(before, price)) {
+                changes.liquidated.add(before.size_usd);
+                Transition::Liquidated
+            } else {
+                changes.closed.add(before.size_usd);
+                Transition::Closed
+            };
+            changes.positions.push(PositionChange {
+                transition,
+                position: at_close,
+                previous_size_usd: before.size_usd,
+            });
+        }
+        for (pubkey, position) in current {
+            let before = previous
+                .positions
+                .get(pubkey)
+                .filter(|before| before.open_time == position.open_time);
+            let transition = match before {
+                None => {
+                    changes.opened.add(position.size_usd);
+                    Transition::Opened
+                }
+                Some(before) if position.size_usd > before.size_usd => {
+                    changes
+                        .increased
+                        .add(position.size_usd.sub(before.size_usd));
+                    Transition::Increased
+                }
+                Some(before) if position.size_usd < before.size_usd => {
+                    changes
+                        .decreased
+                        .add(before.size_usd.sub(position.size_usd));
+                    Transition::Decreased
+                }
+                Some(before) if position.collateral_usd != before.collateral_usd => {
+                    Transition::CollateralChanged
+                }
+                Some(_) => continue,
+            };
+            changes.positions.push(PositionChange {
+                transition,
+                position: position.clone(),
+                previous_size_usd: before.map_or(0.0, |before| before.size_usd),
+            });
+        }
+        Some(changes)
+    }
+
+    /// Writes the index file, if any
+    pub fn save(&self) -> Result<(), Box<dyn std::error::Error>> {
+        let (Some(path), Some(index)) = (&self.path, &self.index) else {
+            return Ok(());
+        };
+        if let Some(dir) = path.parent() {
+            fs::create_dir_all(dir)?;
+        }
+        fs::write(path, serde_json::to_vec(index)?)?;
+        Ok(())
+    }
+}
@@ -0,0 +1,129 @@
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::snapshot::{collateral_value, PositionValuation};
+use crate::state::PerpState;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod report;
+#[cfg(not(target_arch = "wasm32"))]
+pub use report::*;
+
+pub struct LiquidationEstimate {
+    pub position_pubkey: Pubkey,
+    pub owner: Pubkey,
+    pub symbol: String,
+    pub side: perp_abi::Side,
+    pub size_usd: f64,
+    /// Collateral left after unrealized P&L and fees owed on close
+    pub remaining_collateral: f64,
+    pub price_at_entry: f64,
+    pub price: f64,
+    pub liquidation_price: f64,
+    /// Relative price move until the liquidation price is hit, negative when already past it
+    pub distance: f64,
+    /// As in the snapshot, current value over remaining collateral before close fees
+    pub effective_leverage: f64,
+}
+
+impl LiquidationEstimate {
+    /// Remaining collateral as a share of size
+    pub fn margin_ratio(&self) -> f64 {
+        self.remaining_collateral.div(self.size_usd)
+    }
+
+    /// Loss beyond the remaining collateral, borne by the pool, if the price gaps straight
+    /// through the liquidation price by `price_move` against the position before it can be
+    /// liquidated
+    pub fn bad_debt_after_gap(&self, price_move: f64) -> f64 {
+        let current_value = self.size_usd.mul(self.price).div(self.price_at_entry);
+        self.remaining_collateral
+            .sub(current_value.mul(price_move))
+            .min(0.0)
+            .abs()
+    }
+}
+
+/// Estimates where a position gets liquidated: once its remaining collateral no longer
+/// covers the custody's max leverage
+pub fn estimate(
+    state: &PerpState,
+    position_pubkey: Pubkey,
+    position: &perp_abi::state::Position,
+) -> Option<LiquidationEstimate> {
+    let custody_state = state.custodies.get(&position.custody)?;
+    let collateral_custody_state = state.custodies.get(&position.collateral_custody)?;
+
+    let long_short_sign: f64 = match position.side {
+        perp_abi::Side::Long => 1.0,
+        perp_abi::Side::Short => -1.0,
+        _ => return None,
+    };
+
+    let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+    // valued like the snapshot values it, stablecoin collateral at its own oracle price
+    let collateral_usd = collateral_value(
+        spl_token::amount_to_ui_amount(position.collateral_usd, 6),
+        &position.custody,
+        collateral_custody_state,
+    );
+    let price_at_entry = spl_token::amount_to_ui_amount(position.price, 6);
+    let price = custody_state.price();
+    let max_leverage = (custody_state.custody.pricing.max_leverage as f64).div(10_000.0);
+
+    let hours_open = (state.unix_time as f64)
+        .sub(position.update_time as f64)
+        .div(3600.0);
+    let borrow_fees = collateral_custody_state
+        .borrow_rate
+        .mul(hours_open)
+        .mul(size_usd)
+        .div(10_000.0);
+    let close_fees = size_usd
+        .mul(state.pool.fees.decrease_position_bps as f64)
+        .div(10_000.0);
+
+    // collateral that can be lost before hitting max leverage, as a share of size
+    let buffer = collateral_usd
+        .sub(borrow_fees)
+        .sub(close_fees)
+        .sub(size_usd.div(max_leverage))
+        .div(size_usd);
+
+    // a long is liquidated below its entry price, a short above it
+    let liquidation_price = price_at_entry.mul(1.0_f64.sub(buffer.mul(long_short_sign)));
+    let pnl = size_usd
+        .mul(price.sub(price_at_entry))
+        .div(price_at_entry)
+        .mul(long_short_sign);
+    let distance = price.sub(liquidation_price).div(price).mul(long_short_sign);
+
+    Some(LiquidationEstimate {
+        position_pubkey,
+        owner: position.owner,
+        symbol: custody_state.symbol(),
+        side: position.side,
+        size_usd,
+        remaining_collateral: collateral_usd.add(pnl).sub(borrow_fees).sub(close_fees),
+        price_at_entry,
+        price,
+        liquidation_price,
+        distance,
+        effective_leverage: PositionValuation::compute(state, position)
+            .map_or(f64::NAN, |valuation| valuation.effective_leverage()),
+    })
+}
+
+/// Estimates for every open position, closest to liquidation first
+pub fn estimate_all(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+) -> Vec<LiquidationEstimate> {
+    let mut estimates: Vec<LiquidationEstimate> = positions
+        .iter()
+        .filter_map(|(position_pubkey, position)| estimate(state, *position_pubkey, position))
+        .collect();
+    estimates.sort_by(|a, b| a.distance.total_cmp(&b.distance));
+    estimates
+}
@@ -0,0 +1,166 @@
+use std::collections::BTreeMap;
+use std::ops::Mul as _;
+use std::time::Duration;
+
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use super::{estimate_all, LiquidationEstimate};
+use crate::state::{load_open_positions, PerpState};
+use crate::supervisor::Supervisor;
+use crate::table;
+
+/// Price moves the notional at risk is reported for
+const RISK_MOVES: [f64; 3] = [0.01, 0.02, 0.05];
+
+/// Writes the `limit` positions closest to liquidation as CSV, for bots, after the header
+/// row if `header`
+fn write_candidates_csv(
+    estimates: &[LiquidationEstimate],
+    limit: usize,
+    header: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    if header {
+        writer.write_record([
+            "position",
+            "owner",
+            "market",
+            "side",
+            "size_usd",
+            "margin_ratio",
+            "effective_leverage",
+            "price",
+            "liquidation_price",
+            "distance",
+        ])?;
+    }
+    for estimate in estimates.iter().take(limit) {
+        writer.write_record([
+            estimate.position_pubkey.to_string(),
+            estimate.owner.to_string(),
+            estimate.symbol.clone(),
+            format!("{:?}", estimate.side),
+            estimate.size_usd.to_string(),
+            estimate.margin_ratio().to_string(),
+            estimate.effective_leverage.to_string(),
+            estimate.price.to_string(),
+            estimate.liquidation_price.to_string(),
+            estimate.distance.to_string(),
+        ])?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Prints the report, or with `csv` the candidates, with the CSV header if `first`
+fn print_report(
+    connection: &RpcClient,
+    limit: usize,
+    csv: bool,
+    first: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let positions = load_open_positions(connection)?;
+    let estimates = estimate_all(&state, &positions);
+    if csv {
+        return write_candidates_csv(&estimates, limit, first);
+    }
+
+    println!("Unix time: {}", state.unix_time);
+    println!("Notional at risk by price move:");
+    let mut market_to_at_risk: BTreeMap<&str, [f64; RISK_MOVES.len()]> = BTreeMap::new();
+    for estimate in &estimates {
+        let at_risk = market_to_at_risk
+            .entry(&estimate.symbol)
+            .or_insert([0.0; RISK_MOVES.len()]);
+        for (i, price_move) in RISK_MOVES.iter().enumerate() {
+            if estimate.distance <= *price_move {
+                at_risk[i] += estimate.size_usd;
+            }
+        }
+    }
+    let usd = |value: f64| format!("${}", value.round().separate_with_commas());
+    let at_risk_rows = market_to_at_risk
+        .into_iter()
+        .map(|(symbol, at_risk)| {
+            let mut row = vec![symbol.to_string()];
+            row.extend(at_risk.map(usd));
+            row
+        })
+        .collect();
+    println!(
+        "{}",
+        table::render(&["Market", "1%", "2%", "5%"], at_risk_rows)
+    );
+
+    println!("Closest to liquidation:");
+    let candidate_rows = estimates
+        .iter()
+        .take(limit)
+        .map(|estimate| {
+            vec![
+                estimate.position_pubkey.to_string(),
+                estimate.owner.to_string(),
+                estimate.symbol.clone(),
+                format!("{:?}", estimate.side),
+                usd(estimate.size_usd),
+                format!("{:.2}%", estimate.margin_ratio().mul(100.0)),
+                format!("{:.2}x", estimate.effective_leverage),
+                format!("${:.4}", estimate.price),
+                format!("${:.4}", estimate.liquidation_price),
+                format!("{:.2}%", estimate.distance.mul(100.0)),
+            ]
+        })
+        .collect();
+    println!(
+        "{}",
+        table::render(
+            &[
+                "Position",
+                "Owner",
+                "Market",
+                "Side",
+                "Size",
+                "Margin",
+                "Leverage",
+                "Price",
+                "Liq. price",
+                "Distance",
+            ],
+            candidate_rows,
+        )
+    );
+    Ok(())
+}
+
+/// Lists positions by distance to liquidation, refreshing every `watch` seconds if given.
+/// With `csv`, only the `limit` closest positions are written, as CSV under a single header
+/// row, so refreshes append to one table.
+pub fn run(
+    connection: &RpcClient,
+    limit: usize,
+    watch: Option<u64>,
+    csv: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let Some(interval) = watch else {
+        return print_report(connection, limit, csv, true);
+    };
+    let mut supervisor = Supervisor::install()?;
+    let mut first = true;
+    loop {
+        print_report(connection, limit, csv, first)?;
+        if !csv {
+            println!();
+        }
+        first = false;
+        supervisor.ready();
+        // a shutdown request lets the in-flight report finish first
+        supervisor.sleep(Duration::from_secs(interval));
+        if supervisor.should_stop() {
+            break;
+        }
+    }
+    supervisor.stopping();
+    Ok(())
+}
@@ -0,0 +1,192 @@
+//! The latest snapshot kept in memory by the collector, which chat bots answer queries
+//! from while the next one is being taken.
+
+use std::collections::HashMap;
+use std::ops::{Div as _, Mul as _};
+use std::sync::{Arc, Mutex};
+
+use solana_sdk::pubkey::Pubkey;
+use thousands::Separable;
+
+use crate::analyze::format_time;
+use crate::snapshot::{self, Snapshot};
+use crate::state::PerpState;
+use crate::store::PositionRecord;
+use crate::table::shorten;
+
+/// Positions listed by `whales`
+const TOP_WHALES: usize = 10;
+
+struct Latest {
+    snapshot: Snapshot,
+    positions: HashMap<String, PositionRecord>,
+}
+
+/// Shared between the collector, which updates it with each snapshot, and the bots
+#[derive(Clone, Default)]
+pub struct LiveView {
+    latest: Arc<Mutex<Option<Latest>>>,
+}
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+impl LiveView {
+    /// Replaces what queries are answered from with `snapshot` and its positions
+    pub fn update(
+        &self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) {
+        let latest = Latest {
+            snapshot: snapshot.clone(),
+            positions: snapshot::position_records(state, positions)
+                .into_iter()
+                .map(|record| (record.position.clone(), record))
+                .collect(),
+        };
+        *self.latest.lock().unwrap_or_else(|err| err.into_inner()) = Some(latest);
+    }
+
+    fn answer(&self, answer: impl FnOnce(&Latest) -> String) -> String {
+        match &*self.latest.lock().unwrap_or_else(|err| err.into_inner()) {
+            Some(latest) => format!(
+                "{}\n\nAs of {} UTC",
+                answer(latest),
+                format_time(latest.snapshot.unix_time)
+            ),
+            None => "No snapshot taken yet, try again in a minute".to_string(),
+        }
+    }
+
+    /// Pool value, open interest, position counts, P&L and leverage across all markets
+    pub fn stats(&self) -> String {
+        self.answer(|latest| {
+            let snapshot = &latest.snapshot;
+            format!(
+                "Pool value {}\nOpen interest {}: {} long, {} short\n{} positions, {} long and {} short, {} winning\nUnrealized P&L {}, {:.2}x average effective leverage",
+                usd(snapshot.total_pool_value),
+                usd(snapshot.cumulative_positions),
+                usd(snapshot.cumulative_long),
+                usd(snapshot.cumulative_short()),
+                snapshot.num_positions,
+                snapshot.num_longs,
+                snapshot.num_shorts(),
+                snapshot.num_winning,
+                usd(snapshot.cumulative_pnl),
+                snapshot.average_effective_leverage(),
+            )
+        })
+    }
+
+    /// The largest open positions
+    pub fn whales(&self) -> String {
+        self.answer(|latest| {
+            let mut positions: Vec<&PositionRecord> = latest.positions.values().collect();
+            positions.sort_by(|a, b| b.size_usd.total_cmp(&a.size_usd));
+            let lines: Vec<String> = positions
+                .into_iter()
+                .take(TOP_WHALES)
+                .map(|position| {
+                    format!(
+                        "{} {} {} by {}, P&L {}",
+                        usd(position.size_usd),
+                        position.market,
+                        position.side,
+                        shorten(&position.owner),
+                        usd(position.unrealized_pnl()),
+                    )
+                })
+                .collect();
+            if lines.is_empty() {
+                "No open positions".to_string()
+            } else {
+                lines.join("\n")
+            }
+        })
+    }
+
+    /// Long and short open interest of `market`, or of every market
+    pub fn open_interest(&self, market: Option<&str>) -> String {
+        self.answer(|latest| {
+            let markets: Vec<_> = latest
+                .snapshot
+                .markets
+                .iter()
+                .filter(|(symbol, _)| {
+                    market.map_or(true, |market| symbol.eq_ignore_ascii_case(market))
+                })
+                .collect();
+            if markets.is_empty() {
+                return format!(
+                    "No market {}, expected one of {}",
+                    market.unwrap_or_default(),
+                    latest
+                        .snapshot
+                        .markets
+                        .keys()
+                        .cloned()
+                        .collect::<Vec<_>>()
+                        .join(", ")
+                );
+            }
+            let lines: Vec<String> = markets
+                .into_iter()
+                .map(|(symbol, market)| {
+                    format!(
+                        "{symbol}: {} long over {} positions, {} short over {}",
+                        usd(market.long_oi),
+                        market.num_longs,
+                        usd(market.short_oi),
+                        market.num_shorts,
+                    )
+                })
+                .collect();
+            lines.join("\n")
+        })
+    }
+
+    /// Borrow APR and utilization of every market
+    pub fn funding(&self) -> String {
+        self.answer(|latest| {
+            let lines: Vec<String> = latest
+                .snapshot
+                .markets
+                .iter()
+                .map(|(symbol, market)| {
+                    format!(
+                        "{symbol}: {:.2}% borrow APR at {:.1}% utilization",
+                        market.borrow_apr,
+                        market.utilization.mul(100.0),
+                    )
+                })
+                .collect();
+            lines.join("\n")
+        })
+    }
+
+    /// Size, prices, P&L and leverage of the position account `pubkey`
+    pub fn position(&self, pubkey: &str) -> String {
+        self.answer(|latest| {
+            let Some(position) = latest.positions.get(pubkey.trim()) else {
+                return format!("No open position {}", pubkey.trim());
+            };
+            let remaining = position.remaining_collateral();
+            format!(
+                "{} {} of {}\nSize {} at entry ${:.4}, now ${:.4}\nP&L {}, collateral {} of {}, {:.2}x leverage",
+                position.market,
+                position.side,
+                position.owner,
+                usd(position.size_usd),
+                position.entry_price,
+                position.price,
+                usd(position.unrealized_pnl()),
+                usd(remaining),
+                usd(position.collateral_usd),
+                position.size_usd.div(remaining),
+            )
+        })
+    }
+}
@@ -0,0 +1,49 @@
+//! Advisory lock file keeping overlapping collector runs (e.g. from cron) apart.
+
+use std::fs::{self, File};
+use std::io::{Seek as _, SeekFrom, Write as _};
+
+use fs2::FileExt as _;
+
+/// Held for as long as the value lives. The lock is an OS advisory lock on the open file,
+/// so it's released when the process exits however it does, and the file is left in place.
+pub struct LockFile {
+    file: File,
+}
+
+impl LockFile {
+    /// Takes the lock at `path`, recording this process's PID in it. When another process
+    /// holds it, either fails right away or, with `wait`, blocks until it is released.
+    pub fn acquire(path: &str, wait: bool) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut file = fs::OpenOptions::new()
+            .read(true)
+            .write(true)
+            .create(true)
+            .open(path)?;
+        match file.try_lock_exclusive() {
+            Ok(()) => {}
+            Err(err) if err.kind() == fs2::lock_contended_error().kind() => {
+                if !wait {
+                    let holder = fs::read_to_string(path).unwrap_or_default();
+                    return Err(match holder.trim() {
+                        "" => format!("{path} is held by another process"),
+                        pid => format!("{path} is held by running process {pid}"),
+                    }
+                    .into());
+                }
+                file.lock_exclusive()?;
+            }
+            Err(err) => return Err(err.into()),
+        }
+        file.set_len(0)?;
+        file.seek(SeekFrom::Start(0))?;
+        writeln!(file, "{}", std::process::id())?;
+        Ok(LockFile { file })
+    }
+}
+
+impl Drop for LockFile {
+    fn drop(&mut self) {
+        let _ = self.file.unlock();
+    }
+}
@@ -1,381 +1,1475 @@
-use std::collections::HashMap;
-use std::fs;
-use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
-use std::str::FromStr;
-use std::time::SystemTime;
-
-use anchor_lang::{AccountDeserialize as _, AnchorDeserialize as _, Discriminator as _};
-use base64::Engine;
-use clap::Parser;
-use pyth_sdk_solana::load_price_feed_from_account;
-use solana_account_decoder::UiAccountEncoding;
-use solana_client::{rpc_client::RpcClient, rpc_config::RpcAccountInfoConfig};
-use solana_sdk::instruction::AccountMeta;
-use solana_sdk::pubkey::Pubkey;
-use thousands::Separable;
-
-const _PERPETUALS_PUBKEY: &str = "H4ND9aYttUVLFmNypZqLjZ52FYiGvdEB45GmwNoKEjTj";
-const _FUNDED_PUBKEY: &str = "HVSZJ2juJnMxd6yCNarTL56YmgUqzfUiwM7y7LtTXKHR";
-
-fn get_price_from_pyth_account(
-    connection: &RpcClient,
-    pyth_account_pubkey: &Pubkey,
-) -> Result<f64, Box<dyn std::error::Error>> {
-    let mut pyth_account = connection.get_account(pyth_account_pubkey)?;
-    let price_feed = load_price_feed_from_account(&pyth_account_pubkey, &mut pyth_account)?;
-    let price = price_feed.get_price_unchecked();
-    let price_as_float = (price.price as f64).div((10 as u32).pow(8) as f64);
-    Ok(price_as_float)
-}
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
 
-fn get_program_accounts_with_discrim(
-    connection: &RpcClient,
-    program_address: &str,
-    discrim: &[u8],
-) -> Result<
-    Vec<(solana_sdk::pubkey::Pubkey, solana_sdk::account::Account)>,
-    Box<dyn std::error::Error>,
-> {
-    use solana_client::{
-        rpc_config::RpcProgramAccountsConfig,
-        rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType},
-    };
-
-    let memcmp = RpcFilterType::Memcmp(Memcmp::new(0, MemcmpEncodedBytes::Bytes(discrim.into())));
-    let config = RpcProgramAccountsConfig {
-        filters: Some(vec![memcmp]),
-        account_config: RpcAccountInfoConfig {
-            encoding: Some(UiAccountEncoding::Base64),
-            ..Default::default()
-        },
-        ..Default::default()
-    };
-    let accounts = connection.get_program_accounts_with_config(
-        &solana_sdk::pubkey::Pubkey::from_str(program_address)?,
-        config,
-    )?;
+use clap::{CommandFactory as _, Parser, Subcommand};
+use solana_sdk::pubkey::Pubkey;
 
-    return Ok(accounts);
-}
+#[cfg(feature = "email")]
+use jupiter_perpetuals_analytics::email;
+#[cfg(feature = "geyser")]
+use jupiter_perpetuals_analytics::geyser;
+#[cfg(feature = "grpc")]
+use jupiter_perpetuals_analytics::grpc;
+#[cfg(feature = "mqtt")]
+use jupiter_perpetuals_analytics::mqtt;
+#[cfg(feature = "nats")]
+use jupiter_perpetuals_analytics::nats;
+#[cfg(feature = "plugins")]
+use jupiter_perpetuals_analytics::plugin;
+#[cfg(feature = "duckdb")]
+use jupiter_perpetuals_analytics::query;
+#[cfg(feature = "scripting")]
+use jupiter_perpetuals_analytics::script;
+#[cfg(feature = "graphql")]
+use jupiter_perpetuals_analytics::serve;
+use jupiter_perpetuals_analytics::{
+    alerts, analyze, anomaly, bench, cache, columns, compact, config, consensus, custody, discord,
+    doctor, elasticsearch, event_stream, export, fallback, fees, filter, forecast, funding, idl,
+    jlp, jupiter, leaderboard, lifecycle, liquidations, live, lock, metadata, orders, pool,
+    porcelain, prices, pushgateway, risk, rpc, sanity, schedule, signals, sink, slippage, snapshot,
+    state, statsd, store, style, summary, supervisor, table, telegram, template, top_positions,
+    transport, twitter, wallets, watch,
+};
 
-fn _get_fees_from_position(
-    connection: &RpcClient,
-    position_pubkey: &Pubkey,
-    position: &perp_abi::Position,
-    custody: &perp_abi::Custody,
-) -> Result<perp_abi::PnlAndFee, Box<dyn std::error::Error>> {
-    let tx = solana_sdk::transaction::Transaction::new_unsigned(
-        solana_sdk::message::Message::new_with_blockhash(
-            &[solana_sdk::instruction::Instruction::new_with_bytes(
-                perp_abi::ID,
-                &perp_abi::instruction::GetPnl::DISCRIMINATOR,
-                vec![
-                    AccountMeta::new_readonly(Pubkey::from_str(_PERPETUALS_PUBKEY)?, false),
-                    AccountMeta::new_readonly(position.pool, false),
-                    AccountMeta::new_readonly(*position_pubkey, false),
-                    AccountMeta::new_readonly(position.custody, false),
-                    AccountMeta::new_readonly(custody.oracle.oracle_account, false),
-                    AccountMeta::new_readonly(position.collateral_custody, false),
-                ],
-            )],
-            Some(&Pubkey::from_str(_FUNDED_PUBKEY)?),
-            &connection.get_latest_blockhash()?,
-        ),
-    );
-    let mut data = base64::prelude::BASE64_STANDARD.decode(
-        connection
-            .simulate_transaction(&tx)?
-            .value
-            .return_data
-            .unwrap_or_default()
-            .data
-            .0,
-    )?;
-    data.resize(41, 0);
-    let pnl_and_fee = perp_abi::PnlAndFee::try_from_slice(&data)?;
-    Ok(pnl_and_fee)
-}
+use cache::AccountCache;
+use config::Config;
+use lock::LockFile;
+use prices::{PriceProvider, PriceProviders};
+use sink::FanOut;
+use snapshot::{PositionTotals, PreviousSnapshot, PriceConsensus, Snapshot, SnapshotAccumulator};
+use state::{
+    for_each_open_position, for_each_open_position_checkpointed, load_open_positions, PerpState,
+};
+use store::{
+    CustodyRecord, MarketRecord, PositionRecord, Record as _, SnapshotRecord, WalletRecord,
+};
+use supervisor::Supervisor;
+use template::SnapshotTemplate;
+use watch::PositionTracker;
 
 #[derive(Parser)]
-#[command(version, about = "Collects analytics about Jup perpetuals usage")]
+#[command(
+    version,
+    about = "Collects analytics about Jup perpetuals usage",
+    subcommand_negates_reqs = true
+)]
 struct Args {
     /// Solana RPC URL
-    #[arg(short)]
-    rpc_url: String,
-    /// Export to CSV
+    #[arg(short, required_unless_present = "cluster")]
+    rpc_url: Option<String>,
+    /// Use the cluster's public RPC endpoint unless -r is given
+    #[arg(long)]
+    cluster: Option<rpc::Cluster>,
+    /// Read a deployment of the perpetuals program other than Jupiter's mainnet one, e.g. on
+    /// devnet or a fork
+    #[arg(long)]
+    program_id: Option<Pubkey>,
+    /// The deployment's perpetuals account, derived from --program-id by default
+    #[arg(long, requires = "program_id")]
+    perpetuals_account: Option<Pubkey>,
+    /// Export to CSV, or another history format picked by file extension
     #[arg(short)]
     csv_path: Option<String>,
+    /// Also store every open position with each snapshot, next to the -c history and in
+    /// --sink stores, so `analyze` can recompute metrics later
+    #[arg(long)]
+    record_positions: bool,
+    /// Also write each snapshot to this sink (repeatable): a history path or database URL,
+    /// json:<PATH> for JSON lines (json:- for stdout) or webhook:<URL>
+    #[arg(long)]
+    sink: Vec<sink::SinkSpec>,
+    /// Also record every custody's oracle price to this history file, format picked by
+    /// extension
+    #[arg(long)]
+    price_history: Option<String>,
+    /// Also record each market side's size and borrow fee accrual to this history file
+    #[arg(long)]
+    funding_history: Option<String>,
     /// Silent
     #[arg(short)]
     silent: bool,
+    /// Don't color console output; also honors NO_COLOR
+    #[arg(long)]
+    no_color: bool,
+    /// Show full pubkeys in tables instead of shortening them to fit the terminal
+    #[arg(long)]
+    wide: bool,
+    /// Snapshots of the `-c` history drawn as sparklines next to key metrics, 0 to hide them
+    #[arg(long, default_value_t = 20)]
+    sparkline: usize,
+    /// Days of the `-c` history current values are ranked against as percentiles, 0 to hide
+    /// them
+    #[arg(long, default_value_t = 90)]
+    percentile_days: u64,
+    /// Print only a versioned machine-readable line per snapshot (json or tsv) to stdout
+    #[arg(long, value_enum, num_args = 0..=1, default_missing_value = "json")]
+    porcelain: Option<porcelain::PorcelainFormat>,
+    /// Render each snapshot to stdout through this Tera template instead of the report
+    #[arg(long, conflicts_with = "porcelain")]
+    template: Option<String>,
+    /// Oracle price positions are valued at: spot, or the feed's EMA to smooth out noise
+    #[arg(long, value_enum, default_value_t = state::PriceKind::Spot)]
+    price_kind: state::PriceKind,
+    /// Read oracle prices from Pyth's Hermes API instead of the oracle accounts, leaving RPC
+    /// for program accounts only
+    #[arg(long)]
+    hermes_prices: bool,
+    /// Price markets whose oracle is stale or undecodable from this HTTP source instead
+    #[arg(long, value_enum)]
+    fallback_prices: Option<fallback::FallbackSource>,
+    /// Price every market with the consensus of these sources (repeatable), rejecting those
+    /// too far off the median; the config file can set sources per market
+    #[arg(long, value_enum)]
+    consensus: Vec<consensus::PriceSource>,
+    /// Percent a source may be off the median price before it's rejected
+    #[arg(long, default_value_t = 1.0)]
+    consensus_tolerance: f64,
+    /// Also fetch JLP's market price through Jupiter and report its premium or discount to
+    /// NAV
+    #[arg(long)]
+    jlp_premium: bool,
+    /// Also fetch each market's DEX spot price through Jupiter and compare it to the oracle
+    #[arg(long)]
+    dex_prices: bool,
+    /// Warn and alert when a DEX spot price is more than this many percent off the oracle
+    #[arg(long, default_value_t = 1.0, requires = "dex_prices")]
+    dex_divergence: f64,
+    /// Warn and alert when the custody or position account count drops by more than this many
+    /// percent since the last snapshot in the -c history, a sign of a truncated RPC response
+    #[arg(long, default_value_t = 20.0)]
+    max_account_drop: f64,
+    /// Record the perpetuals program's executable hash with each snapshot and warn when it
+    /// changes since the last snapshot in the -c history
+    #[arg(long)]
+    program_hash: bool,
+    /// Only aggregate positions matching this expression, e.g. 'size_usd > 100000 && side == "long"'
+    #[arg(long)]
+    filter: Option<filter::Filter>,
+    /// Flag unusual jumps in open interest, skew and borrow rates against the -c history
+    #[arg(long, requires = "csv_path")]
+    anomalies: bool,
+    /// Number of recent snapshots the anomaly bands mostly reflect
+    #[arg(long, default_value_t = 48.0)]
+    anomaly_span: f64,
+    /// Standard deviations a change must be from the recent mean to be flagged
+    #[arg(long, default_value_t = 4.0)]
+    anomaly_threshold: f64,
+    /// Post alerts, such as flagged anomalies, to this webhook URL (Discord, Slack or any
+    /// JSON endpoint)
+    #[arg(long)]
+    alert_webhook: Option<String>,
+    /// Seconds before an alert that's still firing is sent again
+    #[arg(long, default_value_t = 3600)]
+    alert_cooldown: u64,
+    /// Consecutive snapshots a condition must hold for before it alerts
+    #[arg(long, default_value_t = 1)]
+    alert_after: u32,
+    /// Keep alert cooldowns and breach counts in this file, so they carry across runs
+    #[arg(long)]
+    alert_state: Option<std::path::PathBuf>,
+    /// Keep the index of open positions in this file, so position changes are classified
+    /// across runs
+    #[arg(long)]
+    position_index: Option<std::path::PathBuf>,
+    /// Take a snapshot every given number of seconds instead of once
+    #[arg(short, long)]
+    watch: Option<u64>,
+    /// Take snapshots on a cron schedule in UTC, e.g. "*/5 * * * *", instead of once
+    #[arg(long, conflicts_with = "watch")]
+    schedule: Option<schedule::Schedule>,
+    /// Redraw the console summary in place every given number of seconds, highlighting what
+    /// changed since the last refresh
+    #[arg(
+        long,
+        conflicts_with_all = ["watch", "schedule", "porcelain", "template", "silent"]
+    )]
+    refresh: Option<u64>,
+    /// Stream account updates from a Yellowstone Geyser gRPC endpoint instead of polling RPC
+    #[arg(long)]
+    geyser_url: Option<String>,
+    /// X-Token for the Geyser endpoint
+    #[arg(long)]
+    geyser_token: Option<String>,
+    /// Rescan all positions every given number of watch cycles, tracking changes over the
+    /// websocket in between
+    #[arg(long, default_value_t = 10)]
+    full_refresh_every: u64,
+    /// Push each snapshot's metrics to this Prometheus Pushgateway URL
+    #[arg(long)]
+    pushgateway: Option<String>,
+    /// Job name metrics are pushed under
+    #[arg(long, default_value = "jupiter_perpetuals_analytics")]
+    pushgateway_job: String,
+    /// Extra grouping label for pushed e.g. instance=collector-1 (repeatable)
+    #[arg(long, value_parser = pushgateway::parse_label)]
+    pushgateway_label: Vec<(String, String)>,
+    /// Send each snapshot's metrics as StatsD gauges to this host:port
+    #[arg(long)]
+    statsd: Option<String>,
+    /// Append market labels to StatsD metric names instead of sending DogStatsD tags
+    #[arg(long)]
+    statsd_no_tags: bool,
+    /// Hold this lock file while running so overlapping invocations don't collide
+    #[arg(long)]
+    lock_file: Option<String>,
+    /// Wait for the lock file to be released instead of exiting
+    #[arg(long, requires = "lock_file")]
+    lock_wait: bool,
+    /// Publish each snapshot's metrics as retained messages to this MQTT broker, e.g.
+    /// mqtt://localhost:1883
+    #[arg(long)]
+    mqtt: Option<String>,
+    /// Topic prefix MQTT metrics are published under
+    #[arg(long, default_value = "jupperps")]
+    mqtt_prefix: String,
+    /// Publish each snapshot and position changes to JetStream on this NATS server, e.g.
+    /// nats://localhost:4222
+    #[arg(long)]
+    nats: Option<String>,
+    /// Subject prefix NATS messages are published under
+    #[arg(long, default_value = "jupperps")]
+    nats_prefix: String,
+    /// JetStream stream capturing the published subjects, created if missing
+    #[arg(long, default_value = "JUPPERPS")]
+    nats_stream: String,
+    /// Index each snapshot summary and its positions into this Elasticsearch or OpenSearch
+    /// URL
+    #[arg(long)]
+    elasticsearch: Option<String>,
+    /// Prefix of the Elasticsearch index names
+    #[arg(long, default_value = "jupperps")]
+    elasticsearch_prefix: String,
+    /// Write a JSON event whenever a wallet under [wallets] in the config opens, increases,
+    /// decreases or closes a position, appended to this JSONL path (`-` for stdout, which
+    /// implies -s) or posted to this http(s) URL
+    #[arg(long)]
+    signals: Option<String>,
+    /// Stream position changes as JSONL to stdout (`-`, which implies -s) or to clients of
+    /// a Unix socket (`unix:<PATH>`) as each snapshot is taken
+    #[arg(long)]
+    events: Option<String>,
+    /// Stream each snapshot and position changes to gRPC subscribers on this address, e.g.
+    /// 127.0.0.1:50051
+    #[arg(long)]
+    grpc_listen: Option<std::net::SocketAddr>,
+    /// Answer `/oi`, `/funding` and `/position` Discord slash commands from the latest
+    /// snapshot, as the bot in the config's `[discord]` table
+    #[arg(long)]
+    discord_bot: bool,
+    /// Answer `/stats`, `/rates` and `/whales` Telegram commands from the latest snapshot,
+    /// as the bot in the config's `[telegram]` table
+    #[arg(long)]
+    telegram_bot: bool,
+    /// WASM plugin adding custom metrics to each snapshot, repeatable
+    #[arg(long = "plugin", value_name = "PATH")]
+    plugins: Vec<std::path::PathBuf>,
+    /// Always fetch pool and custody accounts and token metadata instead of using the on-disk
+    /// cache
+    #[arg(long)]
+    no_cache: bool,
+    /// Seconds cached pool and custody accounts are used before being refetched. Their
+    /// AUM, balances and fee reserves are then as of the cached slot, so by default they're
+    /// refetched every snapshot and only the program scans finding them are cached
+    #[arg(long, default_value_t = 0)]
+    cache_ttl: u64,
+    /// Fetch positions a page of 100 accounts at a time instead of in one response, keeping
+    /// memory flat with many positions at the cost of more RPC requests
+    #[arg(long)]
+    paged_positions: bool,
+    /// Record each page of positions fetched in this file, so a snapshot interrupted less
+    /// than 15 minutes ago resumes from the pages it had fetched instead of starting over
+    #[arg(
+        long,
+        requires = "paged_positions",
+        conflicts_with_all = ["watch", "refresh", "schedule", "geyser_url"]
+    )]
+    checkpoint: Option<std::path::PathBuf>,
+    /// Decode the pool, custodies and positions with the program's on-chain Anchor IDL,
+    /// falling back to the compiled layout, so a program upgrade doesn't need a rebuild
+    #[arg(long)]
+    idl: bool,
+    /// Seconds fetched oracle accounts are reused across quick successive cycles
+    #[arg(long, default_value_t = 0)]
+    price_cache_ttl: u64,
+    /// Log diagnostics such as cache hit rates to stderr
+    #[arg(short, long)]
+    verbose: bool,
+    /// Route outbound HTTP connections through this proxy, e.g. socks5://127.0.0.1:1080
+    #[arg(long)]
+    proxy: Option<String>,
+    /// Config file with per-endpoint settings such as HTTP headers, by default
+    /// ~/.config/jupiter_perpetuals_analytics/config.toml if present
+    #[arg(long)]
+    config: Option<String>,
+    #[command(subcommand)]
+    command: Option<Command>,
+}
+
+#[derive(Subcommand)]
+enum Command {
+    /// Inspect a single custody: assets, utilization, borrow rate, oracle and OI caps
+    Custody {
+        /// Market symbol (e.g. SOL), custody pubkey or mint
+        market: String,
+    },
+    /// Print the pool's fee schedule, custodies with target weights, AUM and limits
+    Pool,
+    /// List positions by distance to liquidation and the notional at risk per market
+    Liquidations {
+        /// Number of positions to list
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+        /// Refresh every given number of seconds
+        #[arg(short, long)]
+        watch: Option<u64>,
+        /// Write only the listed positions, as CSV
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Rank owners by the unrealized P&L and ROE of all their open positions
+    Leaderboard {
+        /// Number of owners to list in each ranking
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+        /// Leave out owners with less than this much open size, in USD
+        #[arg(long, default_value_t = 1000.0)]
+        min_size: f64,
+        /// Leave out owners with less than this much collateral, in USD
+        #[arg(long, default_value_t = 100.0)]
+        min_collateral: f64,
+        /// Write every ranked owner, as CSV
+        #[arg(long)]
+        csv: bool,
+    },
+    /// List the largest open positions by size with their owners
+    TopPositions {
+        /// Number of positions to list
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+        /// Replace owners with salted hashes, round amounts and leave out position accounts,
+        /// entry prices and open times, for publishing. The salt is read from the
+        /// PERPS_ANONYMIZE_SALT environment variable or `anonymize_salt` in the config.
+        #[arg(long)]
+        anonymize: bool,
+        /// Write the listed positions as CSV
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Show pending limit/TP/SL orders grouped by market and trigger price
+    Orders,
+    /// JLP liquidity tools
+    Jlp {
+        #[command(subcommand)]
+        command: JlpCommand,
+    },
+    /// Project borrow APRs over the next hours if nothing changes, if pending requests
+    /// execute and if the recorded utilization trend continues
+    Forecast {
+        /// Horizon in hours
+        #[arg(long, default_value_t = 24.0)]
+        hours: f64,
+        /// Snapshot history written with -c, whose custody rows the utilization trend is
+        /// fitted to
+        #[arg(long)]
+        history: Option<String>,
+    },
+    /// Print a one-page risk report for daily distribution
+    Risk {
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = risk::ReportFormat::Console)]
+        format: risk::ReportFormat,
+        /// Oracle jump, in percent, to estimate the resulting bad debt for; repeat for
+        /// several (default 10, 20 and 30)
+        #[arg(long = "gap-move")]
+        gap_moves: Vec<f64>,
+    },
+    /// Diagnose the RPC endpoint: reachability, latency, getProgramAccounts, decoding, websocket and archival support
+    Doctor,
+    /// Time the RPC calls, decoding and aggregation behind a snapshot over several runs
+    Bench {
+        /// Snapshots to time
+        #[arg(long, default_value_t = 5)]
+        runs: usize,
+    },
+    /// Convert stored history between formats (CSV, JSONL, SQLite, Parquet, DuckDB)
+    Export {
+        /// History file to read, format picked by extension
+        input: String,
+        /// History file to append to, format picked by extension
+        output: String,
+        /// Only export snapshots taken at or after this unix time
+        #[arg(long)]
+        from: Option<u64>,
+        /// Only export snapshots taken at or before this unix time
+        #[arg(long)]
+        to: Option<u64>,
+    },
+    /// Estimate entry slippage per market from positions' entry prices and recorded oracle prices
+    Slippage {
+        /// Oracle price history written with --price-history
+        #[arg(long, required_unless_present = "hermes")]
+        price_history: Option<String>,
+        /// Look up prices the history doesn't have on Pyth's Hermes API
+        #[arg(long)]
+        hermes: bool,
+        /// Ignore positions without a recorded price within this many seconds of opening
+        #[arg(long, default_value_t = 120)]
+        max_gap: u64,
+    },
+    /// Estimate borrow fees paid by longs vs shorts per market from the funding history
+    Funding {
+        /// Funding history written with --funding-history
+        #[arg(long)]
+        funding_history: String,
+        /// Trailing window in hours (repeatable)
+        #[arg(long = "window", default_values_t = [24, 168, 720])]
+        windows: Vec<u64>,
+    },
+    /// Split the fees collected over trailing windows into open/close, borrow and swap fees
+    Fees {
+        /// Snapshot history written with -c, ideally with --record-positions
+        #[arg(long)]
+        history: String,
+        /// Funding history written with --funding-history, to attribute borrow fees
+        #[arg(long)]
+        funding_history: Option<String>,
+        /// Trailing window in hours (repeatable)
+        #[arg(long = "window", default_values_t = [24, 168])]
+        windows: Vec<u64>,
+        /// Open and close fee charged on position size changes, in BPS
+        #[arg(long, default_value_t = 6.0)]
+        position_fee_bps: f64,
+        /// Share of fees going to the protocol rather than JLP holders, in BPS; read from
+        /// the pool when -r is given
+        #[arg(long)]
+        protocol_share_bps: Option<f64>,
+    },
+    /// Summarize the stored history over the last day, week or month: open interest, volume,
+    /// fees, likely liquidations and borrow rate extremes
+    Report {
+        /// Snapshot history written with -c, ideally with --record-positions
+        history: String,
+        /// Period summarized
+        #[arg(long, value_enum, default_value_t = summary::Period::Week)]
+        period: summary::Period,
+        /// End the period at this unix time instead of the latest snapshot
+        #[arg(long)]
+        end: Option<u64>,
+        /// Output format
+        #[arg(short, long, value_enum, default_value_t = risk::ReportFormat::Markdown)]
+        format: risk::ReportFormat,
+        /// Email the summary to the config's email routes listing the period instead of
+        /// printing it
+        #[arg(long)]
+        email: bool,
+    },
+    /// Post a daily stats thread to X: pool value, open interest, 24h volume and borrow rate
+    /// extremes from the stored history
+    Tweet {
+        /// Snapshot history written with -c, ideally with --record-positions
+        history: String,
+        /// Print the thread instead of posting it
+        #[arg(long)]
+        dry_run: bool,
+    },
+    /// Recompute analytics for stored snapshots from the positions recorded with them
+    Analyze {
+        /// Snapshot history written with -c and --record-positions
+        history: String,
+        /// Only analyze snapshots taken at or after this unix time
+        #[arg(long)]
+        from: Option<u64>,
+        /// Only analyze snapshots taken at or before this unix time
+        #[arg(long)]
+        to: Option<u64>,
+        /// Only aggregate positions in this market, e.g. SOL
+        #[arg(long, conflicts_with = "correlations")]
+        market: Option<String>,
+        /// Print the correlations between the markets' open interest and price changes
+        /// instead, using the custody rows for prices
+        #[arg(long)]
+        correlations: bool,
+        /// Print how the markets' long/short ratios evolved around large price moves
+        /// instead
+        #[arg(long, conflicts_with = "correlations")]
+        skew: bool,
+        /// Price change between snapshots, in percent, counted as a large move by --skew
+        #[arg(long, default_value_t = 5.0)]
+        move_threshold: f64,
+        /// Snapshots shown either side of each large move by --skew
+        #[arg(long, default_value_t = 6)]
+        context: usize,
+    },
+    /// Show the equity curves of the wallets watched through the config's [wallets]
+    Wallets {
+        /// Snapshot history written with -c while wallets were configured
+        history: String,
+        /// Only show the wallet with this label
+        #[arg(long)]
+        wallet: Option<String>,
+        /// Only include snapshots taken at or after this unix time
+        #[arg(long)]
+        from: Option<u64>,
+        /// Only include snapshots taken at or before this unix time
+        #[arg(long)]
+        to: Option<u64>,
+        /// Number of the latest snapshots listed per wallet
+        #[arg(short, long, default_value_t = 20)]
+        limit: usize,
+    },
+    /// Run SQL over stored history, e.g. "SELECT market, avg(borrow_rate) FROM custodies GROUP BY market"
+    Query {
+        /// Query over the snapshots, custodies, markets, positions and wallets tables, plus
+        /// oracle_prices and funding when their histories are given
+        sql: String,
+        /// Snapshot history written with -c
+        #[arg(long)]
+        history: String,
+        /// Oracle price history written with --price-history, queried as oracle_prices
+        #[arg(long)]
+        price_history: Option<String>,
+        /// Funding history written with --funding-history, queried as funding
+        #[arg(long)]
+        funding_history: Option<String>,
+        /// Print CSV instead of a table
+        #[arg(long)]
+        csv: bool,
+    },
+    /// Deduplicate stored snapshots and downsample old ones per the retention policy
+    Compact {
+        /// History file to compact in place
+        path: String,
+        /// Keep every snapshot younger than this many days
+        #[arg(long, default_value_t = 7)]
+        raw_days: u64,
+        /// Keep hourly snapshots up to this many days old, daily ones beyond
+        #[arg(long, default_value_t = 90)]
+        hourly_days: u64,
+    },
+    /// Serve a GraphQL API over the stored history, with live top positions when -r is given
+    Serve {
+        /// Snapshot history written with -c
+        history: String,
+        /// Also serve open interest from this funding history
+        #[arg(long)]
+        funding_history: Option<String>,
+        /// Address to listen on
+        #[arg(long, default_value = "127.0.0.1:8080")]
+        listen: std::net::SocketAddr,
+        /// Seconds since the latest stored snapshot after which /healthz and /readyz fail
+        #[arg(long, default_value_t = 600)]
+        max_snapshot_age: u64,
+    },
+    /// Print a shell completion script to stdout
+    Completions {
+        /// Shell to complete for
+        shell: clap_complete::Shell,
+    },
+    /// Print a man page in roff format to stdout
+    Man,
+    /// Print the JSON Schema of a JSON document the tool emits
+    Schema {
+        /// Document to describe
+        #[arg(value_enum, default_value = "snapshot")]
+        document: porcelain::SchemaDocument,
+    },
+}
+
+#[derive(Subcommand)]
+enum JlpCommand {
+    /// Estimate the fee for minting or redeeming JLP with a token at current pool weights
+    Fee {
+        /// Token amount deposited, or withdrawn with --redeem
+        #[arg(long)]
+        amount: f64,
+        /// Token symbol (e.g. SOL), custody pubkey or mint
+        #[arg(long)]
+        token: String,
+        /// Redeem JLP for the token instead of minting with it
+        #[arg(long)]
+        redeem: bool,
+    },
 }
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
-    let args = Args::parse();
-
-    let mut pubkey_to_custody: HashMap<Pubkey, perp_abi::state::Custody> = HashMap::new();
-    let mut custody_pubkey_to_borrow_rate: HashMap<Pubkey, f64> = HashMap::new();
-    let mut mint_to_price: HashMap<Pubkey, f64> = HashMap::new();
-
-    let rpc_client = RpcClient::new(args.rpc_url);
-
-    let pool_accounts = get_program_accounts_with_discrim(
-        &rpc_client,
-        &perp_abi::ID.to_string(),
-        &perp_abi::state::Pool::DISCRIMINATOR,
-    )?;
-
-    let pool = perp_abi::state::Pool::try_deserialize(&mut &*pool_accounts[0].1.data)?;
-    let total_pool_value: f64 = spl_token::amount_to_ui_amount(pool.aum_usd as u64, 6);
-
-    let unix_time = SystemTime::now()
-        .duration_since(std::time::UNIX_EPOCH)?
-        .as_secs();
-
-    let custody_accounts = get_program_accounts_with_discrim(
-        &rpc_client,
-        &perp_abi::ID.to_string(),
-        &perp_abi::state::Custody::DISCRIMINATOR,
-    )?;
-
-    let mut stable_custodys = vec![];
-    let mut stable_aum = 0;
-    let mut stable_borrow = 0;
-
-    for (custody_pubkey, custody) in custody_accounts {
-        let custody = perp_abi::state::Custody::try_deserialize(&mut &*custody.data)?;
-        let price = get_price_from_pyth_account(&rpc_client, &custody.oracle.oracle_account)?;
-        pubkey_to_custody.insert(custody_pubkey, custody);
-
-        if price.round() == 1.0 {
-            // stablecoin borrow rates set by utilization percentage of all stablecoins
-            stable_custodys.push(custody_pubkey);
-            stable_aum += custody.assets.owned;
-            stable_borrow += custody.assets.locked;
-        } else {
-            mint_to_price.insert(custody.mint, price);
-            // non-stablecoin borrow rates set by utilization percentage
-            custody_pubkey_to_borrow_rate.insert(
-                custody_pubkey,
-                spl_token::amount_to_ui_amount(custody.assets.locked, 6)
-                    .div(spl_token::amount_to_ui_amount(custody.assets.owned, 6))
-                    .mul(custody.funding_rate_state.hourly_funding_bps as f64),
+    let mut args = Args::parse();
+    style::init(args.no_color);
+    table::init(args.wide);
+    if let (None, Some(cluster)) = (&args.rpc_url, args.cluster) {
+        args.rpc_url = Some(cluster.rpc_url().to_string());
+    }
+    if let Some(program_id) = args.program_id {
+        rpc::set_program(program_id, args.perpetuals_account)?;
+    }
+    // a JSONL stream on stdout takes it over, so the report would corrupt the stream
+    let stdout_streams: Vec<_> = [("--signals", &args.signals), ("--events", &args.events)]
+        .into_iter()
+        .filter(|(_, destination)| destination.as_deref() == Some("-"))
+        .map(|(flag, _)| flag)
+        .collect();
+    if !stdout_streams.is_empty() {
+        if stdout_streams.len() > 1 {
+            return Err("--signals and --events can't both stream to stdout".into());
+        }
+        if args.porcelain.is_some() || args.template.is_some() || args.refresh.is_some() {
+            return Err(format!(
+                "{} - streams to stdout, which can't be shared with --porcelain, --template or --refresh",
+                stdout_streams[0]
+            )
+            .into());
+        }
+        args.silent = true;
+    }
+    if let (false, Some(dir)) = (args.no_cache, AccountCache::default_dir()) {
+        metadata::set_cache_dir(&dir)?;
+    }
+
+    let _lock = match &args.lock_file {
+        Some(lock_file) => Some(LockFile::acquire(lock_file, args.lock_wait)?),
+        None => None,
+    };
+
+    // subcommands working on local files don't need an RPC endpoint
+    match &args.command {
+        Some(Command::Export {
+            input,
+            output,
+            from,
+            to,
+        }) => return export::run(input, output, *from, *to),
+        Some(Command::Funding {
+            funding_history,
+            windows,
+        }) => return funding::run(funding_history, windows),
+        Some(Command::Report {
+            history,
+            period,
+            end,
+            format,
+            email,
+        }) => {
+            if !email {
+                return summary::run(history, *period, *end, *format);
+            }
+            #[cfg(feature = "email")]
+            {
+                let report = summary::render(history, *period, *end, *format)?;
+                let mailer = email::Mailer::from_config(&Config::load(args.config.as_deref())?)?
+                    .ok_or("no [[email]] routes in the config")?;
+                return mailer.send_report(
+                    period.name(),
+                    &report,
+                    matches!(format, risk::ReportFormat::Html),
+                );
+            }
+            #[cfg(not(feature = "email"))]
+            return Err(
+                "can't email the report: built without the `email` feature, rebuild with `--features email`"
+                    .into(),
+            );
+        }
+        Some(Command::Tweet { history, dry_run }) => {
+            if *dry_run {
+                return twitter::run(history, None, args.proxy.as_deref());
+            }
+            let config = Config::load(args.config.as_deref())?;
+            let credentials = config
+                .x
+                .as_ref()
+                .ok_or("no [x] keys in the config to post with, or use --dry-run")?;
+            return twitter::run(history, Some(credentials), args.proxy.as_deref());
+        }
+        Some(Command::Fees {
+            history,
+            funding_history,
+            windows,
+            position_fee_bps,
+            protocol_share_bps,
+        }) => {
+            let protocol_share_bps = match (protocol_share_bps, &args.rpc_url) {
+                (Some(protocol_share_bps), _) => Some(*protocol_share_bps),
+                (None, Some(rpc_url)) => {
+                    let headers = Config::load(args.config.as_deref())?.headers_for(rpc_url);
+                    let connection =
+                        transport::rpc_client(rpc_url.clone(), args.proxy.as_deref(), &headers)?;
+                    let state = PerpState::load(&connection)?;
+                    Some(state.pool.fees.protocol_share_bps as f64)
+                }
+                (None, None) => None,
+            };
+            return fees::run(
+                history,
+                funding_history.as_deref(),
+                windows,
+                *position_fee_bps,
+                protocol_share_bps,
+            );
+        }
+        Some(Command::Wallets {
+            history,
+            wallet,
+            from,
+            to,
+            limit,
+        }) => return wallets::run(history, wallet.as_deref(), *from, *to, *limit),
+        Some(Command::Analyze {
+            history,
+            from,
+            to,
+            correlations: true,
+            ..
+        }) => return analyze::correlations(history, *from, *to),
+        Some(Command::Analyze {
+            history,
+            from,
+            to,
+            market,
+            skew: true,
+            move_threshold,
+            context,
+            ..
+        }) => {
+            return analyze::skew(
+                history,
+                *from,
+                *to,
+                market.as_deref(),
+                *move_threshold,
+                *context,
+            )
+        }
+        Some(Command::Analyze {
+            history,
+            from,
+            to,
+            market,
+            ..
+        }) => return analyze::run(history, *from, *to, market.as_deref()),
+        Some(Command::Query {
+            sql,
+            history,
+            price_history,
+            funding_history,
+            csv,
+        }) => {
+            #[cfg(feature = "duckdb")]
+            return query::run(
+                sql,
+                history,
+                price_history.as_deref(),
+                funding_history.as_deref(),
+                *csv,
             );
+            #[cfg(not(feature = "duckdb"))]
+            {
+                let _ = (sql, price_history, funding_history, csv);
+                return Err(format!(
+                    "can't query {history}: built without the `duckdb` feature, rebuild with `--features duckdb`"
+                )
+                .into());
+            }
+        }
+        Some(Command::Compact {
+            path,
+            raw_days,
+            hourly_days,
+        }) => return compact::run(path, *raw_days, *hourly_days),
+        Some(Command::Serve {
+            history,
+            funding_history,
+            listen,
+            max_snapshot_age,
+        }) => {
+            #[cfg(feature = "graphql")]
+            {
+                let connection = match &args.rpc_url {
+                    Some(rpc_url) => {
+                        let headers = Config::load(args.config.as_deref())?.headers_for(rpc_url);
+                        Some(transport::rpc_client(
+                            rpc_url.clone(),
+                            args.proxy.as_deref(),
+                            &headers,
+                        )?)
+                    }
+                    None => None,
+                };
+                return serve::run(
+                    *listen,
+                    history,
+                    funding_history.as_deref(),
+                    connection,
+                    *max_snapshot_age,
+                );
+            }
+            #[cfg(not(feature = "graphql"))]
+            {
+                let _ = (funding_history, max_snapshot_age);
+                return Err(format!(
+                    "can't serve {history} on {listen}: built without the `graphql` feature, rebuild with `--features graphql`"
+                )
+                .into());
+            }
+        }
+        Some(Command::Completions { shell }) => {
+            clap_complete::generate(
+                *shell,
+                &mut Args::command(),
+                env!("CARGO_PKG_NAME"),
+                &mut std::io::stdout(),
+            );
+            return Ok(());
+        }
+        Some(Command::Man) => {
+            clap_mangen::Man::new(Args::command()).render(&mut std::io::stdout())?;
+            return Ok(());
+        }
+        Some(Command::Schema { document }) => {
+            let schema = porcelain::schema(*document);
+            println!("{}", serde_json::to_string_pretty(&schema)?);
+            return Ok(());
         }
+        _ => {}
     }
 
-    for stable_custody in stable_custodys {
-        custody_pubkey_to_borrow_rate.insert(
-            stable_custody,
-            (stable_borrow as f64).div(stable_aum as f64),
+    let rpc_url = args
+        .rpc_url
+        .clone()
+        .ok_or("-r <RPC_URL> or --cluster is required for this command")?;
+    let config = Config::load(args.config.as_deref())?;
+    let rpc_headers = config.headers_for(&rpc_url);
+    let rpc_endpoint = rpc::redacted_url(&rpc_url);
+    let rpc_client = transport::rpc_client(rpc_url, args.proxy.as_deref(), &rpc_headers)?;
+    if args.idl {
+        idl::Idl::fetch(&rpc_client)?.install()?;
+    }
+
+    match args.command {
+        Some(Command::Custody { market }) => return custody::run(&rpc_client, &market),
+        Some(Command::Pool) => return pool::run(&rpc_client),
+        Some(Command::Liquidations { limit, watch, csv }) => {
+            return liquidations::run(&rpc_client, limit, watch, csv)
+        }
+        Some(Command::Leaderboard {
+            limit,
+            min_size,
+            min_collateral,
+            csv,
+        }) => return leaderboard::run(&rpc_client, limit, min_size, min_collateral, csv),
+        Some(Command::TopPositions {
+            limit,
+            anonymize,
+            csv,
+        }) => {
+            let salt = match anonymize {
+                true => Some(top_positions::salt(&config)?),
+                false => None,
+            };
+            return top_positions::run(&rpc_client, limit, salt.as_deref(), csv);
+        }
+        Some(Command::Orders) => return orders::run(&rpc_client),
+        Some(Command::Jlp {
+            command:
+                JlpCommand::Fee {
+                    amount,
+                    token,
+                    redeem,
+                },
+        }) => return jlp::fee(&rpc_client, amount, &token, redeem),
+        Some(Command::Forecast { hours, history }) => {
+            return forecast::run(&rpc_client, hours, history.as_deref())
+        }
+        Some(Command::Risk { format, gap_moves }) => {
+            return risk::run(&rpc_client, format, &gap_moves)
+        }
+        Some(Command::Doctor) => return doctor::run(&rpc_client),
+        Some(Command::Bench { runs }) => return bench::run(&rpc_client, runs),
+        Some(Command::Slippage {
+            price_history,
+            hermes,
+            max_gap,
+        }) => {
+            return slippage::run(
+                &rpc_client,
+                price_history.as_deref(),
+                hermes,
+                args.proxy.as_deref(),
+                max_gap,
+            )
+        }
+        Some(
+            Command::Export { .. }
+            | Command::Funding { .. }
+            | Command::Report { .. }
+            | Command::Tweet { .. }
+            | Command::Fees { .. }
+            | Command::Analyze { .. }
+            | Command::Wallets { .. }
+            | Command::Query { .. }
+            | Command::Compact { .. }
+            | Command::Serve { .. }
+            | Command::Completions { .. }
+            | Command::Man
+            | Command::Schema { .. },
+        )
+        | None => {}
+    }
+
+    let template = args
+        .template
+        .as_deref()
+        .map(SnapshotTemplate::load)
+        .transpose()?;
+
+    #[cfg(feature = "grpc")]
+    let grpc_publisher = args.grpc_listen.map(grpc::spawn).transpose()?;
+    #[cfg(not(feature = "grpc"))]
+    if let Some(listen) = args.grpc_listen {
+        return Err(format!(
+            "can't serve gRPC on {listen}: built without the `grpc` feature, rebuild with `--features grpc`"
+        )
+        .into());
+    }
+
+    #[cfg(not(feature = "nats"))]
+    if let Some(url) = &args.nats {
+        return Err(format!(
+            "can't publish to {url}: built without the `nats` feature, rebuild with `--features nats`"
+        )
+        .into());
+    }
+
+    #[cfg(not(feature = "mqtt"))]
+    if let Some(broker_url) = &args.mqtt {
+        return Err(format!(
+            "can't publish to {broker_url}: built without the `mqtt` feature, rebuild with `--features mqtt`"
+        )
+        .into());
+    }
+
+    let live_view = live::LiveView::default();
+    if args.discord_bot {
+        let discord_config = config
+            .discord
+            .as_ref()
+            .ok_or("--discord-bot needs the bot's token in a [discord] table of the config")?;
+        discord::spawn(discord_config, args.proxy.as_deref(), live_view.clone())?;
+    }
+    if args.telegram_bot {
+        let telegram_config = config
+            .telegram
+            .as_ref()
+            .ok_or("--telegram-bot needs the bot's token in a [telegram] table of the config")?;
+        telegram::spawn(telegram_config, args.proxy.as_deref(), live_view.clone())?;
+    }
+
+    let columns = columns::Columns::from_config(&config.columns)?;
+    #[cfg(feature = "plugins")]
+    let plugins = plugin::Plugins::load(&args.plugins)?;
+    #[cfg(not(feature = "plugins"))]
+    if let Some(path) = args.plugins.first() {
+        return Err(format!(
+            "can't load plugin {}: built without the `plugins` feature, rebuild with `--features plugins`",
+            path.display()
+        )
+        .into());
+    }
+
+    #[cfg(feature = "scripting")]
+    let script = config
+        .script
+        .as_deref()
+        .map(script::Script::load)
+        .transpose()?;
+    #[cfg(not(feature = "scripting"))]
+    if let Some(path) = &config.script {
+        return Err(format!(
+            "can't run script {}: built without the `scripting` feature, rebuild with `--features scripting`",
+            path.display()
+        )
+        .into());
+    }
+
+    #[cfg(feature = "email")]
+    let mailer = email::Mailer::from_config(&config)?;
+    #[cfg(not(feature = "email"))]
+    if !config.emails.is_empty() {
+        return Err(
+            "can't send the configured emails: built without the `email` feature, rebuild with `--features email`"
+                .into(),
         );
     }
 
-    let position_accounts = get_program_accounts_with_discrim(
-        &rpc_client,
-        &perp_abi::ID.to_string(),
-        &perp_abi::state::Position::DISCRIMINATOR,
-    )?;
-
-    let mut num_positions: u64 = 0;
-    let mut num_longs: u64 = 0;
-    let mut num_winning: u64 = 0;
-    let mut long_short_sign: f64 = 0.0;
-    let mut cumulative_positions: f64 = 0.0;
-    let mut cumulative_long: f64 = 0.0;
-    let mut cumulative_positions_at_entry: f64 = 0.0;
-    let mut cumulative_collateral: f64 = 0.0;
-    let mut cumulative_collateral_at_entry: f64 = 0.0;
-    let mut cumulative_fees: f64 = 0.0;
-    let mut cumulative_pnl: f64 = 0.0;
-
-    let mut highest_unrealized_profit: f64 = 0.0;
-    let mut highest_unrealized_losses: f64 = 0.0;
-
-    let mut most_profitable_trade: (Pubkey, f64, f64, perp_abi::Side, Pubkey) = Default::default();
-    let mut least_profitable_trade: (Pubkey, f64, f64, perp_abi::Side, Pubkey) = Default::default();
-
-    for (position_pubkey, position) in position_accounts {
-        let position = perp_abi::state::Position::try_deserialize(&mut &*position.data)?;
-        if position.size_usd != 0 {
-            num_positions += 1;
-
-            let mint = pubkey_to_custody.get(&position.custody).unwrap().mint;
-            let amount = (position.size_usd as f64).div(position.price as f64);
-            let price_at_entry = spl_token::amount_to_ui_amount(position.price, 6);
-            let price = mint_to_price.get(&mint).unwrap();
-            let interval = (unix_time.sub(position.update_time as u64) as f64).div(3600.0);
-
-            let current_position_value: f64 = amount.mul(price);
-            let position_value_at_entry = spl_token::amount_to_ui_amount(position.size_usd, 6);
-
-            let entry_fees: f64 = position_value_at_entry
-                .mul(pool.fees.increase_position_bps as f64)
-                .div(10_000.0);
-
-            let borrow_fees: f64 = custody_pubkey_to_borrow_rate
-                .get(&position.collateral_custody)
-                .unwrap()
-                // mul by hours
-                .mul(interval)
-                // get value in USD
-                .mul(position_value_at_entry)
-                // BPS to absolute value
-                .div(10_000.0);
-
-            if let perp_abi::Side::Long = position.side {
-                num_longs += 1;
-                long_short_sign = 1.0;
-                cumulative_long += current_position_value;
-            } else if let perp_abi::Side::Short = position.side {
-                long_short_sign = -1.0;
-            }
+    let alert_tracker = RefCell::new(alerts::AlertTracker::new(
+        args.alert_cooldown,
+        args.alert_after,
+        args.alert_state.clone(),
+    )?);
+    let position_index = RefCell::new(lifecycle::PositionIndex::load(args.position_index.clone())?);
 
-            let collateral_at_entry = spl_token::amount_to_ui_amount(position.collateral_usd, 6);
-            let current_collateral: f64 =
-            // get collateral at entry
-            collateral_at_entry.add(
-                    // add difference in value between now and entry
-                    amount
-                        .mul(price.sub(price_at_entry))
-                        // short's price is reversed
-                        .mul(long_short_sign),
+    let mut sinks = FanOut::default();
+    for spec in &args.sink {
+        sinks.push(spec.open(args.record_positions, args.proxy.as_deref(), &config)?);
+    }
+    #[cfg(feature = "nats")]
+    if let Some(url) = &args.nats {
+        sinks.push(Box::new(nats::NatsPublisher::connect(
+            url,
+            &args.nats_prefix,
+            &args.nats_stream,
+        )?));
+    }
+    if let Some(url) = &args.elasticsearch {
+        sinks.push(Box::new(elasticsearch::ElasticsearchSink::connect(
+            url,
+            &args.elasticsearch_prefix,
+            args.proxy.as_deref(),
+            &config.headers_for(url),
+        )?));
+    }
+    if let Some(destination) = &args.signals {
+        sinks.push(Box::new(signals::SignalFeed::open(
+            destination,
+            &config.wallets,
+            args.proxy.as_deref(),
+            &config.headers_for(destination),
+        )?));
+    }
+    if let Some(destination) = &args.events {
+        sinks.push(Box::new(event_stream::EventStream::open(destination)?));
+    }
+    let sinks = RefCell::new(sinks);
+
+    let genesis_hash = rpc_client.get_genesis_hash()?.to_string();
+    let cluster = rpc::Cluster::from_genesis_hash(&genesis_hash);
+    // what --refresh last drew, to highlight what changed since
+    let last_render = RefCell::new(None::<String>);
+    let report = |state: &PerpState,
+                  positions: &[(Pubkey, perp_abi::state::Position)],
+                  totals: Option<&PositionTotals>,
+                  folded: Option<(Snapshot, Vec<(String, String)>)>,
+                  price_consensus: BTreeMap<String, PriceConsensus>,
+                  collection: Duration| {
+        let (mut snapshot, mut sanity_warnings, positions) = match folded {
+            Some((snapshot, sanity_warnings)) => (snapshot, sanity_warnings, vec![]),
+            None => {
+                let position_accounts = positions.len() as u64;
+                let sanity_warnings = sanity::state_violations(state, positions);
+                // every position is indexed, so one leaving the filter doesn't count as closed
+                let lifecycle = position_index.borrow_mut().update(state, positions);
+                let positions: Vec<_> = positions
+                    .iter()
+                    .filter(|(pubkey, position)| {
+                        args.filter
+                            .as_ref()
+                            .map_or(true, |filter| filter.matches(state, pubkey, position))
+                    })
+                    .copied()
+                    .collect();
+                // sums kept by the tracker cover every position, so not a filtered set
+                let mut snapshot = match totals {
+                    Some(totals) if args.filter.is_none() => {
+                        Snapshot::compute_with_totals(state, totals, &positions)
+                    }
+                    _ => Snapshot::compute(state, &positions),
+                };
+                snapshot.position_accounts = position_accounts;
+                snapshot.lifecycle = lifecycle;
+                (snapshot, sanity_warnings, positions)
+            }
+        };
+        snapshot.price_consensus = price_consensus;
+        snapshot.provenance.cluster =
+            cluster.map_or(String::new(), |cluster| cluster.name().to_string());
+        snapshot.provenance.rpc_endpoint = rpc_endpoint.clone();
+        snapshot.provenance.collection_secs = collection.as_secs_f64();
+        if args.program_hash {
+            snapshot.program_hash = Some(rpc::program_hash(&rpc_client)?);
+        }
+        if args.jlp_premium {
+            snapshot.jlp = Some(jlp::price(&rpc_client, state, args.proxy.as_deref())?);
+        }
+        if args.dex_prices {
+            let dex_prices = jupiter::market_prices(state, args.proxy.as_deref())?;
+            for (symbol, market) in &mut snapshot.markets {
+                market.dex_price = dex_prices.get(symbol).copied();
+            }
+        }
+        columns.apply(&mut snapshot);
+        #[cfg(feature = "plugins")]
+        plugins.apply(&mut snapshot)?;
+        #[cfg(feature = "scripting")]
+        let script_alerts = match &script {
+            Some(script) => script.run(&mut snapshot)?,
+            None => vec![],
+        };
+        #[cfg(not(feature = "scripting"))]
+        let script_alerts: Vec<alerts::Alert> = vec![];
+        if let Some(format) = args.porcelain {
+            porcelain::print(&snapshot, format)?;
+        } else if let Some(template) = &template {
+            print!("{}", template.render(&snapshot)?);
+        } else if !args.silent {
+            // read before this snapshot is appended to the history
+            let previous = args
+                .csv_path
+                .as_deref()
+                .map(|csv_path| {
+                    PreviousSnapshot::load(
+                        csv_path,
+                        &positions,
+                        args.sparkline,
+                        args.percentile_days,
+                    )
+                })
+                .transpose()?
+                .flatten();
+            if args.refresh.is_some() {
+                let render = snapshot.render(previous.as_ref());
+                print!(
+                    "{}",
+                    style::redraw(&render, last_render.borrow().as_deref())
                 );
+                last_render.replace(Some(render));
+            } else {
+                snapshot.print(previous.as_ref());
+            }
+        }
 
-            cumulative_positions_at_entry += position_value_at_entry;
-            cumulative_collateral_at_entry += collateral_at_entry;
-            cumulative_positions += current_position_value;
-            cumulative_collateral += current_collateral;
-
-            // paper unrealized pnl
-            let unrealized_pnl = current_position_value
-                .sub(position_value_at_entry)
-                .mul(long_short_sign);
-
-            if unrealized_pnl > 0.0 {
-                num_winning += 1;
-                if unrealized_pnl > highest_unrealized_profit {
-                    highest_unrealized_profit = unrealized_pnl;
-                    most_profitable_trade = (
-                        position_pubkey,
-                        unrealized_pnl,
-                        price_at_entry,
-                        position.side,
-                        mint,
-                    );
+        // compared before the snapshot joins the history
+        let history = match &args.csv_path {
+            Some(csv_path) => store::read_existing_records::<SnapshotRecord>(csv_path)?,
+            None => vec![],
+        };
+        let mut alerts = vec![];
+        if let Some(previous) = history.iter().max_by_key(|record| record.unix_time) {
+            sanity_warnings.extend(sanity::account_count_drops(
+                previous,
+                &snapshot,
+                args.max_account_drop / 100.0,
+            ));
+            sanity_warnings.extend(
+                sanity::program_change(previous, &snapshot)
+                    .map(|message| ("program-upgrade".to_string(), message)),
+            );
+        }
+        for (key, message) in sanity_warnings {
+            if args.porcelain.is_none() && template.is_none() && !args.silent {
+                println!("{}", style::warning(format!("Warning: {message}")));
+            }
+            alerts.push(alerts::Alert { key, message });
+        }
+        if let (true, Some(csv_path)) = (args.anomalies, &args.csv_path) {
+            for anomaly in anomaly::detect(
+                csv_path,
+                state,
+                &snapshot,
+                args.anomaly_span,
+                args.anomaly_threshold,
+            )? {
+                if args.porcelain.is_none() && template.is_none() && !args.silent {
+                    println!("{}", style::warning(format!("Anomaly: {anomaly}")));
                 }
+                alerts.push(anomaly.to_alert());
             }
+        }
+        for (symbol, market) in &snapshot.markets {
+            let (Some(dex_price), Some(divergence)) = (market.dex_price, market.dex_divergence())
+            else {
+                continue;
+            };
+            if divergence.abs() * 100.0 <= args.dex_divergence {
+                continue;
+            }
+            let message = format!(
+                "{symbol} oracle price ${:.4} is {:+.2}% off the DEX price ${dex_price:.4}",
+                market.price,
+                (market.price / dex_price - 1.0) * 100.0,
+            );
+            if args.porcelain.is_none() && template.is_none() && !args.silent {
+                println!("{}", style::warning(format!("Warning: {message}")));
+            }
+            alerts.push(alerts::Alert {
+                key: format!("dex-divergence:{symbol}"),
+                message,
+            });
+        }
+        for alert in script_alerts {
+            if args.porcelain.is_none() && template.is_none() && !args.silent {
+                println!("{}", style::warning(format!("Alert: {}", alert.message)));
+            }
+            alerts.push(alert);
+        }
+        position_index.borrow().save()?;
 
-            if highest_unrealized_losses > unrealized_pnl {
-                highest_unrealized_losses = unrealized_pnl;
-                least_profitable_trade = (
-                    position_pubkey,
-                    unrealized_pnl,
-                    price_at_entry,
-                    position.side,
-                    mint,
-                );
+        // CSV exports for plotting data over time. The snapshot row goes last, so an
+        // interrupted run never leaves a snapshot with only some of its child rows.
+        if let Some(csv_path) = &args.csv_path {
+            store::append_records(
+                &store::child_path(csv_path, CustodyRecord::TABLE),
+                &snapshot::custody_records(state),
+            )?;
+            store::append_records(
+                &store::child_path(csv_path, MarketRecord::TABLE),
+                &snapshot::market_records(&snapshot),
+            )?;
+            if !config.wallets.is_empty() {
+                store::append_records(
+                    &store::child_path(csv_path, WalletRecord::TABLE),
+                    &snapshot::wallet_records(state, &positions, &config.wallets),
+                )?;
             }
+            if args.record_positions {
+                store::append_records(
+                    &store::child_path(csv_path, PositionRecord::TABLE),
+                    &snapshot::position_records(state, &positions),
+                )?;
+            }
+            store::append_records(
+                csv_path,
+                &[snapshot.to_record().with_trailing_changes(&history)],
+            )?;
+        }
+        if let Some(price_history) = &args.price_history {
+            store::append_records(price_history, &slippage::oracle_price_records(state))?;
+        }
+        if let Some(funding_history) = &args.funding_history {
+            store::append_records(
+                funding_history,
+                &funding::funding_records(state, &positions),
+            )?;
+        }
 
-            cumulative_pnl += unrealized_pnl;
-            cumulative_fees += entry_fees.mul(2.0).add(borrow_fees);
+        // alerts go out once the snapshot is stored, and only count as sent once delivered
+        let mut tracker = alert_tracker.borrow_mut();
+        let alerts = tracker.update(snapshot.unix_time, alerts);
+        let delivery = (|| {
+            if let Some(webhook_url) = &args.alert_webhook {
+                alerts::send(webhook_url, args.proxy.as_deref(), &alerts)?;
+            }
+            #[cfg(feature = "email")]
+            if let Some(mailer) = &mailer {
+                mailer.send_alerts(&alerts)?;
+            }
+            Ok::<(), Box<dyn std::error::Error>>(())
+        })();
+        if delivery.is_ok() {
+            tracker.delivered(snapshot.unix_time, &alerts);
         }
-    }
+        tracker.save()?;
+        delivery?;
 
-    let average_leverage_at_entry =
-        (cumulative_positions_at_entry as f64).div(cumulative_collateral_at_entry as f64);
-    let average_effective_leverage =
-        (cumulative_positions as f64).div(cumulative_collateral as f64);
-    let num_short = num_positions.sub(num_longs);
-
-    if !args.silent {
-        // Desperately need string interpolation in rust
-        let total_pool_value_str = total_pool_value.round().separate_with_commas();
-        let unrealized_pnl = cumulative_pnl.round().separate_with_commas();
-        let total_fees = cumulative_fees.round().separate_with_commas();
-        let real_unrealized_pnl = cumulative_pnl
-            .sub(cumulative_fees)
-            .round()
-            .separate_with_commas();
-        let total_position_value = cumulative_positions.round().separate_with_commas();
-        let total_collateral_value = cumulative_collateral.round().separate_with_commas();
-        let value_long = cumulative_long.round().separate_with_commas();
-        let value_short = cumulative_positions
-            .sub(cumulative_long)
-            .round()
-            .separate_with_commas();
-        let long_short_ratio = (num_longs as f64).div(num_positions.sub(num_longs) as f64);
-        let long_short_value = cumulative_long.div(cumulative_positions.sub(cumulative_long));
-        let num_losing = num_positions.sub(num_winning);
-        println!(
-            "Unix time: {unix_time}
-Total pool value: ${total_pool_value_str}
-Total traders unrealized paper P&L: ${unrealized_pnl}
-Total traders fees: ${total_fees}
-Total traders unrealized real P&L ${real_unrealized_pnl}
-Total value of positions: ${total_position_value}
-Total value of collateral: ${total_collateral_value}
-Average leverage at entry: {average_leverage_at_entry:.4}
-Average effective leverage: {average_effective_leverage:.4}
-Long trades: {num_longs} (${value_long})
-Short trades: {num_short} (${value_short})
-L/S ratio: {long_short_ratio:.4} ({long_short_value:.4})
-Winning trades: {num_winning} Losing trades: {num_losing}"
-        );
+        if let Some(gateway_url) = &args.pushgateway {
+            pushgateway::push(
+                gateway_url,
+                &args.pushgateway_job,
+                &args.pushgateway_label,
+                args.proxy.as_deref(),
+                &snapshot,
+            )?;
+        }
 
-        println!(
-        "Most profitable open trade: {} Open P&L: ${} Entry Price ${:.2} Side: {:?} Mint {}\nMost unprofitable open trade: {} Open P&L: ${} Entry Price ${:.2} Side: {:?} Mint {}",
-        most_profitable_trade.0,
-        most_profitable_trade.1.round().separate_with_commas(),
-        most_profitable_trade.2,
-        most_profitable_trade.3,
-        most_profitable_trade.4,
-        least_profitable_trade.0,
-        least_profitable_trade.1.round().separate_with_commas(),
-        least_profitable_trade.2,
-        least_profitable_trade.3,
-        least_profitable_trade.4,
-        );
+        if let Some(address) = &args.statsd {
+            statsd::emit(address, !args.statsd_no_tags, &snapshot)?;
+        }
+
+        #[cfg(feature = "mqtt")]
+        if let Some(broker_url) = &args.mqtt {
+            mqtt::publish(broker_url, &args.mqtt_prefix, &snapshot)?;
+        }
+
+        sinks.borrow_mut().write(state, &positions, &snapshot)?;
+
+        if args.discord_bot || args.telegram_bot {
+            live_view.update(state, &positions, &snapshot);
+        }
+
+        #[cfg(feature = "grpc")]
+        if let Some(publisher) = &grpc_publisher {
+            publisher.publish(&snapshot);
+        }
+        Ok::<(), Box<dyn std::error::Error>>(())
+    };
+
+    // fallback prices fill in for bad oracles before the consensus weighs the oracle in
+    let price = |state: PerpState| {
+        let state = state.with_price_kind(args.price_kind);
+        let state = match args.fallback_prices {
+            Some(source) => fallback::apply(
+                state,
+                source,
+                args.proxy.as_deref(),
+                &config.headers_for(source.url()),
+            )?,
+            None => state,
+        };
+        if args.consensus.is_empty() && config.price_sources.is_empty() {
+            return Ok((state, BTreeMap::new()));
+        }
+        consensus::apply(
+            state,
+            &args.consensus,
+            args.consensus_tolerance / 100.0,
+            args.proxy.as_deref(),
+            &config,
+        )
+    };
+
+    let mut cache = if args.no_cache {
+        None
+    } else {
+        AccountCache::default_dir().map(|dir| {
+            AccountCache::open(&dir, &genesis_hash, args.cache_ttl)
+                .with_oracle_ttl(Duration::from_secs(args.price_cache_ttl))
+        })
+    };
+
+    let default_prices: Box<dyn PriceProvider> = if args.hermes_prices {
+        Box::new(prices::Hermes {
+            client: transport::blocking_client(args.proxy.as_deref())?,
+        })
+    } else {
+        Box::new(prices::PythLegacy)
+    };
+    let price_providers =
+        PriceProviders::from_config(default_prices, &config.oracles, args.proxy.as_deref())?;
+
+    // accounts streamed from Geyser take the place of fetching them each cycle
+    #[cfg(feature = "geyser")]
+    let geyser_source = args
+        .geyser_url
+        .as_deref()
+        .map(|endpoint| {
+            geyser::GeyserSource::start(
+                &rpc_client,
+                endpoint,
+                args.geyser_token.clone(),
+                &price_providers,
+            )
+        })
+        .transpose()?;
+    #[cfg(not(feature = "geyser"))]
+    if let Some(endpoint) = &args.geyser_url {
+        return Err(format!(
+            "can't stream from {endpoint}: built without the `geyser` feature, rebuild with `--features geyser`"
+        )
+        .into());
     }
 
-    // CSV exports for plotting data over time
-    if let Some(csv_path) = args.csv_path {
-        let csv_file = fs::OpenOptions::new()
-            .create(true)
-            .append(true)
-            .open(csv_path)?;
-        let mut csv_writer = csv::Writer::from_writer(csv_file.try_clone()?);
-        if csv_file.metadata()?.len() == 0 {
-            csv_writer.write_record(&[
-                "Unix Time",
-                "Total Pool Value",
-                "Unrealized Paper P&L",
-                "Total Fees",
-                "Total Value of Positions",
-                "Total Value of Collateral",
-                "Average Leverage At Entry",
-                "Average Effective Leverage",
-                "Long Trades",
-                "Long Value",
-                "Short Trades",
-                "Short Value",
-            ])?;
-        }
-        csv_writer.serialize((
-            unix_time,
-            total_pool_value,
-            cumulative_pnl,
-            cumulative_fees,
-            cumulative_positions,
-            cumulative_collateral,
-            average_leverage_at_entry,
-            average_effective_leverage,
-            num_longs,
-            cumulative_long,
-            num_short,
-            cumulative_positions.sub(cumulative_long),
-        ))?;
-        csv_writer.flush()?;
+    // a one-off paged snapshot folds the positions in as they arrive, unless something reads
+    // them one by one once it's taken
+    let fold_positions = args.paged_positions
+        && args.filter.is_none()
+        && args.position_index.is_none()
+        && args.csv_path.is_none()
+        && args.funding_history.is_none()
+        && sinks.borrow().is_empty()
+        && !args.discord_bot
+        && !args.telegram_bot;
+    // an interrupted scan resumes from the --checkpoint file
+    let page_positions =
+        |f: &mut dyn FnMut(Pubkey, perp_abi::state::Position)| match &args.checkpoint {
+            Some(checkpoint) => for_each_open_position_checkpointed(&rpc_client, checkpoint, f),
+            None => for_each_open_position(&rpc_client, f),
+        };
+    let fold = |state: &PerpState| {
+        let mut accumulator = SnapshotAccumulator::new(state);
+        let mut problems = sanity::PositionProblems::default();
+        page_positions(&mut |pubkey, position| {
+            accumulator.add(&pubkey, &position);
+            problems.add(state, &pubkey, &position);
+        })?;
+        let mut sanity_warnings = sanity::custody_violations(state);
+        sanity_warnings.extend(problems.violations());
+        Ok::<_, Box<dyn std::error::Error>>((accumulator.finish(), sanity_warnings))
+    };
+
+    let mut supervisor = Supervisor::install()?;
+    // a Geyser stream is reported from every minute unless told otherwise
+    let polling = args
+        .watch
+        .or(args.refresh)
+        .or(args.geyser_url.as_ref().map(|_| 60));
+    // the pubsub client connects directly, so behind a proxy positions are rescanned instead
+    let tracking = args.geyser_url.is_none() && (polling.is_some() || args.schedule.is_some());
+    let mut position_tracker = tracking.then(|| {
+        PositionTracker::new(
+            &rpc_client,
+            args.full_refresh_every,
+            args.proxy.is_none(),
+            &rpc_headers,
+            args.paged_positions,
+        )
+    });
+
+    loop {
+        if let Some(schedule) = &args.schedule {
+            supervisor.sleep(schedule.until_next()?);
+            if supervisor.should_stop() {
+                break;
+            }
+        }
+
+        let started = Instant::now();
+        #[cfg(feature = "geyser")]
+        let streamed = match &geyser_source {
+            Some(geyser_source) => Some(geyser_source.load(&price_providers)?),
+            None => None,
+        };
+        #[cfg(not(feature = "geyser"))]
+        let streamed: Option<(PerpState, Vec<(Pubkey, perp_abi::state::Position)>)> = None;
+        let (state, streamed_positions) = match streamed {
+            Some((state, positions)) => (state, Some(positions)),
+            None => (
+                PerpState::load_with_prices(&rpc_client, cache.as_mut(), &price_providers)?,
+                None,
+            ),
+        };
+        // positions are folded into the snapshot at the prices it's taken at
+        let (state, price_consensus) = price(state)?;
+        let mut folded = None;
+        let positions = match (streamed_positions, &mut position_tracker) {
+            (Some(positions), _) => positions,
+            (None, Some(position_tracker)) => position_tracker.open_positions(&rpc_client)?,
+            (None, None) if fold_positions => {
+                folded = Some(fold(&state)?);
+                vec![]
+            }
+            (None, None) if args.paged_positions => {
+                let mut positions = vec![];
+                page_positions(&mut |pubkey, position| positions.push((pubkey, position)))?;
+                positions
+            }
+            (None, None) => load_open_positions(&rpc_client)?,
+        };
+        let totals = position_tracker.as_ref().map(PositionTracker::totals);
+        report(
+            &state,
+            &positions,
+            totals,
+            folded,
+            price_consensus,
+            started.elapsed(),
+        )?;
+        if let (true, Some(cache)) = (args.verbose, &cache) {
+            let (hits, misses) = cache.oracle_stats();
+            eprintln!("Oracle account cache: {hits} hits, {misses} misses");
+        }
+        supervisor.ready();
+
+        if args.schedule.is_some() {
+            continue;
+        }
+        let Some(interval) = polling else {
+            break;
+        };
+        // a shutdown request lets the in-flight snapshot finish and be written out first
+        supervisor.sleep(Duration::from_secs(interval));
+        if supervisor.should_stop() {
+            break;
+        }
     }
+    supervisor.stopping();
+
     Ok(())
 }
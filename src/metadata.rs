@@ -0,0 +1,127 @@
+//! Token names and symbols from Metaplex metadata accounts, for mints without an oracle
+//! symbol, cached on disk since they practically never change.
+
+use std::collections::HashMap;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::fetcher::AccountFetcher;
+use crate::state;
+
+const METADATA_PROGRAM_ID: &str = "metaqbxxUerdq28cj1RbAWkYQm3ybzjb6a8bt518x1s";
+const CACHE_FILE: &str = "token_metadata.json";
+/// Key, update authority and mint preceding the name in a metadata account
+const NAME_OFFSET: usize = 1 + 32 + 32;
+
+static CACHE_DIR: OnceLock<PathBuf> = OnceLock::new();
+
+#[derive(Clone, Default, Serialize, Deserialize)]
+pub struct TokenMetadata {
+    pub name: String,
+    pub symbol: String,
+}
+
+/// Keeps resolved metadata in `dir` for the rest of the process, instead of refetching it
+/// every run
+pub fn set_cache_dir(dir: &Path) -> Result<(), Box<dyn std::error::Error>> {
+    CACHE_DIR
+        .set(dir.to_path_buf())
+        .map_err(|_| "the token metadata cache is already set".into())
+}
+
+/// Metadata account of `mint`
+pub fn metadata_account(mint: &Pubkey) -> Result<Pubkey, Box<dyn std::error::Error>> {
+    let program_id = Pubkey::from_str(METADATA_PROGRAM_ID)?;
+    Ok(Pubkey::find_program_address(
+        &[b"metadata", program_id.as_ref(), mint.as_ref()],
+        &program_id,
+    )
+    .0)
+}
+
+/// Reads a borsh string at `offset`, returning it with the offset past it. Metaplex pads
+/// names and symbols with NULs to their maximum length.
+fn read_string(data: &[u8], offset: usize) -> Option<(String, usize)> {
+    let len = u32::from_le_bytes(data.get(offset..offset + 4)?.try_into().ok()?) as usize;
+    let start = offset + 4;
+    let bytes = data.get(start..start + len)?;
+    let string = String::from_utf8_lossy(bytes)
+        .trim_end_matches('\0')
+        .trim()
+        .to_string();
+    Some((string, start + len))
+}
+
+/// Name and symbol of a metadata account's raw data
+pub fn decode(data: &[u8]) -> Option<TokenMetadata> {
+    let (name, offset) = read_string(data, NAME_OFFSET)?;
+    let (symbol, _) = read_string(data, offset)?;
+    Some(TokenMetadata { name, symbol })
+}
+
+fn read_cache(path: &Path) -> HashMap<String, TokenMetadata> {
+    fs::read(path)
+        .ok()
+        .and_then(|contents| serde_json::from_slice(&contents).ok())
+        .unwrap_or_default()
+}
+
+/// Registers the name of each custody mint, and its symbol unless it already has one. Mints
+/// without a metadata account are cached with an empty name so they aren't looked up again.
+pub fn resolve_names(
+    connection: &dyn AccountFetcher,
+    custodies: &[(Pubkey, perp_abi::state::Custody)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let cache_path = CACHE_DIR.get().map(|dir| dir.join(CACHE_FILE));
+    let mut cache = cache_path.as_deref().map(read_cache).unwrap_or_default();
+
+    let mut mints: Vec<Pubkey> = custodies
+        .iter()
+        .map(|(_, custody)| custody.mint)
+        .filter(|mint| state::token_name(mint).is_none())
+        .collect();
+    mints.sort();
+    mints.dedup();
+    let missing: Vec<Pubkey> = mints
+        .iter()
+        .filter(|mint| !cache.contains_key(&mint.to_string()))
+        .copied()
+        .collect();
+    if !missing.is_empty() {
+        let accounts = missing
+            .iter()
+            .map(metadata_account)
+            .collect::<Result<Vec<_>, _>>()?;
+        let fetched = connection.multiple_accounts(&accounts)?;
+        for (mint, account) in missing.iter().zip(fetched) {
+            let metadata = account
+                .and_then(|account| decode(&account.data))
+                .unwrap_or_default();
+            cache.insert(mint.to_string(), metadata);
+        }
+        if let Some(cache_path) = &cache_path {
+            if let Some(dir) = cache_path.parent() {
+                fs::create_dir_all(dir)?;
+            }
+            fs::write(cache_path, serde_json::to_vec(&cache)?)?;
+        }
+    }
+
+    for mint in mints {
+        let Some(metadata) = cache.get(&mint.to_string()) else {
+            continue;
+        };
+        if !metadata.name.is_empty() {
+            state::register_name(mint, metadata.name.clone());
+        }
+        if !metadata.symbol.is_empty() && !state::has_symbol(&mint) {
+            state::register_symbol(mint, metadata.symbol.clone());
+        }
+    }
+    Ok(())
+}
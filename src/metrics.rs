@@ -0,0 +1,142 @@
+//! Flat list of snapshot metrics shared by the metric emitters.
+
+use crate::snapshot::{MarketSnapshot, Snapshot};
+
+pub struct Metric {
+    /// Dot separated name, e.g. `pool.value_usd`
+    pub name: &'static str,
+    pub help: &'static str,
+    pub labels: Vec<(&'static str, String)>,
+    pub value: f64,
+}
+
+impl Metric {
+    fn new(name: &'static str, help: &'static str, value: f64) -> Self {
+        Metric {
+            name,
+            help,
+            labels: vec![],
+            value,
+        }
+    }
+}
+
+pub fn snapshot_metrics(snapshot: &Snapshot) -> Vec<Metric> {
+    let mut metrics = vec![
+        Metric::new(
+            "pool.value_usd",
+            "Total pool value in USD",
+            snapshot.total_pool_value,
+        ),
+        Metric::new(
+            "traders.unrealized_pnl_usd",
+            "Traders unrealized paper P&L in USD",
+            snapshot.cumulative_pnl,
+        ),
+        Metric::new(
+            "traders.fees_usd",
+            "Estimated fees owed by traders in USD",
+            snapshot.cumulative_fees,
+        ),
+        Metric::new(
+            "positions.value_usd",
+            "Total value of open positions in USD",
+            snapshot.cumulative_positions,
+        ),
+        Metric::new(
+            "positions.collateral_usd",
+            "Total value of collateral in USD",
+            snapshot.cumulative_collateral,
+        ),
+        Metric::new(
+            "positions.leverage_at_entry",
+            "Average leverage at entry",
+            snapshot.average_leverage_at_entry(),
+        ),
+        Metric::new(
+            "positions.effective_leverage",
+            "Average effective leverage",
+            snapshot.average_effective_leverage(),
+        ),
+        Metric::new(
+            "positions.long_count",
+            "Number of open long positions",
+            snapshot.num_longs as f64,
+        ),
+        Metric::new(
+            "positions.short_count",
+            "Number of open short positions",
+            snapshot.num_shorts() as f64,
+        ),
+        Metric::new(
+            "positions.long_value_usd",
+            "Total value of open long positions in USD",
+            snapshot.cumulative_long,
+        ),
+        Metric::new(
+            "positions.short_value_usd",
+            "Total value of open short positions in USD",
+            snapshot.cumulative_short(),
+        ),
+        Metric::new(
+            "positions.winning_count",
+            "Number of open positions in profit",
+            snapshot.num_winning as f64,
+        ),
+        Metric::new(
+            "collection.custody_accounts",
+            "Custody accounts fetched",
+            snapshot.custody_accounts as f64,
+        ),
+        Metric::new(
+            "collection.position_accounts",
+            "Open position accounts fetched, before any filter",
+            snapshot.position_accounts as f64,
+        ),
+    ];
+
+    // grouped by name so exposition formats can emit each name's metadata once
+    let market_metrics: [(&str, &str, fn(&MarketSnapshot) -> f64); 6] = [
+        ("oi.long", "Long open interest in USD", |market| {
+            market.long_oi
+        }),
+        ("oi.short", "Short open interest in USD", |market| {
+            market.short_oi
+        }),
+        ("market.price", "Oracle price in USD", |market| market.price),
+        (
+            "market.long_count",
+            "Number of open long positions",
+            |market| market.num_longs as f64,
+        ),
+        (
+            "market.short_count",
+            "Number of open short positions",
+            |market| market.num_shorts as f64,
+        ),
+        (
+            "market.unrealized_pnl_usd",
+            "Traders unrealized paper P&L in USD",
+            |market| market.unrealized_pnl,
+        ),
+    ];
+    for (name, help, value) in market_metrics {
+        for (symbol, market) in &snapshot.markets {
+            metrics.push(Metric {
+                name,
+                help,
+                labels: vec![("market", symbol.clone())],
+                value: value(market),
+            });
+        }
+    }
+    for (name, value) in &snapshot.custom_metrics {
+        metrics.push(Metric {
+            name: "custom.value",
+            help: "Metric added by a plugin",
+            labels: vec![("name", name.clone())],
+            value: *value,
+        });
+    }
+    metrics
+}
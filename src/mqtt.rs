@@ -0,0 +1,76 @@
+use std::time::Duration;
+
+use reqwest::Url;
+use rumqttc::{Client, Event, MqttOptions, Outgoing, Packet, QoS};
+
+use crate::metrics::snapshot_metrics;
+use crate::snapshot::v1::SnapshotV1;
+use crate::snapshot::Snapshot;
+
+/// Topic of each metric under `prefix`, labels appended as levels, e.g. `jupperps/oi/long/SOL`
+fn topics(prefix: &str, snapshot: &Snapshot) -> Vec<(String, String)> {
+    snapshot_metrics(snapshot)
+        .into_iter()
+        .filter(|metric| metric.value.is_finite())
+        .map(|metric| {
+            let mut topic = format!("{prefix}/{}", metric.name.replace('.', "/"));
+            for (_, value) in &metric.labels {
+                topic += &format!("/{value}");
+            }
+            (topic, metric.value.to_string())
+        })
+        .collect()
+}
+
+/// Publishes each snapshot metric as a retained message to the broker at `broker_url`
+/// (`mqtt://[user:pass@]host[:port]`), so subscribers get the latest values right away,
+/// plus the whole snapshot as JSON to `<prefix>/snapshot`
+pub fn publish(
+    broker_url: &str,
+    prefix: &str,
+    snapshot: &Snapshot,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let url = Url::parse(broker_url)?;
+    if url.scheme() != "mqtt" {
+        return Err(format!("unsupported MQTT broker URL {broker_url}, expected mqtt://").into());
+    }
+    let host = url
+        .host_str()
+        .ok_or_else(|| format!("no host in {broker_url}"))?;
+    let mut options = MqttOptions::new(
+        format!("jupiter_perpetuals_analytics-{}", std::process::id()),
+        host,
+        url.port().unwrap_or(1883),
+    );
+    options.set_keep_alive(Duration::from_secs(30));
+    if !url.username().is_empty() {
+        options.set_credentials(url.username(), url.password().unwrap_or_default());
+    }
+
+    let mut messages = topics(prefix, snapshot);
+    messages.push((
+        format!("{prefix}/snapshot"),
+        serde_json::to_string(&SnapshotV1::from(snapshot))?,
+    ));
+    // room for every message so publishing doesn't wait on the event loop below
+    let (client, mut connection) = Client::new(options, messages.len() + 1);
+    for (topic, payload) in &messages {
+        client.publish(topic, QoS::AtLeastOnce, true, payload.as_bytes())?;
+    }
+
+    // disconnect once the broker acknowledged everything
+    let mut unacknowledged = messages.len();
+    for event in connection.iter() {
+        match event? {
+            Event::Incoming(Packet::PubAck(_)) => {
+                unacknowledged -= 1;
+                if unacknowledged == 0 {
+                    client.disconnect()?;
+                }
+            }
+            Event::Outgoing(Outgoing::Disconnect) => break,
+            _ => {}
+        }
+    }
+    Ok(())
+}
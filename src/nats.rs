@@ -0,0 +1,93 @@
+use nats::jetstream::{JetStream, StreamConfig};
+use solana_sdk::pubkey::Pubkey;
+
+use crate::events::position_events;
+use crate::sink::OutputSink;
+use crate::snapshot::v1::SnapshotV1;
+use crate::snapshot::Snapshot;
+use crate::state::PerpState;
+
+/// Publishes snapshots and position changes to JetStream subjects under a prefix
+pub struct NatsPublisher {
+    jetstream: JetStream,
+    prefix: String,
+    /// Snapshot written as a sink, published on the flush
+    pending: Option<Vec<u8>>,
+}
+
+impl NatsPublisher {
+    /// Connects to the NATS server at `url` and creates the JetStream `stream` capturing
+    /// `<prefix>.>` if it doesn't exist yet
+    pub fn connect(
+        url: &str,
+        prefix: &str,
+        stream: &str,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let jetstream = nats::jetstream::new(nats::connect(url)?);
+        if jetstream.stream_info(stream).is_err() {
+            jetstream.add_stream(StreamConfig {
+                name: stream.to_string(),
+                subjects: vec![format!("{prefix}.>")],
+                ..Default::default()
+            })?;
+        }
+        Ok(NatsPublisher {
+            jetstream,
+            prefix: prefix.to_string(),
+            pending: None,
+        })
+    }
+
+    /// Publishes the position changes `snapshot` found to `<prefix>.positions.<kind>`, then
+    /// `snapshot` to `<prefix>.snapshot` in the versioned data model, as JSON. Each
+    /// publish waits for JetStream to acknowledge it's persisted.
+    pub fn publish(&self, snapshot: &Snapshot) -> Result<(), Box<dyn std::error::Error>> {
+        self.publish_events(snapshot)?;
+        self.publish_snapshot(&serde_json::to_vec(&SnapshotV1::from(snapshot))?)
+    }
+
+    fn publish_events(&self, snapshot: &Snapshot) -> Result<(), Box<dyn std::error::Error>> {
+        for event in position_events(snapshot) {
+            self.jetstream.publish(
+                &format!("{}.positions.{}", self.prefix, event.kind.as_str()),
+                serde_json::to_vec(&event)?,
+            )?;
+        }
+        Ok(())
+    }
+
+    fn publish_snapshot(&self, message: &[u8]) -> Result<(), Box<dyn std::error::Error>> {
+        self.jetstream
+            .publish(&format!("{}.snapshot", self.prefix), message)?;
+        Ok(())
+    }
+}
+
+/// As a sink, the snapshot is held back until the flush so it still follows its position
+/// changes
+impl OutputSink for NatsPublisher {
+    fn write_snapshot(
+        &mut self,
+        _state: &PerpState,
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.pending = Some(serde_json::to_vec(&SnapshotV1::from(snapshot))?);
+        Ok(())
+    }
+
+    fn write_positions(
+        &mut self,
+        _state: &PerpState,
+        _positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.publish_events(snapshot)
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.pending.take() {
+            Some(message) => self.publish_snapshot(&message),
+            None => Ok(()),
+        }
+    }
+}
@@ -0,0 +1,99 @@
+use std::collections::{BTreeMap, HashMap};
+
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::state::{load_open_position_summaries, load_pending_position_requests, PerpState};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord)]
+pub enum OrderKind {
+    Limit,
+    TakeProfit,
+    StopLoss,
+}
+
+/// Classifies a pending trigger request, market requests have no kind
+pub fn order_kind(request: &perp_abi::state::PositionRequest) -> Option<OrderKind> {
+    if let perp_abi::RequestType::Market = request.request_type {
+        return None;
+    }
+    if let perp_abi::RequestChange::Increase = request.request_change {
+        return Some(OrderKind::Limit);
+    }
+    // closing a long above the trigger price or a short below it takes profit
+    let above = request.trigger_above_threshold.unwrap_or_default();
+    let is_long = matches!(request.side, perp_abi::Side::Long);
+    if above == is_long {
+        Some(OrderKind::TakeProfit)
+    } else {
+        Some(OrderKind::StopLoss)
+    }
+}
+
+#[derive(Default)]
+struct PriceLevel {
+    count: u64,
+    size_usd: u64,
+}
+
+/// Prints pending trigger orders grouped by market and trigger price
+pub fn run(connection: &RpcClient) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let position_to_size: HashMap<_, _> = load_open_position_summaries(connection)?
+        .into_iter()
+        .map(|position| (position.pubkey, position.size_usd))
+        .collect();
+
+    let mut book: BTreeMap<String, BTreeMap<(u64, OrderKind), PriceLevel>> = BTreeMap::new();
+    for (_, request) in load_pending_position_requests(connection)? {
+        let (Some(kind), Some(trigger_price)) = (order_kind(&request), request.trigger_price)
+        else {
+            continue;
+        };
+        let symbol = state
+            .custodies
+            .get(&request.custody)
+            .map(|custody_state| custody_state.symbol())
+            .unwrap_or_else(|| request.custody.to_string());
+        let size_usd = if request.entire_position.unwrap_or_default() {
+            position_to_size
+                .get(&request.position)
+                .copied()
+                .unwrap_or_default()
+        } else {
+            request.size_usd_delta
+        };
+
+        let level = book
+            .entry(symbol)
+            .or_default()
+            .entry((trigger_price, kind))
+            .or_default();
+        level.count += 1;
+        level.size_usd += size_usd;
+    }
+
+    for (symbol, levels) in book {
+        println!("{symbol}");
+        let mut total_size_usd = 0;
+        for ((trigger_price, kind), level) in levels.iter().rev() {
+            total_size_usd += level.size_usd;
+            println!(
+                "  ${:<12.4} {:<10} {:>4} orders ${}",
+                spl_token::amount_to_ui_amount(*trigger_price, 6),
+                format!("{kind:?}"),
+                level.count,
+                spl_token::amount_to_ui_amount(level.size_usd, 6)
+                    .round()
+                    .separate_with_commas(),
+            );
+        }
+        println!(
+            "  Total: ${}",
+            spl_token::amount_to_ui_amount(total_size_usd, 6)
+                .round()
+                .separate_with_commas()
+        );
+    }
+    Ok(())
+}
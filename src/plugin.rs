@@ -0,0 +1,88 @@
+//! WASM plugins adding custom metrics to each snapshot. A plugin is a module exporting its
+//! `memory`, `alloc(len: i32) -> i32` and `metrics(ptr: i32, len: i32) -> i64`: the
+//! snapshot is written as JSON in the versioned model to memory `alloc` returned, and
+//! `metrics` returns the location of a JSON object of metric names to numbers, its offset
+//! in the high 32 bits and its length in the low ones.
+//!
+//! Plugins run sandboxed, without imports, and with a fuel limit so a plugin stuck in a
+//! loop fails the snapshot instead of hanging the watch loop.
+
+use std::collections::BTreeMap;
+use std::path::PathBuf;
+
+use wasmtime::{Engine, Instance, Module, Store};
+
+use crate::snapshot::v1::SnapshotV1;
+use crate::snapshot::Snapshot;
+
+/// Instructions, roughly, a plugin may run per snapshot
+const FUEL: u64 = 1_000_000_000;
+
+pub struct Plugins {
+    engine: Engine,
+    modules: Vec<(PathBuf, Module)>,
+}
+
+impl Plugins {
+    /// Compiles the plugins at `paths`
+    pub fn load(paths: &[PathBuf]) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut config = wasmtime::Config::new();
+        config.consume_fuel(true);
+        let engine = Engine::new(&config)?;
+        let modules = paths
+            .iter()
+            .map(|path| {
+                let module = Module::from_file(&engine, path)
+                    .map_err(|err| format!("can't load plugin {}: {err}", path.display()))?;
+                Ok((path.clone(), module))
+            })
+            .collect::<Result<_, Box<dyn std::error::Error>>>()?;
+        Ok(Plugins { engine, modules })
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.modules.is_empty()
+    }
+
+    /// Runs every plugin on `snapshot` and adds the metrics they return to its custom
+    /// metrics, a later plugin's value winning where names collide
+    pub fn apply(&self, snapshot: &mut Snapshot) -> Result<(), Box<dyn std::error::Error>> {
+        if self.is_empty() {
+            return Ok(());
+        }
+        let input = serde_json::to_vec(&SnapshotV1::from(&*snapshot))?;
+        for (path, module) in &self.modules {
+            let metrics = self
+                .run(module, &input)
+                .map_err(|err| format!("plugin {} failed: {err}", path.display()))?;
+            snapshot.custom_metrics.extend(metrics);
+        }
+        Ok(())
+    }
+
+    /// One plugin's metrics, from a fresh instance so no state carries over between
+    /// snapshots
+    fn run(
+        &self,
+        module: &Module,
+        input: &[u8],
+    ) -> Result<BTreeMap<String, f64>, Box<dyn std::error::Error>> {
+        let mut store = Store::new(&self.engine, ());
+        store.set_fuel(FUEL)?;
+        let instance = Instance::new(&mut store, module, &[])?;
+        let memory = instance
+            .get_memory(&mut store, "memory")
+            .ok_or("no exported memory")?;
+        let alloc = instance.get_typed_func::<u32, u32>(&mut store, "alloc")?;
+        let metrics = instance.get_typed_func::<(u32, u32), u64>(&mut store, "metrics")?;
+
+        let len = u32::try_from(input.len())?;
+        let ptr = alloc.call(&mut store, len)?;
+        memory.write(&mut store, ptr as usize, input)?;
+        let packed = metrics.call(&mut store, (ptr, len))?;
+        let (out_ptr, out_len) = ((packed >> 32) as usize, (packed & 0xffff_ffff) as usize);
+        let mut output = vec![0; out_len];
+        memory.read(&store, out_ptr, &mut output)?;
+        Ok(serde_json::from_slice(&output)?)
+    }
+}
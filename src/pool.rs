@@ -0,0 +1,102 @@
+use std::ops::{Div as _, Mul as _};
+
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::state::PerpState;
+use crate::table;
+
+fn bps_to_percent(bps: u64) -> f64 {
+    (bps as f64).div(100.0)
+}
+
+/// Prints the pool's fee schedule, limits and custodies
+pub fn run(connection: &RpcClient) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let pool = &state.pool;
+    let fees = &pool.fees;
+    let total_pool_value = state.total_pool_value();
+
+    println!(
+        "Pool: {} ({})
+AUM: ${}
+Max AUM: ${}
+Max individual LP token: {}
+Max position size: ${}
+Max request execution: {}s
+Fee APR: {:.2}%
+Realized fees: ${}
+
+Fees:
+  Increase position: {:.2}%
+  Decrease position: {:.2}%
+  Add/remove liquidity: {:.2}%
+  Swap: {:.2}%
+  Tax: {:.2}%
+  Stable swap: {:.2}%
+  Stable swap tax: {:.2}%
+  Liquidation reward: {:.2}%
+  Protocol share: {:.2}%
+
+Custodies:",
+        pool.name,
+        state.pool_pubkey,
+        total_pool_value.round().separate_with_commas(),
+        spl_token::amount_to_ui_amount(pool.limit.max_aum_usd as u64, 6)
+            .round()
+            .separate_with_commas(),
+        spl_token::amount_to_ui_amount(pool.limit.max_individual_lp_token as u64, 6)
+            .round()
+            .separate_with_commas(),
+        spl_token::amount_to_ui_amount(pool.limit.max_position_usd, 6)
+            .round()
+            .separate_with_commas(),
+        pool.max_request_execution_sec,
+        bps_to_percent(pool.pool_apr.fee_apr_bps),
+        spl_token::amount_to_ui_amount(pool.pool_apr.realized_fee_usd, 6)
+            .round()
+            .separate_with_commas(),
+        bps_to_percent(fees.increase_position_bps),
+        bps_to_percent(fees.decrease_position_bps),
+        bps_to_percent(fees.add_remove_liquidity_bps),
+        bps_to_percent(fees.swap_bps),
+        bps_to_percent(fees.tax_bps),
+        bps_to_percent(fees.stable_swap_bps),
+        bps_to_percent(fees.stable_swap_tax_bps),
+        bps_to_percent(fees.liquidation_reward_bps),
+        bps_to_percent(fees.protocol_share_bps),
+    );
+
+    let rows = state
+        .custodies_in_pool_order()
+        .into_iter()
+        .map(|custody_state| {
+            let custody = &custody_state.custody;
+            let owned_value =
+                spl_token::amount_to_ui_amount(custody.assets.owned, custody.decimals)
+                    .mul(custody_state.price());
+            vec![
+                custody_state.symbol(),
+                custody_state.pubkey.to_string(),
+                format!("{:.2}%", bps_to_percent(custody.target_ratio_bps)),
+                format!("{:.2}%", owned_value.div(total_pool_value).mul(100.0)),
+                format!("${}", owned_value.round().separate_with_commas()),
+            ]
+        })
+        .collect();
+    println!(
+        "{}",
+        table::render(
+            &[
+                "Market",
+                "Custody",
+                "Target weight",
+                "Current weight",
+                "Value"
+            ],
+            rows
+        )
+    );
+
+    Ok(())
+}
@@ -0,0 +1,52 @@
+//! Machine-readable snapshot output for scripts, in the versioned data model of
+//! [`crate::snapshot::v1`].
+
+use clap::ValueEnum;
+use schemars::schema::RootSchema;
+use schemars::schema_for;
+
+use crate::events::PositionEvent;
+use crate::snapshot::v1::SnapshotV1;
+use crate::snapshot::Snapshot;
+use crate::store::PositionRecord;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum PorcelainFormat {
+    /// One JSON object per line
+    Json,
+    /// One tab-separated line of the top-level fields, in documented order
+    Tsv,
+}
+
+/// JSON documents a schema can be printed for
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum SchemaDocument {
+    /// A `--porcelain json` snapshot line
+    Snapshot,
+    /// A position row stored with `--record-positions` in a JSON lines history
+    Position,
+    /// A position change published by the streaming sinks
+    PositionEvent,
+}
+
+/// Prints the snapshot as a single line in `format`
+pub fn print(
+    snapshot: &Snapshot,
+    format: PorcelainFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let document = SnapshotV1::from(snapshot);
+    match format {
+        PorcelainFormat::Json => println!("{}", serde_json::to_string(&document)?),
+        PorcelainFormat::Tsv => println!("{}", document.tsv_line()),
+    }
+    Ok(())
+}
+
+/// JSON Schema of `document`, for consumers to validate against or generate code from
+pub fn schema(document: SchemaDocument) -> RootSchema {
+    match document {
+        SchemaDocument::Snapshot => schema_for!(SnapshotV1),
+        SchemaDocument::Position => schema_for!(PositionRecord),
+        SchemaDocument::PositionEvent => schema_for!(PositionEvent),
+    }
+}
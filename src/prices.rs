@@ -0,0 +1,384 @@
+//! Price providers the custodies are priced from. Each market is priced by the provider
+//! configured for it, or the default one, so an oracle migration or a test scenario is a
+//! configuration change rather than a change to the loaders or the aggregation.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::Mul as _;
+use std::str::FromStr;
+use std::time::SystemTime;
+
+use solana_sdk::pubkey::Pubkey;
+
+use crate::cache::AccountCache;
+use crate::fetcher::AccountFetcher;
+use crate::pyth;
+use crate::rpc::{decode_oracle_price, get_accounts_data, OraclePrice};
+use crate::state;
+
+/// Prices custodies, from accounts fetched for it or from elsewhere
+pub trait PriceProvider {
+    /// Accounts the provider reads to price `custody`, fetched with those of every other
+    /// provider in one batch
+    fn accounts(&self, _custody: &perp_abi::state::Custody) -> Vec<Pubkey> {
+        vec![]
+    }
+
+    /// Prices of `custodies`, keyed by custody, leaving out those it can't price
+    fn prices(
+        &self,
+        custodies: &[&(Pubkey, perp_abi::state::Custody)],
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>>;
+}
+
+/// Prices each custody from its own oracle account with `decode`
+fn account_prices(
+    custodies: &[&(Pubkey, perp_abi::state::Custody)],
+    accounts: &HashMap<Pubkey, Vec<u8>>,
+    decode: impl Fn(&Pubkey, &[u8]) -> Option<OraclePrice>,
+) -> HashMap<Pubkey, OraclePrice> {
+    custodies
+        .iter()
+        .filter_map(|(custody_pubkey, custody)| {
+            let oracle_account = &custody.oracle.oracle_account;
+            let oracle = decode(oracle_account, accounts.get(oracle_account)?)?;
+            Some((*custody_pubkey, oracle))
+        })
+        .collect()
+}
+
+fn read_i64(data: &[u8], offset: usize) -> Option<i64> {
+    Some(i64::from_le_bytes(
+        data.get(offset..offset + 8)?.try_into().ok()?,
+    ))
+}
+
+/// Legacy Pyth push oracle price accounts
+pub struct PythLegacy;
+
+impl PriceProvider for PythLegacy {
+    fn accounts(&self, custody: &perp_abi::state::Custody) -> Vec<Pubkey> {
+        vec![custody.oracle.oracle_account]
+    }
+
+    fn prices(
+        &self,
+        custodies: &[&(Pubkey, perp_abi::state::Custody)],
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        Ok(account_prices(custodies, accounts, |pubkey, data| {
+            decode_oracle_price(pubkey, data).ok()
+        }))
+    }
+}
+
+/// `PriceUpdateV2` accounts posted by the Pyth pull oracle's receiver program
+pub struct PythPull;
+
+impl PythPull {
+    /// Decodes a `PriceUpdateV2`: discriminator, write authority, verification level, then
+    /// the price message
+    pub fn decode(data: &[u8]) -> Option<OraclePrice> {
+        // a partial verification level carries its signature count
+        let message = match data.get(8 + 32)? {
+            0 => 8 + 32 + 2,
+            1 => 8 + 32 + 1,
+            _ => return None,
+        };
+        let price = read_i64(data, message + 32)?;
+        let exponent = i32::from_le_bytes(data.get(message + 48..message + 52)?.try_into().ok()?);
+        let publish_time = read_i64(data, message + 52)?;
+        let ema_price = read_i64(data, message + 68)?;
+        let scale = 10f64.powi(exponent);
+        Some(OraclePrice {
+            price: (price as f64).mul(scale),
+            ema_price: (ema_price as f64).mul(scale),
+            publish_time,
+            fallback: None,
+        })
+    }
+}
+
+impl PriceProvider for PythPull {
+    fn accounts(&self, custody: &perp_abi::state::Custody) -> Vec<Pubkey> {
+        vec![custody.oracle.oracle_account]
+    }
+
+    fn prices(
+        &self,
+        custodies: &[&(Pubkey, perp_abi::state::Custody)],
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        Ok(account_prices(custodies, accounts, |_, data| {
+            PythPull::decode(data)
+        }))
+    }
+}
+
+/// Jupiter's Doves oracle price feeds
+pub struct Doves;
+
+impl Doves {
+    /// Decodes a Doves `PriceFeed`: discriminator, pair and signer, then the price, its
+    /// exponent and timestamp. Doves keeps no moving average, so the EMA is the price.
+    pub fn decode(data: &[u8]) -> Option<OraclePrice> {
+        let offset = 8 + 32 + 32;
+        let price = u64::from_le_bytes(data.get(offset..offset + 8)?.try_into().ok()?);
+        let exponent = *data.get(offset + 8)? as i8;
+        let timestamp = read_i64(data, offset + 9)?;
+        let price = (price as f64).mul(10f64.powi(exponent.into()));
+        Some(OraclePrice {
+            price,
+            ema_price: price,
+            publish_time: timestamp,
+            fallback: None,
+        })
+    }
+}
+
+impl PriceProvider for Doves {
+    fn accounts(&self, custody: &perp_abi::state::Custody) -> Vec<Pubkey> {
+        vec![custody.oracle.oracle_account]
+    }
+
+    fn prices(
+        &self,
+        custodies: &[&(Pubkey, perp_abi::state::Custody)],
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        Ok(account_prices(custodies, accounts, |_, data| {
+            Doves::decode(data)
+        }))
+    }
+}
+
+/// Pyth's Hermes HTTP API, by feed id, in one request for every custody with a known feed
+pub struct Hermes {
+    pub client: reqwest::blocking::Client,
+}
+
+impl PriceProvider for Hermes {
+    fn prices(
+        &self,
+        custodies: &[&(Pubkey, perp_abi::state::Custody)],
+        _accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        let feed_ids: Vec<(Pubkey, &str)> = custodies
+            .iter()
+            .filter_map(|(custody_pubkey, custody)| {
+                let symbol = state::mint_symbol(&custody.mint);
+                Some((*custody_pubkey, pyth::feed_id_for_symbol(&symbol)?))
+            })
+            .collect();
+        if feed_ids.is_empty() {
+            return Ok(HashMap::new());
+        }
+        let ids: Vec<&str> = feed_ids.iter().map(|(_, feed_id)| *feed_id).collect();
+        let prices = pyth::latest_prices(&self.client, &ids)?;
+        Ok(feed_ids
+            .into_iter()
+            .filter_map(|(custody_pubkey, feed_id)| Some((custody_pubkey, *prices.get(feed_id)?)))
+            .collect())
+    }
+}
+
+/// A fixed price, published now, for test scenarios and what-ifs
+pub struct Fixed(pub f64);
+
+impl PriceProvider for Fixed {
+    fn prices(
+        &self,
+        custodies: &[&(Pubkey, perp_abi::state::Custody)],
+        _accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)?
+            .as_secs() as i64;
+        Ok(custodies
+            .iter()
+            .map(|(custody_pubkey, _)| {
+                let oracle = OraclePrice {
+                    price: self.0,
+                    ema_price: self.0,
+                    publish_time: now,
+                    fallback: None,
+                };
+                (*custody_pubkey, oracle)
+            })
+            .collect())
+    }
+}
+
+/// A provider named in the config file: `pyth-legacy`, `pyth-pull`, `doves`, `hermes` or
+/// `fixed:<PRICE>`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ProviderName {
+    PythLegacy,
+    PythPull,
+    Doves,
+    Hermes,
+    Fixed(f64),
+}
+
+impl FromStr for ProviderName {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        if let Some(price) = name.strip_prefix("fixed:") {
+            return price
+                .parse()
+                .map(ProviderName::Fixed)
+                .map_err(|err| format!("invalid fixed price {price}: {err}"));
+        }
+        match name {
+            "pyth-legacy" => Ok(ProviderName::PythLegacy),
+            "pyth-pull" => Ok(ProviderName::PythPull),
+            "doves" => Ok(ProviderName::Doves),
+            "hermes" => Ok(ProviderName::Hermes),
+            _ => Err(format!(
+                "unknown price provider {name}, expected pyth-legacy, pyth-pull, doves, hermes or fixed:<PRICE>"
+            )),
+        }
+    }
+}
+
+impl ProviderName {
+    pub fn provider(
+        self,
+        proxy_url: Option<&str>,
+    ) -> Result<Box<dyn PriceProvider>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            ProviderName::PythLegacy => Box::new(PythLegacy),
+            ProviderName::PythPull => Box::new(PythPull),
+            ProviderName::Doves => Box::new(Doves),
+            ProviderName::Hermes => Box::new(Hermes {
+                client: crate::transport::blocking_client(proxy_url)?,
+            }),
+            ProviderName::Fixed(price) => Box::new(Fixed(price)),
+        })
+    }
+}
+
+/// The provider each custody is priced by: the one configured for its market, or the
+/// default
+pub struct PriceProviders {
+    default: Box<dyn PriceProvider>,
+    by_symbol: BTreeMap<String, Box<dyn PriceProvider>>,
+}
+
+impl Default for PriceProviders {
+    /// Legacy Pyth oracle accounts for every market
+    fn default() -> Self {
+        PriceProviders::new(Box::new(PythLegacy))
+    }
+}
+
+impl PriceProviders {
+    pub fn new(default: Box<dyn PriceProvider>) -> Self {
+        PriceProviders {
+            default,
+            by_symbol: BTreeMap::new(),
+        }
+    }
+
+    /// Prices the market `symbol` with `provider` instead of the default
+    pub fn with_market(mut self, symbol: &str, provider: Box<dyn PriceProvider>) -> Self {
+        self.by_symbol.insert(symbol.to_string(), provider);
+        self
+    }
+
+    /// `default`, with the providers named for markets in the config file
+    pub fn from_config(
+        default: Box<dyn PriceProvider>,
+        oracles: &BTreeMap<String, String>,
+        proxy_url: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut providers = PriceProviders::new(default);
+        for (symbol, name) in oracles {
+            let name = ProviderName::from_str(name)
+                .map_err(|err| format!("invalid price provider for {symbol}: {err}"))?;
+            providers = providers.with_market(symbol, name.provider(proxy_url)?);
+        }
+        Ok(providers)
+    }
+
+    /// Market whose own provider prices `custody`, or `None` for the default
+    fn market_of(&self, custody: &perp_abi::state::Custody) -> Option<String> {
+        let symbol = state::mint_symbol(&custody.mint);
+        self.by_symbol.contains_key(&symbol).then_some(symbol)
+    }
+
+    fn provider(&self, market: &Option<String>) -> &dyn PriceProvider {
+        market
+            .as_ref()
+            .and_then(|symbol| self.by_symbol.get(symbol))
+            .unwrap_or(&self.default)
+            .as_ref()
+    }
+
+    /// Custodies grouped by the market whose provider prices them, `None` for the default,
+    /// so HTTP providers make one request each
+    fn groups<'a>(
+        &self,
+        custodies: &'a [(Pubkey, perp_abi::state::Custody)],
+    ) -> BTreeMap<Option<String>, Vec<&'a (Pubkey, perp_abi::state::Custody)>> {
+        let mut groups: BTreeMap<Option<String>, Vec<&(Pubkey, perp_abi::state::Custody)>> =
+            BTreeMap::new();
+        for custody in custodies {
+            groups
+                .entry(self.market_of(&custody.1))
+                .or_default()
+                .push(custody);
+        }
+        groups
+    }
+
+    /// Accounts the providers read to price `custodies`, sorted and deduplicated
+    pub fn accounts(&self, custodies: &[(Pubkey, perp_abi::state::Custody)]) -> Vec<Pubkey> {
+        let mut pubkeys: Vec<Pubkey> = self
+            .groups(custodies)
+            .iter()
+            .flat_map(|(market, members)| {
+                let provider = self.provider(market);
+                members
+                    .iter()
+                    .flat_map(move |(_, custody)| provider.accounts(custody))
+            })
+            .collect();
+        pubkeys.sort();
+        pubkeys.dedup();
+        pubkeys
+    }
+
+    /// Prices of `custodies`, keyed by custody, from `accounts` already fetched
+    pub fn prices_from_accounts(
+        &self,
+        custodies: &[(Pubkey, perp_abi::state::Custody)],
+        accounts: &HashMap<Pubkey, Vec<u8>>,
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        let mut prices = HashMap::new();
+        for (market, members) in &self.groups(custodies) {
+            prices.extend(self.provider(market).prices(members, accounts)?);
+        }
+        Ok(prices)
+    }
+
+    /// Prices of `custodies`, keyed by custody, fetching the accounts every provider needs
+    /// at once, through `cache` if given
+    pub fn prices(
+        &self,
+        connection: &dyn AccountFetcher,
+        cache: Option<&mut AccountCache>,
+        custodies: &[(Pubkey, perp_abi::state::Custody)],
+    ) -> Result<HashMap<Pubkey, OraclePrice>, Box<dyn std::error::Error>> {
+        let pubkeys = self.accounts(custodies);
+        let accounts: HashMap<Pubkey, Vec<u8>> = match (cache, pubkeys.is_empty()) {
+            (_, true) => HashMap::new(),
+            (Some(cache), false) => cache.oracle_accounts(connection, &pubkeys)?,
+            (None, false) => get_accounts_data(connection, &pubkeys)?
+                .into_iter()
+                .collect(),
+        };
+        self.prices_from_accounts(custodies, &accounts)
+    }
+}
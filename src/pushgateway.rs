@@ -0,0 +1,68 @@
+use std::fmt::Write as _;
+
+use crate::metrics::{snapshot_metrics, Metric};
+use crate::snapshot::Snapshot;
+use crate::transport::blocking_client;
+
+const METRIC_PREFIX: &str = "jupperps";
+
+fn prometheus_name(metric: &Metric) -> String {
+    format!("{METRIC_PREFIX}_{}", metric.name.replace('.', "_"))
+}
+
+/// Renders metrics in the Prometheus text exposition format
+pub fn render(metrics: &[Metric]) -> String {
+    let mut out = String::new();
+    let mut last_name = String::new();
+    for metric in metrics {
+        let name = prometheus_name(metric);
+        if name != last_name {
+            let _ = writeln!(out, "# HELP {name} {}", metric.help);
+            let _ = writeln!(out, "# TYPE {name} gauge");
+            last_name = name.clone();
+        }
+        let labels = metric
+            .labels
+            .iter()
+            .map(|(key, value)| format!("{key}=\"{}\"", value.replace('"', "\\\"")))
+            .collect::<Vec<_>>()
+            .join(",");
+        if labels.is_empty() {
+            let _ = writeln!(out, "{name} {}", metric.value);
+        } else {
+            let _ = writeln!(out, "{name}{{{labels}}} {}", metric.value);
+        }
+    }
+    out
+}
+
+/// Replaces the metrics of the job/label group on a Prometheus Pushgateway
+pub fn push(
+    gateway_url: &str,
+    job: &str,
+    labels: &[(String, String)],
+    proxy_url: Option<&str>,
+    snapshot: &Snapshot,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut url = format!("{}/metrics/job/{job}", gateway_url.trim_end_matches('/'));
+    for (key, value) in labels {
+        url += &format!("/{key}/{value}");
+    }
+    let response = blocking_client(proxy_url)?
+        .put(&url)
+        .header("Content-Type", "text/plain; version=0.0.4")
+        .body(render(&snapshot_metrics(snapshot)))
+        .send()?;
+    if !response.status().is_success() {
+        return Err(format!("pushgateway {url} responded {}", response.status()).into());
+    }
+    Ok(())
+}
+
+/// Parses a `key=value` label
+pub fn parse_label(label: &str) -> Result<(String, String), String> {
+    label
+        .split_once('=')
+        .map(|(key, value)| (key.to_string(), value.to_string()))
+        .ok_or_else(|| format!("expected key=value, got {label}"))
+}
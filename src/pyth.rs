@@ -0,0 +1,181 @@
+//! Pyth's Hermes HTTP API, serving the same price feeds as the on-chain oracle accounts,
+//! including at past timestamps that would otherwise need archival account state, and the
+//! on-chain product accounts naming the markets its feeds price.
+
+use std::collections::HashMap;
+use std::ops::Mul as _;
+
+use pyth_sdk_solana::state::{load_price_account, load_product_account};
+use serde::Deserialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::fetcher::AccountFetcher;
+use crate::rpc::{get_accounts_data, OraclePrice};
+use crate::state;
+
+const HERMES_URL: &str = "https://hermes.pyth.network";
+
+/// Pyth USD price feed ids of the markets listed on Jupiter perpetuals, keyed by symbol
+pub const KNOWN_FEED_IDS: [(&str, &str); 5] = [
+    (
+        "SOL",
+        "ef0d8b6fda2ceba41da15d4095d1da392a0d2f8ed0c6c7bc0f4cfac8c280b56d",
+    ),
+    (
+        "ETH",
+        "ff61491a931112ddf1bd8147cd1b641375f79f5825126d665480874634fd0ace",
+    ),
+    (
+        "BTC",
+        "e62df6c8b4a85fe1a67db44dc12de5db330f7ac66b72dc658afedf0f4a415b43",
+    ),
+    (
+        "USDC",
+        "eaa020c61cc479712813461ce153894a96a6c00b21ed0cfc2798d1f9a9e9c94a",
+    ),
+    (
+        "USDT",
+        "2b89b9dc8fdf9f34709a5b106b472f0f39bb6ca9ce04b0fd7f2e971688e2e53b",
+    ),
+];
+
+pub fn feed_id_for_symbol(symbol: &str) -> Option<&'static str> {
+    KNOWN_FEED_IDS
+        .iter()
+        .find(|(known_symbol, _)| *known_symbol == symbol)
+        .map(|(_, feed_id)| *feed_id)
+}
+
+#[derive(Deserialize)]
+struct UpdatesResponse {
+    parsed: Vec<ParsedUpdate>,
+}
+
+#[derive(Deserialize)]
+struct ParsedUpdate {
+    /// Hex feed id, without a 0x prefix
+    id: String,
+    price: HermesPrice,
+    ema_price: HermesPrice,
+}
+
+#[derive(Deserialize)]
+struct HermesPrice {
+    /// Integer price, as a string
+    price: String,
+    expo: i32,
+    publish_time: i64,
+}
+
+impl HermesPrice {
+    fn value(&self) -> Result<f64, Box<dyn std::error::Error>> {
+        Ok(self.price.parse::<f64>()?.mul(10_f64.powi(self.expo)))
+    }
+}
+
+/// Prices of `feed_ids` from the Hermes updates endpoint at `path`, keyed by feed id
+fn updates(
+    client: &reqwest::blocking::Client,
+    path: &str,
+    feed_ids: &[&str],
+) -> Result<HashMap<String, OraclePrice>, Box<dyn std::error::Error>> {
+    let mut query: Vec<(&str, &str)> = feed_ids.iter().map(|feed_id| ("ids[]", *feed_id)).collect();
+    query.push(("parsed", "true"));
+    let response = client
+        .get(format!("{HERMES_URL}/v2/updates/price/{path}"))
+        .query(&query)
+        .send()?;
+    if !response.status().is_success() {
+        return Err(format!("Hermes responded {}", response.status()).into());
+    }
+    let mut prices = HashMap::new();
+    for update in response.json::<UpdatesResponse>()?.parsed {
+        prices.insert(
+            update.id.trim_start_matches("0x").to_string(),
+            OraclePrice {
+                price: update.price.value()?,
+                ema_price: update.ema_price.value()?,
+                publish_time: update.price.publish_time,
+                fallback: None,
+            },
+        );
+    }
+    Ok(prices)
+}
+
+/// Prices of `feed_ids` as first published at or after `unix_time`, keyed by feed id
+pub fn prices_at(
+    client: &reqwest::blocking::Client,
+    feed_ids: &[&str],
+    unix_time: i64,
+) -> Result<HashMap<String, OraclePrice>, Box<dyn std::error::Error>> {
+    updates(client, &unix_time.to_string(), feed_ids)
+}
+
+/// Latest prices of `feed_ids`, keyed by feed id
+pub fn latest_prices(
+    client: &reqwest::blocking::Client,
+    feed_ids: &[&str],
+) -> Result<HashMap<String, OraclePrice>, Box<dyn std::error::Error>> {
+    updates(client, "latest", feed_ids)
+}
+
+/// Base symbol named by a Pyth product account, from its `base` attribute or else its
+/// `symbol`, e.g. "Crypto.JUP/USD"
+fn product_symbol(data: &[u8]) -> Option<String> {
+    let product = load_product_account(data).ok()?;
+    let mut base = None;
+    let mut symbol = None;
+    for (key, value) in product.iter() {
+        match key {
+            "base" => base = Some(value.to_string()),
+            "symbol" => symbol = Some(value.to_string()),
+            _ => {}
+        }
+    }
+    base.filter(|base| !base.is_empty()).or_else(|| {
+        let symbol = symbol?;
+        let pair = symbol.rsplit('.').next()?;
+        Some(pair.split('/').next()?.to_string())
+    })
+}
+
+/// Registers a symbol for each custody mint without one yet, read from the Pyth product
+/// linked from its oracle price account. Oracles that aren't Pyth price accounts are skipped.
+pub fn resolve_symbols(
+    connection: &dyn AccountFetcher,
+    custodies: &[(Pubkey, perp_abi::state::Custody)],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let unresolved: Vec<(Pubkey, Pubkey)> = custodies
+        .iter()
+        .filter(|(_, custody)| !state::has_symbol(&custody.mint))
+        .map(|(_, custody)| (custody.mint, custody.oracle.oracle_account))
+        .collect();
+    if unresolved.is_empty() {
+        return Ok(());
+    }
+    let oracle_pubkeys: Vec<Pubkey> = unresolved.iter().map(|(_, oracle)| *oracle).collect();
+    let oracle_accounts: HashMap<_, _> = get_accounts_data(connection, &oracle_pubkeys)?
+        .into_iter()
+        .collect();
+    let products: Vec<(Pubkey, Pubkey)> = unresolved
+        .iter()
+        .filter_map(|(mint, oracle)| {
+            let price_account = load_price_account(oracle_accounts.get(oracle)?).ok()?;
+            Some((*mint, price_account.prod))
+        })
+        .collect();
+    let product_pubkeys: Vec<Pubkey> = products.iter().map(|(_, product)| *product).collect();
+    let product_accounts: HashMap<_, _> = get_accounts_data(connection, &product_pubkeys)?
+        .into_iter()
+        .collect();
+    for (mint, product) in products {
+        if let Some(symbol) = product_accounts
+            .get(&product)
+            .and_then(|data| product_symbol(data))
+        {
+            state::register_symbol(mint, symbol);
+        }
+    }
+    Ok(())
+}
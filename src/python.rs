@@ -0,0 +1,39 @@
+//! `jup_perps_analytics` Python module, built with `maturin` from the `python` feature.
+//!
+//! ```python
+//! import jup_perps_analytics
+//! data = jup_perps_analytics.fetch_snapshot("https://api.mainnet-beta.solana.com")
+//! data["snapshot"]["total_pool_value"], data["custodies"][0]["borrow_rate"]
+//! ```
+
+use pyo3::exceptions::PyRuntimeError;
+use pyo3::prelude::*;
+use solana_client::rpc_client::RpcClient;
+
+use crate::snapshot;
+use crate::state::{load_open_positions, PerpState};
+
+/// Loads the current state and everything derived from it as plain JSON values
+fn snapshot_json(rpc_url: &str) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let connection = RpcClient::new(rpc_url.to_string());
+    let state = PerpState::load(&connection)?;
+    let positions = load_open_positions(&connection)?;
+    Ok(snapshot::to_json(&state, &positions)?)
+}
+
+/// Fetches a snapshot from `rpc_url`, returning a dict with the aggregate `snapshot`, a
+/// list of `custodies` with prices and borrow rates, and every open position
+#[pyfunction]
+fn fetch_snapshot(py: Python<'_>, rpc_url: &str) -> PyResult<PyObject> {
+    // the RPC round trips don't need the interpreter
+    let value = py
+        .allow_threads(|| snapshot_json(rpc_url).map_err(|err| err.to_string()))
+        .map_err(PyRuntimeError::new_err)?;
+    Ok(pythonize::pythonize(py, &value)?)
+}
+
+#[pymodule]
+fn jup_perps_analytics(_py: Python<'_>, module: &PyModule) -> PyResult<()> {
+    module.add_function(wrap_pyfunction!(fetch_snapshot, module)?)?;
+    Ok(())
+}
@@ -0,0 +1,115 @@
+//! Ad-hoc SQL over stored history: every table kept with a history is loaded into an
+//! in-memory DuckDB database under its store table name, whatever format it's stored in.
+
+use ::duckdb::types::Value;
+use ::duckdb::Connection;
+
+use crate::store::duckdb::insert_records;
+use crate::store::{
+    child_path, read_existing_records, CustodyRecord, FundingRecord, MarketRecord,
+    OraclePriceRecord, PositionRecord, Record, SnapshotRecord, WalletRecord,
+};
+
+/// Loads the `R` records at `path` into their table, skipping child files never written
+fn load<R: Record>(connection: &Connection, path: &str) -> Result<(), Box<dyn std::error::Error>> {
+    insert_records(connection, &read_existing_records::<R>(path)?)
+}
+
+fn format_value(value: Value) -> String {
+    match value {
+        Value::Null => String::new(),
+        Value::Boolean(boolean) => boolean.to_string(),
+        Value::TinyInt(integer) => integer.to_string(),
+        Value::SmallInt(integer) => integer.to_string(),
+        Value::Int(integer) => integer.to_string(),
+        Value::BigInt(integer) => integer.to_string(),
+        Value::HugeInt(integer) => integer.to_string(),
+        Value::UTinyInt(integer) => integer.to_string(),
+        Value::USmallInt(integer) => integer.to_string(),
+        Value::UInt(integer) => integer.to_string(),
+        Value::UBigInt(integer) => integer.to_string(),
+        Value::Float(real) => real.to_string(),
+        Value::Double(real) => real.to_string(),
+        Value::Text(text) => text,
+        other => format!("{other:?}"),
+    }
+}
+
+/// Runs `sql` over the snapshots at `history` and their `custodies`, `markets`,
+/// `positions` and `wallets` child rows, plus the `oracle_prices` and `funding` histories if given,
+/// printing the result as an aligned table or CSV
+pub fn run(
+    sql: &str,
+    history: &str,
+    price_history: Option<&str>,
+    funding_history: Option<&str>,
+    csv: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connection = Connection::open_in_memory()?;
+    load::<SnapshotRecord>(&connection, history)?;
+    load::<CustodyRecord>(&connection, &child_path(history, CustodyRecord::TABLE))?;
+    load::<MarketRecord>(&connection, &child_path(history, MarketRecord::TABLE))?;
+    load::<PositionRecord>(&connection, &child_path(history, PositionRecord::TABLE))?;
+    load::<WalletRecord>(&connection, &child_path(history, WalletRecord::TABLE))?;
+    if let Some(price_history) = price_history {
+        load::<OraclePriceRecord>(&connection, price_history)?;
+    }
+    if let Some(funding_history) = funding_history {
+        load::<FundingRecord>(&connection, funding_history)?;
+    }
+
+    let mut statement = connection.prepare(sql)?;
+    let mut rows = statement.query([])?;
+    let (column_count, header) = match rows.as_ref() {
+        Some(statement) => (statement.column_count(), statement.column_names()),
+        None => (0, vec![]),
+    };
+    let mut table = vec![];
+    while let Some(row) = rows.next()? {
+        let mut cells = vec![];
+        for i in 0..column_count {
+            cells.push(format_value(row.get::<_, Value>(i)?));
+        }
+        table.push(cells);
+    }
+
+    if csv {
+        let mut writer = csv::Writer::from_writer(std::io::stdout());
+        writer.write_record(&header)?;
+        for cells in &table {
+            writer.write_record(cells)?;
+        }
+        writer.flush()?;
+        return Ok(());
+    }
+    let widths: Vec<usize> = (0..column_count)
+        .map(|i| {
+            table
+                .iter()
+                .map(|cells| cells[i].len())
+                .chain([header[i].len()])
+                .max()
+                .unwrap_or_default()
+        })
+        .collect();
+    let print_row = |cells: &[String]| {
+        let line: Vec<_> = cells
+            .iter()
+            .zip(&widths)
+            .map(|(cell, width)| format!("{cell:<width$}"))
+            .collect();
+        println!("{}", line.join("  ").trim_end());
+    };
+    print_row(&header);
+    print_row(
+        &widths
+            .iter()
+            .map(|width| "-".repeat(*width))
+            .collect::<Vec<_>>(),
+    );
+    for cells in &table {
+        print_row(cells);
+    }
+    println!("({} rows)", table.len());
+    Ok(())
+}
@@ -0,0 +1,400 @@
+use std::collections::BTreeMap;
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use clap::ValueEnum;
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::liquidations::estimate_all;
+use crate::state::{load_open_positions, PerpState};
+use crate::table;
+
+/// Price moves the liquidation heatmap and stress scenarios are evaluated at
+const PRICE_MOVES: [f64; 4] = [0.02, 0.05, 0.10, 0.20];
+/// Simultaneous moves of every non-stable market the pool drawdown table is evaluated at
+const DRAWDOWN_MOVES: [f64; 4] = [0.05, 0.10, 0.20, 0.30];
+/// Oracle jumps bad debt is estimated for when none are given
+const DEFAULT_GAP_MOVES: [f64; 3] = [0.10, 0.20, 0.30];
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum ReportFormat {
+    Console,
+    Markdown,
+    Html,
+}
+
+struct Section {
+    title: String,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+fn percent(value: f64) -> String {
+    format!("{:.2}%", value.mul(100.0))
+}
+
+#[derive(Default)]
+struct MarketExposure {
+    long_oi: f64,
+    short_oi: f64,
+    trader_pnl: f64,
+}
+
+fn build_sections(
+    connection: &RpcClient,
+    gap_moves: &[f64],
+) -> Result<(u64, Vec<Section>), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let positions = load_open_positions(connection)?;
+    let estimates = estimate_all(&state, &positions);
+
+    let mut market_to_exposure: BTreeMap<String, MarketExposure> = BTreeMap::new();
+    for (_, position) in &positions {
+        let Some(custody_state) = state.custodies.get(&position.custody) else {
+            continue;
+        };
+        let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+        let price_at_entry = spl_token::amount_to_ui_amount(position.price, 6);
+        let price_change = custody_state
+            .price()
+            .sub(price_at_entry)
+            .div(price_at_entry);
+        let exposure = market_to_exposure
+            .entry(custody_state.symbol())
+            .or_default();
+        if let perp_abi::Side::Long = position.side {
+            exposure.long_oi += size_usd;
+            exposure.trader_pnl += size_usd.mul(price_change);
+        } else {
+            exposure.short_oi += size_usd;
+            exposure.trader_pnl -= size_usd.mul(price_change);
+        }
+    }
+
+    let mut move_headers = vec!["Market".to_string()];
+    for price_move in PRICE_MOVES.iter().rev() {
+        move_headers.push(format!("-{}", percent(*price_move)));
+    }
+    for price_move in PRICE_MOVES {
+        move_headers.push(format!("+{}", percent(price_move)));
+    }
+
+    // longs are liquidated by falling prices, shorts by rising prices
+    let mut heatmap_rows = vec![];
+    for symbol in market_to_exposure.keys() {
+        let mut row = vec![symbol.clone()];
+        let at_risk = |is_long: bool, price_move: f64| {
+            estimates
+                .iter()
+                .filter(|estimate| {
+                    &estimate.symbol == symbol
+                        && matches!(estimate.side, perp_abi::Side::Long) == is_long
+                        && estimate.distance <= price_move
+                })
+                .map(|estimate| estimate.size_usd)
+                .sum::<f64>()
+        };
+        for price_move in PRICE_MOVES.iter().rev() {
+            row.push(usd(at_risk(true, *price_move)));
+        }
+        for price_move in PRICE_MOVES {
+            row.push(usd(at_risk(false, price_move)));
+        }
+        heatmap_rows.push(row);
+    }
+
+    let skew_rows = market_to_exposure
+        .iter()
+        .map(|(symbol, exposure)| {
+            let total_oi = exposure.long_oi + exposure.short_oi;
+            vec![
+                symbol.clone(),
+                usd(exposure.long_oi),
+                usd(exposure.short_oi),
+                percent(exposure.long_oi.sub(exposure.short_oi).div(total_oi)),
+            ]
+        })
+        .collect();
+
+    let utilization_rows = state
+        .custodies_in_pool_order()
+        .into_iter()
+        .map(|custody_state| {
+            vec![
+                custody_state.symbol(),
+                percent(custody_state.utilization),
+                format!("{:.2}%", custody_state.borrow_apr()),
+            ]
+        })
+        .collect();
+
+    // the pool takes the other side of every trade
+    let exposure_rows = market_to_exposure
+        .iter()
+        .map(|(symbol, exposure)| {
+            vec![
+                symbol.clone(),
+                usd(exposure.short_oi.sub(exposure.long_oi)),
+                usd(-exposure.trader_pnl),
+            ]
+        })
+        .collect();
+
+    let mut stress_rows = vec![];
+    for price_move in PRICE_MOVES {
+        for direction in [-1.0, 1.0] {
+            let signed_move = price_move.mul(direction);
+            let trader_pnl_change: f64 = market_to_exposure
+                .values()
+                .map(|exposure| exposure.long_oi.sub(exposure.short_oi).mul(signed_move))
+                .sum();
+            let liquidated: f64 = estimates
+                .iter()
+                .filter(|estimate| {
+                    matches!(estimate.side, perp_abi::Side::Long) == (direction < 0.0)
+                        && estimate.distance <= price_move
+                })
+                .map(|estimate| estimate.size_usd)
+                .sum();
+            stress_rows.push(vec![
+                format!(
+                    "{}{}",
+                    if direction < 0.0 { "-" } else { "+" },
+                    percent(price_move)
+                ),
+                usd(-trader_pnl_change),
+                usd(liquidated),
+            ]);
+        }
+    }
+
+    // the pool holds the tokens it custodies, which revalue with the market, and pays out
+    // what traders make
+    let custody_value: f64 = state
+        .custodies_in_pool_order()
+        .into_iter()
+        .filter(|custody_state| !custody_state.is_stable())
+        .map(|custody_state| {
+            spl_token::amount_to_ui_amount(
+                custody_state.custody.assets.owned,
+                custody_state.custody.decimals,
+            )
+            .mul(custody_state.price())
+        })
+        .sum();
+    let net_long: f64 = market_to_exposure
+        .values()
+        .map(|exposure| exposure.long_oi.sub(exposure.short_oi))
+        .sum();
+    let total_pool_value = state.total_pool_value();
+    let mut drawdown_rows = vec![];
+    for direction in [-1.0, 1.0] {
+        for price_move in DRAWDOWN_MOVES {
+            let signed_move = price_move.mul(direction);
+            let revaluation = custody_value.mul(signed_move);
+            let trader_pnl_transfer = -net_long.mul(signed_move);
+            let pool_pnl = revaluation.add(trader_pnl_transfer);
+            drawdown_rows.push(vec![
+                format!(
+                    "{}{}",
+                    if direction < 0.0 { "-" } else { "+" },
+                    percent(price_move)
+                ),
+                usd(revaluation),
+                usd(trader_pnl_transfer),
+                usd(pool_pnl),
+                percent(pool_pnl.div(total_pool_value)),
+            ]);
+        }
+    }
+
+    // an oracle jump skips the prices where liquidators could have closed positions
+    let mut gap_headers = vec!["Market".to_string()];
+    for gap_move in gap_moves.iter().rev() {
+        gap_headers.push(format!("-{}", percent(*gap_move)));
+    }
+    for gap_move in gap_moves {
+        gap_headers.push(format!("+{}", percent(*gap_move)));
+    }
+    let mut bad_debt_rows = vec![];
+    for symbol in market_to_exposure.keys() {
+        let mut row = vec![symbol.clone()];
+        let bad_debt = |is_long: bool, gap_move: f64| {
+            let (count, total) = estimates
+                .iter()
+                .filter(|estimate| {
+                    &estimate.symbol == symbol
+                        && matches!(estimate.side, perp_abi::Side::Long) == is_long
+                })
+                .map(|estimate| estimate.bad_debt_after_gap(gap_move))
+                .filter(|bad_debt| *bad_debt > 0.0)
+                .fold((0, 0.0), |(count, total), bad_debt| {
+                    (count + 1, total + bad_debt)
+                });
+            format!("{} ({count})", usd(total))
+        };
+        for gap_move in gap_moves.iter().rev() {
+            row.push(bad_debt(true, *gap_move));
+        }
+        for gap_move in gap_moves {
+            row.push(bad_debt(false, *gap_move));
+        }
+        bad_debt_rows.push(row);
+    }
+
+    let sections = vec![
+        Section {
+            title: "Liquidation heatmap (notional liquidated by price move)".to_string(),
+            headers: move_headers,
+            rows: heatmap_rows,
+        },
+        Section {
+            title: "Open interest skew".to_string(),
+            headers: vec!["Market", "Long OI", "Short OI", "Skew"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: skew_rows,
+        },
+        Section {
+            title: "Utilization".to_string(),
+            headers: vec!["Custody", "Utilization", "Borrow APR"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: utilization_rows,
+        },
+        Section {
+            title: "Pool counterparty exposure".to_string(),
+            headers: vec!["Market", "Pool net delta", "Pool unrealized P&L"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: exposure_rows,
+        },
+        Section {
+            title: "Stress scenarios (all non-stable markets move together)".to_string(),
+            headers: vec!["Price move", "Pool P&L change", "Notional liquidated"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: stress_rows,
+        },
+        Section {
+            title: "Pool drawdown (all non-stable markets move together)".to_string(),
+            headers: vec![
+                "Price move",
+                "Token revaluation",
+                "Trader P&L transfer",
+                "Pool P&L",
+                "Of pool value",
+            ]
+            .into_iter()
+            .map(str::to_string)
+            .collect(),
+            rows: drawdown_rows,
+        },
+        Section {
+            title: "Bad debt after a price gap (pool loss beyond collateral, positions)"
+                .to_string(),
+            headers: gap_headers,
+            rows: bad_debt_rows,
+        },
+    ];
+    Ok((state.unix_time, sections))
+}
+
+fn render_console(unix_time: u64, sections: &[Section]) -> String {
+    let mut out = format!("Risk report\nUnix time: {unix_time}\n");
+    for section in sections {
+        let headers: Vec<&str> = section.headers.iter().map(String::as_str).collect();
+        out += &format!(
+            "\n{}\n{}\n",
+            section.title,
+            table::render(&headers, section.rows.clone())
+        );
+    }
+    out
+}
+
+fn render_markdown(unix_time: u64, sections: &[Section]) -> String {
+    let mut out = format!("# Risk report\n\nUnix time: {unix_time}\n");
+    for section in sections {
+        out += &format!("\n## {}\n\n", section.title);
+        out += &format!("| {} |\n", section.headers.join(" | "));
+        out += &format!("|{}\n", "---|".repeat(section.headers.len()));
+        for row in &section.rows {
+            out += &format!("| {} |\n", row.join(" | "));
+        }
+    }
+    out
+}
+
+/// `text` with the characters HTML gives meaning to escaped, as symbols and names come
+/// from on-chain data anyone can write
+pub(crate) fn escape_html(text: &str) -> String {
+    let mut escaped = String::with_capacity(text.len());
+    for c in text.chars() {
+        match c {
+            '&' => escaped.push_str("&amp;"),
+            '<' => escaped.push_str("&lt;"),
+            '>' => escaped.push_str("&gt;"),
+            '"' => escaped.push_str("&quot;"),
+            '\'' => escaped.push_str("&#39;"),
+            c => escaped.push(c),
+        }
+    }
+    escaped
+}
+
+fn render_html(unix_time: u64, sections: &[Section]) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>Risk report</title></head>\n<body>\n<h1>Risk report</h1>\n<p>Unix time: {unix_time}</p>\n"
+    );
+    for section in sections {
+        out += &format!("<h2>{}</h2>\n<table>\n<tr>", escape_html(&section.title));
+        for header in &section.headers {
+            out += &format!("<th>{}</th>", escape_html(header));
+        }
+        out += "</tr>\n";
+        for row in &section.rows {
+            out += "<tr>";
+            for cell in row {
+                out += &format!("<td>{}</td>", escape_html(cell));
+            }
+            out += "</tr>\n";
+        }
+        out += "</table>\n";
+    }
+    out += "</body>\n</html>\n";
+    out
+}
+
+/// Prints a one-page risk report combining liquidation, skew, utilization and stress data,
+/// with the bad debt left by oracle jumps of each of `gap_moves` percent
+pub fn run(
+    connection: &RpcClient,
+    format: ReportFormat,
+    gap_moves: &[f64],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let gap_moves: Vec<f64> = if gap_moves.is_empty() {
+        DEFAULT_GAP_MOVES.to_vec()
+    } else {
+        gap_moves
+            .iter()
+            .map(|gap_move| gap_move.div(100.0))
+            .collect()
+    };
+    let (unix_time, sections) = build_sections(connection, &gap_moves)?;
+    let report = match format {
+        ReportFormat::Console => render_console(unix_time, &sections),
+        ReportFormat::Markdown => render_markdown(unix_time, &sections),
+        ReportFormat::Html => render_html(unix_time, &sections),
+    };
+    print!("{report}");
+    Ok(())
+}
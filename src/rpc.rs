@@ -0,0 +1,134 @@
+use std::ops::Div as _;
+use std::str::FromStr;
+use std::sync::OnceLock;
+
+use clap::ValueEnum;
+use pyth_sdk_solana::state::load_price_account;
+use solana_sdk::pubkey::Pubkey;
+
+#[cfg(not(target_arch = "wasm32"))]
+mod fetch;
+#[cfg(not(target_arch = "wasm32"))]
+pub use fetch::*;
+
+pub const _PERPETUALS_PUBKEY: &str = "H4ND9aYttUVLFmNypZqLjZ52FYiGvdEB45GmwNoKEjTj";
+pub const _FUNDED_PUBKEY: &str = "HVSZJ2juJnMxd6yCNarTL56YmgUqzfUiwM7y7LtTXKHR";
+
+/// Program and perpetuals account of the deployment in use, when not the compiled in one
+static PROGRAM: OnceLock<(Pubkey, Pubkey)> = OnceLock::new();
+
+/// Solana cluster presets
+#[derive(Clone, Copy, Debug, PartialEq, Eq, ValueEnum)]
+pub enum Cluster {
+    Mainnet,
+    Devnet,
+}
+
+impl Cluster {
+    /// The cluster's public RPC endpoint, rate limited but enough for occasional snapshots
+    pub fn rpc_url(self) -> &'static str {
+        match self {
+            Cluster::Mainnet => "https://api.mainnet-beta.solana.com",
+            Cluster::Devnet => "https://api.devnet.solana.com",
+        }
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Cluster::Mainnet => "mainnet",
+            Cluster::Devnet => "devnet",
+        }
+    }
+
+    /// The cluster with genesis block `genesis_hash`
+    pub fn from_genesis_hash(genesis_hash: &str) -> Option<Self> {
+        match genesis_hash {
+            "5eykt4UsFv8P8NJdTREpY1vzqKqZKvdpKuc147dw2N9d" => Some(Cluster::Mainnet),
+            "EtWTRABZaYq6iMfeYKouRu166VU2xqa1wcaWoxPkrZBG" => Some(Cluster::Devnet),
+            _ => None,
+        }
+    }
+}
+
+/// Points every loader at another deployment of the program for the rest of the process,
+/// with its perpetuals account derived from `program_id` unless given
+pub fn set_program(
+    program_id: Pubkey,
+    perpetuals_account: Option<Pubkey>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let perpetuals_account = perpetuals_account
+        .unwrap_or_else(|| Pubkey::find_program_address(&[b"perpetuals"], &program_id).0);
+    PROGRAM
+        .set((program_id, perpetuals_account))
+        .map_err(|_| "the program is already set".into())
+}
+
+/// Perpetuals program the accounts are read from
+pub fn program_id() -> Pubkey {
+    PROGRAM
+        .get()
+        .map_or(perp_abi::ID, |(program_id, _)| *program_id)
+}
+
+/// The program's global perpetuals account
+pub fn perpetuals_account() -> Result<Pubkey, solana_sdk::pubkey::ParsePubkeyError> {
+    match PROGRAM.get() {
+        Some((_, perpetuals_account)) => Ok(*perpetuals_account),
+        None => Pubkey::from_str(_PERPETUALS_PUBKEY),
+    }
+}
+
+/// Oracle price along with the unix time it was published at
+#[derive(Clone, Copy, Debug, Default)]
+pub struct OraclePrice {
+    pub price: f64,
+    /// Exponentially weighted moving average of the price, as maintained by the feed
+    pub ema_price: f64,
+    pub publish_time: i64,
+    /// HTTP source the price was taken from instead, when the oracle was stale or undecodable
+    pub fallback: Option<&'static str>,
+}
+
+/// Websocket URL the RPC's pubsub endpoint is conventionally served on
+pub fn websocket_url(rpc_url: &str) -> String {
+    if let Some(rest) = rpc_url.strip_prefix("https://") {
+        format!("wss://{rest}")
+    } else if let Some(rest) = rpc_url.strip_prefix("http://") {
+        format!("ws://{rest}")
+    } else {
+        rpc_url.to_string()
+    }
+}
+
+/// `rpc_url` with only its scheme and host, since providers put API keys in the user info,
+/// path or query
+pub fn redacted_url(rpc_url: &str) -> String {
+    let (scheme, rest) = rpc_url.split_once("://").unwrap_or(("", rpc_url));
+    let authority = rest.split(['/', '?', '#']).next().unwrap_or_default();
+    let host = authority
+        .rsplit_once('@')
+        .map_or(authority, |(_, host)| host);
+    if scheme.is_empty() {
+        host.to_string()
+    } else {
+        format!("{scheme}://{host}")
+    }
+}
+
+/// Decodes a Pyth price account's raw data
+pub fn decode_oracle_price(
+    pyth_account_pubkey: &Pubkey,
+    data: &[u8],
+) -> Result<OraclePrice, Box<dyn std::error::Error>> {
+    let price_account = load_price_account(data)?;
+    let price_feed = price_account.to_price_feed(pyth_account_pubkey);
+    let price = price_feed.get_price_unchecked();
+    let price_as_float = (price.price as f64).div((10 as u32).pow(8) as f64);
+    let ema_price = price_feed.get_ema_price_unchecked();
+    Ok(OraclePrice {
+        price: price_as_float,
+        ema_price: (ema_price.price as f64).div((10 as u32).pow(8) as f64),
+        publish_time: price.publish_time,
+        fallback: None,
+    })
+}
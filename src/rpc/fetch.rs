@@ -0,0 +1,116 @@
+//! Fetching accounts over RPC, unavailable on wasm32 where callers supply account bytes.
+
+use std::str::FromStr;
+
+use anchor_lang::{AnchorDeserialize as _, Discriminator as _};
+use solana_account_decoder::UiDataSliceConfig;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::instruction::AccountMeta;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::fetcher::AccountFetcher;
+
+use super::{decode_oracle_price, perpetuals_account, program_id, OraclePrice, _FUNDED_PUBKEY};
+
+pub fn get_oracle_price(
+    connection: &RpcClient,
+    pyth_account_pubkey: &Pubkey,
+) -> Result<OraclePrice, Box<dyn std::error::Error>> {
+    let pyth_account = connection.get_account(pyth_account_pubkey)?;
+    decode_oracle_price(pyth_account_pubkey, &pyth_account.data)
+}
+
+/// Data of `pubkeys` in one request, failing if any of them doesn't exist
+pub fn get_accounts_data(
+    connection: &dyn AccountFetcher,
+    pubkeys: &[Pubkey],
+) -> Result<Vec<(Pubkey, Vec<u8>)>, Box<dyn std::error::Error>> {
+    if pubkeys.is_empty() {
+        return Ok(vec![]);
+    }
+    pubkeys
+        .iter()
+        .zip(connection.multiple_accounts(pubkeys)?)
+        .map(|(pubkey, account)| {
+            let account = account.ok_or_else(|| format!("missing account {pubkey}"))?;
+            Ok((*pubkey, account.data))
+        })
+        .collect()
+}
+
+/// Hash of the perpetuals program's deployed executable, which changes with every upgrade
+pub fn program_hash(connection: &RpcClient) -> Result<String, Box<dyn std::error::Error>> {
+    use solana_sdk::bpf_loader_upgradeable::{self, UpgradeableLoaderState};
+
+    let programdata_address =
+        Pubkey::find_program_address(&[program_id().as_ref()], &bpf_loader_upgradeable::ID).0;
+    let data = connection.get_account_data(&programdata_address)?;
+    let executable = data
+        .get(UpgradeableLoaderState::size_of_programdata_metadata()..)
+        .ok_or("perpetuals program data is truncated")?;
+    Ok(solana_sdk::hash::hash(executable).to_string())
+}
+
+pub fn get_price_from_pyth_account(
+    connection: &RpcClient,
+    pyth_account_pubkey: &Pubkey,
+) -> Result<f64, Box<dyn std::error::Error>> {
+    Ok(get_oracle_price(connection, pyth_account_pubkey)?.price)
+}
+
+pub fn get_program_accounts_with_discrim(
+    connection: &dyn AccountFetcher,
+    program_address: &str,
+    discrim: &[u8],
+) -> Result<
+    Vec<(solana_sdk::pubkey::Pubkey, solana_sdk::account::Account)>,
+    Box<dyn std::error::Error>,
+> {
+    get_program_accounts_filtered(connection, program_address, discrim, None, None)
+}
+
+/// Like [`get_program_accounts_with_discrim`], additionally letting the RPC drop accounts of
+/// the wrong size and return only `data_slice` of each account's data
+pub fn get_program_accounts_filtered(
+    connection: &dyn AccountFetcher,
+    program_address: &str,
+    discrim: &[u8],
+    data_size: Option<u64>,
+    data_slice: Option<UiDataSliceConfig>,
+) -> Result<
+    Vec<(solana_sdk::pubkey::Pubkey, solana_sdk::account::Account)>,
+    Box<dyn std::error::Error>,
+> {
+    connection.program_accounts(
+        &Pubkey::from_str(program_address)?,
+        discrim,
+        data_size,
+        data_slice,
+    )
+}
+
+pub fn _get_fees_from_position(
+    connection: &dyn AccountFetcher,
+    position_pubkey: &Pubkey,
+    position: &perp_abi::Position,
+    custody: &perp_abi::Custody,
+) -> Result<perp_abi::PnlAndFee, Box<dyn std::error::Error>> {
+    let instruction = solana_sdk::instruction::Instruction::new_with_bytes(
+        program_id(),
+        &perp_abi::instruction::GetPnl::DISCRIMINATOR,
+        vec![
+            AccountMeta::new_readonly(perpetuals_account()?, false),
+            AccountMeta::new_readonly(position.pool, false),
+            AccountMeta::new_readonly(*position_pubkey, false),
+            AccountMeta::new_readonly(position.custody, false),
+            AccountMeta::new_readonly(custody.oracle.oracle_account, false),
+            AccountMeta::new_readonly(position.collateral_custody, false),
+        ],
+    );
+    let mut data = connection
+        .simulate(&[instruction], &Pubkey::from_str(_FUNDED_PUBKEY)?)?
+        .unwrap_or_default();
+    data.resize(41, 0);
+    let pnl_and_fee = perp_abi::PnlAndFee::try_from_slice(&data)?;
+    Ok(pnl_and_fee)
+}
@@ -0,0 +1,203 @@
+//! Sanity checks on collected data, so an RPC that truncates responses or a program upgrade
+//! that changes the account layout shows up as a warning instead of plausible-looking but
+//! wrong aggregates.
+
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use solana_sdk::pubkey::Pubkey;
+use thousands::Separable;
+
+use crate::snapshot::Snapshot;
+use crate::state::PerpState;
+use crate::store::SnapshotRecord;
+
+/// No position can have been opened before the program launched, in January 2023
+const PROGRAM_LAUNCH_UNIX_TIME: i64 = 1_672_531_200;
+/// Borrow rate no custody would plausibly charge, 100% an hour
+const MAX_HOURLY_FUNDING_BPS: f64 = 10_000.0;
+
+/// Warnings for account counts that dropped by more than `max_drop` (a fraction) since
+/// `previous`, as (alert key, message) pairs
+pub fn account_count_drops(
+    previous: &SnapshotRecord,
+    snapshot: &Snapshot,
+    max_drop: f64,
+) -> Vec<(String, String)> {
+    [
+        (
+            "Custody",
+            previous.custody_accounts,
+            snapshot.custody_accounts,
+        ),
+        (
+            "Position",
+            previous.position_accounts,
+            snapshot.position_accounts,
+        ),
+    ]
+    .into_iter()
+    .filter_map(|(kind, previous_count, count)| {
+        let previous_count = previous_count.filter(|previous_count| *previous_count > 0)?;
+        let change = (count as f64).div(previous_count as f64).sub(1.0);
+        (change < -max_drop).then(|| {
+            let message = format!(
+                "{kind} accounts dropped from {} to {} ({:.0}%) since unix time {}, the RPC may have truncated the response",
+                previous_count.separate_with_commas(),
+                count.separate_with_commas(),
+                change.mul(100.0),
+                previous.unix_time,
+            );
+            (format!("account-count:{}", kind.to_lowercase()), message)
+        })
+    })
+    .collect()
+}
+
+/// Warning if the perpetuals program's executable changed since `previous`, when both hashes
+/// were recorded
+pub fn program_change(previous: &SnapshotRecord, snapshot: &Snapshot) -> Option<String> {
+    let hash = snapshot.program_hash.as_deref()?;
+    (!previous.program_hash.is_empty() && previous.program_hash != hash).then(|| {
+        format!(
+            "The perpetuals program was upgraded since unix time {} (executable hash {} -> {hash}), check that accounts still decode correctly",
+            previous.unix_time, previous.program_hash,
+        )
+    })
+}
+
+/// Values of the decoded custodies and positions outside any plausible range, which after a
+/// program upgrade usually means the account layout no longer matches. Each comes as an
+/// alert key naming the custody and check, e.g. `layout:SOL:locked>owned`, and a message.
+pub fn state_violations(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+) -> Vec<(String, String)> {
+    let mut problems = PositionProblems::default();
+    for (pubkey, position) in positions {
+        problems.add(state, pubkey, position);
+    }
+    let mut violations = custody_violations(state);
+    violations.extend(problems.violations());
+    violations
+}
+
+/// The custody checks of [`state_violations`]
+pub fn custody_violations(state: &PerpState) -> Vec<(String, String)> {
+    let mut violations = vec![];
+    for custody_state in state.custodies_in_pool_order() {
+        let custody = &custody_state.custody;
+        let symbol = custody_state.symbol();
+        let mut violation = |check: &str, message: String| {
+            violations.push((format!("layout:{symbol}:{check}"), message))
+        };
+        if custody.decimals > 18 {
+            violation(
+                "decimals",
+                format!("{symbol} custody has {} decimals", custody.decimals),
+            );
+        }
+        if custody.target_ratio_bps > 10_000 {
+            violation(
+                "target-ratio",
+                format!(
+                    "{symbol} custody targets {} bps of the pool",
+                    custody.target_ratio_bps
+                ),
+            );
+        }
+        if custody.assets.locked > custody.assets.owned {
+            violation(
+                "locked>owned",
+                format!(
+                    "{symbol} custody has more tokens locked ({}) than owned ({})",
+                    custody.assets.locked, custody.assets.owned
+                ),
+            );
+        }
+        if custody.pricing.max_leverage == 0 {
+            violation(
+                "max-leverage",
+                format!("{symbol} custody allows no leverage"),
+            );
+        }
+        let hourly_funding_bps = custody.funding_rate_state.hourly_funding_bps as f64;
+        if hourly_funding_bps > MAX_HOURLY_FUNDING_BPS {
+            violation(
+                "borrow-rate",
+                format!("{symbol} custody charges {hourly_funding_bps} bps an hour"),
+            );
+        }
+        // undecodable oracles are NaN and reported where prices are used
+        if custody_state.price() <= 0.0 {
+            violation(
+                "price",
+                format!("{symbol} oracle price is {}", custody_state.price()),
+            );
+        }
+    }
+
+    violations
+}
+
+/// The position checks of [`state_violations`], for positions handed over one at a time,
+/// such as by [`state::for_each_open_position`](crate::state::for_each_open_position)
+#[derive(Default)]
+pub struct PositionProblems {
+    /// (check, problem, count, example)
+    problems: Vec<(&'static str, &'static str, usize, Pubkey)>,
+}
+
+impl PositionProblems {
+    pub fn add(
+        &mut self,
+        state: &PerpState,
+        pubkey: &Pubkey,
+        position: &perp_abi::state::Position,
+    ) {
+        let latest_open_time = (state.unix_time as i64).add(86_400);
+        if !state.custodies.contains_key(&position.custody) {
+            self.note("custody", "reference an unknown custody", pubkey);
+        }
+        if !state.custodies.contains_key(&position.collateral_custody) {
+            self.note(
+                "collateral-custody",
+                "reference an unknown collateral custody",
+                pubkey,
+            );
+        }
+        let open_time = position.open_time as i64;
+        if !(PROGRAM_LAUNCH_UNIX_TIME..=latest_open_time).contains(&open_time) {
+            self.note(
+                "open-time",
+                "have an open time outside the program's lifetime",
+                pubkey,
+            );
+        }
+    }
+
+    fn note(&mut self, check: &'static str, problem: &'static str, pubkey: &Pubkey) {
+        match self
+            .problems
+            .iter_mut()
+            .find(|(noted, _, _, _)| *noted == check)
+        {
+            Some((_, _, count, _)) => *count += 1,
+            None => self.problems.push((check, problem, 1, *pubkey)),
+        }
+    }
+
+    pub fn violations(self) -> Vec<(String, String)> {
+        self.problems
+            .into_iter()
+            .map(|(check, problem, count, example)| {
+                (
+                    format!("layout:positions:{check}"),
+                    format!(
+                        "Positions that {problem}: {}, e.g. {example}",
+                        count.separate_with_commas()
+                    ),
+                )
+            })
+            .collect()
+    }
+}
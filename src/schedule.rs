@@ -0,0 +1,239 @@
+//! Five-field cron expressions (`minute hour day-of-month month day-of-week`) for taking
+//! snapshots on wall-clock-aligned boundaries, evaluated in UTC.
+
+use std::str::FromStr;
+use std::time::Duration;
+
+use chrono::{DateTime, Datelike as _, DurationRound as _, Timelike as _, Utc};
+
+/// Latest a schedule is searched ahead for its next match
+const MAX_LOOKAHEAD_MINUTES: i64 = 366 * 24 * 60;
+
+#[derive(Clone, Debug)]
+struct Field {
+    allowed: Vec<bool>,
+    /// `*` without a step, relevant to how day-of-month and day-of-week combine
+    unrestricted: bool,
+}
+
+impl Field {
+    fn parse(expression: &str, min: u32, max: u32) -> Result<Self, String> {
+        let mut allowed = vec![false; max as usize + 1];
+        for part in expression.split(',') {
+            let (range, step) = match part.split_once('/') {
+                Some((range, step)) => (
+                    range,
+                    step.parse::<u32>()
+                        .map_err(|_| format!("invalid step in {part}"))?,
+                ),
+                None => (part, 1),
+            };
+            if step == 0 {
+                return Err(format!("step can't be zero in {part}"));
+            }
+            let (start, end) = if range == "*" {
+                (min, max)
+            } else if let Some((start, end)) = range.split_once('-') {
+                (parse_value(start, part)?, parse_value(end, part)?)
+            } else {
+                let start = parse_value(range, part)?;
+                // `5/15` means every 15 starting at 5
+                (start, if part.contains('/') { max } else { start })
+            };
+            if start < min || end > max || start > end {
+                return Err(format!("{part} is outside {min}-{max}"));
+            }
+            for value in (start..=end).step_by(step as usize) {
+                allowed[value as usize] = true;
+            }
+        }
+        Ok(Field {
+            allowed,
+            unrestricted: expression == "*",
+        })
+    }
+
+    fn matches(&self, value: u32) -> bool {
+        self.allowed.get(value as usize).copied().unwrap_or(false)
+    }
+}
+
+fn parse_value(value: &str, part: &str) -> Result<u32, String> {
+    value
+        .parse()
+        .map_err(|_| format!("invalid value {value} in {part}"))
+}
+
+#[derive(Clone, Debug)]
+pub struct Schedule {
+    minute: Field,
+    hour: Field,
+    day_of_month: Field,
+    month: Field,
+    day_of_week: Field,
+}
+
+impl FromStr for Schedule {
+    type Err = String;
+
+    fn from_str(expression: &str) -> Result<Self, Self::Err> {
+        let fields: Vec<&str> = expression.split_whitespace().collect();
+        let [minute, hour, day_of_month, month, day_of_week] = fields[..] else {
+            return Err(format!(
+                "expected 5 fields (minute hour day-of-month month day-of-week), got {}",
+                fields.len()
+            ));
+        };
+        let mut day_of_week = Field::parse(day_of_week, 0, 7)?;
+        // both 0 and 7 are Sunday
+        if day_of_week.matches(7) {
+            day_of_week.allowed[0] = true;
+        }
+        Ok(Schedule {
+            minute: Field::parse(minute, 0, 59)?,
+            hour: Field::parse(hour, 0, 23)?,
+            day_of_month: Field::parse(day_of_month, 1, 31)?,
+            month: Field::parse(month, 1, 12)?,
+            day_of_week,
+        })
+    }
+}
+
+impl Schedule {
+    fn matches(&self, time: &DateTime<Utc>) -> bool {
+        let day_of_month = self.day_of_month.matches(time.day());
+        let day_of_week = self
+            .day_of_week
+            .matches(time.weekday().num_days_from_sunday());
+        // as in cron, restricting both day fields matches either of them
+        let day = match (
+            self.day_of_month.unrestricted,
+            self.day_of_week.unrestricted,
+        ) {
+            (false, false) => day_of_month || day_of_week,
+            _ => day_of_month && day_of_week,
+        };
+        day && self.minute.matches(time.minute())
+            && self.hour.matches(time.hour())
+            && self.month.matches(time.month())
+    }
+
+    /// First matching minute strictly after `after`
+    pub fn next_after(&self, after: DateTime<Utc>) -> Option<DateTime<Utc>> {
+        let mut candidate = after.duration_trunc(chrono::Duration::minutes(1)).ok()?;
+        for _ in 0..MAX_LOOKAHEAD_MINUTES {
+            candidate += chrono::Duration::minutes(1);
+            if self.matches(&candidate) {
+                return Some(candidate);
+            }
+        }
+        None
+    }
+
+    /// How long to sleep until the next matching minute
+    pub fn until_next(&self) -> Result<Duration, Box<dyn std::error::Error>> {
+        let now = Utc::now();
+        let next = self
+            .next_after(now)
+            .ok_or("schedule never matches within a year")?;
+        Ok((next - now).to_std()?)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn at(time: &str) -> DateTime<Utc> {
+        time.parse().unwrap()
+    }
+
+    fn next(expression: &str, after: &str) -> Option<DateTime<Utc>> {
+        Schedule::from_str(expression)
+            .unwrap()
+            .next_after(at(after))
+    }
+
+    #[test]
+    fn steps_from_the_start_of_the_range() {
+        assert_eq!(
+            next("*/5 * * * *", "2024-04-02T12:03:30Z"),
+            Some(at("2024-04-02T12:05:00Z"))
+        );
+        assert_eq!(
+            next("5/15 * * * *", "2024-04-02T12:05:00Z"),
+            Some(at("2024-04-02T12:20:00Z"))
+        );
+    }
+
+    #[test]
+    fn next_match_is_strictly_after() {
+        assert_eq!(
+            next("0 * * * *", "2024-04-02T23:00:00Z"),
+            Some(at("2024-04-03T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn lists_and_ranges() {
+        assert_eq!(
+            next("0 9-17/4 * * *", "2024-04-02T13:00:00Z"),
+            Some(at("2024-04-02T17:00:00Z"))
+        );
+        assert_eq!(
+            next("30 6,18 * * *", "2024-04-02T18:30:00Z"),
+            Some(at("2024-04-03T06:30:00Z"))
+        );
+    }
+
+    #[test]
+    fn restricted_day_fields_match_either() {
+        // 2024-04-02 is a Tuesday
+        assert_eq!(
+            next("0 0 1 * 1", "2024-04-02T00:00:00Z"),
+            Some(at("2024-04-08T00:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 1 * *", "2024-04-02T00:00:00Z"),
+            Some(at("2024-05-01T00:00:00Z"))
+        );
+        assert_eq!(
+            next("0 0 */10 * 1", "2024-04-02T00:00:00Z"),
+            Some(at("2024-04-08T00:00:00Z"))
+        );
+    }
+
+    #[test]
+    fn sunday_is_0_or_7() {
+        for expression in ["0 0 * * 0", "0 0 * * 7"] {
+            assert_eq!(
+                next(expression, "2024-04-02T00:00:00Z"),
+                Some(at("2024-04-07T00:00:00Z"))
+            );
+        }
+    }
+
+    #[test]
+    fn impossible_dates_never_match() {
+        assert_eq!(next("0 0 31 2 *", "2024-04-02T00:00:00Z"), None);
+    }
+
+    #[test]
+    fn rejects_invalid_expressions() {
+        for expression in [
+            "* * * *",
+            "* * * * * *",
+            "60 * * * *",
+            "* 24 * * *",
+            "* * 0 * *",
+            "*/0 * * * *",
+            "5-1 * * * *",
+            "a * * * *",
+        ] {
+            assert!(
+                Schedule::from_str(expression).is_err(),
+                "{expression} parsed"
+            );
+        }
+    }
+}
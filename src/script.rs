@@ -0,0 +1,90 @@
+//! Rhai script hooks, for derived metrics and alerts without writing Rust. The script
+//! named by `script` in the config runs on each snapshot with it in scope as `snapshot`,
+//! in the versioned JSON model, and reports through two functions:
+//!
+//! ```rhai
+//! let skew = snapshot.long_value / snapshot.short_value;
+//! metric("skew", skew);
+//! if skew > 3.0 {
+//!     alert("skew", `longs outweigh shorts ${skew}x`);
+//! }
+//! ```
+
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::rc::Rc;
+
+use rhai::{Engine, Scope, AST};
+
+use crate::alerts::Alert;
+use crate::snapshot::v1::SnapshotV1;
+use crate::snapshot::Snapshot;
+
+/// Operations a script may run per snapshot, so a runaway loop fails instead of hanging
+const MAX_OPERATIONS: u64 = 10_000_000;
+
+#[derive(Default)]
+struct Output {
+    metrics: BTreeMap<String, f64>,
+    alerts: Vec<Alert>,
+}
+
+pub struct Script {
+    engine: Engine,
+    ast: AST,
+    output: Rc<RefCell<Output>>,
+}
+
+impl Script {
+    /// Compiles the script at `path`
+    pub fn load(path: &Path) -> Result<Self, Box<dyn std::error::Error>> {
+        let output = Rc::new(RefCell::new(Output::default()));
+        let mut engine = Engine::new();
+        engine.set_max_operations(MAX_OPERATIONS);
+        let metrics = output.clone();
+        engine.register_fn("metric", move |name: &str, value: f64| {
+            metrics.borrow_mut().metrics.insert(name.to_string(), value);
+        });
+        let metrics = output.clone();
+        engine.register_fn("metric", move |name: &str, value: i64| {
+            metrics
+                .borrow_mut()
+                .metrics
+                .insert(name.to_string(), value as f64);
+        });
+        let alerts = output.clone();
+        engine.register_fn("alert", move |key: &str, message: &str| {
+            alerts.borrow_mut().alerts.push(Alert {
+                key: format!("script:{key}"),
+                message: message.to_string(),
+            });
+        });
+        let ast = engine
+            .compile_file(path.to_path_buf())
+            .map_err(|err| format!("can't load script {}: {err}", path.display()))?;
+        Ok(Script {
+            engine,
+            ast,
+            output,
+        })
+    }
+
+    /// Runs the script on `snapshot`, adding the metrics it reports to the snapshot's
+    /// custom metrics, and returns the alerts it raised
+    pub fn run(&self, snapshot: &mut Snapshot) -> Result<Vec<Alert>, Box<dyn std::error::Error>> {
+        // anything left over from a run that failed halfway
+        self.output.take();
+        let mut scope = Scope::new();
+        scope.push_constant_dynamic(
+            "snapshot",
+            rhai::serde::to_dynamic(SnapshotV1::from(&*snapshot))?,
+        );
+        self.engine
+            .run_ast_with_scope(&mut scope, &self.ast)
+            .map_err(|err| format!("script failed: {err}"))?;
+        let output = self.output.take();
+        snapshot.custom_metrics.extend(output.metrics);
+        Ok(output.alerts)
+    }
+}
@@ -0,0 +1,297 @@
+//! GraphQL API over the snapshot store, so frontends can fetch the fields and history ranges
+//! they need in one request. The store is reread on every query, so a collector can keep
+//! appending to it while the server runs. Health and readiness endpoints report how fresh
+//! the store is, so a supervisor can restart a stuck collector.
+
+use std::net::SocketAddr;
+use std::ops::Div as _;
+use std::sync::Arc;
+use std::time::SystemTime;
+
+use async_graphql::{Context, EmptyMutation, EmptySubscription, Object, Schema, SimpleObject};
+use async_graphql_axum::GraphQL;
+use axum::extract::State;
+use axum::http::StatusCode;
+use axum::response::{Html, IntoResponse};
+use axum::routing::get;
+use axum::{Json, Router};
+use serde::Serialize;
+use solana_client::rpc_client::RpcClient;
+
+use crate::state::{load_open_positions, PerpState};
+use crate::store::{
+    child_path, read_records, CustodyRecord, FundingRecord, Record, SnapshotRecord,
+};
+
+#[derive(Clone)]
+struct Stores {
+    history: String,
+    funding_history: Option<String>,
+    connection: Option<Arc<RpcClient>>,
+}
+
+/// An open position, as listed by `topPositions`
+#[derive(SimpleObject)]
+struct Position {
+    pubkey: String,
+    owner: String,
+    market: String,
+    side: String,
+    size_usd: f64,
+    collateral_usd: f64,
+    entry_price: f64,
+    leverage: f64,
+    open_time: i64,
+}
+
+fn in_range(unix_time: u64, from: Option<u64>, to: Option<u64>) -> bool {
+    from.map_or(true, |from| unix_time >= from) && to.map_or(true, |to| unix_time <= to)
+}
+
+fn read_range<R: Record>(
+    path: &str,
+    from: Option<u64>,
+    to: Option<u64>,
+) -> async_graphql::Result<Vec<R>> {
+    let records =
+        read_records::<R>(path).map_err(|err| async_graphql::Error::new(err.to_string()))?;
+    Ok(records
+        .into_iter()
+        .filter(|record| in_range(record.unix_time(), from, to))
+        .collect())
+}
+
+fn top_positions(connection: &RpcClient, limit: usize) -> Result<Vec<Position>, String> {
+    let state = PerpState::load(connection).map_err(|err| err.to_string())?;
+    let mut positions = load_open_positions(connection).map_err(|err| err.to_string())?;
+    positions.sort_by_key(|(_, position)| std::cmp::Reverse(position.size_usd));
+    Ok(positions
+        .into_iter()
+        .take(limit)
+        .map(|(pubkey, position)| {
+            let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+            let collateral_usd = spl_token::amount_to_ui_amount(position.collateral_usd, 6);
+            Position {
+                pubkey: pubkey.to_string(),
+                owner: position.owner.to_string(),
+                market: state
+                    .custodies
+                    .get(&position.custody)
+                    .map_or_else(|| position.custody.to_string(), |custody| custody.symbol()),
+                side: format!("{:?}", position.side),
+                size_usd,
+                collateral_usd,
+                entry_price: spl_token::amount_to_ui_amount(position.price, 6),
+                leverage: size_usd.div(collateral_usd),
+                open_time: position.open_time,
+            }
+        })
+        .collect())
+}
+
+struct Query;
+
+#[Object]
+impl Query {
+    /// Stored snapshots taken between `from` and `to` (unix times, inclusive)
+    async fn snapshots(
+        &self,
+        ctx: &Context<'_>,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> async_graphql::Result<Vec<SnapshotRecord>> {
+        read_range(&ctx.data::<Stores>()?.history, from, to)
+    }
+
+    /// The most recent stored snapshot
+    async fn latest_snapshot(
+        &self,
+        ctx: &Context<'_>,
+    ) -> async_graphql::Result<Option<SnapshotRecord>> {
+        Ok(
+            read_range::<SnapshotRecord>(&ctx.data::<Stores>()?.history, None, None)?
+                .into_iter()
+                .max_by_key(|record| record.unix_time),
+        )
+    }
+
+    /// Stored custody rows, optionally of a single market
+    async fn custodies(
+        &self,
+        ctx: &Context<'_>,
+        market: Option<String>,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> async_graphql::Result<Vec<CustodyRecord>> {
+        let path = child_path(&ctx.data::<Stores>()?.history, CustodyRecord::TABLE);
+        let records = read_range::<CustodyRecord>(&path, from, to)?;
+        Ok(records
+            .into_iter()
+            .filter(|record| {
+                market
+                    .as_ref()
+                    .map_or(true, |market| &record.market == market)
+            })
+            .collect())
+    }
+
+    /// Open interest per market side over time, from the funding history
+    async fn open_interest(
+        &self,
+        ctx: &Context<'_>,
+        market: Option<String>,
+        from: Option<u64>,
+        to: Option<u64>,
+    ) -> async_graphql::Result<Vec<FundingRecord>> {
+        let Some(path) = &ctx.data::<Stores>()?.funding_history else {
+            return Err("the server was started without --funding-history".into());
+        };
+        let records = read_range::<FundingRecord>(path, from, to)?;
+        Ok(records
+            .into_iter()
+            .filter(|record| {
+                market
+                    .as_ref()
+                    .map_or(true, |market| &record.market == market)
+            })
+            .collect())
+    }
+
+    /// The largest open positions right now, fetched over RPC
+    async fn top_positions(
+        &self,
+        ctx: &Context<'_>,
+        #[graphql(default = 10)] limit: usize,
+    ) -> async_graphql::Result<Vec<Position>> {
+        let Some(connection) = ctx.data::<Stores>()?.connection.clone() else {
+            return Err("the server was started without -r <RPC_URL>".into());
+        };
+        // the blocking client must stay off the async workers
+        tokio::task::spawn_blocking(move || top_positions(&connection, limit))
+            .await?
+            .map_err(async_graphql::Error::new)
+    }
+}
+
+/// What `/healthz` and `/readyz` report
+#[derive(Serialize)]
+struct Health {
+    ok: bool,
+    last_snapshot_unix_time: Option<u64>,
+    last_snapshot_age_secs: Option<u64>,
+    /// `ok`, `not configured` without -r, or the error the RPC returned
+    rpc: String,
+}
+
+#[derive(Clone)]
+struct HealthCheck {
+    stores: Stores,
+    /// Age of the latest snapshot past which the collector counts as stuck
+    max_snapshot_age: u64,
+}
+
+impl HealthCheck {
+    /// Reads the latest snapshot time and asks the RPC node, if any, for its health
+    fn check(&self) -> Health {
+        let last_snapshot_unix_time = read_records::<SnapshotRecord>(&self.stores.history)
+            .ok()
+            .and_then(|records| records.iter().map(|record| record.unix_time).max());
+        let now = SystemTime::now()
+            .duration_since(SystemTime::UNIX_EPOCH)
+            .map_or(0, |now| now.as_secs());
+        let last_snapshot_age_secs =
+            last_snapshot_unix_time.map(|unix_time| now.saturating_sub(unix_time));
+        let rpc = match &self.stores.connection {
+            Some(connection) => match connection.get_health() {
+                Ok(()) => "ok".to_string(),
+                Err(err) => err.to_string(),
+            },
+            None => "not configured".to_string(),
+        };
+        Health {
+            ok: last_snapshot_age_secs.map_or(false, |age| age <= self.max_snapshot_age),
+            last_snapshot_unix_time,
+            last_snapshot_age_secs,
+            rpc,
+        }
+    }
+}
+
+fn health_response(health: Health) -> impl IntoResponse {
+    let status = if health.ok {
+        StatusCode::OK
+    } else {
+        StatusCode::SERVICE_UNAVAILABLE
+    };
+    (status, Json(health))
+}
+
+/// Liveness: fails once the latest snapshot is older than the allowed age
+async fn healthz(State(check): State<HealthCheck>) -> impl IntoResponse {
+    // the blocking client must stay off the async workers
+    let health = tokio::task::spawn_blocking(move || check.check()).await;
+    match health {
+        Ok(health) => health_response(health).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+/// Readiness: additionally fails while the RPC node, if configured, is unhealthy
+async fn readyz(State(check): State<HealthCheck>) -> impl IntoResponse {
+    let health = tokio::task::spawn_blocking(move || {
+        let mut health = check.check();
+        health.ok &= health.rpc == "ok" || check.stores.connection.is_none();
+        health
+    })
+    .await;
+    match health {
+        Ok(health) => health_response(health).into_response(),
+        Err(err) => (StatusCode::INTERNAL_SERVER_ERROR, err.to_string()).into_response(),
+    }
+}
+
+async fn graphiql() -> impl IntoResponse {
+    Html(
+        async_graphql::http::GraphiQLSource::build()
+            .endpoint("/graphql")
+            .finish(),
+    )
+}
+
+/// Serves the GraphQL API on `listen` at `/graphql`, with GraphiQL on GET, over the
+/// snapshots at `history`. Live positions are only available with a `connection`.
+/// `/healthz` and `/readyz` fail once the latest snapshot is older than `max_snapshot_age`
+/// seconds, and `/readyz` also while the RPC node is unhealthy.
+pub fn run(
+    listen: SocketAddr,
+    history: &str,
+    funding_history: Option<&str>,
+    connection: Option<RpcClient>,
+    max_snapshot_age: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let stores = Stores {
+        history: history.to_string(),
+        funding_history: funding_history.map(str::to_string),
+        connection: connection.map(Arc::new),
+    };
+    let schema = Schema::build(Query, EmptyMutation, EmptySubscription)
+        .data(stores.clone())
+        .finish();
+    let app = Router::new()
+        .route("/graphql", get(graphiql).post_service(GraphQL::new(schema)))
+        .route("/healthz", get(healthz))
+        .route("/readyz", get(readyz))
+        .with_state(HealthCheck {
+            stores,
+            max_snapshot_age,
+        });
+
+    println!("Serving GraphQL on http://{listen}/graphql");
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        axum::Server::bind(&listen)
+            .serve(app.into_make_service())
+            .await
+    })?;
+    Ok(())
+}
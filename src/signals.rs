@@ -0,0 +1,195 @@
+//! Copy-trading signals: whenever a wallet watched through the config's `[wallets]`
+//! opens, increases, decreases or closes a position between snapshots, a JSON event is
+//! appended to a JSONL file or posted to a webhook. With `--watch` over a websocket or
+//! `--geyser-url`, snapshots follow the live subscription, so signals do too.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::ops::{Div as _, Sub as _};
+use std::str::FromStr as _;
+
+use serde::Serialize;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::lifecycle::{PositionChange, Transition};
+use crate::sink::OutputSink;
+use crate::snapshot::Snapshot;
+use crate::state::PerpState;
+use crate::transport::blocking_client_with_headers;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Action {
+    Opened,
+    Increased,
+    Decreased,
+    Closed,
+}
+
+/// A watched wallet's trade, as written to the feed
+#[derive(Clone, Debug, Serialize)]
+pub struct TradeSignal {
+    pub unix_time: u64,
+    pub slot: u64,
+    /// Name of the wallet in the config
+    pub label: String,
+    pub wallet: String,
+    pub action: Action,
+    pub position: String,
+    pub market: String,
+    pub side: String,
+    /// Change in size, negative when decreased or closed
+    pub size_delta_usd: f64,
+    /// Size after the trade, zero once closed
+    pub size_usd: f64,
+    pub collateral_usd: f64,
+    /// Size over collateral after the trade, or before closing
+    pub leverage: f64,
+    /// Oracle price of the market at the snapshot
+    pub price: f64,
+}
+
+enum Destination {
+    /// Appended to, `-` for stdout
+    File(String),
+    Webhook {
+        client: reqwest::blocking::Client,
+        url: String,
+    },
+}
+
+/// Writes the trades among the position changes each snapshot found that the watched
+/// wallets made
+pub struct SignalFeed {
+    /// Labels by wallet address
+    wallets: HashMap<String, String>,
+    destination: Destination,
+}
+
+impl SignalFeed {
+    /// Writes the trades of `wallets`, addresses by label, to `destination`: an http(s)
+    /// URL each signal is posted to, or a JSONL path
+    pub fn open(
+        destination: &str,
+        wallets: &BTreeMap<String, String>,
+        proxy_url: Option<&str>,
+        headers: &BTreeMap<String, String>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        if wallets.is_empty() {
+            return Err("--signals needs wallets to watch under [wallets] in the config".into());
+        }
+        let wallets = wallets
+            .iter()
+            .map(|(label, wallet)| {
+                let pubkey = Pubkey::from_str(wallet)
+                    .map_err(|err| format!("invalid address of wallet {label}: {err}"))?;
+                Ok((pubkey.to_string(), label.clone()))
+            })
+            .collect::<Result<_, String>>()?;
+        let destination =
+            if destination.starts_with("http://") || destination.starts_with("https://") {
+                Destination::Webhook {
+                    client: blocking_client_with_headers(proxy_url, headers)?,
+                    url: destination.to_string(),
+                }
+            } else {
+                Destination::File(destination.to_string())
+            };
+        Ok(SignalFeed {
+            wallets,
+            destination,
+        })
+    }
+
+    /// Trades of the watched wallets among the position changes `snapshot` found
+    fn signals(&self, snapshot: &Snapshot) -> Vec<TradeSignal> {
+        let Some(lifecycle) = &snapshot.lifecycle else {
+            return vec![];
+        };
+        lifecycle
+            .positions
+            .iter()
+            .filter_map(|change| self.signal(change))
+            .collect()
+    }
+
+    fn signal(&self, change: &PositionChange) -> Option<TradeSignal> {
+        let position = &change.position;
+        let label = self.wallets.get(&position.owner)?;
+        let action = match change.transition {
+            Transition::Opened => Action::Opened,
+            Transition::Increased => Action::Increased,
+            Transition::Decreased => Action::Decreased,
+            Transition::Closed | Transition::Liquidated => Action::Closed,
+            Transition::CollateralChanged => return None,
+        };
+        let size_usd = match action {
+            Action::Closed => 0.0,
+            _ => position.size_usd,
+        };
+        Some(TradeSignal {
+            unix_time: position.unix_time,
+            slot: position.slot,
+            label: label.clone(),
+            wallet: position.owner.clone(),
+            action,
+            position: position.position.clone(),
+            market: position.market.clone(),
+            side: position.side.clone(),
+            size_delta_usd: size_usd.sub(change.previous_size_usd),
+            size_usd,
+            collateral_usd: position.collateral_usd,
+            leverage: position.size_usd.div(position.collateral_usd),
+            price: position.price,
+        })
+    }
+
+    fn write(&self, signals: &[TradeSignal]) -> Result<(), Box<dyn std::error::Error>> {
+        match &self.destination {
+            Destination::File(path) if path == "-" => {
+                for signal in signals {
+                    println!("{}", serde_json::to_string(signal)?);
+                }
+            }
+            Destination::File(path) => {
+                let mut file = OpenOptions::new().create(true).append(true).open(path)?;
+                for signal in signals {
+                    writeln!(file, "{}", serde_json::to_string(signal)?)?;
+                }
+            }
+            Destination::Webhook { client, url } => {
+                for signal in signals {
+                    let response = client.post(url).json(signal).send()?;
+                    if !response.status().is_success() {
+                        return Err(format!(
+                            "signal webhook {url} responded {}",
+                            response.status()
+                        )
+                        .into());
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl OutputSink for SignalFeed {
+    fn write_snapshot(
+        &mut self,
+        _state: &PerpState,
+        _snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+
+    fn write_positions(
+        &mut self,
+        _state: &PerpState,
+        _positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.write(&self.signals(snapshot))
+    }
+}
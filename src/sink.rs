@@ -0,0 +1,276 @@
+//! Outputs each snapshot is written to. Every destination implements [`OutputSink`], so
+//! one run can append to several stores, publish and post a webhook, configured with a
+//! repeatable `--sink`.
+
+use std::collections::BTreeMap;
+use std::fs::OpenOptions;
+use std::io::Write as _;
+use std::str::FromStr;
+
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::config::Config;
+use crate::snapshot::v1::SnapshotV1;
+use crate::snapshot::{self, Snapshot};
+use crate::state::PerpState;
+use crate::store::{
+    self, CustodyRecord, MarketRecord, PositionRecord, Record as _, SnapshotRecord, WalletRecord,
+};
+use crate::transport::blocking_client_with_headers;
+
+/// A destination for snapshots. Each snapshot is written with `write_snapshot`, then
+/// `write_positions` with the positions it aggregates, then `flush`.
+pub trait OutputSink {
+    fn write_snapshot(
+        &mut self,
+        state: &PerpState,
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    fn write_positions(
+        &mut self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>>;
+
+    /// Sends anything buffered by the writes
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+/// A `--sink`: `webhook:<URL>`, `json:<PATH>` (`json:-` for stdout), or a history path or
+/// database URL in any storage format
+#[derive(Clone, Debug)]
+pub enum SinkSpec {
+    Store(String),
+    Json(String),
+    Webhook(String),
+}
+
+impl FromStr for SinkSpec {
+    type Err = String;
+
+    fn from_str(spec: &str) -> Result<Self, Self::Err> {
+        if let Some(url) = spec.strip_prefix("webhook:") {
+            if !url.starts_with("http://") && !url.starts_with("https://") {
+                return Err(format!("webhook sink {url} is not an http(s) URL"));
+            }
+            return Ok(SinkSpec::Webhook(url.to_string()));
+        }
+        if let Some(path) = spec.strip_prefix("json:") {
+            return Ok(SinkSpec::Json(path.to_string()));
+        }
+        if spec.is_empty() {
+            return Err("sink is empty".to_string());
+        }
+        Ok(SinkSpec::Store(spec.to_string()))
+    }
+}
+
+impl SinkSpec {
+    /// Opens the sink, writing positions only where `record_positions` would store them
+    pub fn open(
+        &self,
+        record_positions: bool,
+        proxy_url: Option<&str>,
+        config: &Config,
+    ) -> Result<Box<dyn OutputSink>, Box<dyn std::error::Error>> {
+        Ok(match self {
+            SinkSpec::Store(path) => Box::new(StoreSink {
+                path: path.clone(),
+                record_positions,
+                wallets: config.wallets.clone(),
+                pending: None,
+            }),
+            SinkSpec::Json(path) => Box::new(JsonSink { path: path.clone() }),
+            SinkSpec::Webhook(url) => Box::new(WebhookSink {
+                client: blocking_client_with_headers(proxy_url, &config.headers_for(url))?,
+                url: url.clone(),
+                record_positions,
+                pending: None,
+            }),
+        })
+    }
+}
+
+/// Appends snapshots and their custody and market rows to a history, like `-c`
+pub struct StoreSink {
+    path: String,
+    record_positions: bool,
+    /// Watched wallets from the config, addresses by label
+    wallets: BTreeMap<String, String>,
+    /// Snapshot row written on the flush, after its child rows
+    pending: Option<SnapshotRecord>,
+}
+
+impl OutputSink for StoreSink {
+    fn write_snapshot(
+        &mut self,
+        state: &PerpState,
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let history = store::read_existing_records::<SnapshotRecord>(&self.path)?;
+        self.pending = Some(snapshot.to_record().with_trailing_changes(&history));
+        store::append_records(
+            &store::child_path(&self.path, CustodyRecord::TABLE),
+            &snapshot::custody_records(state),
+        )?;
+        store::append_records(
+            &store::child_path(&self.path, MarketRecord::TABLE),
+            &snapshot::market_records(snapshot),
+        )
+    }
+
+    fn write_positions(
+        &mut self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        _snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if !self.wallets.is_empty() {
+            store::append_records(
+                &store::child_path(&self.path, WalletRecord::TABLE),
+                &snapshot::wallet_records(state, positions, &self.wallets),
+            )?;
+        }
+        if !self.record_positions {
+            return Ok(());
+        }
+        store::append_records(
+            &store::child_path(&self.path, PositionRecord::TABLE),
+            &snapshot::position_records(state, positions),
+        )
+    }
+
+    /// Appends the snapshot row last, so an interrupted run never leaves a snapshot with
+    /// only some of its child rows
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        match self.pending.take() {
+            Some(record) => store::append_records(&self.path, &[record]),
+            None => Ok(()),
+        }
+    }
+}
+
+/// Appends each snapshot as a line of the versioned JSON model, as `--porcelain json`
+/// prints it. Positions aren't part of that model.
+pub struct JsonSink {
+    path: String,
+}
+
+impl OutputSink for JsonSink {
+    fn write_snapshot(
+        &mut self,
+        _state: &PerpState,
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let line = serde_json::to_string(&SnapshotV1::from(snapshot))?;
+        if self.path == "-" {
+            println!("{line}");
+            return Ok(());
+        }
+        let mut file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&self.path)?;
+        writeln!(file, "{line}")?;
+        Ok(())
+    }
+
+    fn write_positions(
+        &mut self,
+        _state: &PerpState,
+        _positions: &[(Pubkey, perp_abi::state::Position)],
+        _snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        Ok(())
+    }
+}
+
+/// Posts each snapshot as `{"snapshot": ..., "positions": [...]}`, with the positions as
+/// stored by `--record-positions` and only if it's set
+pub struct WebhookSink {
+    client: reqwest::blocking::Client,
+    url: String,
+    record_positions: bool,
+    pending: Option<serde_json::Value>,
+}
+
+impl OutputSink for WebhookSink {
+    fn write_snapshot(
+        &mut self,
+        _state: &PerpState,
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        self.pending = Some(json!({
+            "snapshot": SnapshotV1::from(snapshot),
+            "positions": [],
+        }));
+        Ok(())
+    }
+
+    fn write_positions(
+        &mut self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        _snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        if let (true, Some(pending)) = (self.record_positions, &mut self.pending) {
+            pending["positions"] =
+                serde_json::to_value(snapshot::position_records(state, positions))?;
+        }
+        Ok(())
+    }
+
+    fn flush(&mut self) -> Result<(), Box<dyn std::error::Error>> {
+        let Some(body) = self.pending.take() else {
+            return Ok(());
+        };
+        let response = self.client.post(&self.url).json(&body).send()?;
+        if !response.status().is_success() {
+            return Err(
+                format!("webhook sink {} responded {}", self.url, response.status()).into(),
+            );
+        }
+        Ok(())
+    }
+}
+
+/// Writes to every sink in turn. A failing sink doesn't keep the snapshot from the others;
+/// the first error is returned once all have been tried.
+#[derive(Default)]
+pub struct FanOut {
+    sinks: Vec<Box<dyn OutputSink>>,
+}
+
+impl FanOut {
+    pub fn push(&mut self, sink: Box<dyn OutputSink>) {
+        self.sinks.push(sink);
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.sinks.is_empty()
+    }
+
+    pub fn write(
+        &mut self,
+        state: &PerpState,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        snapshot: &Snapshot,
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        let mut first_error = None;
+        for sink in &mut self.sinks {
+            let written = sink
+                .write_snapshot(state, snapshot)
+                .and_then(|()| sink.write_positions(state, positions, snapshot))
+                .and_then(|()| sink.flush());
+            if let Err(err) = written {
+                first_error.get_or_insert(err);
+            }
+        }
+        first_error.map_or(Ok(()), Err)
+    }
+}
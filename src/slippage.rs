@@ -0,0 +1,184 @@
+//! Estimates the spread and price impact traders paid at entry by comparing each position's
+//! entry price with the oracle price recorded when it was opened, or looked up on Pyth's
+//! Hermes API.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Div as _, Mul as _, Sub as _};
+
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::pyth;
+use crate::state::{load_open_positions, PerpState};
+use crate::store::{read_records, OraclePriceRecord};
+use crate::transport::blocking_client;
+
+/// Oracle prices of every custody, for appending to the price history
+pub fn oracle_price_records(state: &PerpState) -> Vec<OraclePriceRecord> {
+    state
+        .custodies_in_pool_order()
+        .into_iter()
+        .map(|custody_state| OraclePriceRecord {
+            unix_time: state.unix_time,
+            market: custody_state.symbol(),
+            price: custody_state.price(),
+            publish_time: custody_state.oracle.publish_time,
+        })
+        .collect()
+}
+
+/// Recorded price of `market` published closest to `time`, if one is within `max_gap` seconds
+fn price_near(
+    history: &BTreeMap<String, Vec<OraclePriceRecord>>,
+    market: &str,
+    time: i64,
+    max_gap: u64,
+) -> Option<f64> {
+    history
+        .get(market)?
+        .iter()
+        .min_by_key(|record| record.publish_time.abs_diff(time))
+        .filter(|record| record.publish_time.abs_diff(time) <= max_gap)
+        .map(|record| record.price)
+}
+
+#[derive(Default)]
+struct MarketSlippage {
+    measured: u64,
+    skipped: u64,
+    sum_bps: f64,
+    weighted_bps: f64,
+    size_usd: f64,
+}
+
+/// Prices of every known feed at the times positions were opened, one Hermes request per
+/// distinct open time
+struct HermesPrices {
+    client: reqwest::blocking::Client,
+    /// Keyed by open time, then feed id
+    prices: HashMap<i64, HashMap<String, f64>>,
+}
+
+impl HermesPrices {
+    fn price_at(
+        &mut self,
+        symbol: &str,
+        time: i64,
+        max_gap: u64,
+    ) -> Result<Option<f64>, Box<dyn std::error::Error>> {
+        let Some(feed_id) = pyth::feed_id_for_symbol(symbol) else {
+            return Ok(None);
+        };
+        if !self.prices.contains_key(&time) {
+            let feed_ids: Vec<&str> = pyth::KNOWN_FEED_IDS
+                .iter()
+                .map(|(_, feed_id)| *feed_id)
+                .collect();
+            let prices = pyth::prices_at(&self.client, &feed_ids, time)?
+                .into_iter()
+                .filter(|(_, price)| price.publish_time.abs_diff(time) <= max_gap)
+                .map(|(feed_id, price)| (feed_id, price.price))
+                .collect();
+            self.prices.insert(time, prices);
+        }
+        Ok(self.prices[&time].get(feed_id).copied())
+    }
+}
+
+/// Prints average entry slippage per market for open positions that haven't been resized,
+/// using prices from the `price_history` store, and from Hermes for positions it has no
+/// price for if `hermes` is set
+pub fn run(
+    connection: &RpcClient,
+    price_history: Option<&str>,
+    hermes: bool,
+    proxy_url: Option<&str>,
+    max_gap: u64,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut history: BTreeMap<String, Vec<OraclePriceRecord>> = BTreeMap::new();
+    if let Some(price_history) = price_history {
+        for record in read_records::<OraclePriceRecord>(price_history)? {
+            history
+                .entry(record.market.clone())
+                .or_default()
+                .push(record);
+        }
+    }
+    let mut hermes_prices = if hermes {
+        Some(HermesPrices {
+            client: blocking_client(proxy_url)?,
+            prices: HashMap::new(),
+        })
+    } else {
+        None
+    };
+
+    let state = PerpState::load(connection)?;
+    let positions = load_open_positions(connection)?;
+
+    let mut markets: BTreeMap<String, MarketSlippage> = BTreeMap::new();
+    for (_, position) in &positions {
+        let Some(custody_state) = state.custodies.get(&position.custody) else {
+            continue;
+        };
+        let market = markets.entry(custody_state.symbol()).or_default();
+        // a resized position's price is an average over several fills
+        if position.open_time != position.update_time {
+            market.skipped += 1;
+            continue;
+        }
+        let mut oracle_price = price_near(
+            &history,
+            &custody_state.symbol(),
+            position.open_time,
+            max_gap,
+        );
+        if let (None, Some(hermes_prices)) = (oracle_price, &mut hermes_prices) {
+            oracle_price =
+                hermes_prices.price_at(&custody_state.symbol(), position.open_time, max_gap)?;
+        }
+        let Some(oracle_price) = oracle_price else {
+            market.skipped += 1;
+            continue;
+        };
+        let entry_price = spl_token::amount_to_ui_amount(position.price, 6);
+        let size_usd = spl_token::amount_to_ui_amount(position.size_usd, 6);
+        // positive when the trader got a worse price than the oracle
+        let side_sign = if let perp_abi::Side::Long = position.side {
+            1.0
+        } else {
+            -1.0
+        };
+        let slippage_bps = entry_price
+            .div(oracle_price)
+            .sub(1.0)
+            .mul(10_000.0)
+            .mul(side_sign);
+
+        market.measured += 1;
+        market.sum_bps += slippage_bps;
+        market.weighted_bps += slippage_bps.mul(size_usd);
+        market.size_usd += size_usd;
+    }
+
+    println!("Unix time: {}", state.unix_time);
+    println!("Entry slippage vs oracle (positive = paid worse than oracle):");
+    for (symbol, market) in markets {
+        if market.measured == 0 {
+            println!(
+                "  {symbol:<5} no prices within {max_gap}s of entry ({} positions skipped)",
+                market.skipped
+            );
+            continue;
+        }
+        println!(
+            "  {symbol:<5} Average: {:.2} bps Size-weighted: {:.2} bps over {} positions (${}), {} skipped",
+            market.sum_bps.div(market.measured as f64),
+            market.weighted_bps.div(market.size_usd),
+            market.measured,
+            market.size_usd.round().separate_with_commas(),
+            market.skipped,
+        );
+    }
+    Ok(())
+}
@@ -0,0 +1,1418 @@
+use std::collections::{BTreeMap, HashSet};
+use std::ops::{Add as _, Div as _, Mul as _, Sub as _};
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize, Serializer};
+use serde_json::json;
+use solana_sdk::pubkey::Pubkey;
+use thousands::Separable;
+
+use crate::analyze::format_time;
+use crate::lifecycle::LifecycleChanges;
+use crate::liquidations;
+use crate::state::{self, CustodyState, PerpState};
+use crate::store::{
+    child_path, read_existing_records, CustodyRecord, MarketRecord, PositionRecord, SnapshotRecord,
+    WalletRecord,
+};
+use crate::style;
+
+pub mod v1;
+
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct TradeSummary {
+    #[serde(serialize_with = "serialize_pubkey")]
+    pub position: Pubkey,
+    pub pnl: f64,
+    pub price_at_entry: f64,
+    #[serde(serialize_with = "serialize_side")]
+    pub side: perp_abi::Side,
+    #[serde(serialize_with = "serialize_pubkey")]
+    pub mint: Pubkey,
+    /// Symbol of the market, or its mint if it has none
+    pub market: String,
+}
+
+fn serialize_pubkey<S: Serializer>(pubkey: &Pubkey, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(pubkey)
+}
+
+fn serialize_side<S: Serializer>(side: &perp_abi::Side, serializer: S) -> Result<S::Ok, S::Error> {
+    serializer.collect_str(&format_args!("{side:?}"))
+}
+
+/// Upper bounds of the distance to liquidation buckets open notional is summarized in
+pub const LIQUIDATION_BUCKETS: [(&str, f64); 4] = [
+    ("<2%", 0.02),
+    ("2-5%", 0.05),
+    ("5-10%", 0.10),
+    (">10%", f64::INFINITY),
+];
+
+/// Open interest and trader P&L of a single market
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct MarketSnapshot {
+    pub price: f64,
+    pub num_longs: u64,
+    pub num_shorts: u64,
+    pub long_oi: f64,
+    pub short_oi: f64,
+    pub unrealized_pnl: f64,
+    /// Share of the market's custody assets locked by positions
+    pub utilization: f64,
+    /// Borrow rate of the market's custody, annualized, in percent
+    pub borrow_apr: f64,
+    /// Open notional by distance to liquidation, bucketed like [`LIQUIDATION_BUCKETS`]
+    pub notional_by_liquidation_distance: [f64; LIQUIDATION_BUCKETS.len()],
+    /// Spot price on DEXes, left empty unless the caller fetched it
+    pub dex_price: Option<f64>,
+}
+
+impl MarketSnapshot {
+    /// Relative difference of the DEX spot price from the oracle price
+    pub fn dex_divergence(&self) -> Option<f64> {
+        self.dex_price
+            .map(|dex_price| dex_price.div(self.price).sub(1.0))
+    }
+}
+
+/// A position marked to the current oracle price
+#[derive(Clone, Copy, Debug, Default)]
+pub struct PositionValuation {
+    pub value_at_entry: f64,
+    pub current_value: f64,
+    /// Paper P&L, before fees
+    pub unrealized_pnl: f64,
+    /// Borrow fees accrued since the position was last updated
+    pub borrow_fees: f64,
+    /// Collateral left after unrealized P&L and accrued borrow fees
+    pub remaining_collateral: f64,
+}
+
+/// Current value of collateral posted as `collateral_usd` to `collateral_custody` for a
+/// position in `custody`. Collateral in the traded token moves with the position's P&L
+/// already, while stablecoin collateral, posted at par, is marked at its own oracle price.
+pub(crate) fn collateral_value(
+    collateral_usd: f64,
+    custody: &Pubkey,
+    collateral_custody: &CustodyState,
+) -> f64 {
+    if collateral_custody.pubkey == *custody {
+        collateral_usd
+    } else {
+        collateral_usd.mul(collateral_custody.price())
+    }
+}
+
+impl PositionValuation {
+    /// Values `position`, or `None` if it's closed or its custodies aren't in the pool
+    pub fn compute(state: &PerpState, position: &perp_abi::state::Position) -> Option<Self> {
+        if position.size_usd == 0 {
+            return None;
+        }
+        // the traded token prices the position, while collateral sits in its own custody:
+        // the traded token for longs, a stablecoin for shorts
+        let position_custody = state.custodies.get(&position.custody)?;
+        let collateral_custody = state.custodies.get(&position.collateral_custody)?;
+        let long_short_sign: f64 = match position.side {
+            perp_abi::Side::Long => 1.0,
+            perp_abi::Side::Short => -1.0,
+            _ => return None,
+        };
+
+        let amount = (position.size_usd as f64).div(position.price as f64);
+        let current_value = amount.mul(position_custody.price());
+        let value_at_entry = spl_token::amount_to_ui_amount(position.size_usd, 6);
+        let interval =
+            (state.unix_time.saturating_sub(position.update_time as u64) as f64).div(3600.0);
+        let borrow_fees = collateral_custody
+            .borrow_rate
+            // mul by hours
+            .mul(interval)
+            // get value in USD
+            .mul(value_at_entry)
+            // BPS to absolute value
+            .div(10_000.0);
+        // short's price is reversed
+        let unrealized_pnl = current_value.sub(value_at_entry).mul(long_short_sign);
+        // what's left of the collateral is its value plus the position's P&L, less fees
+        let remaining_collateral = collateral_value(
+            spl_token::amount_to_ui_amount(position.collateral_usd, 6),
+            &position.custody,
+            collateral_custody,
+        )
+        .add(unrealized_pnl)
+        .sub(borrow_fees);
+        Some(PositionValuation {
+            value_at_entry,
+            current_value,
+            unrealized_pnl,
+            borrow_fees,
+            remaining_collateral,
+        })
+    }
+
+    /// Current value over remaining collateral
+    pub fn effective_leverage(&self) -> f64 {
+        self.current_value.div(self.remaining_collateral)
+    }
+}
+
+/// Collateral posted in one token, by the side of the positions it backs
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct CollateralSnapshot {
+    pub long_positions: u64,
+    pub short_positions: u64,
+    /// Current value of the collateral, in USD
+    pub long_value: f64,
+    pub short_value: f64,
+}
+
+/// JLP's net asset value per token next to what it trades for
+#[derive(Clone, Copy, Debug, Default, Serialize)]
+pub struct JlpPrice {
+    /// Pool value over JLP supply, in USD
+    pub nav: f64,
+    /// Market price in USDC
+    pub market_price: f64,
+}
+
+impl JlpPrice {
+    /// Premium of the market price over NAV, negative for a discount
+    pub fn premium(&self) -> f64 {
+        self.market_price.div(self.nav).sub(1.0)
+    }
+}
+
+/// A market's price as agreed by several sources
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct PriceConsensus {
+    pub price: f64,
+    /// Price quoted by each source, keyed by its name
+    pub sources: BTreeMap<String, f64>,
+    /// Sources too far from the median to count towards the price
+    pub outliers: Vec<String>,
+}
+
+/// Where and how a snapshot was collected, so datasets merged from several collectors stay
+/// auditable
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+#[serde(default)]
+pub struct Provenance {
+    #[serde(rename = "tool_version")]
+    pub tool_version: String,
+    /// Cluster the RPC endpoint serves, empty if unknown
+    #[serde(rename = "cluster")]
+    pub cluster: String,
+    /// RPC endpoint reduced to its scheme and host, leaving out any credentials
+    #[serde(rename = "rpc_endpoint")]
+    pub rpc_endpoint: String,
+    /// Seconds spent fetching the accounts
+    #[serde(rename = "collection_secs")]
+    pub collection_secs: f64,
+}
+
+/// Aggregate metrics over all open positions
+#[derive(Clone, Debug, Default, Serialize)]
+pub struct Snapshot {
+    pub unix_time: u64,
+    pub slot: u64,
+    /// Custody accounts the state was loaded from
+    pub custody_accounts: u64,
+    /// Open position accounts the snapshot was computed from; callers filtering positions
+    /// set it to the number fetched
+    pub position_accounts: u64,
+    /// Hash of the perpetuals program's executable, if checked
+    pub program_hash: Option<String>,
+    pub total_pool_value: f64,
+    pub num_positions: u64,
+    pub num_longs: u64,
+    pub num_winning: u64,
+    pub cumulative_positions: f64,
+    pub cumulative_long: f64,
+    pub cumulative_positions_at_entry: f64,
+    pub cumulative_collateral: f64,
+    pub cumulative_collateral_at_entry: f64,
+    pub cumulative_fees: f64,
+    pub cumulative_pnl: f64,
+    pub most_profitable_trade: TradeSummary,
+    pub least_profitable_trade: TradeSummary,
+    /// Per-market breakdown keyed by symbol
+    pub markets: BTreeMap<String, MarketSnapshot>,
+    /// Collateral breakdown keyed by the symbol of the collateral token
+    pub collateral: BTreeMap<String, CollateralSnapshot>,
+    /// Markets whose oracle price is older than the custody's max price age, with its age
+    /// in seconds
+    pub stale_oracles: Vec<(String, i64)>,
+    /// Markets priced from a fallback source instead of their oracle, with the source
+    pub fallback_prices: Vec<(String, String)>,
+    /// Consensus behind each market's price, keyed by symbol, when priced from several
+    /// sources
+    pub price_consensus: BTreeMap<String, PriceConsensus>,
+    /// JLP NAV and market price, left empty unless the caller fetched the market price
+    pub jlp: Option<JlpPrice>,
+    /// Tool version, with the collection details left for the caller to fill in
+    pub provenance: Provenance,
+    /// Metrics added by plugins, keyed by name
+    pub custom_metrics: BTreeMap<String, f64>,
+    /// Position changes since the previous snapshot, left for the caller to fill in from
+    /// its position index
+    pub lifecycle: Option<LifecycleChanges>,
+}
+
+/// The latest stored snapshot, which console output shows changes against
+pub struct PreviousSnapshot {
+    pub record: SnapshotRecord,
+    /// Positions opened and closed since, known if positions were recorded with it
+    pub opened_closed: Option<(usize, usize)>,
+    /// The latest snapshots, oldest first, that sparklines of recent history are drawn from
+    pub recent: Vec<SnapshotRecord>,
+    /// Hourly borrow rate of each market over `recent`, oldest first
+    pub recent_borrow_rates: BTreeMap<String, Vec<f64>>,
+    /// Snapshots of the last `percentile_days` days, which current values are ranked
+    /// against
+    pub window: Vec<SnapshotRecord>,
+    /// Per-market rows of the same days
+    pub window_markets: Vec<MarketRecord>,
+    pub percentile_days: u64,
+}
+
+impl PreviousSnapshot {
+    /// Loads the latest snapshot in `history_path`, if there is one yet, and compares the
+    /// positions recorded with it to `positions`. The `sparkline_length` latest snapshots are
+    /// kept for sparklines, and those of the last `percentile_days` days for percentiles.
+    pub fn load(
+        history_path: &str,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+        sparkline_length: usize,
+        percentile_days: u64,
+    ) -> Result<Option<Self>, Box<dyn std::error::Error>> {
+        let mut history = read_existing_records::<SnapshotRecord>(history_path)?;
+        history.sort_by_key(|record| record.unix_time);
+        let Some(record) = history.last().cloned() else {
+            return Ok(None);
+        };
+        let window_start = record
+            .unix_time
+            .saturating_sub(percentile_days.saturating_mul(86_400));
+        let (window, window_markets) = if percentile_days == 0 {
+            (vec![], vec![])
+        } else {
+            let markets_path = child_path(history_path, MarketRecord::TABLE);
+            let mut window_markets = read_existing_records::<MarketRecord>(&markets_path)?;
+            window_markets.retain(|market| market.unix_time >= window_start);
+            (
+                history
+                    .iter()
+                    .filter(|record| record.unix_time >= window_start)
+                    .cloned()
+                    .collect(),
+                window_markets,
+            )
+        };
+        let recent = history.split_off(history.len().saturating_sub(sparkline_length));
+        let mut recent_borrow_rates: BTreeMap<String, Vec<f64>> = BTreeMap::new();
+        if let Some(first) = recent.first() {
+            let custodies_path = child_path(history_path, CustodyRecord::TABLE);
+            let mut custodies = read_existing_records::<CustodyRecord>(&custodies_path)?;
+            custodies.retain(|custody| custody.unix_time >= first.unix_time);
+            custodies.sort_by_key(|custody| custody.unix_time);
+            for custody in custodies {
+                recent_borrow_rates
+                    .entry(custody.market)
+                    .or_default()
+                    .push(custody.borrow_rate);
+            }
+        }
+        let positions_path = child_path(history_path, PositionRecord::TABLE);
+        let previous: HashSet<String> = read_existing_records::<PositionRecord>(&positions_path)?
+            .into_iter()
+            .filter(|position| position.unix_time == record.unix_time)
+            .map(|position| position.position)
+            .collect();
+        let opened_closed = (!previous.is_empty()).then(|| {
+            let current: HashSet<String> = positions
+                .iter()
+                .filter(|(_, position)| position.size_usd != 0)
+                .map(|(pubkey, _)| pubkey.to_string())
+                .collect();
+            (
+                current.difference(&previous).count(),
+                previous.difference(&current).count(),
+            )
+        });
+        Ok(Some(PreviousSnapshot {
+            record,
+            opened_closed,
+            recent,
+            recent_borrow_rates,
+            window,
+            window_markets,
+            percentile_days,
+        }))
+    }
+}
+
+/// Lookback of the trailing changes stored with each snapshot
+const TRAILING_CHANGE_SECS: u64 = 86_400;
+/// How far from exactly a day back the snapshot compared against may have been taken
+const TRAILING_CHANGE_TOLERANCE_SECS: u64 = 3_600;
+
+impl SnapshotRecord {
+    /// Fills in the 24h change columns from the `history` snapshot taken closest to a day
+    /// before this one, leaving them empty if none was taken within an hour of that
+    pub fn with_trailing_changes(mut self, history: &[SnapshotRecord]) -> Self {
+        let target = self.unix_time.saturating_sub(TRAILING_CHANGE_SECS);
+        let Some(earlier) = history
+            .iter()
+            .filter(|record| record.unix_time.abs_diff(target) <= TRAILING_CHANGE_TOLERANCE_SECS)
+            .min_by_key(|record| record.unix_time.abs_diff(target))
+        else {
+            return self;
+        };
+        self.total_pool_value_change_24h =
+            Some(self.total_pool_value.sub(earlier.total_pool_value));
+        self.total_position_value_change_24h =
+            Some(self.total_position_value.sub(earlier.total_position_value));
+        self.unrealized_pnl_change_24h = Some(self.unrealized_pnl.sub(earlier.unrealized_pnl));
+        self.total_fees_change_24h = Some(self.total_fees.sub(earlier.total_fees));
+        self
+    }
+
+    /// The plugin metrics stored with the snapshot, empty if none or unreadable
+    pub fn custom_metrics(&self) -> BTreeMap<String, f64> {
+        serde_json::from_str(&self.custom_metrics).unwrap_or_default()
+    }
+}
+
+/// Stored values needed before a percentile means much
+const MIN_PERCENTILE_SAMPLES: usize = 10;
+
+/// Percentile rank of `value` among `history`, counting equal values as half below
+fn percentile_rank(history: &[f64], value: f64) -> Option<f64> {
+    let history: Vec<f64> = history.iter().copied().filter(|x| x.is_finite()).collect();
+    if history.len() < MIN_PERCENTILE_SAMPLES || !value.is_finite() {
+        return None;
+    }
+    let below = history.iter().filter(|x| **x < value).count() as f64;
+    let equal = history.iter().filter(|x| **x == value).count() as f64;
+    Some(
+        below
+            .add(equal.div(2.0))
+            .div(history.len() as f64)
+            .mul(100.0),
+    )
+}
+
+/// `97th`, `21st`, ...
+fn ordinal(n: u64) -> String {
+    let suffix = match (n % 10, n % 100) {
+        (_, 11..=13) => "th",
+        (1, _) => "st",
+        (2, _) => "nd",
+        (3, _) => "rd",
+        _ => "th",
+    };
+    format!("{n}{suffix}")
+}
+
+/// Signed dollar change abbreviated to thousands, millions or billions, e.g. `+1.2M`
+fn compact_change(value: f64) -> String {
+    let sign = if value < 0.0 { "-" } else { "+" };
+    let value = value.abs();
+    let (scaled, suffix) = if value >= 1e9 {
+        (value.div(1e9), "B")
+    } else if value >= 1e6 {
+        (value.div(1e6), "M")
+    } else if value >= 1e3 {
+        (value.div(1e3), "K")
+    } else {
+        return format!("{sign}{value:.0}");
+    };
+    format!("{sign}{scaled:.1}{suffix}")
+}
+
+/// Price-independent sums over the open positions of one market, side and collateral token,
+/// from which their value, P&L and fees at the current prices follow
+#[derive(Clone, Copy, Debug, Default)]
+struct PositionGroup {
+    count: u64,
+    /// Size in USD at entry
+    value_at_entry: f64,
+    /// Size in the traded token
+    amount: f64,
+    collateral_at_entry: f64,
+    /// Size in USD at entry times the unix time of the last update, which borrow fees have
+    /// accrued since
+    value_since_update: f64,
+}
+
+/// Running sums over the open positions, which can be updated as single positions open,
+/// change and close instead of revisiting every position for each snapshot
+#[derive(Clone, Debug, Default)]
+pub struct PositionTotals {
+    /// Keyed by position custody, collateral custody and whether the positions are long
+    groups: BTreeMap<(Pubkey, Pubkey, bool), PositionGroup>,
+}
+
+impl PositionTotals {
+    pub fn new(positions: &[(Pubkey, perp_abi::state::Position)]) -> Self {
+        let mut totals = PositionTotals::default();
+        for (_, position) in positions {
+            totals.add(position);
+        }
+        totals
+    }
+
+    pub fn add(&mut self, position: &perp_abi::state::Position) {
+        self.apply(position, 1.0);
+    }
+
+    pub fn remove(&mut self, position: &perp_abi::state::Position) {
+        self.apply(position, -1.0);
+    }
+
+    fn apply(&mut self, position: &perp_abi::state::Position, sign: f64) {
+        let is_long = match position.side {
+            _ if position.size_usd == 0 => return,
+            perp_abi::Side::Long => true,
+            perp_abi::Side::Short => false,
+            _ => return,
+        };
+        let key = (position.custody, position.collateral_custody, is_long);
+        let group = self.groups.entry(key).or_default();
+        let value_at_entry = spl_token::amount_to_ui_amount(position.size_usd, 6);
+        if sign > 0.0 {
+            group.count += 1;
+        } else {
+            group.count = group.count.saturating_sub(1);
+        }
+        group.value_at_entry += value_at_entry.mul(sign);
+        group.amount += (position.size_usd as f64)
+            .div(position.price as f64)
+            .mul(sign);
+        group.collateral_at_entry +=
+            spl_token::amount_to_ui_amount(position.collateral_usd, 6).mul(sign);
+        group.value_since_update += value_at_entry.mul(position.update_time as f64).mul(sign);
+        // dropped once empty, rather than left holding rounding residue
+        if group.count == 0 {
+            self.groups.remove(&key);
+        }
+    }
+}
+
+/// What a snapshot needs of each position beyond the sums in [`PositionTotals`]: P&L
+/// rankings and liquidation distances, which move with prices
+#[derive(Default)]
+struct PositionRankings {
+    num_winning: u64,
+    most_profitable_trade: TradeSummary,
+    least_profitable_trade: TradeSummary,
+    highest_unrealized_profit: f64,
+    highest_unrealized_losses: f64,
+    /// Keyed by market symbol
+    notional_by_liquidation_distance: BTreeMap<String, [f64; LIQUIDATION_BUCKETS.len()]>,
+}
+
+impl PositionRankings {
+    fn add(&mut self, state: &PerpState, pubkey: &Pubkey, position: &perp_abi::state::Position) {
+        let Some(valuation) = PositionValuation::compute(state, position) else {
+            return;
+        };
+        let position_custody = &state.custodies[&position.custody];
+
+        // paper unrealized pnl
+        let unrealized_pnl = valuation.unrealized_pnl;
+
+        let trade = TradeSummary {
+            position: *pubkey,
+            pnl: unrealized_pnl,
+            price_at_entry: spl_token::amount_to_ui_amount(position.price, 6),
+            side: position.side,
+            mint: position_custody.custody.mint,
+            market: String::new(),
+        };
+
+        if unrealized_pnl > 0.0 {
+            self.num_winning += 1;
+            if unrealized_pnl > self.highest_unrealized_profit {
+                self.highest_unrealized_profit = unrealized_pnl;
+                self.most_profitable_trade = trade.clone();
+            }
+        }
+
+        if self.highest_unrealized_losses > unrealized_pnl {
+            self.highest_unrealized_losses = unrealized_pnl;
+            self.least_profitable_trade = trade;
+        }
+
+        if let Some(estimate) = liquidations::estimate(state, *pubkey, position) {
+            // positions already past their liquidation price land in the closest bucket
+            let bucket = LIQUIDATION_BUCKETS
+                .iter()
+                .position(|(_, bound)| estimate.distance < *bound)
+                .unwrap_or(LIQUIDATION_BUCKETS.len() - 1);
+            self.notional_by_liquidation_distance
+                .entry(position_custody.symbol())
+                .or_default()[bucket] += estimate.size_usd;
+        }
+    }
+}
+
+/// Builds a snapshot from positions handed over one at a time, such as by
+/// [`state::for_each_open_position`], so they needn't all be held at once
+pub struct SnapshotAccumulator<'a> {
+    state: &'a PerpState,
+    totals: PositionTotals,
+    rankings: PositionRankings,
+    position_accounts: u64,
+}
+
+impl<'a> SnapshotAccumulator<'a> {
+    pub fn new(state: &'a PerpState) -> Self {
+        SnapshotAccumulator {
+            state,
+            totals: PositionTotals::default(),
+            rankings: PositionRankings::default(),
+            position_accounts: 0,
+        }
+    }
+
+    pub fn add(&mut self, pubkey: &Pubkey, position: &perp_abi::state::Position) {
+        self.position_accounts += 1;
+        self.totals.add(position);
+        self.rankings.add(self.state, pubkey, position);
+    }
+
+    pub fn finish(self) -> Snapshot {
+        Snapshot::assemble(
+            self.state,
+            &self.totals,
+            self.rankings,
+            self.position_accounts,
+        )
+    }
+}
+
+impl Snapshot {
+    pub fn compute(state: &PerpState, positions: &[(Pubkey, perp_abi::state::Position)]) -> Self {
+        let mut accumulator = SnapshotAccumulator::new(state);
+        for (pubkey, position) in positions {
+            accumulator.add(pubkey, position);
+        }
+        accumulator.finish()
+    }
+
+    /// Like [`Snapshot::compute`], with the sums over `positions` kept up to date by the
+    /// caller, so only P&L rankings and liquidation distances are worked out per position
+    pub fn compute_with_totals(
+        state: &PerpState,
+        totals: &PositionTotals,
+        positions: &[(Pubkey, perp_abi::state::Position)],
+    ) -> Self {
+        let mut rankings = PositionRankings::default();
+        for (pubkey, position) in positions {
+            rankings.add(state, pubkey, position);
+        }
+        Self::assemble(state, totals, rankings, positions.len() as u64)
+    }
+
+    fn assemble(
+        state: &PerpState,
+        totals: &PositionTotals,
+        rankings: PositionRankings,
+        position_accounts: u64,
+    ) -> Self {
+        let pool = &state.pool;
+        let unix_time = state.unix_time;
+        let mut snapshot = Snapshot {
+            unix_time,
+            slot: state.slot,
+            custody_accounts: state.custodies.len() as u64,
+            position_accounts,
+            total_pool_value: state.total_pool_value(),
+            num_winning: rankings.num_winning,
+            most_profitable_trade: rankings.most_profitable_trade,
+            least_profitable_trade: rankings.least_profitable_trade,
+            provenance: Provenance {
+                tool_version: env!("CARGO_PKG_VERSION").to_string(),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+
+        for custody_state in state.custodies_in_pool_order() {
+            let price_age = (unix_time as i64).sub(custody_state.oracle.publish_time);
+            if price_age > custody_state.custody.oracle.max_price_age_sec as i64 {
+                snapshot
+                    .stale_oracles
+                    .push((custody_state.symbol(), price_age));
+            }
+            if let Some(source) = custody_state.oracle.fallback {
+                snapshot
+                    .fallback_prices
+                    .push((custody_state.symbol(), source.to_string()));
+            }
+        }
+
+        for ((custody, collateral_custody, is_long), group) in &totals.groups {
+            // the traded token prices the positions, while collateral sits in its own
+            // custody: the traded token for longs, a stablecoin for shorts
+            let (Some(position_custody), Some(collateral_custody)) = (
+                state.custodies.get(custody),
+                state.custodies.get(collateral_custody),
+            ) else {
+                continue;
+            };
+            let long_short_sign: f64 = if *is_long { 1.0 } else { -1.0 };
+            let current_value = group.amount.mul(position_custody.price());
+            // short's price is reversed
+            let unrealized_pnl = current_value.sub(group.value_at_entry).mul(long_short_sign);
+            let borrow_fees = collateral_custody
+                .borrow_rate
+                // mul by hours since each position's last update, weighted by its size
+                .mul(
+                    (unix_time as f64)
+                        .mul(group.value_at_entry)
+                        .sub(group.value_since_update)
+                        .div(3600.0),
+                )
+                // BPS to absolute value
+                .div(10_000.0);
+            let entry_fees = group
+                .value_at_entry
+                .mul(pool.fees.increase_position_bps as f64)
+                .div(10_000.0);
+            // what's left of the collateral is its value plus the positions' P&L, less fees
+            let current_collateral =
+                collateral_value(group.collateral_at_entry, custody, collateral_custody)
+                    .add(unrealized_pnl)
+                    .sub(borrow_fees);
+
+            snapshot.num_positions += group.count;
+            if *is_long {
+                snapshot.num_longs += group.count;
+                snapshot.cumulative_long += current_value;
+            }
+            snapshot.cumulative_positions_at_entry += group.value_at_entry;
+            snapshot.cumulative_collateral_at_entry += group.collateral_at_entry;
+            snapshot.cumulative_positions += current_value;
+            snapshot.cumulative_collateral += current_collateral;
+            snapshot.cumulative_pnl += unrealized_pnl;
+            snapshot.cumulative_fees += entry_fees.mul(2.0).add(borrow_fees);
+
+            let market = snapshot
+                .markets
+                .entry(position_custody.symbol())
+                .or_default();
+            market.price = position_custody.price();
+            market.utilization = position_custody.utilization;
+            market.borrow_apr = position_custody.borrow_apr();
+            market.unrealized_pnl += unrealized_pnl;
+            if *is_long {
+                market.num_longs += group.count;
+                market.long_oi += current_value;
+            } else {
+                market.num_shorts += group.count;
+                market.short_oi += current_value;
+            }
+
+            let collateral = snapshot
+                .collateral
+                .entry(collateral_custody.symbol())
+                .or_default();
+            if *is_long {
+                collateral.long_positions += group.count;
+                collateral.long_value += current_collateral;
+            } else {
+                collateral.short_positions += group.count;
+                collateral.short_value += current_collateral;
+            }
+        }
+
+        for (symbol, notional) in rankings.notional_by_liquidation_distance {
+            snapshot
+                .markets
+                .entry(symbol)
+                .or_default()
+                .notional_by_liquidation_distance = notional;
+        }
+        for trade in [
+            &mut snapshot.most_profitable_trade,
+            &mut snapshot.least_profitable_trade,
+        ] {
+            trade.market = state::mint_symbol(&trade.mint);
+        }
+
+        snapshot
+    }
+
+    pub fn num_shorts(&self) -> u64 {
+        self.num_positions.sub(self.num_longs)
+    }
+
+    pub fn cumulative_short(&self) -> f64 {
+        self.cumulative_positions.sub(self.cumulative_long)
+    }
+
+    pub fn average_leverage_at_entry(&self) -> f64 {
+        self.cumulative_positions_at_entry
+            .div(self.cumulative_collateral_at_entry)
+    }
+
+    /// Open interest over the collateral left after P&L and accrued borrow fees, the ratio
+    /// of sums so nearly wiped out positions don't dominate it
+    pub fn average_effective_leverage(&self) -> f64 {
+        self.cumulative_positions.div(self.cumulative_collateral)
+    }
+
+    pub fn to_record(&self) -> SnapshotRecord {
+        SnapshotRecord {
+            unix_time: self.unix_time,
+            total_pool_value: self.total_pool_value,
+            unrealized_pnl: self.cumulative_pnl,
+            total_fees: self.cumulative_fees,
+            total_position_value: self.cumulative_positions,
+            total_collateral_value: self.cumulative_collateral,
+            average_leverage_at_entry: self.average_leverage_at_entry(),
+            average_effective_leverage: self.average_effective_leverage(),
+            long_trades: self.num_longs,
+            long_value: self.cumulative_long,
+            short_trades: self.num_shorts(),
+            short_value: self.cumulative_short(),
+            slot: self.slot,
+            custody_accounts: Some(self.custody_accounts),
+            position_accounts: Some(self.position_accounts),
+            program_hash: self.program_hash.clone().unwrap_or_default(),
+            jlp_nav: self.jlp.map(|jlp| jlp.nav),
+            jlp_price: self.jlp.map(|jlp| jlp.market_price),
+            tool_version: self.provenance.tool_version.clone(),
+            cluster: self.provenance.cluster.clone(),
+            rpc_endpoint: self.provenance.rpc_endpoint.clone(),
+            collection_secs: Some(self.provenance.collection_secs),
+            custom_metrics: if self.custom_metrics.is_empty() {
+                String::new()
+            } else {
+                serde_json::to_string(&self.custom_metrics).unwrap_or_default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Prints the console report, with changes since `previous` next to the metrics
+    pub fn print(&self, previous: Option<&PreviousSnapshot>) {
+        print!("{}", self.render(previous));
+    }
+
+    /// The console report, with changes since `previous` next to the metrics
+    pub fn render(&self, previous: Option<&PreviousSnapshot>) -> String {
+        let mut out = String::new();
+        // Desperately need string interpolation in rust
+        let row = |label: &str, value: String| format!("  {label:<30} {value}\n");
+        let usd = |value: f64| format!("${}", value.round().separate_with_commas());
+        let usd_change = |value: f64, field: fn(&SnapshotRecord) -> f64| {
+            previous.map_or(String::new(), |previous| {
+                format!(" ({})", compact_change(value.sub(field(&previous.record))))
+            })
+        };
+        let ratio_change = |value: f64, field: fn(&SnapshotRecord) -> f64| {
+            previous.map_or(String::new(), |previous| {
+                format!(" ({:+.4})", value.sub(field(&previous.record)))
+            })
+        };
+        let count_change = |value: u64, field: fn(&SnapshotRecord) -> u64| {
+            previous.map_or(String::new(), |previous| {
+                format!(" ({:+})", value as i64 - field(&previous.record) as i64)
+            })
+        };
+        // the recent history with the current value last, if there's enough of it to draw
+        let trend = |values: Vec<f64>| {
+            if values.len() < 2 {
+                String::new()
+            } else {
+                format!(" {}", style::sparkline(&values))
+            }
+        };
+        let record_trend = |value: f64, field: fn(&SnapshotRecord) -> f64| {
+            previous.map_or(String::new(), |previous| {
+                let mut values: Vec<f64> = previous.recent.iter().map(field).collect();
+                values.push(value);
+                trend(values)
+            })
+        };
+        let real_unrealized_pnl = self.cumulative_pnl.sub(self.cumulative_fees);
+        let num_short = self.num_shorts();
+        let long_short_ratio = (self.num_longs as f64).div(num_short as f64);
+        let long_short_value = self.cumulative_long.div(self.cumulative_short());
+        let num_losing = self.num_positions.sub(self.num_winning);
+
+        out += &format!(
+            "{} unix time {}, slot {}\n",
+            style::heading("Snapshot"),
+            self.unix_time,
+            self.slot
+        );
+        for (symbol, price_age) in &self.stale_oracles {
+            // undecodable oracles count as published at the epoch
+            let warning = if *price_age >= self.unix_time as i64 {
+                format!("Warning: {symbol} oracle price couldn't be decoded")
+            } else {
+                format!("Warning: {symbol} oracle price is {price_age}s old")
+            };
+            out += &format!("{}\n", style::warning(warning));
+        }
+        for (symbol, source) in &self.fallback_prices {
+            out += &format!(
+                "{}\n",
+                style::warning(format!("Warning: {symbol} is priced from {source}"))
+            );
+        }
+        if let Some(previous) = previous {
+            out += &format!("  Changes since unix time {}\n", previous.record.unix_time);
+        }
+
+        out += &format!("{}\n", style::heading("Pool"));
+        out += &row(
+            "Total pool value",
+            format!(
+                "{}{}{}",
+                usd(self.total_pool_value),
+                usd_change(self.total_pool_value, |record| record.total_pool_value),
+                record_trend(self.total_pool_value, |record| record.total_pool_value)
+            ),
+        );
+        if let Some(jlp) = self.jlp {
+            out += &row(
+                "JLP NAV / market price",
+                format!(
+                    "${:.4} / ${:.4} ({:+.2}% {})",
+                    jlp.nav,
+                    jlp.market_price,
+                    jlp.premium().mul(100.0),
+                    if jlp.premium() < 0.0 {
+                        "discount"
+                    } else {
+                        "premium"
+                    },
+                ),
+            );
+        }
+
+        out += &format!("{}\n", style::heading("Traders"));
+        out += &row(
+            "Unrealized paper P&L",
+            format!(
+                "{}{}",
+                style::pnl(self.cumulative_pnl, usd(self.cumulative_pnl)),
+                usd_change(self.cumulative_pnl, |record| record.unrealized_pnl)
+            ),
+        );
+        out += &row(
+            "Fees",
+            format!(
+                "{}{}",
+                usd(self.cumulative_fees),
+                usd_change(self.cumulative_fees, |record| record.total_fees)
+            ),
+        );
+        out += &row(
+            "Unrealized real P&L",
+            format!(
+                "{}{}",
+                style::pnl(real_unrealized_pnl, usd(real_unrealized_pnl)),
+                usd_change(real_unrealized_pnl, |record| record
+                    .unrealized_pnl
+                    .sub(record.total_fees))
+            ),
+        );
+
+        out += &format!("{}\n", style::heading("Positions"));
+        out += &row(
+            "Total value of positions",
+            format!(
+                "{}{}{}",
+                usd(self.cumulative_positions),
+                usd_change(self.cumulative_positions, |record| record
+                    .total_position_value),
+                record_trend(self.cumulative_positions, |record| record
+                    .total_position_value)
+            ),
+        );
+        out += &row(
+            "Total value of collateral",
+            format!(
+                "{}{}",
+                usd(self.cumulative_collateral),
+                usd_change(self.cumulative_collateral, |record| record
+                    .total_collateral_value)
+            ),
+        );
+        out += &row(
+            "Average leverage at entry",
+            format!(
+                "{:.4}{}",
+                self.average_leverage_at_entry(),
+                ratio_change(self.average_leverage_at_entry(), |record| record
+                    .average_leverage_at_entry)
+            ),
+        );
+        out += &row(
+            "Average effective leverage",
+            format!(
+                "{:.4}{}",
+                self.average_effective_leverage(),
+                ratio_change(self.average_effective_leverage(), |record| record
+                    .average_effective_leverage)
+            ),
+        );
+        out += &row(
+            "Long trades",
+            format!(
+                "{}{} ({}{})",
+                self.num_longs,
+                count_change(self.num_longs, |record| record.long_trades),
+                usd(self.cumulative_long),
+                usd_change(self.cumulative_long, |record| record.long_value)
+            ),
+        );
+        out += &row(
+            "Short trades",
+            format!(
+                "{num_short}{} ({}{})",
+                count_change(num_short, |record| record.short_trades),
+                usd(self.cumulative_short()),
+                usd_change(self.cumulative_short(), |record| record.short_value)
+            ),
+        );
+        out += &row(
+            "L/S ratio",
+            format!("{long_short_ratio:.4} ({long_short_value:.4})"),
+        );
+        out += &row(
+            "Winning / losing trades",
+            format!("{} / {num_losing}", self.num_winning),
+        );
+        if let Some((opened, closed)) = previous.and_then(|previous| previous.opened_closed) {
+            out += &row("Opened / closed since last", format!("{opened} / {closed}"));
+        }
+
+        out += &format!("{}\n", style::heading("Markets"));
+        let headers = [
+            "Price",
+            "Long OI",
+            "Short OI",
+            "Utilization",
+            "Borrow APR",
+            "Trader P&L",
+        ];
+        let market_rows: Vec<(&String, f64, [String; 6])> = self
+            .markets
+            .iter()
+            .map(|(symbol, market)| {
+                let cells = [
+                    format!("${:.2}", market.price),
+                    usd(market.long_oi),
+                    usd(market.short_oi),
+                    format!("{:.2}%", market.utilization.mul(100.0)),
+                    format!("{:.2}%", market.borrow_apr),
+                    usd(market.unrealized_pnl),
+                ];
+                (symbol, market.unrealized_pnl, cells)
+            })
+            .collect();
+        let mut widths = headers.map(str::len);
+        for (_, _, cells) in &market_rows {
+            for (width, cell) in widths.iter_mut().zip(cells) {
+                *width = (*width).max(cell.len());
+            }
+        }
+        let columns = |cells: [String; 6]| {
+            cells
+                .iter()
+                .zip(widths)
+                .map(|(cell, width)| format!("{cell:>width$}"))
+                .collect::<Vec<_>>()
+        };
+        out += &row("", columns(headers.map(str::to_string)).join(" "));
+        for (symbol, pnl, cells) in market_rows {
+            let mut cells = columns(cells);
+            // colored after padding, so escape codes don't count towards the width
+            cells[5] = style::pnl(pnl, &cells[5]);
+            let borrow_apr_trend = previous.map_or(String::new(), |previous| {
+                let mut values: Vec<f64> = previous
+                    .recent_borrow_rates
+                    .get(symbol)
+                    .into_iter()
+                    .flatten()
+                    .map(|borrow_rate| borrow_rate.mul(24.0 * 365.0).div(100.0))
+                    .collect();
+                values.push(self.markets[symbol].borrow_apr);
+                trend(values)
+            });
+            out += &row(symbol, format!("{}{borrow_apr_trend}", cells.join(" ")));
+        }
+
+        out += &format!(
+            "{}\n",
+            style::heading("Notional by distance to liquidation")
+        );
+        out += &row(
+            "",
+            LIQUIDATION_BUCKETS
+                .iter()
+                .map(|(label, _)| format!("{label:>14}"))
+                .collect(),
+        );
+        for (symbol, market) in &self.markets {
+            out += &row(
+                symbol,
+                market
+                    .notional_by_liquidation_distance
+                    .iter()
+                    .map(|notional| format!("{:>14}", usd(*notional)))
+                    .collect(),
+            );
+        }
+
+        if !self.price_consensus.is_empty() {
+            out += &format!("{}\n", style::heading("Price consensus"));
+            for (symbol, consensus) in &self.price_consensus {
+                let sources: Vec<String> = consensus
+                    .sources
+                    .iter()
+                    .map(|(source, price)| format!("{source} ${price:.4}"))
+                    .collect();
+                let outliers = if consensus.outliers.is_empty() {
+                    String::new()
+                } else {
+                    style::warning(format!(" Disagreed: {}", consensus.outliers.join(", ")))
+                };
+                out += &row(
+                    symbol,
+                    format!("${:.4} ({}){outliers}", consensus.price, sources.join(", ")),
+                );
+            }
+        }
+
+        if self
+            .markets
+            .values()
+            .any(|market| market.dex_price.is_some())
+        {
+            out += &format!("{}\n", style::heading("Oracle vs DEX price"));
+            for (symbol, market) in &self.markets {
+                let (Some(dex_price), Some(divergence)) =
+                    (market.dex_price, market.dex_divergence())
+                else {
+                    continue;
+                };
+                out += &row(
+                    symbol,
+                    format!(
+                        "Oracle: ${:.4} DEX: ${dex_price:.4} ({:+.3}%)",
+                        market.price,
+                        divergence.mul(100.0),
+                    ),
+                );
+            }
+        }
+
+        out += &format!("{}\n", style::heading("Collateral"));
+        let long_collateral: f64 = self
+            .collateral
+            .values()
+            .map(|collateral| collateral.long_value)
+            .sum();
+        let short_collateral: f64 = self
+            .collateral
+            .values()
+            .map(|collateral| collateral.short_value)
+            .sum();
+        let share = |value: f64, total: f64| {
+            if total > 0.0 {
+                format!("{:.1}%", value.div(total).mul(100.0))
+            } else {
+                "-".to_string()
+            }
+        };
+        for (symbol, collateral) in &self.collateral {
+            out += &row(
+                symbol,
+                format!(
+                    "Longs: {} ({}) Shorts: {} ({})",
+                    usd(collateral.long_value),
+                    share(collateral.long_value, long_collateral),
+                    usd(collateral.short_value),
+                    share(collateral.short_value, short_collateral),
+                ),
+            );
+        }
+
+        out += &format!("{}\n", style::heading("Open trades"));
+        for (label, trade) in [
+            ("Most profitable", &self.most_profitable_trade),
+            ("Most unprofitable", &self.least_profitable_trade),
+        ] {
+            out += &row(
+                label,
+                format!(
+                    "{} Open P&L: {} Entry Price ${:.2} Side: {:?} Market {}",
+                    trade.position,
+                    style::pnl(trade.pnl, usd(trade.pnl)),
+                    trade.price_at_entry,
+                    trade.side,
+                    trade.market,
+                ),
+            );
+        }
+
+        if let Some(previous) = previous {
+            let days = previous.percentile_days;
+            let percentile = |value: f64, history: Vec<f64>| {
+                let rank = percentile_rank(&history, value)?;
+                Some(match rank {
+                    rank if rank >= 100.0 => format!("Highest of the last {days} days"),
+                    rank if rank <= 0.0 => format!("Lowest of the last {days} days"),
+                    rank => format!(
+                        "{} percentile of the last {days} days",
+                        ordinal(rank.round().clamp(1.0, 99.0) as u64)
+                    ),
+                })
+            };
+            let headline: [(&str, f64, fn(&SnapshotRecord) -> f64); 7] = [
+                ("Total pool value", self.total_pool_value, |record| {
+                    record.total_pool_value
+                }),
+                (
+                    "Total value of positions",
+                    self.cumulative_positions,
+                    |record| record.total_position_value,
+                ),
+                ("Long value", self.cumulative_long, |record| {
+                    record.long_value
+                }),
+                ("Short value", self.cumulative_short(), |record| {
+                    record.short_value
+                }),
+                ("Unrealized paper P&L", self.cumulative_pnl, |record| {
+                    record.unrealized_pnl
+                }),
+                ("Fees", self.cumulative_fees, |record| record.total_fees),
+                (
+                    "Average effective leverage",
+                    self.average_effective_leverage(),
+                    |record| record.average_effective_leverage,
+                ),
+            ];
+            let mut rows = vec![];
+            for (label, value, field) in headline {
+                let history = previous.window.iter().map(field).collect();
+                if let Some(text) = percentile(value, history) {
+                    rows.push((label.to_string(), text));
+                }
+            }
+            for (symbol, market) in &self.markets {
+                let sides: [(&str, f64, fn(&MarketRecord) -> f64); 2] = [
+                    ("long OI", market.long_oi, |record| record.long_value),
+                    ("short OI", market.short_oi, |record| record.short_value),
+                ];
+                for (side, value, field) in sides {
+                    let history = previous
+                        .window_markets
+                        .iter()
+                        .filter(|record| record.market == *symbol)
+                        .map(field)
+                        .collect();
+                    if let Some(text) = percentile(value, history) {
+                        rows.push((format!("{symbol} {side}"), text));
+                    }
+                }
+            }
+            if !rows.is_empty() {
+                out += &format!("{}\n", style::heading("History percentiles"));
+                for (label, text) in rows {
+                    out += &row(&label, text);
+                }
+            }
+        }
+
+        if let Some(lifecycle) = &self.lifecycle {
+            out += &format!(
+                "{}\n",
+                style::heading(&format!(
+                    "Position changes since {}",
+                    format_time(lifecycle.since)
+                ))
+            );
+            for (label, totals) in lifecycle.categories() {
+                out += &row(
+                    label,
+                    format!("{} ({})", totals.count, usd(totals.notional_usd)),
+                );
+            }
+        }
+
+        if !self.custom_metrics.is_empty() {
+            out += &format!("{}\n", style::heading("Custom metrics"));
+            for (name, value) in &self.custom_metrics {
+                out += &row(name, value.to_string());
+            }
+        }
+        out
+    }
+}
+
+/// Per-custody rows stored alongside each snapshot
+pub fn custody_records(state: &PerpState) -> Vec<CustodyRecord> {
+    state
+        .custodies_in_pool_order()
+        .into_iter()
+        .map(|custody_state| CustodyRecord {
+            unix_time: state.unix_time,
+            slot: state.slot,
+            market: custody_state.symbol(),
+            custody: custody_state.pubkey.to_string(),
+            price: custody_state.price(),
+            utilization: custody_state.utilization,
+            borrow_rate: custody_state.borrow_rate,
+            fees_reserves_usd: spl_token::amount_to_ui_amount(
+                custody_state.custody.assets.fees_reserves,
+                custody_state.custody.decimals,
+            )
+            .mul(custody_state.price()),
+            fees_reserves: Some(spl_token::amount_to_ui_amount(
+                custody_state.custody.assets.fees_reserves,
+                custody_state.custody.decimals,
+            )),
+        })
+        .collect()
+}
+
+/// Per-market rows stored alongside each snapshot, for markets with open positions
+pub fn market_records(snapshot: &Snapshot) -> Vec<MarketRecord> {
+    snapshot
+        .markets
+        .iter()
+        .map(|(symbol, market)| MarketRecord {
+            unix_time: snapshot.unix_time,
+            slot: snapshot.slot,
+            market: symbol.clone(),
+            price: market.price,
+            long_positions: market.num_longs,
+            short_positions: market.num_shorts,
+            long_value: market.long_oi,
+            short_value: market.short_oi,
+            long_short_ratio: (market.num_shorts > 0)
+                .then(|| (market.num_longs as f64).div(market.num_shorts as f64)),
+            long_short_value_ratio: (market.short_oi > 0.0)
+                .then(|| market.long_oi.div(market.short_oi)),
+            dex_price: market.dex_price,
+        })
+        .collect()
+}
+
+/// Per-position rows stored alongside each snapshot with `--record-positions`
+pub fn position_records(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+) -> Vec<PositionRecord> {
+    positions
+        .iter()
+        .filter(|(_, position)| position.size_usd > 0)
+        .filter_map(|(pubkey, position)| {
+            let custody_state = state.custodies.get(&position.custody)?;
+            let valuation = PositionValuation::compute(state, position)?;
+            Some(PositionRecord {
+                unix_time: state.unix_time,
+                slot: state.slot,
+                position: pubkey.to_string(),
+                owner: position.owner.to_string(),
+                market: custody_state.symbol(),
+                side: format!("{:?}", position.side),
+                size_usd: spl_token::amount_to_ui_amount(position.size_usd, 6),
+                collateral_usd: spl_token::amount_to_ui_amount(position.collateral_usd, 6),
+                entry_price: spl_token::amount_to_ui_amount(position.price, 6),
+                price: custody_state.price(),
+                open_time: position.open_time,
+                update_time: position.update_time,
+                borrow_fees_usd: valuation.borrow_fees,
+                effective_leverage: valuation.effective_leverage(),
+            })
+        })
+        .collect()
+}
+
+/// Rows of the watched `wallets`, addresses by label, stored alongside each snapshot; a
+/// wallet without open positions gets a row of zeros, so its equity curve has no gaps
+pub fn wallet_records(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+    wallets: &BTreeMap<String, String>,
+) -> Vec<WalletRecord> {
+    wallets
+        .iter()
+        .map(|(label, wallet)| {
+            let mut record = WalletRecord {
+                unix_time: state.unix_time,
+                slot: state.slot,
+                label: label.clone(),
+                wallet: wallet.clone(),
+                ..Default::default()
+            };
+            for (_, position) in positions {
+                if position.owner.to_string() != *wallet {
+                    continue;
+                }
+                let Some(valuation) = PositionValuation::compute(state, position) else {
+                    continue;
+                };
+                record.positions += 1;
+                record.size_usd += valuation.value_at_entry;
+                record.collateral_usd += spl_token::amount_to_ui_amount(position.collateral_usd, 6);
+                record.unrealized_pnl += valuation.unrealized_pnl;
+                record.equity += valuation.remaining_collateral;
+            }
+            record
+        })
+        .collect()
+}
+
+/// The snapshot with its derived ratios, every custody's rates and every open position as
+/// plain JSON, the shape handed to the Python, WebAssembly and C bindings
+pub fn to_json(
+    state: &PerpState,
+    positions: &[(Pubkey, perp_abi::state::Position)],
+) -> Result<serde_json::Value, serde_json::Error> {
+    let snapshot = Snapshot::compute(state, positions);
+    let mut aggregates = serde_json::to_value(&snapshot)?;
+    aggregates["num_shorts"] = json!(snapshot.num_shorts());
+    aggregates["cumulative_short"] = json!(snapshot.cumulative_short());
+    aggregates["average_leverage_at_entry"] = json!(snapshot.average_leverage_at_entry());
+    aggregates["average_effective_leverage"] = json!(snapshot.average_effective_leverage());
+
+    let positions: Vec<_> = positions
+        .iter()
+        .map(|(pubkey, position)| {
+            json!({
+                "pubkey": pubkey.to_string(),
+                "owner": position.owner.to_string(),
+                "market": state
+                    .custodies
+                    .get(&position.custody)
+                    .map(|custody_state| custody_state.symbol()),
+                "side": format!("{:?}", position.side),
+                "size_usd": spl_token::amount_to_ui_amount(position.size_usd, 6),
+                "collateral_usd": spl_token::amount_to_ui_amount(position.collateral_usd, 6),
+                "entry_price": spl_token::amount_to_ui_amount(position.price, 6),
+                "open_time": position.open_time,
+                "update_time": position.update_time,
+            })
+        })
+        .collect();
+
+    Ok(json!({
+        "snapshot": aggregates,
+        "custodies": custody_records(state),
+        "positions": positions,
+    }))
+}
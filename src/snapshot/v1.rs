@@ -0,0 +1,190 @@
+//! Version 1 of the snapshot data model, the JSON published to scripts and streaming sinks.
+//!
+//! Field names are pinned with explicit renames, so refactoring the Rust side never changes
+//! the format. Within a version, fields are only ever added, each with a serde default so
+//! documents written before it still parse, and unknown fields are ignored so documents from
+//! later releases parse too. Renaming, removing or retyping a field bumps [`SCHEMA_VERSION`]
+//! and adds a new module next to this one, keeping this one to read older documents.
+
+use std::collections::BTreeMap;
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::{Provenance, Snapshot};
+use crate::lifecycle::LifecycleChanges;
+use crate::store::nan_if_null;
+
+pub const SCHEMA_VERSION: u32 = 1;
+
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct MarketV1 {
+    /// Oracle price in USD
+    #[serde(rename = "price")]
+    pub price: f64,
+    #[serde(rename = "num_longs")]
+    pub num_longs: u64,
+    #[serde(rename = "num_shorts")]
+    pub num_shorts: u64,
+    /// Current value of long positions in USD
+    #[serde(rename = "long_value")]
+    pub long_value: f64,
+    /// Current value of short positions in USD
+    #[serde(rename = "short_value")]
+    pub short_value: f64,
+    /// Traders' unrealized paper P&L in USD
+    #[serde(rename = "unrealized_pnl")]
+    pub unrealized_pnl: f64,
+}
+
+/// One snapshot of Jupiter perpetuals, as printed by `--porcelain json`
+#[derive(Clone, Debug, Serialize, Deserialize, JsonSchema)]
+#[schemars(rename = "Snapshot")]
+pub struct SnapshotV1 {
+    /// Version of the field set, bumped when fields are renamed or removed
+    #[serde(rename = "schema_version")]
+    pub schema_version: u32,
+    /// Time the snapshot was taken
+    #[serde(rename = "unix_time")]
+    pub unix_time: u64,
+    /// Slot the state was read at
+    #[serde(rename = "slot")]
+    pub slot: u64,
+    /// Pool AUM in USD
+    #[serde(rename = "total_pool_value")]
+    pub total_pool_value: f64,
+    #[serde(rename = "num_positions")]
+    pub num_positions: u64,
+    #[serde(rename = "num_longs")]
+    pub num_longs: u64,
+    #[serde(rename = "num_shorts")]
+    pub num_shorts: u64,
+    /// Positions with positive unrealized P&L
+    #[serde(rename = "num_winning")]
+    pub num_winning: u64,
+    /// Current value of all positions in USD
+    #[serde(rename = "total_position_value")]
+    pub total_position_value: f64,
+    #[serde(rename = "long_value")]
+    pub long_value: f64,
+    #[serde(rename = "short_value")]
+    pub short_value: f64,
+    /// Current value of collateral in USD
+    #[serde(rename = "total_collateral_value")]
+    pub total_collateral_value: f64,
+    /// Traders' unrealized paper P&L in USD
+    #[serde(rename = "unrealized_pnl")]
+    pub unrealized_pnl: f64,
+    /// Estimated open and close fees plus borrow fees in USD
+    #[serde(rename = "total_fees")]
+    pub total_fees: f64,
+    /// Position value over collateral at entry, null without positions
+    #[serde(rename = "average_leverage_at_entry", deserialize_with = "nan_if_null")]
+    #[schemars(with = "Option<f64>")]
+    pub average_leverage_at_entry: f64,
+    /// Position value over the collateral left after unrealized P&L and accrued borrow
+    /// fees, null without positions
+    #[serde(
+        rename = "average_effective_leverage",
+        deserialize_with = "nan_if_null"
+    )]
+    #[schemars(with = "Option<f64>")]
+    pub average_effective_leverage: f64,
+    /// Per-market breakdown keyed by symbol
+    #[serde(rename = "markets")]
+    pub markets: BTreeMap<String, MarketV1>,
+    /// Missing from documents written before provenance was recorded
+    #[serde(rename = "provenance", default)]
+    pub provenance: Provenance,
+    /// Metrics added by plugins, keyed by name
+    #[serde(rename = "custom_metrics", default)]
+    pub custom_metrics: BTreeMap<String, f64>,
+    /// Position changes since the previous snapshot, absent without a position index
+    #[serde(rename = "lifecycle", default)]
+    pub lifecycle: Option<LifecycleChanges>,
+}
+
+impl From<&Snapshot> for SnapshotV1 {
+    fn from(snapshot: &Snapshot) -> Self {
+        SnapshotV1 {
+            schema_version: SCHEMA_VERSION,
+            unix_time: snapshot.unix_time,
+            slot: snapshot.slot,
+            total_pool_value: snapshot.total_pool_value,
+            num_positions: snapshot.num_positions,
+            num_longs: snapshot.num_longs,
+            num_shorts: snapshot.num_shorts(),
+            num_winning: snapshot.num_winning,
+            total_position_value: snapshot.cumulative_positions,
+            long_value: snapshot.cumulative_long,
+            short_value: snapshot.cumulative_short(),
+            total_collateral_value: snapshot.cumulative_collateral,
+            unrealized_pnl: snapshot.cumulative_pnl,
+            total_fees: snapshot.cumulative_fees,
+            average_leverage_at_entry: snapshot.average_leverage_at_entry(),
+            average_effective_leverage: snapshot.average_effective_leverage(),
+            markets: snapshot
+                .markets
+                .iter()
+                .map(|(symbol, market)| {
+                    (
+                        symbol.clone(),
+                        MarketV1 {
+                            price: market.price,
+                            num_longs: market.num_longs,
+                            num_shorts: market.num_shorts,
+                            long_value: market.long_oi,
+                            short_value: market.short_oi,
+                            unrealized_pnl: market.unrealized_pnl,
+                        },
+                    )
+                })
+                .collect(),
+            provenance: snapshot.provenance.clone(),
+            custom_metrics: snapshot.custom_metrics.clone(),
+            lifecycle: snapshot.lifecycle.clone(),
+        }
+    }
+}
+
+impl SnapshotV1 {
+    /// Parses a stored or published snapshot, failing on documents of another version
+    pub fn from_json(json: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        #[derive(Deserialize)]
+        struct Version {
+            schema_version: u32,
+        }
+        let version: Version = serde_json::from_str(json)?;
+        if version.schema_version != SCHEMA_VERSION {
+            return Err(format!(
+                "snapshot has schema version {}, this version of the tool reads {SCHEMA_VERSION}",
+                version.schema_version
+            )
+            .into());
+        }
+        Ok(serde_json::from_str(json)?)
+    }
+
+    /// The top-level scalar fields tab-separated, in documented order
+    pub fn tsv_line(&self) -> String {
+        [
+            self.schema_version.to_string(),
+            self.unix_time.to_string(),
+            self.slot.to_string(),
+            self.total_pool_value.to_string(),
+            self.num_positions.to_string(),
+            self.num_longs.to_string(),
+            self.num_shorts.to_string(),
+            self.num_winning.to_string(),
+            self.total_position_value.to_string(),
+            self.long_value.to_string(),
+            self.short_value.to_string(),
+            self.total_collateral_value.to_string(),
+            self.unrealized_pnl.to_string(),
+            self.total_fees.to_string(),
+            self.average_leverage_at_entry.to_string(),
+            self.average_effective_leverage.to_string(),
+        ]
+        .join("\t")
+    }
+}
@@ -0,0 +1,304 @@
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Div as _, Mul as _};
+use std::str::FromStr;
+use std::sync::RwLock;
+
+use clap::ValueEnum;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::idl;
+use crate::rpc::{decode_oracle_price, OraclePrice};
+
+#[cfg(not(target_arch = "wasm32"))]
+mod load;
+#[cfg(not(target_arch = "wasm32"))]
+pub use load::*;
+
+/// Markets listed on Jupiter perpetuals, keyed by mint
+const KNOWN_MINTS: [(&str, &str); 5] = [
+    ("So11111111111111111111111111111111111111112", "SOL"),
+    ("7vfCXTUXx5WJV5JADk17DUJ4ksgau7utNKj4b963voxs", "ETH"),
+    ("3NZ9JMVBmGAqocybic2c7LQCJScmgsAZ6vQqTDzcqmJh", "BTC"),
+    ("EPjFWdd5AufqSSqeM2qN1xzybapC8G4wEGGkZwyTDt1v", "USDC"),
+    ("Es9vMFrzaCERmJfrF4H2FYD4KCoNkY11McCe8BenwNYB", "USDT"),
+];
+
+/// Symbols of markets outside [`KNOWN_MINTS`], as resolved from their oracles' Pyth products
+static RESOLVED_SYMBOLS: RwLock<BTreeMap<Pubkey, String>> = RwLock::new(BTreeMap::new());
+
+pub fn symbol_for_mint(mint: &Pubkey) -> Option<&'static str> {
+    let mint = mint.to_string();
+    KNOWN_MINTS
+        .iter()
+        .find(|(known_mint, _)| *known_mint == mint)
+        .map(|(_, symbol)| *symbol)
+}
+
+/// Labels the market of `mint` with `symbol` from now on, unless it's a known one
+pub fn register_symbol(mint: Pubkey, symbol: String) {
+    RESOLVED_SYMBOLS.write().unwrap().insert(mint, symbol);
+}
+
+/// Token names of mints, as resolved from their Metaplex metadata
+static RESOLVED_NAMES: RwLock<BTreeMap<Pubkey, String>> = RwLock::new(BTreeMap::new());
+
+/// Names `mint` with the token `name` from now on
+pub fn register_name(mint: Pubkey, name: String) {
+    RESOLVED_NAMES.write().unwrap().insert(mint, name);
+}
+
+/// Token name of `mint`, once resolved
+pub fn token_name(mint: &Pubkey) -> Option<String> {
+    RESOLVED_NAMES.read().unwrap().get(mint).cloned()
+}
+
+/// Whether the market of `mint` is known or has been resolved
+pub fn has_symbol(mint: &Pubkey) -> bool {
+    symbol_for_mint(mint).is_some() || RESOLVED_SYMBOLS.read().unwrap().contains_key(mint)
+}
+
+/// Symbol of the market of `mint`, known or resolved, or else the mint itself
+pub fn mint_symbol(mint: &Pubkey) -> String {
+    symbol_for_mint(mint)
+        .map(str::to_string)
+        .or_else(|| RESOLVED_SYMBOLS.read().unwrap().get(mint).cloned())
+        .unwrap_or_else(|| mint.to_string())
+}
+
+/// Which of an oracle feed's prices values positions
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, ValueEnum)]
+pub enum PriceKind {
+    /// The latest aggregate price
+    #[default]
+    Spot,
+    /// The feed's moving average, smoothing out noise between snapshots
+    Ema,
+}
+
+pub struct CustodyState {
+    pub pubkey: Pubkey,
+    pub custody: perp_abi::state::Custody,
+    pub oracle: OraclePrice,
+    /// Share of owned assets currently locked in positions, pooled across stablecoins
+    pub utilization: f64,
+    /// Hourly borrow rate in BPS
+    pub borrow_rate: f64,
+}
+
+impl CustodyState {
+    pub fn price(&self) -> f64 {
+        self.oracle.price
+    }
+
+    pub fn is_stable(&self) -> bool {
+        self.oracle.price.round() == 1.0
+    }
+
+    pub fn symbol(&self) -> String {
+        mint_symbol(&self.custody.mint)
+    }
+
+    /// Borrow rate annualized, in percent
+    pub fn borrow_apr(&self) -> f64 {
+        self.borrow_rate.mul(24.0 * 365.0).div(100.0)
+    }
+}
+
+/// Pool, custodies and oracle prices as of `unix_time`
+pub struct PerpState {
+    pub pool_pubkey: Pubkey,
+    pub pool: perp_abi::state::Pool,
+    pub custodies: HashMap<Pubkey, CustodyState>,
+    pub unix_time: u64,
+    pub slot: u64,
+}
+
+impl PerpState {
+    /// Decodes raw pool, custody and oracle accounts without any RPC calls, for callers that
+    /// fetch accounts themselves
+    pub fn from_accounts(
+        pool_account: (Pubkey, &[u8]),
+        custody_accounts: &[(Pubkey, Vec<u8>)],
+        oracle_accounts: &HashMap<Pubkey, Vec<u8>>,
+        unix_time: u64,
+        slot: u64,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (pool_pubkey, pool_data) = pool_account;
+        let pool = idl::decode_pool(pool_data)?;
+        let mut custodies = vec![];
+        for (custody_pubkey, custody_data) in custody_accounts {
+            let custody = idl::decode_custody(custody_data)?;
+            let oracle_account = &custody.oracle.oracle_account;
+            let oracle_data = oracle_accounts
+                .get(oracle_account)
+                .ok_or_else(|| format!("missing oracle account {oracle_account}"))?;
+            let oracle = decode_oracle_price(oracle_account, oracle_data)?;
+            custodies.push((*custody_pubkey, custody, oracle));
+        }
+        Ok(PerpState::from_parts(
+            pool_pubkey,
+            pool,
+            custodies,
+            unix_time,
+            slot,
+        ))
+    }
+
+    /// Builds the state from already decoded accounts, deriving utilization and borrow rates
+    pub fn from_parts(
+        pool_pubkey: Pubkey,
+        pool: perp_abi::state::Pool,
+        custody_accounts: Vec<(Pubkey, perp_abi::state::Custody, OraclePrice)>,
+        unix_time: u64,
+        slot: u64,
+    ) -> Self {
+        let mut custodies = HashMap::new();
+        let mut stable_aum = 0;
+        let mut stable_borrow = 0;
+
+        for (custody_pubkey, custody, oracle) in custody_accounts {
+            let custody_state = CustodyState {
+                pubkey: custody_pubkey,
+                custody,
+                oracle,
+                utilization: utilization(custody.assets.locked, custody.assets.owned),
+                borrow_rate: 0.0,
+            };
+            if custody_state.is_stable() {
+                stable_aum += custody.assets.owned;
+                stable_borrow += custody.assets.locked;
+            }
+            custodies.insert(custody_pubkey, custody_state);
+        }
+
+        for custody_state in custodies.values_mut() {
+            // stablecoin borrow rates set by utilization percentage of all stablecoins
+            if custody_state.is_stable() {
+                custody_state.utilization = utilization(stable_borrow, stable_aum);
+            }
+            custody_state.borrow_rate = custody_state
+                .utilization
+                .mul(custody_state.custody.funding_rate_state.hourly_funding_bps as f64);
+        }
+
+        PerpState {
+            pool_pubkey,
+            pool,
+            custodies,
+            unix_time,
+            slot,
+        }
+    }
+
+    /// Replaces the oracle prices of the custodies in `prices`, re-deriving the stablecoin
+    /// utilization, which depends on which custodies price as stable
+    pub fn with_oracle_prices(self, prices: &HashMap<Pubkey, OraclePrice>) -> Self {
+        let custodies = self
+            .custodies
+            .into_values()
+            .map(|custody_state| {
+                let oracle = prices
+                    .get(&custody_state.pubkey)
+                    .copied()
+                    .unwrap_or(custody_state.oracle);
+                (custody_state.pubkey, custody_state.custody, oracle)
+            })
+            .collect();
+        PerpState::from_parts(
+            self.pool_pubkey,
+            self.pool,
+            custodies,
+            self.unix_time,
+            self.slot,
+        )
+    }
+
+    /// Values everything at the oracles' `kind` of price
+    pub fn with_price_kind(self, kind: PriceKind) -> Self {
+        if kind == PriceKind::Spot {
+            return self;
+        }
+        let prices = self
+            .custodies
+            .values()
+            .map(|custody_state| {
+                let oracle = custody_state.oracle;
+                (
+                    custody_state.pubkey,
+                    OraclePrice {
+                        price: oracle.ema_price,
+                        ..oracle
+                    },
+                )
+            })
+            .collect();
+        self.with_oracle_prices(&prices)
+    }
+
+    pub fn total_pool_value(&self) -> f64 {
+        spl_token::amount_to_ui_amount(self.pool.aum_usd as u64, 6)
+    }
+
+    /// Looks up a custody by market symbol, custody pubkey or mint
+    pub fn find_custody(&self, query: &str) -> Option<&CustodyState> {
+        if let Ok(pubkey) = Pubkey::from_str(query) {
+            return self.custodies.get(&pubkey).or_else(|| {
+                self.custodies
+                    .values()
+                    .find(|custody_state| custody_state.custody.mint == pubkey)
+            });
+        }
+        self.custodies
+            .values()
+            .find(|custody_state| custody_state.symbol().eq_ignore_ascii_case(query))
+    }
+
+    /// Custodies in the order the pool lists them
+    pub fn custodies_in_pool_order(&self) -> Vec<&CustodyState> {
+        self.pool
+            .custodies
+            .iter()
+            .filter_map(|custody_pubkey| self.custodies.get(custody_pubkey))
+            .collect()
+    }
+}
+
+fn utilization(locked: u64, owned: u64) -> f64 {
+    if owned == 0 {
+        return 0.0;
+    }
+    (locked as f64).div(owned as f64)
+}
+
+/// Size of a position account: discriminator, four pubkeys, two timestamps, side, price,
+/// size, collateral, realised P&L, interest snapshot, locked amount and bump
+pub const POSITION_ACCOUNT_SIZE: u64 = 8 + 4 * 32 + 2 * 8 + 1 + 4 * 8 + 16 + 8 + 1;
+
+/// Size to filter position accounts by: the compiled layout's, or none once an IDL is
+/// installed, as a program upgrade may have resized them
+pub fn position_account_size() -> Option<u64> {
+    (!idl::is_installed()).then_some(POSITION_ACCOUNT_SIZE)
+}
+
+/// The fields of a position needed for counting open interest
+pub struct PositionSummary {
+    pub pubkey: Pubkey,
+    pub custody: Pubkey,
+    pub collateral_custody: Pubkey,
+    pub side: perp_abi::Side,
+    pub size_usd: u64,
+}
+
+/// Decodes position accounts, keeping only those currently open
+pub fn decode_open_positions(
+    position_accounts: &[(Pubkey, Vec<u8>)],
+) -> Result<Vec<(Pubkey, perp_abi::state::Position)>, Box<dyn std::error::Error>> {
+    let mut positions = vec![];
+    for (position_pubkey, position_data) in position_accounts {
+        let position = idl::decode_position(position_data)?;
+        if position.size_usd != 0 {
+            positions.push((*position_pubkey, position));
+        }
+    }
+    Ok(positions)
+}
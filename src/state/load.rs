@@ -0,0 +1,386 @@
+//! Loading state and positions over RPC, unavailable on wasm32 where callers decode account
+//! bytes they fetched themselves.
+
+use std::path::Path;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::thread;
+use std::time::SystemTime;
+
+use anchor_lang::{AccountDeserialize as _, AnchorDeserialize as _, Discriminator as _};
+use solana_account_decoder::UiDataSliceConfig;
+use solana_sdk::pubkey::Pubkey;
+
+use super::{decode_open_positions, position_account_size, PerpState, PositionSummary};
+use crate::cache::AccountCache;
+use crate::checkpoint::PageCheckpoint;
+use crate::fetcher::AccountFetcher;
+use crate::idl;
+use crate::metadata;
+use crate::prices::{self, PriceProviders};
+use crate::pyth;
+use crate::rpc::{
+    get_program_accounts_filtered, get_program_accounts_with_discrim, program_id, OraclePrice,
+};
+
+/// Pool and custody accounts, scanned from the program
+fn program_accounts(
+    connection: &dyn AccountFetcher,
+) -> Result<((Pubkey, Vec<u8>), Vec<(Pubkey, Vec<u8>)>), Box<dyn std::error::Error>> {
+    let pool_accounts = get_program_accounts_with_discrim(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Pool::DISCRIMINATOR,
+    )?;
+    let (pool_pubkey, pool) = pool_accounts
+        .first()
+        .ok_or_else(|| format!("no Pool account owned by {}", program_id()))?;
+    let custody_accounts = get_program_accounts_with_discrim(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Custody::DISCRIMINATOR,
+    )?;
+    Ok((
+        (*pool_pubkey, pool.data.clone()),
+        custody_accounts
+            .into_iter()
+            .map(|(custody_pubkey, custody)| (custody_pubkey, custody.data))
+            .collect(),
+    ))
+}
+
+impl PerpState {
+    pub fn load(connection: &dyn AccountFetcher) -> Result<Self, Box<dyn std::error::Error>> {
+        PerpState::load_with_prices(connection, None, &PriceProviders::default())
+    }
+
+    /// Like [`PerpState::load`], but serving pool and custody accounts from the on-disk
+    /// cache while it is fresh, and oracle accounts from memory within the cache's oracle
+    /// TTL
+    pub fn load_cached(
+        connection: &dyn AccountFetcher,
+        cache: &mut AccountCache,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        PerpState::load_with_prices(connection, Some(cache), &PriceProviders::default())
+    }
+
+    /// Like [`PerpState::load_cached`], or [`PerpState::load`] without a cache, but taking
+    /// oracle prices from Hermes
+    pub fn load_hermes(
+        connection: &dyn AccountFetcher,
+        cache: Option<&mut AccountCache>,
+        client: &reqwest::blocking::Client,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let providers = PriceProviders::new(Box::new(prices::Hermes {
+            client: client.clone(),
+        }));
+        PerpState::load_with_prices(connection, cache, &providers)
+    }
+
+    /// Like [`PerpState::load_cached`], or [`PerpState::load`] without a cache, but pricing
+    /// each custody with the provider configured for its market
+    pub fn load_with_prices(
+        connection: &dyn AccountFetcher,
+        mut cache: Option<&mut AccountCache>,
+        providers: &PriceProviders,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (pool_account, custody_accounts) = match cache.as_deref_mut() {
+            Some(cache) => cache.pool_and_custody_accounts(connection)?,
+            None => program_accounts(connection)?,
+        };
+        PerpState::decode(connection, pool_account, custody_accounts, cache, providers)
+    }
+
+    /// Decodes raw pool and custody accounts and prices the custodies with `providers`,
+    /// fetching each oracle account once
+    fn decode(
+        connection: &dyn AccountFetcher,
+        pool_account: (Pubkey, Vec<u8>),
+        custody_accounts: Vec<(Pubkey, Vec<u8>)>,
+        cache: Option<&mut AccountCache>,
+        providers: &PriceProviders,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let (pool_pubkey, pool_data) = pool_account;
+        let pool = idl::decode_pool(&pool_data)?;
+
+        let unix_time = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        let slot = connection.slot()?;
+
+        let custodies = custody_accounts
+            .iter()
+            .map(|(custody_pubkey, custody_data)| {
+                let custody = idl::decode_custody(custody_data)?;
+                Ok((*custody_pubkey, custody))
+            })
+            .collect::<Result<Vec<_>, Box<dyn std::error::Error>>>()?;
+        // markets stay labeled by mint if neither their product nor their metadata can be read
+        let _ = pyth::resolve_symbols(connection, &custodies);
+        let _ = metadata::resolve_names(connection, &custodies);
+        let oracle_prices = providers.prices(connection, cache, &custodies)?;
+
+        // a missing or undecodable price counts as never published, so a fallback price can
+        // stand in
+        let custodies = custodies
+            .into_iter()
+            .map(|(custody_pubkey, custody)| {
+                let oracle = oracle_prices
+                    .get(&custody_pubkey)
+                    .copied()
+                    .unwrap_or(OraclePrice {
+                        price: f64::NAN,
+                        ema_price: f64::NAN,
+                        publish_time: 0,
+                        fallback: None,
+                    });
+                (custody_pubkey, custody, oracle)
+            })
+            .collect();
+
+        Ok(PerpState::from_parts(
+            pool_pubkey,
+            pool,
+            custodies,
+            unix_time,
+            slot,
+        ))
+    }
+}
+
+/// Byte range of a position account holding custody, collateral custody, open/update
+/// times, side, price and size, in the compiled layout
+const POSITION_SUMMARY_SLICE: UiDataSliceConfig = UiDataSliceConfig {
+    offset: 72,
+    length: 2 * 32 + 2 * 8 + 1 + 2 * 8,
+};
+
+/// Fetches all positions that are currently open
+pub fn load_open_positions(
+    connection: &dyn AccountFetcher,
+) -> Result<Vec<(Pubkey, perp_abi::state::Position)>, Box<dyn std::error::Error>> {
+    let position_accounts = get_program_accounts_filtered(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Position::DISCRIMINATOR,
+        position_account_size(),
+        None,
+    )?;
+
+    decode_open_positions(
+        &position_accounts
+            .into_iter()
+            .map(|(position_pubkey, position)| (position_pubkey, position.data))
+            .collect::<Vec<_>>(),
+    )
+}
+
+/// Accounts per `getMultipleAccounts` request, the RPC's limit
+const POSITION_PAGE_SIZE: usize = 100;
+/// Threads fetching pages at once; the transport's adaptive limit decides how many of their
+/// requests are actually in flight
+const POSITION_PAGE_WORKERS: usize = 8;
+
+/// Keys of every position account, listed without their data
+fn open_position_keys(
+    connection: &dyn AccountFetcher,
+) -> Result<Vec<Pubkey>, Box<dyn std::error::Error>> {
+    Ok(get_program_accounts_filtered(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Position::DISCRIMINATOR,
+        position_account_size(),
+        Some(UiDataSliceConfig {
+            offset: 0,
+            length: 0,
+        }),
+    )?
+    .into_iter()
+    .map(|(pubkey, _)| pubkey)
+    .collect())
+}
+
+/// Fetches the listed `pages` of `keys`, a few at a time, handing the data of each page's
+/// accounts to `f` as it arrives, in no particular order
+fn fetch_pages(
+    connection: &dyn AccountFetcher,
+    keys: &[Pubkey],
+    pages: &[usize],
+    mut f: impl FnMut(usize, Vec<Option<Vec<u8>>>) -> Result<(), Box<dyn std::error::Error>>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let chunks: Vec<&[Pubkey]> = keys.chunks(POSITION_PAGE_SIZE).collect();
+    let next_page = AtomicUsize::new(0);
+    thread::scope(|scope| {
+        let (sender, receiver) = mpsc::sync_channel(POSITION_PAGE_WORKERS);
+        for _ in 0..POSITION_PAGE_WORKERS.min(pages.len()) {
+            let (sender, chunks, next_page) = (sender.clone(), &chunks, &next_page);
+            scope.spawn(move || {
+                while let Some(&page) = pages.get(next_page.fetch_add(1, Ordering::Relaxed)) {
+                    let accounts = connection
+                        .multiple_accounts(chunks[page])
+                        .map_err(|err| err.to_string());
+                    let failed = accounts.is_err();
+                    // the receiver is gone once a page failed
+                    if sender.send((page, accounts)).is_err() || failed {
+                        break;
+                    }
+                }
+            });
+        }
+        drop(sender);
+
+        for (page, accounts) in receiver {
+            let data = accounts?
+                .into_iter()
+                .map(|account| account.map(|account| account.data))
+                .collect();
+            f(page, data)?;
+        }
+        Ok(())
+    })
+}
+
+/// Decodes the accounts of `page` of `keys`, handing the open positions to `f`
+fn decode_page(
+    keys: &[Pubkey],
+    page: usize,
+    accounts: &[Option<Vec<u8>>],
+    f: &mut impl FnMut(Pubkey, perp_abi::state::Position),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let page_keys = keys
+        .chunks(POSITION_PAGE_SIZE)
+        .nth(page)
+        .unwrap_or_default();
+    for (pubkey, data) in page_keys.iter().zip(accounts) {
+        // closed since the keys were listed
+        let Some(data) = data else {
+            continue;
+        };
+        let position = idl::decode_position(data)?;
+        if position.size_usd != 0 {
+            f(*pubkey, position);
+        }
+    }
+    Ok(())
+}
+
+/// Calls `f` with every open position, in no particular order, fetching position keys first
+/// and then their data a few pages at a time, so only those pages of raw account data are
+/// held in memory at once
+pub fn for_each_open_position(
+    connection: &dyn AccountFetcher,
+    mut f: impl FnMut(Pubkey, perp_abi::state::Position),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let keys = open_position_keys(connection)?;
+    let pages: Vec<usize> = (0..keys.len().div_ceil(POSITION_PAGE_SIZE)).collect();
+    fetch_pages(connection, &keys, &pages, |page, accounts| {
+        decode_page(&keys, page, &accounts, &mut f)
+    })
+}
+
+/// Like [`for_each_open_position`], recording each page fetched in the checkpoint file at
+/// `checkpoint`. A scan interrupted less than 15 minutes earlier resumes from it: the keys
+/// it listed are paged through again, with the pages it had fetched taken from the file
+/// rather than fetched again. The file is removed once the scan completes.
+pub fn for_each_open_position_checkpointed(
+    connection: &dyn AccountFetcher,
+    checkpoint: &Path,
+    mut f: impl FnMut(Pubkey, perp_abi::state::Position),
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut checkpoint = PageCheckpoint::open(checkpoint, || open_position_keys(connection))?;
+    let keys = std::mem::take(&mut checkpoint.keys);
+    let fetched = std::mem::take(&mut checkpoint.fetched);
+    let pages: Vec<usize> = (0..keys.len().div_ceil(POSITION_PAGE_SIZE))
+        .filter(|page| !fetched.contains_key(page))
+        .collect();
+    for (page, accounts) in fetched {
+        decode_page(&keys, page, &accounts, &mut f)?;
+    }
+    fetch_pages(connection, &keys, &pages, |page, accounts| {
+        checkpoint.record(page, &accounts)?;
+        decode_page(&keys, page, &accounts, &mut f)
+    })?;
+    checkpoint.finish()
+}
+
+/// Like [`load_open_positions`], but paging through the positions with
+/// [`for_each_open_position`], so only a few pages of raw account data are held at once.
+/// The decoded positions are all collected; to keep memory flat, fold them in with
+/// [`for_each_open_position`] instead.
+pub fn load_open_positions_paged(
+    connection: &dyn AccountFetcher,
+) -> Result<Vec<(Pubkey, perp_abi::state::Position)>, Box<dyn std::error::Error>> {
+    let mut positions = vec![];
+    for_each_open_position(connection, |pubkey, position| {
+        positions.push((pubkey, position))
+    })?;
+    Ok(positions)
+}
+
+/// Fetches all position requests that have not been executed yet
+pub fn load_pending_position_requests(
+    connection: &dyn AccountFetcher,
+) -> Result<Vec<(Pubkey, perp_abi::state::PositionRequest)>, Box<dyn std::error::Error>> {
+    let request_accounts = get_program_accounts_with_discrim(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::PositionRequest::DISCRIMINATOR,
+    )?;
+
+    let mut requests = vec![];
+    for (request_pubkey, request) in request_accounts {
+        let request = perp_abi::state::PositionRequest::try_deserialize(&mut &*request.data)?;
+        if !request.executed {
+            requests.push((request_pubkey, request));
+        }
+    }
+    Ok(requests)
+}
+
+/// Fetches only the fields of open positions needed for counts and open interest, roughly
+/// halving the response size of a full position fetch
+pub fn load_open_position_summaries(
+    connection: &dyn AccountFetcher,
+) -> Result<Vec<PositionSummary>, Box<dyn std::error::Error>> {
+    // the slice follows the compiled layout, so with an IDL whole positions are decoded
+    if idl::is_installed() {
+        return Ok(load_open_positions(connection)?
+            .into_iter()
+            .map(|(pubkey, position)| PositionSummary {
+                pubkey,
+                custody: position.custody,
+                collateral_custody: position.collateral_custody,
+                side: position.side,
+                size_usd: position.size_usd,
+            })
+            .collect());
+    }
+    let position_accounts = get_program_accounts_filtered(
+        connection,
+        &program_id().to_string(),
+        &perp_abi::state::Position::DISCRIMINATOR,
+        position_account_size(),
+        Some(POSITION_SUMMARY_SLICE),
+    )?;
+
+    let mut summaries = vec![];
+    for (pubkey, account) in position_accounts {
+        let data = &account.data;
+        if data.len() < POSITION_SUMMARY_SLICE.length {
+            return Err(format!("position {pubkey} returned a truncated slice").into());
+        }
+        // offsets relative to the start of the slice
+        let size_usd = u64::from_le_bytes(data[89..97].try_into()?);
+        if size_usd == 0 {
+            continue;
+        }
+        summaries.push(PositionSummary {
+            pubkey,
+            custody: Pubkey::try_from(&data[0..32])?,
+            collateral_custody: Pubkey::try_from(&data[32..64])?,
+            side: perp_abi::Side::try_from_slice(&data[80..81])?,
+            size_usd,
+        });
+    }
+    Ok(summaries)
+}
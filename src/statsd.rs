@@ -0,0 +1,41 @@
+use std::net::UdpSocket;
+
+use crate::metrics::snapshot_metrics;
+use crate::snapshot::Snapshot;
+
+const METRIC_PREFIX: &str = "jupperps";
+
+/// Emits snapshot metrics as StatsD gauges over UDP. Labels become DogStatsD tags
+/// (`jupperps.oi.long:1000|g|#market:SOL`), or are appended to the name for plain StatsD
+/// servers without tag support (`jupperps.oi.long.SOL:1000|g`).
+pub fn emit(
+    address: &str,
+    tags: bool,
+    snapshot: &Snapshot,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let socket = UdpSocket::bind("0.0.0.0:0")?;
+    socket.connect(address)?;
+    for metric in snapshot_metrics(snapshot) {
+        if !metric.value.is_finite() {
+            continue;
+        }
+        let line = if tags && !metric.labels.is_empty() {
+            let tags = metric
+                .labels
+                .iter()
+                .map(|(key, value)| format!("{key}:{value}"))
+                .collect::<Vec<_>>()
+                .join(",");
+            format!("{METRIC_PREFIX}.{}:{}|g|#{tags}", metric.name, metric.value)
+        } else {
+            let suffix: String = metric
+                .labels
+                .iter()
+                .map(|(_, value)| format!(".{value}"))
+                .collect();
+            format!("{METRIC_PREFIX}.{}{suffix}:{}|g", metric.name, metric.value)
+        };
+        socket.send(line.as_bytes())?;
+    }
+    Ok(())
+}
@@ -0,0 +1,35 @@
+use std::fs;
+
+use super::Record;
+
+/// Reads records positionally so legacy headers don't need to match field names, and
+/// flexibly so rows from before a column was added still load
+pub fn read_records<R: Record>(path: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let mut reader = ::csv::ReaderBuilder::new().flexible(true).from_path(path)?;
+    let mut records = vec![];
+    for row in reader.records() {
+        records.push(row?.deserialize(None)?);
+    }
+    Ok(records)
+}
+
+pub fn append_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let csv_file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    let mut csv_writer = ::csv::WriterBuilder::new()
+        .has_headers(false)
+        .from_writer(csv_file.try_clone()?);
+    if csv_file.metadata()?.len() == 0 {
+        csv_writer.write_record(R::csv_headers())?;
+    }
+    for record in records {
+        csv_writer.serialize(record)?;
+    }
+    csv_writer.flush()?;
+    Ok(())
+}
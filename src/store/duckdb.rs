@@ -0,0 +1,97 @@
+use ::duckdb::types::{Null, ToSqlOutput, ValueRef};
+use ::duckdb::{params_from_iter, Connection, ToSql};
+
+use super::{from_row, to_row, ColumnType, Record, Value};
+
+impl ToSql for Value {
+    fn to_sql(&self) -> ::duckdb::Result<ToSqlOutput<'_>> {
+        Ok(match self {
+            Value::Integer(integer) => ToSqlOutput::from(*integer),
+            Value::Real(real) => ToSqlOutput::from(*real),
+            Value::Text(text) => ToSqlOutput::from(text.as_str()),
+            Value::Null => ToSqlOutput::from(Null),
+        })
+    }
+}
+
+fn create_table<R: Record>(connection: &Connection) -> ::duckdb::Result<()> {
+    let columns = R::COLUMNS
+        .iter()
+        .map(|(name, column_type)| format!("{name} {}", column_type.sql_type()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    connection.execute_batch(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({columns})",
+        R::TABLE
+    ))?;
+    // tables created by older versions lack the columns added since
+    for (name, column_type) in R::COLUMNS {
+        connection.execute_batch(&format!(
+            "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {name} {}",
+            R::TABLE,
+            column_type.sql_type()
+        ))?;
+    }
+    Ok(())
+}
+
+pub fn read_records<R: Record>(path: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let connection = Connection::open(path)?;
+    create_table::<R>(&connection)?;
+    let columns = R::COLUMNS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut statement = connection.prepare(&format!(
+        "SELECT {columns} FROM {} ORDER BY unix_time",
+        R::TABLE
+    ))?;
+    let mut rows = statement.query([])?;
+    let mut records = vec![];
+    while let Some(row) = rows.next()? {
+        let mut values = vec![];
+        for (i, (_, column_type)) in R::COLUMNS.iter().enumerate() {
+            values.push(match (column_type, row.get_ref(i)?) {
+                (ColumnType::Integer, ValueRef::BigInt(integer)) => Value::Integer(integer),
+                (ColumnType::Real, ValueRef::Double(real)) => Value::Real(real),
+                (_, ValueRef::Null) => Value::Null,
+                (_, value) => Value::Text(value.as_str()?.to_string()),
+            });
+        }
+        records.push(from_row(values)?);
+    }
+    Ok(records)
+}
+
+pub fn append_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    insert_records(&Connection::open(path)?, records)
+}
+
+/// Appends records to their table in an open database, creating it if needed
+pub(crate) fn insert_records<R: Record>(
+    connection: &Connection,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    create_table::<R>(connection)?;
+    let mut appender = connection.appender(R::TABLE)?;
+    for record in records {
+        appender.append_row(params_from_iter(to_row(record)?))?;
+    }
+    appender.flush();
+    Ok(())
+}
+
+pub fn replace_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let connection = Connection::open(path)?;
+    create_table::<R>(&connection)?;
+    connection.execute_batch(&format!("DELETE FROM {}", R::TABLE))?;
+    drop(connection);
+    append_records(path, records)
+}
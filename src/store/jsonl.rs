@@ -0,0 +1,30 @@
+use std::fs;
+use std::io::{BufRead as _, BufReader, Write as _};
+
+use super::Record;
+
+pub fn read_records<R: Record>(path: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let mut records = vec![];
+    for line in BufReader::new(fs::File::open(path)?).lines() {
+        let line = line?;
+        if line.trim().is_empty() {
+            continue;
+        }
+        records.push(serde_json::from_str(&line)?);
+    }
+    Ok(records)
+}
+
+pub fn append_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut file = fs::OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)?;
+    for record in records {
+        writeln!(file, "{}", serde_json::to_string(record)?)?;
+    }
+    Ok(())
+}
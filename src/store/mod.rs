@@ -0,0 +1,675 @@
+//! Snapshot history storage in a handful of interchangeable file formats.
+//!
+//! Every stored record type implements [`Record`], which describes its columns once so each
+//! backend can lay it out as CSV/JSONL rows, SQL tables or Parquet columns.
+
+use std::fs;
+use std::path::Path;
+
+use schemars::JsonSchema;
+use serde::de::DeserializeOwned;
+use serde::{Deserialize, Deserializer, Serialize};
+
+mod csv;
+#[cfg(feature = "duckdb")]
+pub(crate) mod duckdb;
+mod jsonl;
+#[cfg(feature = "parquet")]
+mod parquet;
+#[cfg(feature = "postgres")]
+mod postgres;
+#[cfg(feature = "sqlite")]
+mod sqlite;
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum ColumnType {
+    Integer,
+    Real,
+    Text,
+}
+
+impl ColumnType {
+    pub fn sql_type(&self) -> &'static str {
+        match self {
+            ColumnType::Integer => "BIGINT",
+            ColumnType::Real => "DOUBLE PRECISION",
+            ColumnType::Text => "TEXT",
+        }
+    }
+}
+
+/// A single cell of a stored record
+#[derive(Clone, Debug, PartialEq)]
+pub enum Value {
+    Integer(i64),
+    Real(f64),
+    Text(String),
+    /// SQL NULL: an empty option, a NaN or infinity, or a column added after the row
+    Null,
+}
+
+/// Reads a float stored as null, by JSON for NaN and infinities or by SQL for an undefined
+/// ratio, back as NaN
+pub(crate) fn nan_if_null<'de, D: Deserializer<'de>>(deserializer: D) -> Result<f64, D::Error> {
+    Ok(Option::<f64>::deserialize(deserializer)?.unwrap_or(f64::NAN))
+}
+
+pub trait Record: Serialize + DeserializeOwned {
+    /// Table name for SQL backends
+    const TABLE: &'static str;
+    /// Column names and types, in serialization order. Columns added later go last, so SQL
+    /// tables created before can be extended in place.
+    const COLUMNS: &'static [(&'static str, ColumnType)];
+
+    fn unix_time(&self) -> u64;
+
+    /// Slot of the snapshot the row belongs to, 0 for tables that don't record it. The rows
+    /// of one snapshot share its `(unix_time, slot)`.
+    fn slot(&self) -> u64 {
+        0
+    }
+
+    /// Header row written to new CSV files
+    fn csv_headers() -> Vec<String> {
+        Self::COLUMNS
+            .iter()
+            .map(|(name, _)| name.to_string())
+            .collect()
+    }
+}
+
+/// Flattens a record into cells ordered like [`Record::COLUMNS`]
+pub fn to_row<R: Record>(record: &R) -> Result<Vec<Value>, Box<dyn std::error::Error>> {
+    let serde_json::Value::Object(mut fields) = serde_json::to_value(record)? else {
+        return Err(format!("{} records must serialize to an object", R::TABLE).into());
+    };
+    let mut row = vec![];
+    for (name, column_type) in R::COLUMNS {
+        let field = fields
+            .remove(*name)
+            .ok_or_else(|| format!("{} record is missing column {name}", R::TABLE))?;
+        row.push(match (column_type, field) {
+            (ColumnType::Integer, serde_json::Value::Number(number)) => Value::Integer(
+                number
+                    .as_i64()
+                    .or_else(|| number.as_u64().map(|n| n as i64))
+                    .ok_or_else(|| format!("{name} is not an integer"))?,
+            ),
+            (ColumnType::Real, serde_json::Value::Number(number)) => {
+                Value::Real(number.as_f64().unwrap_or_default())
+            }
+            // empty options, and NaN and infinities, serialize to null
+            (_, serde_json::Value::Null) => Value::Null,
+            (ColumnType::Text, serde_json::Value::String(text)) => Value::Text(text),
+            (ColumnType::Text, other) => Value::Text(other.to_string()),
+            (_, other) => return Err(format!("unexpected value {other} for {name}").into()),
+        });
+    }
+    Ok(row)
+}
+
+/// Rebuilds a record from cells ordered like [`Record::COLUMNS`]. Null reals read as NaN
+/// or an empty option, other null cells as the field's default.
+pub fn from_row<R: Record>(row: Vec<Value>) -> Result<R, Box<dyn std::error::Error>> {
+    let mut fields = serde_json::Map::new();
+    for ((name, column_type), value) in R::COLUMNS.iter().zip(row) {
+        let value = match value {
+            Value::Integer(integer) => serde_json::Value::from(integer),
+            Value::Real(real) => serde_json::Value::from(real),
+            Value::Text(text) => serde_json::Value::from(text),
+            Value::Null if *column_type == ColumnType::Real => serde_json::Value::Null,
+            // left out, so rows from before a column was added get its default
+            Value::Null => continue,
+        };
+        fields.insert(name.to_string(), value);
+    }
+    Ok(serde_json::from_value(serde_json::Value::Object(fields))?)
+}
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum StoreFormat {
+    Csv,
+    Jsonl,
+    Sqlite,
+    Parquet,
+    Duckdb,
+    Postgres,
+}
+
+impl StoreFormat {
+    /// Picks the format from the file extension, anything unknown is treated as CSV.
+    /// `postgres://` URLs are databases rather than files.
+    pub fn from_path(path: &str) -> Self {
+        if path.starts_with("postgres://") || path.starts_with("postgresql://") {
+            return StoreFormat::Postgres;
+        }
+        match Path::new(path)
+            .extension()
+            .and_then(|extension| extension.to_str())
+        {
+            Some("jsonl") | Some("ndjson") => StoreFormat::Jsonl,
+            Some("sqlite") | Some("sqlite3") | Some("db") => StoreFormat::Sqlite,
+            Some("parquet") => StoreFormat::Parquet,
+            Some("duckdb") => StoreFormat::Duckdb,
+            _ => StoreFormat::Csv,
+        }
+    }
+}
+
+/// Where records of `table` accompanying the snapshots at `path` are stored: the same
+/// database for SQL formats, a sibling file such as `history.custodies.csv` otherwise
+pub fn child_path(path: &str, table: &str) -> String {
+    match StoreFormat::from_path(path) {
+        StoreFormat::Sqlite | StoreFormat::Duckdb | StoreFormat::Postgres => path.to_string(),
+        StoreFormat::Csv | StoreFormat::Jsonl | StoreFormat::Parquet => {
+            let path = Path::new(path);
+            let stem = path
+                .file_stem()
+                .map(|stem| stem.to_string_lossy())
+                .unwrap_or_default();
+            let file_name = match path.extension() {
+                Some(extension) => format!("{stem}.{table}.{}", extension.to_string_lossy()),
+                None => format!("{stem}.{table}"),
+            };
+            path.with_file_name(file_name)
+                .to_string_lossy()
+                .into_owned()
+        }
+    }
+}
+
+#[allow(dead_code)]
+fn missing_feature(feature: &str) -> Box<dyn std::error::Error> {
+    format!("built without the `{feature}` feature, rebuild with `--features {feature}`").into()
+}
+
+/// Reads every record of type `R` stored at `path`
+pub fn read_records<R: Record>(path: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    match StoreFormat::from_path(path) {
+        StoreFormat::Csv => csv::read_records(path),
+        StoreFormat::Jsonl => jsonl::read_records(path),
+        #[cfg(feature = "sqlite")]
+        StoreFormat::Sqlite => sqlite::read_records(path),
+        #[cfg(not(feature = "sqlite"))]
+        StoreFormat::Sqlite => Err(missing_feature("sqlite")),
+        #[cfg(feature = "parquet")]
+        StoreFormat::Parquet => parquet::read_records(path),
+        #[cfg(not(feature = "parquet"))]
+        StoreFormat::Parquet => Err(missing_feature("parquet")),
+        #[cfg(feature = "duckdb")]
+        StoreFormat::Duckdb => duckdb::read_records(path),
+        #[cfg(not(feature = "duckdb"))]
+        StoreFormat::Duckdb => Err(missing_feature("duckdb")),
+        #[cfg(feature = "postgres")]
+        StoreFormat::Postgres => postgres::read_records(path),
+        #[cfg(not(feature = "postgres"))]
+        StoreFormat::Postgres => Err(missing_feature("postgres")),
+    }
+}
+
+/// Like [`read_records`], but a file that hasn't been written yet reads as empty
+pub fn read_existing_records<R: Record>(path: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let is_file = matches!(
+        StoreFormat::from_path(path),
+        StoreFormat::Csv | StoreFormat::Jsonl | StoreFormat::Parquet
+    );
+    if is_file && !Path::new(path).exists() {
+        return Ok(vec![]);
+    }
+    read_records(path)
+}
+
+/// Appends records to the store at `path`, creating it if needed
+pub fn append_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    match StoreFormat::from_path(path) {
+        StoreFormat::Csv => csv::append_records(path, records),
+        StoreFormat::Jsonl => jsonl::append_records(path, records),
+        #[cfg(feature = "sqlite")]
+        StoreFormat::Sqlite => sqlite::append_records(path, records),
+        #[cfg(not(feature = "sqlite"))]
+        StoreFormat::Sqlite => Err(missing_feature("sqlite")),
+        #[cfg(feature = "parquet")]
+        StoreFormat::Parquet => parquet::append_records(path, records),
+        #[cfg(not(feature = "parquet"))]
+        StoreFormat::Parquet => Err(missing_feature("parquet")),
+        #[cfg(feature = "duckdb")]
+        StoreFormat::Duckdb => duckdb::append_records(path, records),
+        #[cfg(not(feature = "duckdb"))]
+        StoreFormat::Duckdb => Err(missing_feature("duckdb")),
+        #[cfg(feature = "postgres")]
+        StoreFormat::Postgres => postgres::append_records(path, records),
+        #[cfg(not(feature = "postgres"))]
+        StoreFormat::Postgres => Err(missing_feature("postgres")),
+    }
+}
+
+/// Overwrites the store at `path` so it holds exactly `records`
+pub fn replace_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    // file formats are rebuilt next to the original and swapped in once complete
+    let tmp_path = format!("{path}.tmp");
+    let _ = fs::remove_file(&tmp_path);
+    match StoreFormat::from_path(path) {
+        StoreFormat::Csv => csv::append_records(&tmp_path, records)?,
+        StoreFormat::Jsonl => jsonl::append_records(&tmp_path, records)?,
+        #[cfg(feature = "parquet")]
+        StoreFormat::Parquet => return parquet::replace_records(path, records),
+        #[cfg(not(feature = "parquet"))]
+        StoreFormat::Parquet => return Err(missing_feature("parquet")),
+        #[cfg(feature = "sqlite")]
+        StoreFormat::Sqlite => return sqlite::replace_records(path, records),
+        #[cfg(not(feature = "sqlite"))]
+        StoreFormat::Sqlite => return Err(missing_feature("sqlite")),
+        #[cfg(feature = "duckdb")]
+        StoreFormat::Duckdb => return duckdb::replace_records(path, records),
+        #[cfg(not(feature = "duckdb"))]
+        StoreFormat::Duckdb => return Err(missing_feature("duckdb")),
+        #[cfg(feature = "postgres")]
+        StoreFormat::Postgres => return postgres::replace_records(path, records),
+        #[cfg(not(feature = "postgres"))]
+        StoreFormat::Postgres => return Err(missing_feature("postgres")),
+    }
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+/// Headline metrics of one snapshot, the rows of the `-c` CSV export
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct SnapshotRecord {
+    pub unix_time: u64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub total_pool_value: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub unrealized_pnl: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub total_fees: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub total_position_value: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub total_collateral_value: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub average_leverage_at_entry: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub average_effective_leverage: f64,
+    pub long_trades: u64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub long_value: f64,
+    pub short_trades: u64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub short_value: f64,
+    /// Missing from rows written before slots were recorded
+    #[serde(default)]
+    pub slot: u64,
+    /// Changes since the stored snapshot taken about 24 hours earlier, empty without one
+    #[serde(default)]
+    pub total_pool_value_change_24h: Option<f64>,
+    #[serde(default)]
+    pub total_position_value_change_24h: Option<f64>,
+    #[serde(default)]
+    pub unrealized_pnl_change_24h: Option<f64>,
+    #[serde(default)]
+    pub total_fees_change_24h: Option<f64>,
+    /// JLP NAV per token and market price in USDC, empty unless fetched with --jlp-premium
+    #[serde(default)]
+    pub jlp_nav: Option<f64>,
+    #[serde(default)]
+    pub jlp_price: Option<f64>,
+    /// Accounts the snapshot was computed from, empty in rows written before they were
+    /// recorded
+    #[serde(default)]
+    pub custody_accounts: Option<u64>,
+    #[serde(default)]
+    pub position_accounts: Option<u64>,
+    /// Hash of the perpetuals program's executable, empty unless checked with --program-hash
+    #[serde(default)]
+    pub program_hash: String,
+    /// Where the snapshot was collected, empty in rows written before it was recorded
+    #[serde(default)]
+    pub tool_version: String,
+    #[serde(default)]
+    pub cluster: String,
+    #[serde(default)]
+    pub rpc_endpoint: String,
+    #[serde(default)]
+    pub collection_secs: Option<f64>,
+    /// Plugin metrics as a JSON object of name to value, empty without plugins
+    #[serde(default)]
+    pub custom_metrics: String,
+}
+
+impl Record for SnapshotRecord {
+    const TABLE: &'static str = "snapshots";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("total_pool_value", ColumnType::Real),
+        ("unrealized_pnl", ColumnType::Real),
+        ("total_fees", ColumnType::Real),
+        ("total_position_value", ColumnType::Real),
+        ("total_collateral_value", ColumnType::Real),
+        ("average_leverage_at_entry", ColumnType::Real),
+        ("average_effective_leverage", ColumnType::Real),
+        ("long_trades", ColumnType::Integer),
+        ("long_value", ColumnType::Real),
+        ("short_trades", ColumnType::Integer),
+        ("short_value", ColumnType::Real),
+        ("slot", ColumnType::Integer),
+        ("total_pool_value_change_24h", ColumnType::Real),
+        ("total_position_value_change_24h", ColumnType::Real),
+        ("unrealized_pnl_change_24h", ColumnType::Real),
+        ("total_fees_change_24h", ColumnType::Real),
+        ("jlp_nav", ColumnType::Real),
+        ("jlp_price", ColumnType::Real),
+        ("custody_accounts", ColumnType::Integer),
+        ("position_accounts", ColumnType::Integer),
+        ("program_hash", ColumnType::Text),
+        ("tool_version", ColumnType::Text),
+        ("cluster", ColumnType::Text),
+        ("rpc_endpoint", ColumnType::Text),
+        ("collection_secs", ColumnType::Real),
+        ("custom_metrics", ColumnType::Text),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+
+    fn csv_headers() -> Vec<String> {
+        [
+            "Unix Time",
+            "Total Pool Value",
+            "Unrealized Paper P&L",
+            "Total Fees",
+            "Total Value of Positions",
+            "Total Value of Collateral",
+            "Average Leverage At Entry",
+            "Average Effective Leverage",
+            "Long Trades",
+            "Long Value",
+            "Short Trades",
+            "Short Value",
+            "Slot",
+            "Total Pool Value 24h Change",
+            "Total Value of Positions 24h Change",
+            "Unrealized Paper P&L 24h Change",
+            "Total Fees 24h Change",
+            "JLP NAV",
+            "JLP Price",
+            "Custody Accounts",
+            "Position Accounts",
+            "Program Hash",
+            "Tool Version",
+            "Cluster",
+            "RPC Endpoint",
+            "Collection Seconds",
+            "Custom Metrics",
+        ]
+        .into_iter()
+        .map(str::to_string)
+        .collect()
+    }
+}
+
+/// A custody's oracle price as seen by one snapshot, kept for looking up historical prices
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct OraclePriceRecord {
+    pub unix_time: u64,
+    pub market: String,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub price: f64,
+    /// When the oracle published the price, which can lag `unix_time`
+    pub publish_time: i64,
+}
+
+impl Record for OraclePriceRecord {
+    const TABLE: &'static str = "oracle_prices";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("market", ColumnType::Text),
+        ("price", ColumnType::Real),
+        ("publish_time", ColumnType::Integer),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+}
+
+/// Open size of one side of a market and the borrow fees it accrues, per snapshot
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct FundingRecord {
+    pub unix_time: u64,
+    pub market: String,
+    /// `Long` or `Short`
+    pub side: String,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub size_usd: f64,
+    /// Borrow fees accruing across the side's positions, in USD per hour
+    #[serde(deserialize_with = "nan_if_null")]
+    pub borrow_usd_per_hour: f64,
+}
+
+impl Record for FundingRecord {
+    const TABLE: &'static str = "funding";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("market", ColumnType::Text),
+        ("side", ColumnType::Text),
+        ("size_usd", ColumnType::Real),
+        ("borrow_usd_per_hour", ColumnType::Real),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+}
+
+/// Oracle price, utilization and borrow rate of one custody, child rows of each snapshot
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+#[cfg_attr(feature = "graphql", derive(async_graphql::SimpleObject))]
+pub struct CustodyRecord {
+    pub unix_time: u64,
+    pub slot: u64,
+    pub market: String,
+    pub custody: String,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub price: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub utilization: f64,
+    /// Hourly borrow rate in BPS
+    #[serde(deserialize_with = "nan_if_null")]
+    pub borrow_rate: f64,
+    /// Value of the fees the custody has collected and not yet distributed, missing from
+    /// rows written before it was recorded
+    #[serde(default, deserialize_with = "nan_if_null")]
+    pub fees_reserves_usd: f64,
+    /// Fees collected and not yet distributed, in the custody's token, missing from rows
+    /// written before it was recorded
+    #[serde(default)]
+    pub fees_reserves: Option<f64>,
+}
+
+impl Record for CustodyRecord {
+    const TABLE: &'static str = "custodies";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("slot", ColumnType::Integer),
+        ("market", ColumnType::Text),
+        ("custody", ColumnType::Text),
+        ("price", ColumnType::Real),
+        ("utilization", ColumnType::Real),
+        ("borrow_rate", ColumnType::Real),
+        ("fees_reserves_usd", ColumnType::Real),
+        ("fees_reserves", ColumnType::Real),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+}
+
+/// Open interest on each side of one market, per snapshot
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct MarketRecord {
+    pub unix_time: u64,
+    pub slot: u64,
+    pub market: String,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub price: f64,
+    pub long_positions: u64,
+    pub short_positions: u64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub long_value: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub short_value: f64,
+    /// Longs per short by count, empty without shorts
+    pub long_short_ratio: Option<f64>,
+    /// Long per short open interest, empty without shorts
+    pub long_short_value_ratio: Option<f64>,
+    /// DEX spot price, empty unless fetched with --dex-prices
+    #[serde(default)]
+    pub dex_price: Option<f64>,
+}
+
+impl Record for MarketRecord {
+    const TABLE: &'static str = "markets";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("slot", ColumnType::Integer),
+        ("market", ColumnType::Text),
+        ("price", ColumnType::Real),
+        ("long_positions", ColumnType::Integer),
+        ("short_positions", ColumnType::Integer),
+        ("long_value", ColumnType::Real),
+        ("short_value", ColumnType::Real),
+        ("long_short_ratio", ColumnType::Real),
+        ("long_short_value_ratio", ColumnType::Real),
+        ("dex_price", ColumnType::Real),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+}
+
+/// One open position as seen by a snapshot, child rows kept with `--record-positions` so
+/// analytics can be recomputed from history
+#[derive(Clone, Debug, Default, Serialize, Deserialize, JsonSchema)]
+pub struct PositionRecord {
+    pub unix_time: u64,
+    pub slot: u64,
+    pub position: String,
+    pub owner: String,
+    pub market: String,
+    /// `Long` or `Short`
+    pub side: String,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub size_usd: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub collateral_usd: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub entry_price: f64,
+    /// Oracle price of the market at the snapshot
+    #[serde(deserialize_with = "nan_if_null")]
+    pub price: f64,
+    pub open_time: i64,
+    pub update_time: i64,
+    /// Borrow fees accrued since the last update, missing from rows written before they
+    /// were recorded
+    #[serde(default, deserialize_with = "nan_if_null")]
+    pub borrow_fees_usd: f64,
+    /// Current value over the collateral left after unrealized P&L and accrued borrow fees,
+    /// missing from rows written before it was recorded
+    #[serde(default, deserialize_with = "nan_if_null")]
+    pub effective_leverage: f64,
+}
+
+impl Record for PositionRecord {
+    const TABLE: &'static str = "positions";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("slot", ColumnType::Integer),
+        ("position", ColumnType::Text),
+        ("owner", ColumnType::Text),
+        ("market", ColumnType::Text),
+        ("side", ColumnType::Text),
+        ("size_usd", ColumnType::Real),
+        ("collateral_usd", ColumnType::Real),
+        ("entry_price", ColumnType::Real),
+        ("price", ColumnType::Real),
+        ("open_time", ColumnType::Integer),
+        ("update_time", ColumnType::Integer),
+        ("borrow_fees_usd", ColumnType::Real),
+        ("effective_leverage", ColumnType::Real),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+}
+
+/// Aggregate positions of one wallet from the config's `[wallets]`, per snapshot, for
+/// equity curves of the wallets being watched
+#[derive(Clone, Debug, Default, Serialize, Deserialize)]
+pub struct WalletRecord {
+    pub unix_time: u64,
+    pub slot: u64,
+    /// Name of the wallet in the config
+    pub label: String,
+    pub wallet: String,
+    pub positions: u64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub size_usd: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub collateral_usd: f64,
+    #[serde(deserialize_with = "nan_if_null")]
+    pub unrealized_pnl: f64,
+    /// Collateral left after P&L and accrued borrow fees
+    #[serde(deserialize_with = "nan_if_null")]
+    pub equity: f64,
+}
+
+impl Record for WalletRecord {
+    const TABLE: &'static str = "wallets";
+    const COLUMNS: &'static [(&'static str, ColumnType)] = &[
+        ("unix_time", ColumnType::Integer),
+        ("slot", ColumnType::Integer),
+        ("label", ColumnType::Text),
+        ("wallet", ColumnType::Text),
+        ("positions", ColumnType::Integer),
+        ("size_usd", ColumnType::Real),
+        ("collateral_usd", ColumnType::Real),
+        ("unrealized_pnl", ColumnType::Real),
+        ("equity", ColumnType::Real),
+    ];
+
+    fn unix_time(&self) -> u64 {
+        self.unix_time
+    }
+
+    fn slot(&self) -> u64 {
+        self.slot
+    }
+}
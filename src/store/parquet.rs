@@ -0,0 +1,221 @@
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use ::parquet::arrow::arrow_reader::ParquetRecordBatchReaderBuilder;
+use ::parquet::arrow::ArrowWriter;
+use arrow_array::{Array, ArrayRef, Float64Array, Int64Array, RecordBatch, StringArray};
+use arrow_schema::{DataType, Field, Schema};
+
+use super::{from_row, to_row, ColumnType, Record, Value};
+
+fn schema<R: Record>() -> Arc<Schema> {
+    Arc::new(Schema::new(
+        R::COLUMNS
+            .iter()
+            .map(|(name, column_type)| {
+                let data_type = match column_type {
+                    ColumnType::Integer => DataType::Int64,
+                    ColumnType::Real => DataType::Float64,
+                    ColumnType::Text => DataType::Utf8,
+                };
+                Field::new(*name, data_type, true)
+            })
+            .collect::<Vec<_>>(),
+    ))
+}
+
+/// The part files of the store at `path`, in the order they were written
+fn parts(path: &Path) -> Result<Vec<PathBuf>, Box<dyn std::error::Error>> {
+    let mut parts = vec![];
+    for entry in fs::read_dir(path)? {
+        let part = entry?.path();
+        if part
+            .extension()
+            .map_or(false, |extension| extension == "parquet")
+        {
+            parts.push(part);
+        }
+    }
+    parts.sort();
+    Ok(parts)
+}
+
+/// Reads the store at `path`: a directory of part files, or a single file as written by
+/// earlier versions
+pub fn read_records<R: Record>(path: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let path = Path::new(path);
+    if !path.is_dir() {
+        return read_file(path);
+    }
+    let mut records = vec![];
+    for part in parts(path)? {
+        records.extend(read_file(&part)?);
+    }
+    Ok(records)
+}
+
+/// Reads one Parquet file
+fn read_file<R: Record>(path: &Path) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let reader = ParquetRecordBatchReaderBuilder::try_new(fs::File::open(path)?)?.build()?;
+    let mut records = vec![];
+    for batch in reader {
+        let batch = batch?;
+        for i in 0..batch.num_rows() {
+            let mut values = vec![];
+            for (name, column_type) in R::COLUMNS {
+                // columns added since the file was written read as empty
+                let Some(column) = batch.column_by_name(name) else {
+                    values.push(Value::Null);
+                    continue;
+                };
+                if column.is_null(i) {
+                    values.push(Value::Null);
+                    continue;
+                }
+                let column = column.as_any();
+                let value = match column_type {
+                    ColumnType::Integer => column
+                        .downcast_ref::<Int64Array>()
+                        .map(|array| Value::Integer(array.value(i))),
+                    ColumnType::Real => column
+                        .downcast_ref::<Float64Array>()
+                        .map(|array| Value::Real(array.value(i))),
+                    ColumnType::Text => column
+                        .downcast_ref::<StringArray>()
+                        .map(|array| Value::Text(array.value(i).to_string())),
+                };
+                values.push(value.ok_or_else(|| format!("{name} has an unexpected type"))?);
+            }
+            records.push(from_row(values)?);
+        }
+    }
+    Ok(records)
+}
+
+/// Parquet files can't be appended to, so the store at `path` is a directory and each
+/// append writes a part file to it, leaving the rows already stored untouched. A single
+/// file written by earlier versions becomes the first part.
+pub fn append_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    if records.is_empty() {
+        return Ok(());
+    }
+    let dir = Path::new(path);
+    if dir.is_file() {
+        let legacy = format!("{path}.legacy");
+        fs::rename(path, &legacy)?;
+        fs::create_dir(dir)?;
+        fs::rename(legacy, dir.join(part_name(0)))?;
+    }
+    fs::create_dir_all(dir)?;
+    let part = dir.join(part_name(parts(dir)?.len()));
+
+    let rows = records.iter().map(to_row).collect::<Result<Vec<_>, _>>()?;
+
+    let schema = schema::<R>();
+    let mut columns: Vec<ArrayRef> = vec![];
+    for (i, (_, column_type)) in R::COLUMNS.iter().enumerate() {
+        let cells = rows.iter().map(|row| &row[i]);
+        columns.push(match column_type {
+            ColumnType::Integer => Arc::new(Int64Array::from_iter(cells.map(|cell| match cell {
+                Value::Integer(integer) => Some(*integer),
+                _ => None,
+            }))),
+            ColumnType::Real => Arc::new(Float64Array::from_iter(cells.map(|cell| match cell {
+                Value::Real(real) => Some(*real),
+                Value::Integer(integer) => Some(*integer as f64),
+                _ => None,
+            }))),
+            ColumnType::Text => Arc::new(StringArray::from_iter(cells.map(|cell| match cell {
+                Value::Text(text) => Some(text.clone()),
+                _ => None,
+            }))),
+        });
+    }
+    let batch = RecordBatch::try_new(schema.clone(), columns)?;
+
+    // readers only pick up the part once it's complete
+    let tmp_path = part.with_extension("parquet.tmp");
+    let mut writer = ArrowWriter::try_new(fs::File::create(&tmp_path)?, schema, None)?;
+    writer.write(&batch)?;
+    writer.close()?;
+    fs::rename(tmp_path, part)?;
+    Ok(())
+}
+
+/// Name of the `index`th part file, zero-padded so parts sort in the order written
+fn part_name(index: usize) -> String {
+    format!("part-{index:010}.parquet")
+}
+
+/// Overwrites the store at `path` so it holds exactly `records`, as a single part
+pub fn replace_records<R: Record>(
+    path: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let tmp_path = format!("{path}.tmp");
+    let _ = fs::remove_dir_all(&tmp_path);
+    fs::create_dir_all(&tmp_path)?;
+    append_records(&tmp_path, records)?;
+    if Path::new(path).is_dir() {
+        fs::remove_dir_all(path)?;
+    }
+    fs::rename(tmp_path, path)?;
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::store::PositionRecord;
+
+    fn record(unix_time: u64) -> PositionRecord {
+        PositionRecord {
+            unix_time,
+            position: unix_time.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn appends_write_parts() {
+        let path = std::env::temp_dir().join(format!("parts-{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_dir_all(path);
+
+        append_records(path, &[record(1), record(2)]).unwrap();
+        append_records(path, &[record(3)]).unwrap();
+        assert_eq!(parts(Path::new(path)).unwrap().len(), 2);
+        let times: Vec<_> = read_records::<PositionRecord>(path)
+            .unwrap()
+            .iter()
+            .map(|record| record.unix_time)
+            .collect();
+        assert_eq!(times, [1, 2, 3]);
+
+        replace_records(path, &[record(4)]).unwrap();
+        assert_eq!(parts(Path::new(path)).unwrap().len(), 1);
+        fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn single_file_becomes_the_first_part() {
+        let path = std::env::temp_dir().join(format!("legacy-{}.parquet", std::process::id()));
+        let path = path.to_str().unwrap();
+        let _ = fs::remove_dir_all(path);
+        // written the way earlier versions did, as one file
+        append_records(path, &[record(1)]).unwrap();
+        let part = parts(Path::new(path)).unwrap().remove(0);
+        fs::rename(&part, format!("{path}.file")).unwrap();
+        fs::remove_dir(path).unwrap();
+        fs::rename(format!("{path}.file"), path).unwrap();
+
+        append_records(path, &[record(2)]).unwrap();
+        assert!(Path::new(path).is_dir());
+        assert_eq!(read_records::<PositionRecord>(path).unwrap().len(), 2);
+        fs::remove_dir_all(path).unwrap();
+    }
+}
@@ -0,0 +1,154 @@
+use std::io::Write as _;
+
+use ::postgres::{Client, NoTls};
+
+use super::{from_row, to_row, ColumnType, Record, Value};
+
+/// Rows older than this are compressed on TimescaleDB
+const COMPRESS_AFTER_SECS: i64 = 7 * 86_400;
+/// Time range covered by each TimescaleDB chunk
+const CHUNK_INTERVAL_SECS: i64 = 86_400;
+
+fn has_timescale(client: &mut Client) -> Result<bool, ::postgres::Error> {
+    Ok(!client
+        .query(
+            "SELECT 1 FROM pg_extension WHERE extname = 'timescaledb'",
+            &[],
+        )?
+        .is_empty())
+}
+
+/// Turns a new table into a hypertable chunked by `unix_time`, compressing old chunks
+/// segmented by market where the table has one
+fn create_hypertable<R: Record>(client: &mut Client) -> Result<(), ::postgres::Error> {
+    let table = R::TABLE;
+    // integer time columns need to be told what "now" is for the compression policy
+    client.batch_execute(&format!(
+        "CREATE OR REPLACE FUNCTION jupperps_unix_now() RETURNS BIGINT LANGUAGE SQL STABLE AS
+            $$ SELECT extract(epoch FROM now())::BIGINT $$;
+        SELECT create_hypertable('{table}', 'unix_time',
+            chunk_time_interval => {CHUNK_INTERVAL_SECS}::BIGINT, migrate_data => TRUE);
+        SELECT set_integer_now_func('{table}', 'jupperps_unix_now', replace_if_exists => TRUE);"
+    ))?;
+    let segment_by = if R::COLUMNS.iter().any(|(name, _)| *name == "market") {
+        ", timescaledb.compress_segmentby = 'market'"
+    } else {
+        ""
+    };
+    client.batch_execute(&format!(
+        "ALTER TABLE {table} SET (timescaledb.compress,
+            timescaledb.compress_orderby = 'unix_time DESC'{segment_by});
+        SELECT add_compression_policy('{table}', compress_after => {COMPRESS_AFTER_SECS}::BIGINT,
+            if_not_exists => TRUE);"
+    ))
+}
+
+fn create_table<R: Record>(client: &mut Client) -> Result<(), ::postgres::Error> {
+    let exists = client
+        .query_one("SELECT to_regclass($1) IS NOT NULL", &[&R::TABLE])?
+        .get::<_, bool>(0);
+    if exists {
+        // tables created by older versions lack the columns added since
+        for (name, column_type) in R::COLUMNS {
+            client.batch_execute(&format!(
+                "ALTER TABLE {} ADD COLUMN IF NOT EXISTS {name} {}",
+                R::TABLE,
+                column_type.sql_type()
+            ))?;
+        }
+        return Ok(());
+    }
+    let columns = R::COLUMNS
+        .iter()
+        .map(|(name, column_type)| format!("{name} {}", column_type.sql_type()))
+        .collect::<Vec<_>>()
+        .join(", ");
+    client.batch_execute(&format!(
+        "CREATE TABLE IF NOT EXISTS {} ({columns})",
+        R::TABLE
+    ))?;
+    if has_timescale(client)? {
+        create_hypertable::<R>(client)?;
+    }
+    Ok(())
+}
+
+/// Formats a cell for `COPY ... WITH (FORMAT csv)`, where an unquoted empty field is NULL
+fn csv_cell(value: &Value) -> String {
+    match value {
+        Value::Integer(integer) => integer.to_string(),
+        Value::Real(real) if real.is_nan() => String::new(),
+        Value::Real(real) if real.is_infinite() => {
+            (if *real > 0.0 { "Infinity" } else { "-Infinity" }).to_string()
+        }
+        Value::Real(real) => real.to_string(),
+        Value::Text(text) => format!("\"{}\"", text.replace('"', "\"\"")),
+        Value::Null => String::new(),
+    }
+}
+
+pub fn read_records<R: Record>(url: &str) -> Result<Vec<R>, Box<dyn std::error::Error>> {
+    let mut client = Client::connect(url, NoTls)?;
+    create_table::<R>(&mut client)?;
+    let columns = R::COLUMNS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut records = vec![];
+    for row in client.query(
+        &format!("SELECT {columns} FROM {} ORDER BY unix_time", R::TABLE),
+        &[],
+    )? {
+        let mut values = vec![];
+        for (i, (_, column_type)) in R::COLUMNS.iter().enumerate() {
+            let value = match column_type {
+                ColumnType::Integer => row.try_get::<_, Option<i64>>(i)?.map(Value::Integer),
+                ColumnType::Real => row.try_get::<_, Option<f64>>(i)?.map(Value::Real),
+                ColumnType::Text => row.try_get::<_, Option<String>>(i)?.map(Value::Text),
+            };
+            values.push(value.unwrap_or(Value::Null));
+        }
+        records.push(from_row(values)?);
+    }
+    Ok(records)
+}
+
+/// Appends with a single `COPY`, which keeps the high-volume child tables cheap to write
+pub fn append_records<R: Record>(
+    url: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = Client::connect(url, NoTls)?;
+    create_table::<R>(&mut client)?;
+    let columns = R::COLUMNS
+        .iter()
+        .map(|(name, _)| *name)
+        .collect::<Vec<_>>()
+        .join(", ");
+    let mut writer = client.copy_in(&format!(
+        "COPY {} ({columns}) FROM STDIN WITH (FORMAT csv)",
+        R::TABLE
+    ))?;
+    for record in records {
+        let line = to_row(record)?
+            .iter()
+            .map(csv_cell)
+            .collect::<Vec<_>>()
+            .join(",");
+        writeln!(writer, "{line}")?;
+    }
+    writer.finish()?;
+    Ok(())
+}
+
+pub fn replace_records<R: Record>(
+    url: &str,
+    records: &[R],
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut client = Client::connect(url, NoTls)?;
+    create_table::<R>(&mut client)?;
+    client.batch_execute(&format!("DELETE FROM {}", R::TABLE))?;
+    drop(client);
+    append_records(url, records)
+}
@@ -0,0 +1,98 @@
+//! ANSI colors for console output, enabled only when stdout is a terminal and neither
+//! `NO_COLOR` nor `--no-color` is set.
+
+use std::fmt::Display;
+use std::io::IsTerminal as _;
+use std::ops::{Div as _, Mul as _, Sub as _};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+
+const GREEN: &str = "32";
+const RED: &str = "31";
+const YELLOW: &str = "33";
+const BOLD: &str = "1";
+const REVERSE: &str = "7";
+/// Clears the terminal and moves the cursor to its top left corner
+const CLEAR_SCREEN: &str = "\x1b[2J\x1b[H";
+
+/// Decides once at startup whether output is colored
+pub fn init(no_color: bool) {
+    let no_color_env = std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty());
+    ENABLED.store(
+        !no_color && !no_color_env && std::io::stdout().is_terminal(),
+        Ordering::Relaxed,
+    );
+}
+
+fn paint(code: &str, text: impl Display) -> String {
+    if ENABLED.load(Ordering::Relaxed) {
+        format!("\x1b[{code}m{text}\x1b[0m")
+    } else {
+        text.to_string()
+    }
+}
+
+pub fn heading(text: impl Display) -> String {
+    paint(BOLD, text)
+}
+
+pub fn warning(text: impl Display) -> String {
+    paint(YELLOW, text)
+}
+
+/// Green for gains, red for losses
+pub fn pnl(value: f64, text: impl Display) -> String {
+    if value > 0.0 {
+        paint(GREEN, text)
+    } else if value < 0.0 {
+        paint(RED, text)
+    } else {
+        text.to_string()
+    }
+}
+
+/// `render` drawn over the whole terminal, with the lines that differ from the `last` render
+/// highlighted
+pub fn redraw(render: &str, last: Option<&str>) -> String {
+    let mut out = CLEAR_SCREEN.to_string();
+    let mut last_lines = last.map(str::lines);
+    for line in render.lines() {
+        let last_line = last_lines.as_mut().and_then(Iterator::next);
+        if last_line.is_some_and(|last_line| last_line != line) {
+            // colors within the line reset the highlight along with themselves
+            let line = line.replace("\x1b[0m", &format!("\x1b[0m\x1b[{REVERSE}m"));
+            out += &paint(REVERSE, line);
+        } else {
+            out += line;
+        }
+        out.push('\n');
+    }
+    out
+}
+
+const SPARK_LEVELS: [char; 8] = ['▁', '▂', '▃', '▄', '▅', '▆', '▇', '█'];
+
+/// `values` as a line of block characters scaled between their minimum and maximum, e.g.
+/// `▁▃▅▇`. A flat series sits mid-height.
+pub fn sparkline(values: &[f64]) -> String {
+    let values: Vec<f64> = values
+        .iter()
+        .copied()
+        .filter(|value| value.is_finite())
+        .collect();
+    let min = values.iter().copied().fold(f64::INFINITY, f64::min);
+    let max = values.iter().copied().fold(f64::NEG_INFINITY, f64::max);
+    let top = (SPARK_LEVELS.len() - 1) as f64;
+    values
+        .iter()
+        .map(|value| {
+            let level = if max > min {
+                value.sub(min).div(max.sub(min)).mul(top).round()
+            } else {
+                top.div(2.0).floor()
+            };
+            SPARK_LEVELS[level as usize]
+        })
+        .collect()
+}
@@ -0,0 +1,363 @@
+//! Narrative summary of the stored history over the last day, week or month: open interest
+//! and pool value changes, volume, fees collected, the biggest likely liquidations and
+//! borrow rate extremes, for distribution as Markdown or HTML.
+
+use std::collections::{BTreeMap, HashMap};
+use std::ops::{Div as _, Mul as _, Sub as _};
+
+use clap::ValueEnum;
+use thousands::Separable;
+
+use crate::analyze::format_time;
+use crate::fees::{collected_by_window, volume_by_window};
+use crate::funding::MAX_SAMPLE_GAP_SECS;
+use crate::lifecycle::likely_liquidated;
+use crate::risk::{escape_html, ReportFormat};
+use crate::store::{
+    child_path, read_existing_records, read_records, CustodyRecord, PositionRecord, Record as _,
+    SnapshotRecord,
+};
+use crate::table;
+
+/// Likely liquidations listed
+const TOP_LIQUIDATIONS: usize = 5;
+
+#[derive(Clone, Copy, Debug, ValueEnum)]
+pub enum Period {
+    Day,
+    Week,
+    Month,
+}
+
+impl Period {
+    /// As given to `--period` and in the config's email routes
+    pub fn name(self) -> &'static str {
+        match self {
+            Period::Day => "day",
+            Period::Week => "week",
+            Period::Month => "month",
+        }
+    }
+
+    fn secs(self) -> u64 {
+        match self {
+            Period::Day => 86_400,
+            Period::Week => 7 * 86_400,
+            Period::Month => 30 * 86_400,
+        }
+    }
+
+    fn title(self) -> &'static str {
+        match self {
+            Period::Day => "Daily summary",
+            Period::Week => "Weekly summary",
+            Period::Month => "Monthly summary",
+        }
+    }
+}
+
+struct Table {
+    title: String,
+    headers: Vec<String>,
+    rows: Vec<Vec<String>>,
+}
+
+struct Summary {
+    title: String,
+    start: u64,
+    end: u64,
+    paragraphs: Vec<String>,
+    tables: Vec<Table>,
+}
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+/// `rose 4.2% from $1 to $2`, or `was flat at $1`
+fn movement(start: f64, end: f64) -> String {
+    if start == end {
+        return format!("was flat at {}", usd(end));
+    }
+    let direction = if end > start { "rose" } else { "fell" };
+    let change = if start != 0.0 {
+        format!(" {:.1}%", end.sub(start).div(start).abs().mul(100.0))
+    } else {
+        String::new()
+    };
+    format!("{direction}{change} from {} to {}", usd(start), usd(end))
+}
+
+/// Positions that disappeared between consecutive snapshots while, at the market price of
+/// the later one, they'd lost most of their collateral, largest first
+fn likely_liquidations(
+    positions: &[PositionRecord],
+    custodies: &[CustodyRecord],
+) -> Vec<(u64, PositionRecord)> {
+    let mut snapshots: BTreeMap<u64, HashMap<&str, &PositionRecord>> = BTreeMap::new();
+    for position in positions {
+        snapshots
+            .entry(position.unix_time)
+            .or_default()
+            .insert(&position.position, position);
+    }
+    let prices: HashMap<(u64, &str), f64> = custodies
+        .iter()
+        .map(|custody| ((custody.unix_time, custody.market.as_str()), custody.price))
+        .collect();
+    let snapshots: Vec<_> = snapshots.into_iter().collect();
+    let mut liquidations = vec![];
+    for pair in snapshots.windows(2) {
+        let ((start_time, start), (end_time, end)) = (&pair[0], &pair[1]);
+        if end_time - start_time > MAX_SAMPLE_GAP_SECS {
+            continue;
+        }
+        for (key, position) in start {
+            if end.contains_key(key) {
+                continue;
+            }
+            let Some(price) = prices.get(&(*end_time, position.market.as_str())) else {
+                continue;
+            };
+            if likely_liquidated(position, *price) {
+                liquidations.push((*end_time, (*position).clone()));
+            }
+        }
+    }
+    liquidations.sort_by(|(_, a), (_, b)| b.size_usd.total_cmp(&a.size_usd));
+    liquidations.truncate(TOP_LIQUIDATIONS);
+    liquidations
+}
+
+/// Lowest and highest borrow APR of each market, in percent, with when they were seen
+pub(crate) fn borrow_extremes(
+    custodies: &[CustodyRecord],
+) -> BTreeMap<String, ((f64, u64), (f64, u64))> {
+    let mut extremes: BTreeMap<String, ((f64, u64), (f64, u64))> = BTreeMap::new();
+    for custody in custodies {
+        let apr = custody.borrow_rate.mul(24.0 * 365.0).div(100.0);
+        let sample = (apr, custody.unix_time);
+        let (lowest, highest) = extremes
+            .entry(custody.market.clone())
+            .or_insert((sample, sample));
+        if apr < lowest.0 {
+            *lowest = sample;
+        }
+        if apr > highest.0 {
+            *highest = sample;
+        }
+    }
+    extremes
+}
+
+fn build(
+    history: &str,
+    period: Period,
+    end: Option<u64>,
+) -> Result<Summary, Box<dyn std::error::Error>> {
+    let mut snapshots = read_records::<SnapshotRecord>(history)?;
+    snapshots.sort_by_key(|record| record.unix_time());
+    let end = match end {
+        Some(end) => end,
+        None => snapshots
+            .last()
+            .map(|record| record.unix_time)
+            .ok_or_else(|| {
+                format!("no snapshots in {history}, take snapshots with -c {history}")
+            })?,
+    };
+    let start = end.saturating_sub(period.secs());
+    snapshots.retain(|record| (start..=end).contains(&record.unix_time));
+    let (Some(first), Some(last)) = (snapshots.first(), snapshots.last()) else {
+        return Err(format!("no snapshots in {history} between {start} and {end}").into());
+    };
+
+    let mut custodies =
+        read_existing_records::<CustodyRecord>(&child_path(history, CustodyRecord::TABLE))?;
+    custodies.retain(|record| (start..=end).contains(&record.unix_time));
+    let mut positions =
+        read_existing_records::<PositionRecord>(&child_path(history, PositionRecord::TABLE))?;
+    positions.retain(|record| (start..=end).contains(&record.unix_time));
+
+    let mut paragraphs = vec![format!(
+        "Open interest {}, with longs ending at {} and shorts at {}. The pool's value {}.",
+        movement(first.total_position_value, last.total_position_value),
+        usd(last.long_value),
+        usd(last.short_value),
+        movement(first.total_pool_value, last.total_pool_value),
+    )];
+    paragraphs.push(format!(
+        "Traders ended the period with {} of unrealized paper P&L across {} positions, at an average effective leverage of {:.2}x.",
+        usd(last.unrealized_pnl),
+        last.long_trades + last.short_trades,
+        last.average_effective_leverage,
+    ));
+    if positions.is_empty() {
+        paragraphs.push(
+            "Volume and liquidations need positions recorded with --record-positions.".to_string(),
+        );
+    } else {
+        let volume = volume_by_window(positions.clone(), end, &[period.secs().div(3600)]);
+        paragraphs.push(format!(
+            "Traders opened, closed and resized {} of positions.",
+            usd(volume[0])
+        ));
+    }
+    if !custodies.is_empty() {
+        let fees = collected_by_window(custodies.clone(), end, &[period.secs().div(3600)]);
+        paragraphs.push(format!("The custodies collected {} in fees.", usd(fees[0])));
+    }
+
+    let mut tables = vec![];
+    let liquidations = likely_liquidations(&positions, &custodies);
+    if !liquidations.is_empty() {
+        tables.push(Table {
+            title: "Biggest likely liquidations".to_string(),
+            headers: ["Time", "Position", "Market", "Side", "Size", "Collateral"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: liquidations
+                .into_iter()
+                .map(|(unix_time, position)| {
+                    vec![
+                        format_time(unix_time),
+                        position.position,
+                        position.market,
+                        position.side,
+                        usd(position.size_usd),
+                        usd(position.collateral_usd),
+                    ]
+                })
+                .collect(),
+        });
+    }
+    let extremes = borrow_extremes(&custodies);
+    if !extremes.is_empty() {
+        tables.push(Table {
+            title: "Borrow rate extremes".to_string(),
+            headers: ["Market", "Lowest APR", "At", "Highest APR", "At"]
+                .into_iter()
+                .map(str::to_string)
+                .collect(),
+            rows: extremes
+                .into_iter()
+                .map(|(market, ((lowest, lowest_at), (highest, highest_at)))| {
+                    vec![
+                        market,
+                        format!("{lowest:.2}%"),
+                        format_time(lowest_at),
+                        format!("{highest:.2}%"),
+                        format_time(highest_at),
+                    ]
+                })
+                .collect(),
+        });
+    }
+
+    Ok(Summary {
+        title: period.title().to_string(),
+        start,
+        end,
+        paragraphs,
+        tables,
+    })
+}
+
+fn render_console(summary: &Summary) -> String {
+    let mut out = format!(
+        "{}\n{} to {} UTC\n",
+        summary.title,
+        format_time(summary.start),
+        format_time(summary.end)
+    );
+    for paragraph in &summary.paragraphs {
+        out += &format!("\n{paragraph}\n");
+    }
+    for section in &summary.tables {
+        let headers: Vec<&str> = section.headers.iter().map(String::as_str).collect();
+        out += &format!(
+            "\n{}\n{}\n",
+            section.title,
+            table::render(&headers, section.rows.clone())
+        );
+    }
+    out
+}
+
+fn render_markdown(summary: &Summary) -> String {
+    let mut out = format!(
+        "# {}\n\n{} to {} UTC\n",
+        summary.title,
+        format_time(summary.start),
+        format_time(summary.end)
+    );
+    for paragraph in &summary.paragraphs {
+        out += &format!("\n{paragraph}\n");
+    }
+    for section in &summary.tables {
+        out += &format!("\n## {}\n\n", section.title);
+        out += &format!("| {} |\n", section.headers.join(" | "));
+        out += &format!("|{}\n", "---|".repeat(section.headers.len()));
+        for row in &section.rows {
+            out += &format!("| {} |\n", row.join(" | "));
+        }
+    }
+    out
+}
+
+fn render_html(summary: &Summary) -> String {
+    let mut out = format!(
+        "<!DOCTYPE html>\n<html>\n<head><meta charset=\"utf-8\"><title>{title}</title></head>\n<body>\n<h1>{title}</h1>\n<p>{} to {} UTC</p>\n",
+        format_time(summary.start),
+        format_time(summary.end),
+        title = escape_html(&summary.title),
+    );
+    for paragraph in &summary.paragraphs {
+        out += &format!("<p>{}</p>\n", escape_html(paragraph));
+    }
+    for section in &summary.tables {
+        out += &format!("<h2>{}</h2>\n<table>\n<tr>", escape_html(&section.title));
+        for header in &section.headers {
+            out += &format!("<th>{}</th>", escape_html(header));
+        }
+        out += "</tr>\n";
+        for row in &section.rows {
+            out += "<tr>";
+            for cell in row {
+                out += &format!("<td>{}</td>", escape_html(cell));
+            }
+            out += "</tr>\n";
+        }
+        out += "</table>\n";
+    }
+    out += "</body>\n</html>\n";
+    out
+}
+
+/// The summary of the `period` of `history` ending at `end`, by default its latest
+/// snapshot, rendered in `format`
+pub fn render(
+    history: &str,
+    period: Period,
+    end: Option<u64>,
+    format: ReportFormat,
+) -> Result<String, Box<dyn std::error::Error>> {
+    let summary = build(history, period, end)?;
+    Ok(match format {
+        ReportFormat::Console => render_console(&summary),
+        ReportFormat::Markdown => render_markdown(&summary),
+        ReportFormat::Html => render_html(&summary),
+    })
+}
+
+/// Prints the summary of the `period` of `history` ending at `end`
+pub fn run(
+    history: &str,
+    period: Period,
+    end: Option<u64>,
+    format: ReportFormat,
+) -> Result<(), Box<dyn std::error::Error>> {
+    print!("{}", render(history, period, end, format)?);
+    Ok(())
+}
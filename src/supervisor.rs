@@ -0,0 +1,76 @@
+//! Cooperation with process supervisors: systemd readiness and watchdog notifications, and
+//! stopping cleanly between snapshots on SIGTERM/SIGINT.
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
+use std::time::{Duration, Instant};
+
+use sd_notify::NotifyState;
+
+/// Longest stretch slept without checking for a shutdown request
+const SLEEP_STEP: Duration = Duration::from_millis(250);
+
+pub struct Supervisor {
+    shutdown: Arc<AtomicBool>,
+    /// Half of systemd's WatchdogSec, when enabled
+    watchdog_interval: Option<Duration>,
+    last_ping: Instant,
+}
+
+impl Supervisor {
+    /// Registers the shutdown signal handlers
+    pub fn install() -> Result<Self, Box<dyn std::error::Error>> {
+        let shutdown = Arc::new(AtomicBool::new(false));
+        for signal in [signal_hook::consts::SIGTERM, signal_hook::consts::SIGINT] {
+            signal_hook::flag::register(signal, Arc::clone(&shutdown))?;
+        }
+        let mut watchdog_usec = 0;
+        let watchdog_interval = sd_notify::watchdog_enabled(false, &mut watchdog_usec)
+            .then(|| Duration::from_micros(watchdog_usec) / 2);
+        Ok(Supervisor {
+            shutdown,
+            watchdog_interval,
+            last_ping: Instant::now(),
+        })
+    }
+
+    /// Tells systemd the service is up, for `Type=notify` units
+    pub fn ready(&mut self) {
+        let _ = sd_notify::notify(false, &[NotifyState::Ready]);
+        self.ping();
+    }
+
+    pub fn ping(&mut self) {
+        if self.watchdog_interval.is_some() {
+            let _ = sd_notify::notify(false, &[NotifyState::Watchdog]);
+        }
+        self.last_ping = Instant::now();
+    }
+
+    pub fn should_stop(&self) -> bool {
+        self.shutdown.load(Ordering::Relaxed)
+    }
+
+    /// Sleeps for `duration`, returning early on a shutdown request and keeping the
+    /// watchdog fed meanwhile
+    pub fn sleep(&mut self, duration: Duration) {
+        let deadline = Instant::now() + duration;
+        while !self.should_stop() {
+            let now = Instant::now();
+            if now >= deadline {
+                break;
+            }
+            if let Some(watchdog_interval) = self.watchdog_interval {
+                if now.duration_since(self.last_ping) >= watchdog_interval {
+                    self.ping();
+                }
+            }
+            thread::sleep(SLEEP_STEP.min(deadline - now));
+        }
+    }
+
+    pub fn stopping(&self) {
+        let _ = sd_notify::notify(false, &[NotifyState::Stopping]);
+    }
+}
@@ -0,0 +1,96 @@
+//! Tables for console output, fitted to the terminal: pubkeys are shortened when a table
+//! wouldn't fit otherwise, unless `--wide` is set, and remaining overflow is wrapped.
+
+use std::str::FromStr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+use comfy_table::{presets, CellAlignment, ContentArrangement, Table};
+use solana_sdk::pubkey::Pubkey;
+
+static WIDE: AtomicBool = AtomicBool::new(false);
+
+/// Characters kept at each end of a shortened pubkey
+const PUBKEY_ENDS: usize = 4;
+
+/// Decides once at startup whether pubkeys are always shown in full
+pub fn init(wide: bool) {
+    WIDE.store(wide, Ordering::Relaxed);
+}
+
+/// `pubkey` with only its first and last characters, e.g. `4mVA…RVQK`
+pub fn shorten(pubkey: &str) -> String {
+    let chars: Vec<char> = pubkey.chars().collect();
+    if chars.len() <= 2 * PUBKEY_ENDS + 1 {
+        return pubkey.to_string();
+    }
+    let start: String = chars[..PUBKEY_ENDS].iter().collect();
+    let end: String = chars[chars.len() - PUBKEY_ENDS..].iter().collect();
+    format!("{start}…{end}")
+}
+
+fn is_pubkey(cell: &str) -> bool {
+    cell.len() >= 32 && Pubkey::from_str(cell).is_ok()
+}
+
+/// Dollar amounts, percentages, counts and the like, which read best right-aligned
+fn is_numeric(cell: &str) -> bool {
+    cell.trim_start_matches(['+', '-', '$'])
+        .chars()
+        .next()
+        .is_some_and(|first| first.is_ascii_digit())
+}
+
+/// Width of the table with cells rendered in full, borders and padding included
+fn natural_width(headers: &[String], rows: &[Vec<String>]) -> usize {
+    let mut widths: Vec<usize> = headers
+        .iter()
+        .map(|header| header.chars().count())
+        .collect();
+    for row in rows {
+        for (width, cell) in widths.iter_mut().zip(row) {
+            *width = (*width).max(cell.chars().count());
+        }
+    }
+    widths.iter().map(|width| width + 3).sum::<usize>() + 1
+}
+
+/// Renders `rows` under `headers`, right-aligning columns that hold only numbers
+pub fn render(headers: &[&str], mut rows: Vec<Vec<String>>) -> String {
+    let headers: Vec<String> = headers.iter().map(|header| header.to_string()).collect();
+    let mut table = Table::new();
+    table
+        .load_preset(presets::UTF8_FULL_CONDENSED)
+        .set_content_arrangement(ContentArrangement::Dynamic);
+
+    let terminal_width = table.width().map(usize::from);
+    let too_wide = terminal_width.is_some_and(|width| natural_width(&headers, &rows) > width);
+    if too_wide && !WIDE.load(Ordering::Relaxed) {
+        for cell in rows.iter_mut().flatten() {
+            if is_pubkey(cell) {
+                *cell = shorten(cell);
+            }
+        }
+    }
+
+    let numeric_columns: Vec<usize> = (0..headers.len())
+        .filter(|column| {
+            let mut cells = rows
+                .iter()
+                .filter_map(|row| row.get(*column))
+                // placeholders for missing values don't decide alignment
+                .filter(|cell| !cell.is_empty() && *cell != "-")
+                .peekable();
+            cells.peek().is_some() && cells.all(|cell| is_numeric(cell))
+        })
+        .collect();
+    table.set_header(headers);
+    for row in rows {
+        table.add_row(row);
+    }
+    for column in numeric_columns {
+        if let Some(column) = table.column_mut(column) {
+            column.set_cell_alignment(CellAlignment::Right);
+        }
+    }
+    table.to_string()
+}
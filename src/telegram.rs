@@ -0,0 +1,137 @@
+//! Telegram bot answering `/stats`, `/rates` and `/whales` from the [`LiveView`] of the
+//! latest snapshot, in the chats allowed by the config. Updates are long-polled, so no
+//! public endpoint is needed.
+
+use std::collections::HashMap;
+use std::time::{Duration, Instant};
+
+use serde_json::{json, Value};
+
+use crate::config::TelegramConfig;
+use crate::live::LiveView;
+use crate::transport::blocking_client;
+
+/// How long a `getUpdates` call waits for new messages, under the client's request timeout
+const POLL_TIMEOUT_SECS: u64 = 20;
+const RETRY_DELAY: Duration = Duration::from_secs(10);
+
+const HELP: &str = "/stats: pool value, open interest and P&L\n/rates: borrow APR of each market\n/whales: the largest open positions";
+
+struct Bot {
+    config: TelegramConfig,
+    client: reqwest::blocking::Client,
+    view: LiveView,
+    /// When each chat was last answered
+    answered: HashMap<i64, Instant>,
+}
+
+impl Bot {
+    fn call(&self, method: &str, body: &Value) -> Result<Value, Box<dyn std::error::Error>> {
+        let response: Value = self
+            .client
+            .post(format!(
+                "https://api.telegram.org/bot{}/{method}",
+                self.config.token
+            ))
+            .json(body)
+            .send()?
+            .json()?;
+        if response["ok"] != true {
+            return Err(format!(
+                "Telegram {method} failed: {}",
+                response["description"].as_str().unwrap_or_default()
+            )
+            .into());
+        }
+        Ok(response["result"].clone())
+    }
+
+    /// The reply to `text` in `chat_id`, if it's a command the chat may have answered now
+    fn answer(&mut self, chat_id: i64, text: &str) -> Option<String> {
+        // `/stats@SomeBot` in groups with several bots
+        let command = text.split_whitespace().next()?.split('@').next()?;
+        let answer = match command {
+            "/stats" => self.view.stats(),
+            "/rates" => self.view.funding(),
+            "/whales" => self.view.whales(),
+            "/start" | "/help" => HELP.to_string(),
+            _ => return None,
+        };
+        if !self.config.chats.contains(&chat_id) {
+            return None;
+        }
+        let interval = Duration::from_secs(self.config.min_interval_secs);
+        // commands arriving sooner are dropped, so a busy group can't flood the chat
+        if let Some(answered) = self.answered.get(&chat_id) {
+            if answered.elapsed() < interval {
+                return None;
+            }
+        }
+        self.answered.insert(chat_id, Instant::now());
+        Some(answer)
+    }
+
+    fn run(&mut self) {
+        let mut offset = 0;
+        loop {
+            let updates = match self.call(
+                "getUpdates",
+                &json!({
+                    "offset": offset,
+                    "timeout": POLL_TIMEOUT_SECS,
+                    "allowed_updates": ["message"],
+                }),
+            ) {
+                Ok(updates) => updates,
+                Err(err) => {
+                    eprintln!("Telegram polling failed: {err}");
+                    std::thread::sleep(RETRY_DELAY);
+                    continue;
+                }
+            };
+            for update in updates.as_array().into_iter().flatten() {
+                if let Some(id) = update["update_id"].as_i64() {
+                    offset = offset.max(id + 1);
+                }
+                let message = &update["message"];
+                let (Some(chat_id), Some(text)) =
+                    (message["chat"]["id"].as_i64(), message["text"].as_str())
+                else {
+                    continue;
+                };
+                let Some(reply) = self.answer(chat_id, text) else {
+                    continue;
+                };
+                // a failed reply only loses that answer
+                if let Err(err) = self.call(
+                    "sendMessage",
+                    &json!({
+                        "chat_id": chat_id,
+                        "text": reply,
+                        "reply_to_message_id": message["message_id"],
+                    }),
+                ) {
+                    eprintln!("Telegram reply failed: {err}");
+                }
+            }
+        }
+    }
+}
+
+/// Answers commands from `view` on a background thread
+pub fn spawn(
+    config: &TelegramConfig,
+    proxy_url: Option<&str>,
+    view: LiveView,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let mut bot = Bot {
+        config: config.clone(),
+        client: blocking_client(proxy_url)?,
+        view,
+        answered: HashMap::new(),
+    };
+    // fails early on a bad token rather than in the background
+    bot.call("getMe", &json!({}))?;
+    std::thread::spawn(move || bot.run());
+    Ok(())
+}
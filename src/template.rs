@@ -0,0 +1,47 @@
+//! Renders snapshots through a user-supplied Tera template, for output shapes such as chat
+//! messages, status page fragments or monitoring plugin lines.
+
+use std::fs;
+
+use tera::{Context, Tera};
+
+use crate::snapshot::Snapshot;
+
+const TEMPLATE_NAME: &str = "snapshot";
+
+pub struct SnapshotTemplate {
+    tera: Tera,
+}
+
+impl SnapshotTemplate {
+    /// Reads and compiles the template at `path`, so syntax errors surface before the first
+    /// snapshot is taken
+    pub fn load(path: &str) -> Result<Self, Box<dyn std::error::Error>> {
+        let source =
+            fs::read_to_string(path).map_err(|err| format!("can't read template {path}: {err}"))?;
+        let mut tera = Tera::default();
+        tera.autoescape_on(vec![]);
+        tera.add_raw_template(TEMPLATE_NAME, &source)
+            .map_err(|err| format!("invalid template {path}: {err:?}"))?;
+        Ok(SnapshotTemplate { tera })
+    }
+
+    /// Renders with every snapshot field in scope, plus the derived `num_shorts`,
+    /// `cumulative_short`, `average_leverage_at_entry` and `average_effective_leverage`
+    pub fn render(&self, snapshot: &Snapshot) -> Result<String, Box<dyn std::error::Error>> {
+        let mut context = Context::from_serialize(snapshot)?;
+        context.insert("num_shorts", &snapshot.num_shorts());
+        context.insert("cumulative_short", &snapshot.cumulative_short());
+        context.insert(
+            "average_leverage_at_entry",
+            &snapshot.average_leverage_at_entry(),
+        );
+        context.insert(
+            "average_effective_leverage",
+            &snapshot.average_effective_leverage(),
+        );
+        self.tera
+            .render(TEMPLATE_NAME, &context)
+            .map_err(|err| format!("rendering template failed: {err:?}").into())
+    }
+}
@@ -0,0 +1,228 @@
+//! The largest open positions by notional with their owners, which can be replaced by
+//! salted hashes for publishing: the same owner and salt always give the same hash, so
+//! entities can be followed across reports without naming their wallets. Published lists
+//! round amounts and leave out entry prices and open times, which would otherwise match
+//! each row to its position account on chain.
+
+use std::ops::{Div as _, Mul as _};
+
+use hmac::{Hmac, Mac as _};
+use sha1::Sha1;
+use solana_client::rpc_client::RpcClient;
+use thousands::Separable;
+
+use crate::analyze::format_time;
+use crate::config::Config;
+use crate::snapshot::position_records;
+use crate::state::{load_open_positions, PerpState};
+use crate::store::PositionRecord;
+use crate::table;
+
+/// Hex digits of the hash kept, enough to tell owners apart
+const HASH_LEN: usize = 16;
+/// Environment variable the salt is read from, ahead of the config file
+const SALT_VAR: &str = "PERPS_ANONYMIZE_SALT";
+
+/// The secret owner hashes are salted with, kept off the command line where process
+/// listings and shell history would show it
+pub fn salt(config: &Config) -> Result<String, Box<dyn std::error::Error>> {
+    std::env::var(SALT_VAR)
+        .ok()
+        .or_else(|| config.anonymize_salt.clone())
+        .filter(|salt| !salt.is_empty())
+        .ok_or_else(|| {
+            format!("--anonymize needs a salt in {SALT_VAR} or `anonymize_salt` in the config")
+                .into()
+        })
+}
+
+/// The HMAC of `owner` keyed by `salt`, shortened
+fn anonymize(owner: &str, salt: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let mut mac = Hmac::<Sha1>::new_from_slice(salt.as_bytes())?;
+    mac.update(owner.as_bytes());
+    let hash: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{byte:02x}"))
+        .collect();
+    Ok(hash[..HASH_LEN].to_string())
+}
+
+/// Significant digits amounts are rounded to when anonymized, so the exact figures can't
+/// be matched against the position accounts on chain
+const ANONYMIZED_DIGITS: i32 = 2;
+
+fn round_significant(value: f64) -> f64 {
+    if value == 0.0 || !value.is_finite() {
+        return value;
+    }
+    let scale = 10f64.powi(value.abs().log10().floor() as i32 + 1 - ANONYMIZED_DIGITS);
+    value.div(scale).round().mul(scale)
+}
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+/// A listed position. Anonymized, the owner is hashed, amounts are rounded and what would
+/// identify the position on chain is left out.
+struct Listed {
+    position: Option<String>,
+    owner: String,
+    market: String,
+    side: String,
+    size_usd: f64,
+    collateral_usd: f64,
+    leverage: f64,
+    entry_price: Option<f64>,
+    price: f64,
+    unrealized_pnl: f64,
+    open_time: Option<i64>,
+}
+
+impl Listed {
+    fn new(
+        record: &PositionRecord,
+        salt: Option<&str>,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let leverage = record.effective_leverage;
+        let listed = Listed {
+            position: Some(record.position.clone()),
+            owner: record.owner.clone(),
+            market: record.market.clone(),
+            side: record.side.clone(),
+            size_usd: record.size_usd,
+            collateral_usd: record.collateral_usd,
+            leverage,
+            entry_price: Some(record.entry_price),
+            price: record.price,
+            unrealized_pnl: record.unrealized_pnl(),
+            open_time: Some(record.open_time),
+        };
+        let Some(salt) = salt else {
+            return Ok(listed);
+        };
+        Ok(Listed {
+            // the position account is derived from the owner, so it would give them away
+            position: None,
+            owner: anonymize(&record.owner, salt)?,
+            size_usd: round_significant(listed.size_usd),
+            collateral_usd: round_significant(listed.collateral_usd),
+            leverage: leverage.mul(10.0).round().div(10.0),
+            entry_price: None,
+            unrealized_pnl: round_significant(listed.unrealized_pnl),
+            open_time: None,
+            ..listed
+        })
+    }
+}
+
+/// Writes the positions as CSV with raw numbers
+fn write_csv(positions: &[Listed], anonymized: bool) -> Result<(), Box<dyn std::error::Error>> {
+    let mut writer = csv::Writer::from_writer(std::io::stdout());
+    let mut header = vec![];
+    if !anonymized {
+        header.push("position");
+    }
+    header.extend([
+        "owner",
+        "market",
+        "side",
+        "size_usd",
+        "collateral_usd",
+        "leverage",
+    ]);
+    if !anonymized {
+        header.push("entry_price");
+    }
+    header.extend(["price", "unrealized_pnl"]);
+    if !anonymized {
+        header.push("open_time");
+    }
+    writer.write_record(header)?;
+    for position in positions {
+        let mut record = vec![];
+        record.extend(position.position.clone());
+        record.extend([
+            position.owner.clone(),
+            position.market.clone(),
+            position.side.clone(),
+            position.size_usd.to_string(),
+            position.collateral_usd.to_string(),
+            position.leverage.to_string(),
+        ]);
+        record.extend(position.entry_price.map(|price| price.to_string()));
+        record.extend([
+            position.price.to_string(),
+            position.unrealized_pnl.to_string(),
+        ]);
+        record.extend(position.open_time.map(|time| time.to_string()));
+        writer.write_record(record)?;
+    }
+    writer.flush()?;
+    Ok(())
+}
+
+/// Prints the `limit` largest open positions by size. With `salt`, owners are replaced by
+/// their salted hashes, amounts are rounded, and position accounts, entry prices and open
+/// times are left out. With `csv`, they're written as CSV.
+pub fn run(
+    connection: &RpcClient,
+    limit: usize,
+    salt: Option<&str>,
+    csv: bool,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let state = PerpState::load(connection)?;
+    let positions = load_open_positions(connection)?;
+    let mut positions = position_records(&state, &positions);
+    positions.sort_by(|a, b| b.size_usd.total_cmp(&a.size_usd));
+    positions.truncate(limit);
+    let positions = positions
+        .iter()
+        .map(|position| Listed::new(position, salt))
+        .collect::<Result<Vec<_>, _>>()?;
+    if csv {
+        return write_csv(&positions, salt.is_some());
+    }
+
+    println!("Unix time: {}", state.unix_time);
+    let rows = positions
+        .iter()
+        .enumerate()
+        .map(|(i, position)| {
+            let mut row = vec![(i + 1).to_string()];
+            row.extend(position.position.clone());
+            row.extend([
+                position.owner.clone(),
+                position.market.clone(),
+                position.side.clone(),
+                usd(position.size_usd),
+                usd(position.collateral_usd),
+                format!("{:.2}x", position.leverage),
+            ]);
+            row.extend(position.entry_price.map(|price| format!("${price:.4}")));
+            row.push(usd(position.unrealized_pnl));
+            row.extend(
+                position
+                    .open_time
+                    .map(|open_time| format_time(open_time.max(0) as u64)),
+            );
+            row
+        })
+        .collect();
+    let mut headers = vec!["#"];
+    if salt.is_none() {
+        headers.push("Position");
+    }
+    headers.extend(["Owner", "Market", "Side", "Size", "Collateral", "Leverage"]);
+    if salt.is_none() {
+        headers.push("Entry");
+    }
+    headers.push("P&L");
+    if salt.is_none() {
+        headers.push("Opened");
+    }
+    println!("{}", table::render(&headers, rows));
+    Ok(())
+}
@@ -0,0 +1,282 @@
+//! HTTP transport for RPC calls and outbound HTTP sinks, optionally routed through an
+//! egress proxy and carrying extra headers. RPC requests in flight are capped by a limit
+//! that adapts to how the endpoint copes, so concurrent callers go fast on paid endpoints
+//! and back off on public ones.
+
+use std::collections::BTreeMap;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Mutex, RwLock};
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use solana_client::client_error::Result as ClientResult;
+use solana_client::rpc_client::{RpcClient, RpcClientConfig};
+use solana_client::rpc_request::{RpcError, RpcRequest, RpcResponseErrorData};
+use solana_client::rpc_sender::{RpcSender, RpcTransportStats};
+use solana_sdk::commitment_config::CommitmentConfig;
+use tokio::sync::{Semaphore, SemaphorePermit};
+
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(30);
+const TOO_MANY_REQUESTS_RETRIES: usize = 5;
+/// RPC requests in flight to start with, and at most
+const INITIAL_CONCURRENCY: usize = 4;
+const MAX_CONCURRENCY: usize = 32;
+/// Responses slower than this are taken as the endpoint struggling
+const SLOW_RESPONSE: Duration = Duration::from_secs(2);
+
+/// Parses an `http://`, `https://`, `socks5://` or `socks5h://` proxy URL
+fn parse_proxy(proxy_url: &str) -> Result<reqwest::Proxy, Box<dyn std::error::Error>> {
+    let scheme = proxy_url.split("://").next().unwrap_or_default();
+    if !["http", "https", "socks5", "socks5h"].contains(&scheme) {
+        return Err(format!(
+            "unsupported proxy {proxy_url}: expected an http://, https:// or socks5:// URL"
+        )
+        .into());
+    }
+    Ok(reqwest::Proxy::all(proxy_url)?)
+}
+
+/// Client for HTTP sinks such as the Pushgateway
+pub fn blocking_client(
+    proxy_url: Option<&str>,
+) -> Result<reqwest::blocking::Client, Box<dyn std::error::Error>> {
+    blocking_client_with_headers(proxy_url, &BTreeMap::new())
+}
+
+/// Client for HTTP sinks that sends `headers` with every request, e.g. for authentication
+pub fn blocking_client_with_headers(
+    proxy_url: Option<&str>,
+    headers: &BTreeMap<String, String>,
+) -> Result<reqwest::blocking::Client, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::blocking::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .default_headers(header_map(headers)?);
+    if let Some(proxy_url) = proxy_url {
+        builder = builder.proxy(parse_proxy(proxy_url)?);
+    }
+    Ok(builder.build()?)
+}
+
+/// Parses configured headers, rejecting names or values HTTP doesn't allow
+pub fn header_map(
+    headers: &BTreeMap<String, String>,
+) -> Result<reqwest::header::HeaderMap, Box<dyn std::error::Error>> {
+    let mut header_map = reqwest::header::HeaderMap::new();
+    for (name, value) in headers {
+        header_map.insert(
+            reqwest::header::HeaderName::from_bytes(name.as_bytes())
+                .map_err(|err| format!("invalid header name {name:?}: {err}"))?,
+            reqwest::header::HeaderValue::from_str(value)
+                .map_err(|err| format!("invalid value for header {name}: {err}"))?,
+        );
+    }
+    Ok(header_map)
+}
+
+/// RPC client for `rpc_url`, with an adaptive limit on requests in flight. Without a proxy
+/// it still honors the `HTTPS_PROXY`/`ALL_PROXY` environment variables.
+pub fn rpc_client(
+    rpc_url: String,
+    proxy_url: Option<&str>,
+    headers: &BTreeMap<String, String>,
+) -> Result<RpcClient, Box<dyn std::error::Error>> {
+    rpc_client_with_commitment(rpc_url, proxy_url, headers, CommitmentConfig::default())
+}
+
+/// Like [`rpc_client`], reading state at `commitment` instead of the default `finalized`
+pub fn rpc_client_with_commitment(
+    rpc_url: String,
+    proxy_url: Option<&str>,
+    headers: &BTreeMap<String, String>,
+    commitment: CommitmentConfig,
+) -> Result<RpcClient, Box<dyn std::error::Error>> {
+    let mut builder = reqwest::Client::builder()
+        .timeout(REQUEST_TIMEOUT)
+        .pool_idle_timeout(REQUEST_TIMEOUT)
+        .default_headers(header_map(headers)?);
+    if let Some(proxy_url) = proxy_url {
+        builder = builder.proxy(parse_proxy(proxy_url)?);
+    }
+    let client = builder.build()?;
+    Ok(RpcClient::new_sender(
+        ClientSender::new(rpc_url, client),
+        RpcClientConfig::with_commitment(commitment),
+    ))
+}
+
+/// Additive-increase/multiplicative-decrease limit on requests in flight: one more after as
+/// many fast responses as the limit, half as many after a 429, a timeout or a slow response
+struct ConcurrencyLimit {
+    semaphore: Semaphore,
+    state: Mutex<LimitState>,
+}
+
+struct LimitState {
+    limit: usize,
+    /// Fast responses since the limit last changed
+    successes: usize,
+    /// Permits to retire as they're released, since the limit shrank below those handed out
+    excess: usize,
+}
+
+impl ConcurrencyLimit {
+    fn new() -> Self {
+        ConcurrencyLimit {
+            semaphore: Semaphore::new(INITIAL_CONCURRENCY),
+            state: Mutex::new(LimitState {
+                limit: INITIAL_CONCURRENCY,
+                successes: 0,
+                excess: 0,
+            }),
+        }
+    }
+
+    async fn acquire(&self) -> ClientResult<SemaphorePermit<'_>> {
+        Ok(self
+            .semaphore
+            .acquire()
+            .await
+            .map_err(|err| RpcError::RpcRequestError(err.to_string()))?)
+    }
+
+    /// Adjusts the limit by how the request went, then returns or retires its permit
+    fn release(&self, permit: SemaphorePermit<'_>, congested: bool) {
+        let mut state = self.state.lock().unwrap();
+        if congested {
+            let limit = (state.limit / 2).max(1);
+            state.excess += state.limit - limit;
+            state.limit = limit;
+            state.successes = 0;
+        } else {
+            state.successes += 1;
+            if state.successes >= state.limit && state.limit < MAX_CONCURRENCY {
+                state.limit += 1;
+                state.successes = 0;
+                if state.excess > 0 {
+                    state.excess -= 1;
+                } else {
+                    self.semaphore.add_permits(1);
+                }
+            }
+        }
+        if state.excess > 0 {
+            state.excess -= 1;
+            permit.forget();
+        }
+    }
+}
+
+/// JSON-RPC over a caller-configured reqwest client, which the stock HTTP sender doesn't
+/// accept
+struct ClientSender {
+    client: reqwest::Client,
+    url: String,
+    request_id: AtomicU64,
+    stats: RwLock<RpcTransportStats>,
+    concurrency: ConcurrencyLimit,
+}
+
+impl ClientSender {
+    fn new(url: String, client: reqwest::Client) -> Self {
+        ClientSender {
+            client,
+            url,
+            request_id: AtomicU64::new(0),
+            stats: RwLock::new(RpcTransportStats::default()),
+            concurrency: ConcurrencyLimit::new(),
+        }
+    }
+
+    fn record(&self, started: Instant, rate_limited_time: Duration) {
+        let mut stats = self.stats.write().unwrap();
+        stats.request_count += 1;
+        stats.elapsed_time += started.elapsed();
+        stats.rate_limited_time += rate_limited_time;
+    }
+
+    async fn send_with_retries(
+        &self,
+        request_json: String,
+        rate_limited_time: &mut Duration,
+    ) -> ClientResult<serde_json::Value> {
+        let mut retries = TOO_MANY_REQUESTS_RETRIES;
+        loop {
+            let permit = self.concurrency.acquire().await?;
+            let started = Instant::now();
+            let response = self
+                .client
+                .post(&self.url)
+                .header(reqwest::header::CONTENT_TYPE, "application/json")
+                .body(request_json.clone())
+                .send()
+                .await;
+            let congested = response.as_ref().map_or(true, |response| {
+                response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS
+            }) || started.elapsed() > SLOW_RESPONSE;
+            self.concurrency.release(permit, congested);
+            let response = response?;
+
+            if response.status() == reqwest::StatusCode::TOO_MANY_REQUESTS && retries > 0 {
+                // same back-off as the stock sender: Retry-After if sane, else half a second
+                let pause = response
+                    .headers()
+                    .get(reqwest::header::RETRY_AFTER)
+                    .and_then(|retry_after| retry_after.to_str().ok()?.parse::<u64>().ok())
+                    .filter(|secs| *secs < 120)
+                    .map_or(Duration::from_millis(500), Duration::from_secs);
+                retries -= 1;
+                tokio::time::sleep(pause).await;
+                *rate_limited_time += pause;
+                continue;
+            }
+
+            let mut json = response
+                .error_for_status()?
+                .json::<serde_json::Value>()
+                .await?;
+            if json["error"].is_object() {
+                let error = &json["error"];
+                return Err(match (error["code"].as_i64(), error["message"].as_str()) {
+                    (Some(code), Some(message)) => RpcError::RpcResponseError {
+                        code,
+                        message: message.to_string(),
+                        data: RpcResponseErrorData::Empty,
+                    },
+                    _ => RpcError::RpcRequestError(format!(
+                        "Failed to deserialize RPC error response: {error}"
+                    )),
+                }
+                .into());
+            }
+            return Ok(json["result"].take());
+        }
+    }
+}
+
+#[async_trait]
+impl RpcSender for ClientSender {
+    async fn send(
+        &self,
+        request: RpcRequest,
+        params: serde_json::Value,
+    ) -> ClientResult<serde_json::Value> {
+        let started = Instant::now();
+        let mut rate_limited_time = Duration::default();
+        let request_id = self.request_id.fetch_add(1, Ordering::Relaxed);
+        let request_json = request.build_request_json(request_id, params).to_string();
+
+        let result = self
+            .send_with_retries(request_json, &mut rate_limited_time)
+            .await;
+        self.record(started, rate_limited_time);
+        result
+    }
+
+    fn get_transport_stats(&self) -> RpcTransportStats {
+        self.stats.read().unwrap().clone()
+    }
+
+    fn url(&self) -> String {
+        self.url.clone()
+    }
+}
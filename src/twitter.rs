@@ -0,0 +1,213 @@
+//! Daily stats thread for X: pool value, open interest, 24h volume and borrow rate
+//! extremes from the stored history, posted through the X API v2 with the OAuth 1.0a keys
+//! of the posting account.
+
+use std::ops::{Div as _, Mul as _};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use base64::Engine as _;
+use hmac::{Hmac, Mac as _};
+use serde_json::json;
+use sha1::Sha1;
+use thousands::Separable;
+
+use crate::analyze::format_time;
+use crate::config::XConfig;
+use crate::fees::volume_by_window;
+use crate::store::{
+    child_path, read_existing_records, read_records, CustodyRecord, PositionRecord, Record as _,
+    SnapshotRecord,
+};
+use crate::summary::borrow_extremes;
+use crate::transport::blocking_client;
+
+const TWEETS_URL: &str = "https://api.twitter.com/2/tweets";
+/// Longest post the API accepts from accounts without a subscription
+const MAX_TWEET_CHARS: usize = 280;
+
+/// `$1.2B`, `$45.3M`, `$812K`
+fn compact_usd(value: f64) -> String {
+    let magnitude = value.abs();
+    if magnitude >= 1e9 {
+        format!("${:.2}B", value.div(1e9))
+    } else if magnitude >= 1e6 {
+        format!("${:.1}M", value.div(1e6))
+    } else if magnitude >= 1e3 {
+        format!("${:.0}K", value.div(1e3))
+    } else {
+        format!("${}", value.round().separate_with_commas())
+    }
+}
+
+/// ` (+1.2%)` against `change` over the last 24 hours, empty if unknown
+fn change_24h(value: f64, change: Option<f64>) -> String {
+    change
+        .filter(|change| value != *change)
+        .map_or(String::new(), |change| {
+            format!(" ({:+.1}%)", change.div(value - change).mul(100.0))
+        })
+}
+
+/// The posts of the thread for the day ending at the latest snapshot in `history`
+pub fn thread(history: &str) -> Result<Vec<String>, Box<dyn std::error::Error>> {
+    let mut snapshots = read_records::<SnapshotRecord>(history)?;
+    snapshots.sort_by_key(|record| record.unix_time());
+    let latest = snapshots
+        .last()
+        .ok_or_else(|| format!("no snapshots in {history}, take snapshots with -c {history}"))?;
+    let end = latest.unix_time;
+    let start = end.saturating_sub(86_400);
+
+    let mut head = format!(
+        "Jupiter Perps daily stats, {} UTC\n\nPool value: {}{}\nOpen interest: {}{}\nLongs {} / shorts {}",
+        format_time(end),
+        compact_usd(latest.total_pool_value),
+        change_24h(latest.total_pool_value, latest.total_pool_value_change_24h),
+        compact_usd(latest.total_position_value),
+        change_24h(
+            latest.total_position_value,
+            latest.total_position_value_change_24h
+        ),
+        compact_usd(latest.long_value),
+        compact_usd(latest.short_value),
+    );
+    let mut positions =
+        read_existing_records::<PositionRecord>(&child_path(history, PositionRecord::TABLE))?;
+    positions.retain(|record| record.unix_time >= start);
+    if !positions.is_empty() {
+        let volume = volume_by_window(positions, end, &[24]);
+        head += &format!("\n24h volume: {}", compact_usd(volume[0]));
+    }
+    let mut thread = vec![head];
+
+    let mut custodies =
+        read_existing_records::<CustodyRecord>(&child_path(history, CustodyRecord::TABLE))?;
+    custodies.retain(|record| record.unix_time >= start);
+    let extremes = borrow_extremes(&custodies);
+    if !extremes.is_empty() {
+        let mut post = "Borrow APR, 24h low / high:".to_string();
+        for (market, ((lowest, _), (highest, _))) in extremes {
+            let line = format!("\n{market} {lowest:.1}% / {highest:.1}%");
+            // continue in another post rather than get rejected
+            if post.chars().count() + line.chars().count() > MAX_TWEET_CHARS {
+                thread.push(std::mem::replace(
+                    &mut post,
+                    "Borrow APR, continued:".to_string(),
+                ));
+            }
+            post += &line;
+        }
+        thread.push(post);
+    }
+    Ok(thread)
+}
+
+/// RFC 3986 percent-encoding, as OAuth 1.0a signs with
+fn percent_encode(value: &str) -> String {
+    value
+        .bytes()
+        .map(|byte| match byte {
+            b'A'..=b'Z' | b'a'..=b'z' | b'0'..=b'9' | b'-' | b'.' | b'_' | b'~' => {
+                (byte as char).to_string()
+            }
+            _ => format!("%{byte:02X}"),
+        })
+        .collect()
+}
+
+/// `Authorization` header of a JSON `POST` to `url`, whose body isn't signed
+fn authorization(credentials: &XConfig, url: &str) -> Result<String, Box<dyn std::error::Error>> {
+    let now = SystemTime::now().duration_since(UNIX_EPOCH)?;
+    let nonce = format!("{:x}{:x}", now.as_nanos(), std::process::id());
+    let timestamp = now.as_secs().to_string();
+    // sorted by name, as the signature base requires
+    let params = [
+        ("oauth_consumer_key", credentials.api_key.as_str()),
+        ("oauth_nonce", nonce.as_str()),
+        ("oauth_signature_method", "HMAC-SHA1"),
+        ("oauth_timestamp", timestamp.as_str()),
+        ("oauth_token", credentials.access_token.as_str()),
+        ("oauth_version", "1.0"),
+    ];
+    let param_string = params
+        .iter()
+        .map(|(name, value)| format!("{name}={}", percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join("&");
+    let base = format!(
+        "POST&{}&{}",
+        percent_encode(url),
+        percent_encode(&param_string)
+    );
+    let key = format!(
+        "{}&{}",
+        percent_encode(&credentials.api_secret),
+        percent_encode(&credentials.access_token_secret)
+    );
+    let mut mac = Hmac::<Sha1>::new_from_slice(key.as_bytes())?;
+    mac.update(base.as_bytes());
+    let signature = base64::prelude::BASE64_STANDARD.encode(mac.finalize().into_bytes());
+    let header = params
+        .iter()
+        .map(|(name, value)| (*name, *value))
+        .chain([("oauth_signature", signature.as_str())])
+        .map(|(name, value)| format!("{name}=\"{}\"", percent_encode(value)))
+        .collect::<Vec<_>>()
+        .join(", ");
+    Ok(format!("OAuth {header}"))
+}
+
+/// Posts `thread`, each post replying to the one before, and returns the first post's id
+pub fn post(
+    credentials: &XConfig,
+    proxy_url: Option<&str>,
+    thread: &[String],
+) -> Result<Option<String>, Box<dyn std::error::Error>> {
+    let client = blocking_client(proxy_url)?;
+    let mut first_id = None;
+    let mut previous_id: Option<String> = None;
+    for text in thread {
+        let mut body = json!({ "text": text });
+        if let Some(previous_id) = &previous_id {
+            body["reply"] = json!({ "in_reply_to_tweet_id": previous_id });
+        }
+        let response = client
+            .post(TWEETS_URL)
+            .header("Authorization", authorization(credentials, TWEETS_URL)?)
+            .json(&body)
+            .send()?;
+        if !response.status().is_success() {
+            return Err(format!(
+                "X API responded {}: {}",
+                response.status(),
+                response.text().unwrap_or_default()
+            )
+            .into());
+        }
+        let response: serde_json::Value = response.json()?;
+        let id = response["data"]["id"]
+            .as_str()
+            .ok_or("X API response has no post id")?
+            .to_string();
+        first_id.get_or_insert_with(|| id.clone());
+        previous_id = Some(id);
+    }
+    Ok(first_id)
+}
+
+/// Prints the thread for `history`, or posts it if `credentials` are given
+pub fn run(
+    history: &str,
+    credentials: Option<&XConfig>,
+    proxy_url: Option<&str>,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let thread = thread(history)?;
+    let Some(credentials) = credentials else {
+        println!("{}", thread.join("\n\n---\n\n"));
+        return Ok(());
+    };
+    if let Some(id) = post(credentials, proxy_url, &thread)? {
+        println!("Posted https://x.com/i/status/{id}");
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+//! In-memory view of the perpetuals program accounts, kept current by applying account
+//! updates from a streaming data source instead of refetching everything each cycle.
+
+use std::collections::HashMap;
+use std::time::SystemTime;
+
+use anchor_lang::Discriminator as _;
+use solana_client::rpc_client::RpcClient;
+use solana_sdk::pubkey::Pubkey;
+
+use crate::idl;
+use crate::metadata;
+use crate::prices::PriceProviders;
+use crate::pyth;
+use crate::rpc::{get_accounts_data, get_program_accounts_with_discrim, program_id, OraclePrice};
+use crate::state::{load_open_positions, PerpState};
+
+#[derive(Clone, Default)]
+pub struct AccountView {
+    pub pool: Option<(Pubkey, perp_abi::state::Pool)>,
+    pub custodies: HashMap<Pubkey, perp_abi::state::Custody>,
+    /// Data of the accounts the price providers read, such as oracles
+    pub price_accounts: HashMap<Pubkey, Vec<u8>>,
+    pub positions: HashMap<Pubkey, perp_abi::state::Position>,
+    pub slot: u64,
+}
+
+impl AccountView {
+    /// Seeds the view with a full fetch over RPC, including the accounts `providers` read
+    pub fn load(
+        connection: &RpcClient,
+        providers: &PriceProviders,
+    ) -> Result<Self, Box<dyn std::error::Error>> {
+        let mut view = AccountView {
+            slot: connection.get_slot()?,
+            ..Default::default()
+        };
+        for discrim in [
+            perp_abi::state::Pool::DISCRIMINATOR,
+            perp_abi::state::Custody::DISCRIMINATOR,
+        ] {
+            for (pubkey, account) in
+                get_program_accounts_with_discrim(connection, &program_id().to_string(), &discrim)?
+            {
+                view.apply_program_account(pubkey, &account.data)?;
+            }
+        }
+        let custodies = view.custody_list();
+        // markets stay labeled by mint if neither their product nor their metadata can be read
+        let _ = pyth::resolve_symbols(connection, &custodies);
+        let _ = metadata::resolve_names(connection, &custodies);
+        view.price_accounts = get_accounts_data(connection, &providers.accounts(&custodies))?
+            .into_iter()
+            .collect();
+        view.positions = load_open_positions(connection)?.into_iter().collect();
+        Ok(view)
+    }
+
+    /// Applies an update to an account owned by the perpetuals program
+    pub fn apply_program_account(
+        &mut self,
+        pubkey: Pubkey,
+        data: &[u8],
+    ) -> Result<(), Box<dyn std::error::Error>> {
+        // closed accounts have no data left
+        if data.len() < 8 {
+            self.positions.remove(&pubkey);
+            return Ok(());
+        }
+        let discrim = &data[..8];
+        if discrim == perp_abi::state::Position::DISCRIMINATOR {
+            let position = idl::decode_position(data)?;
+            if position.size_usd == 0 {
+                self.positions.remove(&pubkey);
+            } else {
+                self.positions.insert(pubkey, position);
+            }
+        } else if discrim == perp_abi::state::Custody::DISCRIMINATOR {
+            let custody = idl::decode_custody(data)?;
+            self.custodies.insert(pubkey, custody);
+        } else if discrim == perp_abi::state::Pool::DISCRIMINATOR {
+            let pool = idl::decode_pool(data)?;
+            self.pool = Some((pubkey, pool));
+        }
+        Ok(())
+    }
+
+    /// Applies an update to one of the accounts the price providers read
+    pub fn apply_price_account(&mut self, pubkey: Pubkey, data: &[u8]) {
+        self.price_accounts.insert(pubkey, data.to_vec());
+    }
+
+    /// Custodies sorted by pubkey
+    pub fn custody_list(&self) -> Vec<(Pubkey, perp_abi::state::Custody)> {
+        let mut custodies: Vec<_> = self
+            .custodies
+            .iter()
+            .map(|(pubkey, custody)| (*pubkey, *custody))
+            .collect();
+        custodies.sort_by_key(|(pubkey, _)| *pubkey);
+        custodies
+    }
+
+    /// Pool, custody and price state as currently known, priced by `providers`
+    pub fn state(
+        &self,
+        providers: &PriceProviders,
+    ) -> Result<PerpState, Box<dyn std::error::Error>> {
+        let (pool_pubkey, pool) = self.pool.clone().ok_or("no pool account received yet")?;
+        let custodies = self.custody_list();
+        let oracle_prices = providers.prices_from_accounts(&custodies, &self.price_accounts)?;
+        // a price not streamed yet counts as never published, so a fallback price can stand in
+        let custodies = custodies
+            .into_iter()
+            .map(|(custody_pubkey, custody)| {
+                let oracle = oracle_prices
+                    .get(&custody_pubkey)
+                    .copied()
+                    .unwrap_or(OraclePrice {
+                        price: f64::NAN,
+                        ema_price: f64::NAN,
+                        publish_time: 0,
+                        fallback: None,
+                    });
+                (custody_pubkey, custody, oracle)
+            })
+            .collect();
+        let unix_time = SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)?
+            .as_secs();
+        Ok(PerpState::from_parts(
+            pool_pubkey,
+            pool,
+            custodies,
+            unix_time,
+            self.slot,
+        ))
+    }
+
+    pub fn open_positions(&self) -> Vec<(Pubkey, perp_abi::state::Position)> {
+        self.positions
+            .iter()
+            .map(|(pubkey, position)| (*pubkey, *position))
+            .collect()
+    }
+}
@@ -0,0 +1,103 @@
+//! Equity curves of the wallets watched through the config's `[wallets]`, from the
+//! `wallets` rows stored with each snapshot.
+
+use std::collections::BTreeMap;
+use std::ops::{Div as _, Mul as _, Sub as _};
+
+use thousands::Separable;
+
+use crate::analyze::format_time;
+use crate::store::{child_path, read_records, Record as _, WalletRecord};
+use crate::style;
+use crate::table;
+
+fn usd(value: f64) -> String {
+    format!("${}", value.round().separate_with_commas())
+}
+
+/// Largest drop of `equity` from a previous peak, and that drop as a share of the peak
+fn max_drawdown(equity: &[f64]) -> (f64, f64) {
+    let mut peak = f64::NEG_INFINITY;
+    let mut drawdown = (0.0, 0.0);
+    for value in equity {
+        peak = peak.max(*value);
+        let drop = peak.sub(value);
+        if drop > drawdown.0 {
+            drawdown = (drop, drop.div(peak));
+        }
+    }
+    drawdown
+}
+
+/// Prints the equity curve of each wallet stored with `history`, or only of the one
+/// labeled `label`, between `from` and `to`, with its latest `limit` rows
+pub fn run(
+    history: &str,
+    label: Option<&str>,
+    from: Option<u64>,
+    to: Option<u64>,
+    limit: usize,
+) -> Result<(), Box<dyn std::error::Error>> {
+    let path = child_path(history, WalletRecord::TABLE);
+    let mut wallets: BTreeMap<String, Vec<WalletRecord>> = BTreeMap::new();
+    for record in read_records::<WalletRecord>(&path)? {
+        if label.map_or(false, |label| record.label != label)
+            || from.map_or(false, |from| record.unix_time < from)
+            || to.map_or(false, |to| record.unix_time > to)
+        {
+            continue;
+        }
+        wallets
+            .entry(record.label.clone())
+            .or_default()
+            .push(record);
+    }
+    if wallets.is_empty() {
+        return Err(match label {
+            Some(label) => format!("no rows for wallet {label} in {path}"),
+            None => format!("no wallet rows in {path}, list wallets under [wallets] in the config and take snapshots with -c {history}"),
+        }
+        .into());
+    }
+
+    for (label, mut records) in wallets {
+        records.sort_by_key(|record| record.unix_time());
+        let equity: Vec<f64> = records.iter().map(|record| record.equity).collect();
+        let (first, last) = (equity[0], equity[equity.len() - 1]);
+        let (drawdown, drawdown_share) = max_drawdown(&equity);
+        println!("{label} ({})", records[0].wallet);
+        println!("Equity {}", style::sparkline(&equity));
+        println!(
+            "{} to {} over {} snapshots, peak {}, max drawdown {} ({:.1}%)",
+            usd(first),
+            usd(last),
+            records.len(),
+            usd(equity.iter().copied().fold(f64::NEG_INFINITY, f64::max)),
+            usd(drawdown),
+            drawdown_share.mul(100.0),
+        );
+        let rows = records
+            .iter()
+            .skip(records.len().saturating_sub(limit))
+            .map(|record| {
+                vec![
+                    format_time(record.unix_time),
+                    record.positions.to_string(),
+                    usd(record.size_usd),
+                    usd(record.collateral_usd),
+                    usd(record.unrealized_pnl),
+                    usd(record.equity),
+                ]
+            })
+            .collect();
+        println!(
+            "{}",
+            table::render(
+                &["Time", "Positions", "Size", "Collateral", "P&L", "Equity"],
+                rows
+            )
+        );
+        println!();
+    }
+    Ok(())
+}
@@ -0,0 +1,77 @@
+//! Browser entry point: the page fetches accounts itself, e.g. with `getProgramAccounts`
+//! and `getMultipleAccounts` in base64 encoding, and the snapshot is computed locally.
+//!
+//! ```js
+//! import init, { computeSnapshot } from "./pkg/jupiter_perpetuals_analytics.js";
+//! await init();
+//! const snapshot = computeSnapshot({ unix_time, slot, pool, custodies, oracles, positions });
+//! ```
+
+use std::collections::HashMap;
+use std::str::FromStr;
+
+use base64::Engine;
+use serde::{Deserialize, Serialize};
+use solana_sdk::pubkey::Pubkey;
+use wasm_bindgen::prelude::*;
+
+use crate::snapshot;
+use crate::state::{decode_open_positions, PerpState};
+
+/// An account as returned by RPC with base64 encoding
+#[derive(Deserialize)]
+struct EncodedAccount {
+    pubkey: String,
+    /// Base64 account data
+    data: String,
+}
+
+impl EncodedAccount {
+    fn decode(&self) -> Result<(Pubkey, Vec<u8>), Box<dyn std::error::Error>> {
+        Ok((
+            Pubkey::from_str(&self.pubkey)?,
+            base64::prelude::BASE64_STANDARD.decode(&self.data)?,
+        ))
+    }
+}
+
+#[derive(Deserialize)]
+struct Accounts {
+    unix_time: u64,
+    slot: u64,
+    pool: EncodedAccount,
+    custodies: Vec<EncodedAccount>,
+    /// Oracle accounts of every custody
+    oracles: Vec<EncodedAccount>,
+    positions: Vec<EncodedAccount>,
+}
+
+fn compute(accounts: Accounts) -> Result<serde_json::Value, Box<dyn std::error::Error>> {
+    let decode_all = |encoded: &[EncodedAccount]| {
+        encoded
+            .iter()
+            .map(EncodedAccount::decode)
+            .collect::<Result<Vec<_>, _>>()
+    };
+    let (pool_pubkey, pool_data) = accounts.pool.decode()?;
+    let oracle_accounts: HashMap<Pubkey, Vec<u8>> =
+        decode_all(&accounts.oracles)?.into_iter().collect();
+    let state = PerpState::from_accounts(
+        (pool_pubkey, &pool_data),
+        &decode_all(&accounts.custodies)?,
+        &oracle_accounts,
+        accounts.unix_time,
+        accounts.slot,
+    )?;
+    let positions = decode_open_positions(&decode_all(&accounts.positions)?)?;
+    Ok(snapshot::to_json(&state, &positions)?)
+}
+
+/// Computes a snapshot from raw accounts, returning `{ snapshot, custodies, positions }` as
+/// plain objects
+#[wasm_bindgen(js_name = computeSnapshot)]
+pub fn compute_snapshot(accounts: JsValue) -> Result<JsValue, JsError> {
+    let accounts: Accounts = serde_wasm_bindgen::from_value(accounts)?;
+    let value = compute(accounts).map_err(|err| JsError::new(&err.to_string()))?;
+    Ok(value.serialize(&serde_wasm_bindgen::Serializer::json_compatible())?)
+}
@@ -0,0 +1,339 @@
+//! Keeps the open position set current between watch cycles from websocket notifications,
+//! so each cycle doesn't need a full position scan.
+
+use std::collections::{BTreeMap, HashMap};
+use std::sync::mpsc;
+
+use anchor_lang::Discriminator as _;
+use solana_account_decoder::UiAccountEncoding;
+use solana_client::pubsub_client::{ProgramSubscription, PubsubClient};
+use solana_client::rpc_client::RpcClient;
+use solana_client::rpc_config::{RpcAccountInfoConfig, RpcProgramAccountsConfig};
+use solana_client::rpc_filter::{Memcmp, MemcmpEncodedBytes, RpcFilterType};
+use solana_client::rpc_response::RpcKeyedAccount;
+use solana_sdk::commitment_config::CommitmentConfig;
+use solana_sdk::pubkey::Pubkey;
+use tungstenite::client::IntoClientRequest as _;
+use tungstenite::Message;
+
+use crate::idl;
+use crate::rpc::{program_id, websocket_url};
+use crate::snapshot::PositionTotals;
+use crate::state::{for_each_open_position, load_open_positions, position_account_size};
+use crate::transport::header_map;
+
+enum Subscription {
+    Pubsub(ProgramSubscription),
+    /// Opened by hand, as the pubsub client can't send extra handshake headers
+    WithHeaders(mpsc::Receiver<RpcKeyedAccount>),
+}
+
+/// Where position changes are subscribed to, kept to subscribe again after the websocket
+/// closes
+struct Endpoint {
+    ws_url: String,
+    headers: BTreeMap<String, String>,
+    commitment: CommitmentConfig,
+}
+
+impl Endpoint {
+    fn subscribe(&self) -> Result<Subscription, Box<dyn std::error::Error>> {
+        let mut filters = vec![RpcFilterType::Memcmp(Memcmp::new(
+            0,
+            MemcmpEncodedBytes::Bytes(perp_abi::state::Position::DISCRIMINATOR.into()),
+        ))];
+        if let Some(data_size) = position_account_size() {
+            filters.push(RpcFilterType::DataSize(data_size));
+        }
+        let config = RpcProgramAccountsConfig {
+            filters: Some(filters),
+            account_config: RpcAccountInfoConfig {
+                encoding: Some(UiAccountEncoding::Base64),
+                commitment: Some(self.commitment),
+                ..Default::default()
+            },
+            ..Default::default()
+        };
+        if self.headers.is_empty() {
+            Ok(Subscription::Pubsub(PubsubClient::program_subscribe(
+                &self.ws_url,
+                &program_id(),
+                Some(config),
+            )?))
+        } else {
+            subscribe_with_headers(&self.ws_url, &self.headers, config)
+                .map(Subscription::WithHeaders)
+        }
+    }
+}
+
+pub struct PositionTracker {
+    positions: HashMap<Pubkey, perp_abi::state::Position>,
+    /// Sums over `positions`, updated with each notification
+    totals: PositionTotals,
+    /// `None` if subscriptions are off
+    endpoint: Option<Endpoint>,
+    subscription: Option<Subscription>,
+    /// Cycles between full rescans checking the tracked set hasn't drifted
+    full_refresh_every: u64,
+    /// Whether rescans page through the positions, as with `--paged-positions`
+    paged: bool,
+    cycle: u64,
+    /// Whether a notification couldn't be applied, so the next cycle rescans
+    rescan: bool,
+}
+
+impl PositionTracker {
+    /// Subscribes to position account changes, falling back to rescanning every cycle when
+    /// the endpoint has no usable websocket or `subscribe` is off. `headers` are sent with
+    /// the websocket handshake. With `paged`, rescans fetch the positions page by page.
+    pub fn new(
+        connection: &RpcClient,
+        full_refresh_every: u64,
+        subscribe: bool,
+        headers: &BTreeMap<String, String>,
+        paged: bool,
+    ) -> Self {
+        let endpoint = subscribe.then(|| Endpoint {
+            ws_url: websocket_url(&connection.url()),
+            headers: headers.clone(),
+            commitment: connection.commitment(),
+        });
+        let subscription = endpoint
+            .as_ref()
+            .and_then(|endpoint| match endpoint.subscribe() {
+                Ok(subscription) => Some(subscription),
+                Err(err) => {
+                    eprintln!(
+                        "Position subscription on {} failed, rescanning every cycle: {err}",
+                        endpoint.ws_url
+                    );
+                    None
+                }
+            });
+        PositionTracker {
+            positions: HashMap::new(),
+            totals: PositionTotals::default(),
+            endpoint,
+            subscription,
+            full_refresh_every: full_refresh_every.max(1),
+            paged,
+            cycle: 0,
+            rescan: false,
+        }
+    }
+
+    /// Applies the queued notifications, dropping the subscription if its websocket closed.
+    /// A notification that can't be applied is skipped, and the next cycle rescans instead.
+    fn apply_notifications(&mut self) {
+        let mut keyed_accounts = vec![];
+        let disconnected = match &self.subscription {
+            Some(Subscription::Pubsub((_, receiver))) => loop {
+                match receiver.try_recv() {
+                    Ok(notification) => keyed_accounts.push(notification.value),
+                    Err(err) => break err.is_disconnected(),
+                }
+            },
+            Some(Subscription::WithHeaders(receiver)) => loop {
+                match receiver.try_recv() {
+                    Ok(keyed_account) => keyed_accounts.push(keyed_account),
+                    Err(err) => break matches!(err, mpsc::TryRecvError::Disconnected),
+                }
+            },
+            None => false,
+        };
+        if disconnected {
+            eprintln!("Position subscription closed, rescanning until it's reopened");
+            self.subscription = None;
+        }
+        for keyed_account in keyed_accounts {
+            if let Err(err) = self.apply(&keyed_account) {
+                eprintln!(
+                    "Skipping position notification for {}, rescanning next cycle: {err}",
+                    keyed_account.pubkey
+                );
+                self.rescan = true;
+            }
+        }
+    }
+
+    fn apply(&mut self, keyed_account: &RpcKeyedAccount) -> Result<(), Box<dyn std::error::Error>> {
+        let pubkey: Pubkey = keyed_account.pubkey.parse()?;
+        let data = keyed_account
+            .account
+            .data
+            .decode()
+            .ok_or("undecodable position notification")?;
+        let position = idl::decode_position(&data)?;
+        if let Some(previous) = self.positions.remove(&pubkey) {
+            self.totals.remove(&previous);
+        }
+        if position.size_usd != 0 {
+            self.totals.add(&position);
+            self.positions.insert(pubkey, position);
+        }
+        Ok(())
+    }
+
+    /// Open positions as of now. The first cycle, every `full_refresh_every` cycles, every
+    /// cycle the subscription was down and the cycle after a notification was skipped rescan
+    /// all positions, reporting how far the tracked set had drifted.
+    pub fn open_positions(
+        &mut self,
+        connection: &RpcClient,
+    ) -> Result<Vec<(Pubkey, perp_abi::state::Position)>, Box<dyn std::error::Error>> {
+        // notifications queued before the rescan are older than its results
+        self.apply_notifications();
+        let subscribed = self.subscription.is_some();
+        // changes made while unsubscribed were missed, so resubscribing is followed by a
+        // rescan
+        if let (None, Some(endpoint)) = (&self.subscription, &self.endpoint) {
+            self.subscription = endpoint.subscribe().ok();
+        }
+        let full_refresh = !subscribed || self.rescan || self.cycle % self.full_refresh_every == 0;
+        self.cycle += 1;
+
+        if full_refresh {
+            let mut positions = HashMap::new();
+            if self.paged {
+                for_each_open_position(connection, |pubkey, position| {
+                    positions.insert(pubkey, position);
+                })?;
+            } else {
+                positions.extend(load_open_positions(connection)?);
+            }
+            if self.cycle > 1 && subscribed {
+                let drifted = positions
+                    .iter()
+                    .filter(|(pubkey, position)| {
+                        self.positions
+                            .get(*pubkey)
+                            .map_or(true, |tracked| tracked.update_time != position.update_time)
+                    })
+                    .count()
+                    + self
+                        .positions
+                        .keys()
+                        .filter(|pubkey| !positions.contains_key(*pubkey))
+                        .count();
+                if drifted > 0 {
+                    eprintln!("Rescan corrected {drifted} positions missed by the subscription");
+                }
+            }
+            // the sums are rebuilt from scratch too, dropping any rounding they accumulated
+            self.totals = PositionTotals::default();
+            for position in positions.values() {
+                self.totals.add(position);
+            }
+            self.positions = positions;
+            self.rescan = false;
+        }
+
+        Ok(self
+            .positions
+            .iter()
+            .map(|(pubkey, position)| (*pubkey, *position))
+            .collect())
+    }
+
+    /// Sums over the positions [`PositionTracker::open_positions`] last returned
+    pub fn totals(&self) -> &PositionTotals {
+        &self.totals
+    }
+}
+
+/// Sends a `programSubscribe` over a websocket whose handshake carries `headers`, forwarding
+/// notifications from a background thread until the socket closes
+fn subscribe_with_headers(
+    ws_url: &str,
+    headers: &BTreeMap<String, String>,
+    config: RpcProgramAccountsConfig,
+) -> Result<mpsc::Receiver<RpcKeyedAccount>, Box<dyn std::error::Error>> {
+    let mut request = ws_url.into_client_request()?;
+    request.headers_mut().extend(header_map(headers)?);
+    let (mut socket, _) = tungstenite::connect(request)?;
+    let subscribe = serde_json::json!({
+        "jsonrpc": "2.0",
+        "id": 1,
+        "method": "programSubscribe",
+        "params": [program_id().to_string(), config],
+    });
+    socket.write_message(Message::Text(subscribe.to_string()))?;
+
+    let (sender, receiver) = mpsc::channel();
+    std::thread::spawn(move || loop {
+        let text = match socket.read_message() {
+            Ok(Message::Text(text)) => text,
+            Ok(_) => continue,
+            Err(err) => {
+                eprintln!("Position subscription closed: {err}");
+                break;
+            }
+        };
+        // the subscription confirmation and anything else without an account is skipped
+        let Ok(message) = serde_json::from_str::<serde_json::Value>(&text) else {
+            continue;
+        };
+        let value = message["params"]["result"]["value"].clone();
+        if let Ok(keyed_account) = serde_json::from_value::<RpcKeyedAccount>(value) {
+            if sender.send(keyed_account).is_err() {
+                break;
+            }
+        }
+    });
+    Ok(receiver)
+}
+
+#[cfg(test)]
+mod tests {
+    use anchor_lang::AccountSerialize as _;
+    use base64::Engine as _;
+
+    use super::*;
+
+    fn notification(pubkey: &str, data: &[u8]) -> RpcKeyedAccount {
+        serde_json::from_value(serde_json::json!({
+            "pubkey": pubkey,
+            "account": {
+                "lamports": 1,
+                "data": [base64::prelude::BASE64_STANDARD.encode(data), "base64"],
+                "owner": program_id().to_string(),
+                "executable": false,
+                "rentEpoch": 0,
+            },
+        }))
+        .unwrap()
+    }
+
+    #[test]
+    fn skips_bad_notifications_and_rescans() {
+        let (sender, receiver) = mpsc::channel();
+        let mut tracker = PositionTracker {
+            positions: HashMap::new(),
+            totals: PositionTotals::default(),
+            endpoint: None,
+            subscription: Some(Subscription::WithHeaders(receiver)),
+            full_refresh_every: 100,
+            paged: false,
+            cycle: 1,
+            rescan: false,
+        };
+        let position = perp_abi::state::Position {
+            size_usd: 1_000_000_000,
+            ..Default::default()
+        };
+        let mut data = vec![];
+        position.try_serialize(&mut data).unwrap();
+        let open = Pubkey::new_unique();
+        sender.send(notification("not a pubkey", &data)).unwrap();
+        sender
+            .send(notification(&Pubkey::new_unique().to_string(), &[1, 2, 3]))
+            .unwrap();
+        sender.send(notification(&open.to_string(), &data)).unwrap();
+
+        tracker.apply_notifications();
+        assert!(tracker.rescan);
+        assert!(tracker.subscription.is_some());
+        assert_eq!(tracker.positions.keys().collect::<Vec<_>>(), [&open]);
+    }
+}